@@ -1,5 +1,7 @@
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use candle_core::{Device, Tensor, DType};
 use candle_transformers::models::bert::{BertModel, Config};
@@ -9,10 +11,178 @@ use candle_hub::api::Api;
 use std::fs;
 use vulkano::instance::Instance as VulkanInstance;
 use super::ethics::EthicalGuard;
+use super::error::MatrixError;
+
+/// What an agent is good for; the router matches these against the task.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Capability {
+    /// Reviews commands for policy and ethics violations. Safety agents see every task.
+    Safety,
+    Compute,
+    Suggestion,
+    Git,
+    Testing,
+    Docs,
+    Summary,
+}
+
+/// The shape of task an agent knows how to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InputKind {
+    /// A command line that parses as shell.
+    ShellCommand,
+    /// Free text that is not a command, e.g. a question.
+    NaturalLanguage,
+}
+
+/// How an agent introduces itself to the registry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentDescriptor {
+    /// Unique within a registry; shown in dry runs, audit entries and receipts.
+    pub name: String,
+    pub capabilities: Vec<Capability>,
+    /// Relative cost of one call: 1 is a cheap local check, tens mean model inference or a network hop.
+    pub cost: u32,
+    pub inputs: Vec<InputKind>,
+    /// Programs this agent specializes in, e.g. `git`; empty means any command.
+    #[serde(default)]
+    pub programs: Vec<String>,
+    /// How long a dry run waits for this agent; `DEFAULT_AGENT_DEADLINE` when unset.
+    #[serde(default)]
+    pub deadline_ms: Option<u64>,
+}
+
+/// Time an agent gets to answer a dry run unless its descriptor allows more or less.
+pub const DEFAULT_AGENT_DEADLINE: Duration = Duration::from_secs(10);
+
+impl AgentDescriptor {
+    pub fn new(name: &str, capabilities: &[Capability], cost: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            capabilities: capabilities.to_vec(),
+            cost,
+            inputs: vec![InputKind::ShellCommand, InputKind::NaturalLanguage],
+            programs: vec![],
+            deadline_ms: None,
+        }
+    }
+
+    pub fn inputs(mut self, inputs: &[InputKind]) -> Self {
+        self.inputs = inputs.to_vec();
+        self
+    }
+
+    pub fn programs(mut self, programs: &[&str]) -> Self {
+        self.programs = programs.iter().map(|p| p.to_string()).collect();
+        self
+    }
+
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline_ms = Some(deadline.as_millis() as u64);
+        self
+    }
+
+    pub fn time_limit(&self) -> Duration {
+        self.deadline_ms.map(Duration::from_millis).unwrap_or(DEFAULT_AGENT_DEADLINE)
+    }
+
+    pub fn has(&self, capability: &Capability) -> bool {
+        self.capabilities.contains(capability)
+    }
+}
+
+/// An agent's stance on a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Vote {
+    Approve,
+    Deny,
+    /// The agent did work but has no opinion on whether the task should proceed.
+    Abstain,
+}
+
+impl std::fmt::Display for Vote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Vote::Approve => write!(f, "APPROVE"),
+            Vote::Deny => write!(f, "DENY"),
+            Vote::Abstain => write!(f, "ABSTAIN"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attachment {
+    pub name: String,
+    pub content: String,
+}
+
+/// What an agent concluded about a task. Violations the guard enforces are still errors;
+/// a `Deny` vote is the agent's opinion and is weighed by the consensus strategy.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AgentOutput {
+    pub vote: Vote,
+    /// How sure the agent is of its vote, from 0 to 1.
+    pub confidence: f32,
+    pub rationale: String,
+    /// A command the agent would run instead, if it has one.
+    #[serde(default)]
+    pub rewrite: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// Wall-clock time of the call, filled in by whoever ran the agent.
+    #[serde(default)]
+    pub elapsed_ms: u64,
+}
+
+impl AgentOutput {
+    pub fn new(vote: Vote, confidence: f32, rationale: impl Into<String>) -> Self {
+        Self { vote, confidence: confidence.clamp(0.0, 1.0), rationale: rationale.into(), rewrite: None, attachments: vec![], elapsed_ms: 0 }
+    }
+
+    pub fn approve(confidence: f32, rationale: impl Into<String>) -> Self {
+        Self::new(Vote::Approve, confidence, rationale)
+    }
+
+    pub fn deny(confidence: f32, rationale: impl Into<String>) -> Self {
+        Self::new(Vote::Deny, confidence, rationale)
+    }
+
+    pub fn abstain(rationale: impl Into<String>) -> Self {
+        Self::new(Vote::Abstain, 0.0, rationale)
+    }
+
+    pub fn with_rewrite(mut self, rewrite: impl Into<String>) -> Self {
+        self.rewrite = Some(rewrite.into());
+        self
+    }
+
+    pub fn with_attachment(mut self, name: &str, content: impl Into<String>) -> Self {
+        self.attachments.push(Attachment { name: name.to_string(), content: content.into() });
+        self
+    }
+
+    /// What a downstream step should work on: the rewrite if there is one, else the rationale.
+    pub fn payload(&self) -> &str {
+        self.rewrite.as_deref().unwrap_or(&self.rationale)
+    }
+}
 
 #[async_trait]
 pub trait Agent: Send + Sync {
-    async fn execute(&self, task: &str) -> Result<String, String>;
+    fn descriptor(&self) -> AgentDescriptor;
+
+    async fn execute(&self, task: &str) -> Result<AgentOutput, MatrixError>;
+}
+
+/// Runs `agent` on `task` and records how long it took.
+pub async fn execute_timed(agent: &dyn Agent, task: &str) -> Result<AgentOutput, MatrixError> {
+    let started = Instant::now();
+    let mut output = agent.execute(task).await?;
+    output.elapsed_ms = started.elapsed().as_millis() as u64;
+    Ok(output)
 }
 
 pub struct EthicalAgent {
@@ -23,37 +193,52 @@ pub struct EthicalAgent {
 }
 
 impl EthicalAgent {
-    pub fn new(guard: Arc<EthicalGuard>) -> Self {
+    pub fn new(guard: Arc<EthicalGuard>) -> Result<Self, MatrixError> {
         let device = Device::Cpu;
-        let api = Api::new().unwrap();
+        let api = Api::new().map_err(|e| MatrixError::model_load("hub api", e))?;
         let repo = api.repo("distilbert-base-uncased".to_string());
-        let config_filename = repo.get("config.json").unwrap();
-        let tokenizer_filename = repo.get("tokenizer.json").unwrap();
-        let weights_filename = repo.get("model.safetensors").unwrap();
-        let config = fs::read_to_string(config_filename).unwrap();
-        let config: Config = serde_json::from_str(&config).unwrap();
-        let tokenizer = Tokenizer::from_file(tokenizer_filename).unwrap();
-        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights_filename], DType::F32, &device).unwrap() };
-        let model = BertModel::load(vb, &config).unwrap();
-        Self {
+        let config_filename = repo.get("config.json").map_err(|e| MatrixError::model_load("config.json", e))?;
+        let tokenizer_filename = repo.get("tokenizer.json").map_err(|e| MatrixError::model_load("tokenizer.json", e))?;
+        let weights_filename = repo.get("model.safetensors").map_err(|e| MatrixError::model_load("model.safetensors", e))?;
+        let config = fs::read_to_string(config_filename).map_err(|e| MatrixError::model_load("config.json", e))?;
+        let config: Config = serde_json::from_str(&config).map_err(|e| MatrixError::model_load("config.json", e))?;
+        let tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(|e| MatrixError::model_load("tokenizer.json", e))?;
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&[weights_filename], DType::F32, &device).map_err(|e| MatrixError::model_load("model.safetensors", e))? };
+        let model = BertModel::load(vb, &config).map_err(|e| MatrixError::model_load("bert", e))?;
+        Ok(Self {
             guard,
             llm_model: Arc::new(model),
             tokenizer: Arc::new(tokenizer),
             device,
-        }
+        })
     }
 }
 
 #[async_trait]
 impl Agent for EthicalAgent {
-    async fn execute(&self, task: &str) -> Result<String, String> {
+    fn descriptor(&self) -> AgentDescriptor {
+        // Inference on CPU takes seconds
+        AgentDescriptor::new("ethical", &[Capability::Safety], 10).deadline(Duration::from_secs(30))
+    }
+
+    async fn execute(&self, task: &str) -> Result<AgentOutput, MatrixError> {
         self.guard.check_command(task).await?;
-        let tokens = self.tokenizer.encode(task, true).map_err(|e| e.to_string())?;
-        let input_ids = Tensor::new(&self.device, &tokens.get_ids()).map_err(|e| e.to_string())?;
-        let outputs = self.llm_model.forward(&input_ids.unsqueeze(0)).map_err(|e| e.to_string())?;
-        let bias_score = outputs.last_hidden_state().mean(1).to_scalar::<f32>().map_err(|e| e.to_string())?;
-        if bias_score > 0.5 { return Err("LLM-detected bias".to_string()); }
-        Ok(format!("Ethically approved (score: {:.2}): {}", bias_score, task))
+        let (model, tokenizer, device, text) = (self.llm_model.clone(), self.tokenizer.clone(), self.device.clone(), task.to_string());
+        // Off the async workers, so a slow forward pass can be abandoned at its deadline
+        let bias_score = tokio::task::spawn_blocking(move || {
+            let tokens = tokenizer.encode(text, true).map_err(|e| MatrixError::inference("tokenize", e))?;
+            let input_ids = Tensor::new(&device, &tokens.get_ids()).map_err(|e| MatrixError::inference("input tensor", e))?;
+            let outputs = model.forward(&input_ids.unsqueeze(0)).map_err(|e| MatrixError::inference("forward", e))?;
+            outputs.last_hidden_state().mean(1).to_scalar::<f32>().map_err(|e| MatrixError::inference("bias score", e))
+        })
+        .await
+        .map_err(|e| MatrixError::AgentPanic(e.to_string()))??;
+        if bias_score > 0.5 {
+            self.guard.enforce(task, "llm_bias".to_string(), format!("LLM-detected bias (score: {:.2})", bias_score)).await?;
+            // Only reachable when the guard is lenient: proceed, but without conviction
+            return Ok(AgentOutput::approve(1.0 - bias_score, format!("Bias flagged and logged (score: {:.2})", bias_score)));
+        }
+        Ok(AgentOutput::approve(1.0 - bias_score, format!("Ethically approved (score: {:.2})", bias_score)))
     }
 }
 
@@ -69,30 +254,155 @@ impl ComputeAgent {
 
 #[async_trait]
 impl Agent for ComputeAgent {
-    async fn execute(&self, task: &str) -> Result<String, String> {
+    fn descriptor(&self) -> AgentDescriptor {
+        let cost = if self.vulkan.is_some() { 2 } else { 5 };
+        AgentDescriptor::new("compute", &[Capability::Compute], cost).inputs(&[InputKind::ShellCommand])
+    }
+
+    async fn execute(&self, task: &str) -> Result<AgentOutput, MatrixError> {
         match &self.vulkan {
             Some(inst) => {
                 let processed = super::gpu::run_compute_shader(inst, task).await;
-                Ok(format!("GPU processed: {}", processed))
+                Ok(AgentOutput::abstain(format!("GPU processed: {}", processed)))
             },
-            None => Ok(format!("CPU fallback processed: {}", task)),
+            None => Ok(AgentOutput::abstain(format!("CPU fallback processed: {}", task))),
+        }
+    }
+}
+
+/// How `orchestrate` turns individual votes into one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Consensus {
+    /// Approve only if no agent denies and at least one approves.
+    #[default]
+    Unanimous,
+    /// More approvals than denials; abstentions do not count.
+    Majority,
+    /// Approval and denial confidences are summed and the larger side wins.
+    Weighted,
+}
+
+impl std::str::FromStr for Consensus {
+    type Err = MatrixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "unanimous" => Ok(Consensus::Unanimous),
+            "majority" => Ok(Consensus::Majority),
+            "weighted" => Ok(Consensus::Weighted),
+            other => Err(MatrixError::Policy(format!("Unknown consensus '{}': expected unanimous, majority or weighted", other))),
+        }
+    }
+}
+
+impl Consensus {
+    /// The combined vote and its support, from 0 to 1. Ties deny: the matrix fails closed.
+    pub fn decide<'a>(&self, outputs: impl IntoIterator<Item = &'a AgentOutput>) -> (Vote, f32) {
+        let (mut approve, mut deny, mut approve_weight, mut deny_weight) = (0usize, 0usize, 0f32, 0f32);
+        for output in outputs {
+            match output.vote {
+                Vote::Approve => {
+                    approve += 1;
+                    approve_weight += output.confidence;
+                },
+                Vote::Deny => {
+                    deny += 1;
+                    deny_weight += output.confidence;
+                },
+                Vote::Abstain => {},
+            }
+        }
+        if approve + deny == 0 {
+            return (Vote::Abstain, 0.0);
+        }
+        let share = |part: f32, whole: f32| if whole > 0.0 { part / whole } else { 0.0 };
+        match self {
+            Consensus::Unanimous if deny == 0 => (Vote::Approve, share(approve_weight, approve as f32)),
+            Consensus::Unanimous => (Vote::Deny, share(deny_weight, deny as f32)),
+            Consensus::Majority if approve > deny => (Vote::Approve, approve as f32 / (approve + deny) as f32),
+            Consensus::Majority => (Vote::Deny, deny as f32 / (approve + deny) as f32),
+            Consensus::Weighted if approve_weight > deny_weight => (Vote::Approve, share(approve_weight, approve_weight + deny_weight)),
+            Consensus::Weighted => (Vote::Deny, share(deny_weight, approve_weight + deny_weight)),
         }
     }
 }
 
-pub async fn orchestrate(agents: Vec<Arc<dyn Agent>>, task: &str) -> Result<String, String> {
-    let mut handles = vec![];
-    for agent in agents {
-        let t = task.to_string();
-        handles.push(tokio::spawn(async move { agent.execute(&t).await }));
+/// Each agent's output and the vote the consensus strategy drew from them.
+#[derive(Debug, Clone)]
+pub struct Tally {
+    pub consensus: Consensus,
+    pub vote: Vote,
+    pub support: f32,
+    pub outputs: Vec<(String, AgentOutput)>,
+}
+
+impl Tally {
+    /// Folds the tally into a single output, e.g. for a remote daemon answering as one agent.
+    pub fn into_output(self) -> AgentOutput {
+        let rationale = self.outputs.iter().map(|(name, o)| format!("{}: {} ({})", name, o.vote, o.rationale)).collect::<Vec<_>>().join(" | ");
+        let mut output = AgentOutput::new(self.vote, self.support, rationale);
+        output.rewrite = self.outputs.iter().find_map(|(_, o)| o.rewrite.clone());
+        output.attachments = self.outputs.into_iter().flat_map(|(_, o)| o.attachments).collect();
+        output
+    }
+}
+
+/// Runs every agent on the same task in parallel and combines their votes. Fails with the
+/// first agent error in registration order. For dependent steps, build a `Workflow` instead.
+pub async fn orchestrate(agents: Vec<Arc<dyn Agent>>, task: &str, consensus: Consensus) -> Result<Tally, MatrixError> {
+    let handles: Vec<_> = agents
+        .into_iter()
+        .map(|agent| {
+            let task = task.to_string();
+            (agent.descriptor().name, tokio::spawn(async move { execute_timed(agent.as_ref(), &task).await }))
+        })
+        .collect();
+    let mut outputs = vec![];
+    for (name, handle) in handles {
+        let output = handle.await.map_err(|e| MatrixError::AgentPanic(e.to_string()))??;
+        outputs.push((name, output));
     }
-    let mut results = vec![];
-    for handle in handles {
-        match handle.await {
-            Ok(Ok(r)) => results.push(r),
-            Ok(Err(e)) => return Err(e),
-            Err(_) => return Err("Agent panic".to_string()),
+    let (vote, support) = consensus.decide(outputs.iter().map(|(_, o)| o));
+    Ok(Tally { consensus, vote, support, outputs })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consensus_strategies_fail_closed() {
+        use Consensus::*;
+        use Vote::*;
+        let cases: &[(Consensus, &[(Vote, f32)], Vote, f32)] = &[
+            // Nobody voted either way
+            (Unanimous, &[], Abstain, 0.0),
+            (Majority, &[(Abstain, 0.0)], Abstain, 0.0),
+            (Weighted, &[(Abstain, 0.0), (Abstain, 0.0)], Abstain, 0.0),
+            (Unanimous, &[(Approve, 0.8), (Approve, 0.6), (Abstain, 0.0)], Approve, 0.7),
+            (Unanimous, &[(Approve, 1.0), (Approve, 1.0), (Deny, 0.2)], Deny, 0.2),
+            (Majority, &[(Approve, 0.1), (Approve, 0.1), (Deny, 1.0)], Approve, 2.0 / 3.0),
+            (Majority, &[(Approve, 1.0), (Deny, 0.1), (Deny, 0.1)], Deny, 2.0 / 3.0),
+            (Majority, &[(Approve, 1.0), (Deny, 0.1), (Abstain, 0.0)], Deny, 0.5),
+            (Weighted, &[(Approve, 0.9), (Deny, 0.3), (Deny, 0.3)], Approve, 0.6),
+            (Weighted, &[(Approve, 0.2), (Approve, 0.2), (Deny, 0.6)], Deny, 0.6),
+            (Weighted, &[(Approve, 0.5), (Deny, 0.5)], Deny, 0.5),
+            // Votes cast with no confidence at all carry no weight either way
+            (Weighted, &[(Approve, 0.0), (Deny, 0.0)], Deny, 0.0),
+            (Weighted, &[(Approve, 0.0)], Deny, 0.0),
+        ];
+        for (consensus, votes, vote, support) in cases {
+            let outputs: Vec<AgentOutput> = votes.iter().map(|(v, c)| AgentOutput::new(*v, *c, "")).collect();
+            let (decided, decided_support) = consensus.decide(&outputs);
+            assert_eq!(decided, *vote, "{:?} over {:?}", consensus, votes);
+            assert!((decided_support - support).abs() < 1e-5, "{:?} over {:?}: support {}", consensus, votes, decided_support);
         }
     }
-    Ok(results.join(" | "))
+
+    #[test]
+    fn consensus_parses_case_insensitively() {
+        assert_eq!("Weighted".parse::<Consensus>().unwrap(), Consensus::Weighted);
+        assert!("plurality".parse::<Consensus>().is_err());
+    }
 }
@@ -0,0 +1,425 @@
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use super::error::MatrixError;
+use super::policy::Verdict;
+use super::receipt::{self, OperatorKey};
+
+/// `prev_hash` of the first entry in a log.
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Something the terminal did that compliance review must be able to reconstruct.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuditEvent {
+    CommandSubmitted { command: String },
+    /// An AI suggestion shown to the operator (GOVERNANCE.md's `ContentGenerated`).
+    ContentGenerated { command: String, content: String },
+    GuardVerdict { command: String, verdict: Verdict, rule_id: Option<String>, reason: String },
+    AgentResult { command: String, agent: String, ok: bool, detail: String },
+    /// The operator confirmed or cancelled a dry run, or released a quarantined command.
+    Confirmation { command: String, approved: bool, quarantine_id: Option<u64> },
+    /// The operator discarded a quarantined command; it was never run.
+    QuarantineRejected { command: String, quarantine_id: u64, rule: String, reason: String },
+    ExecutionFinished { command: String, exit_code: Option<i32> },
+}
+
+impl AuditEvent {
+    pub fn command(&self) -> &str {
+        match self {
+            AuditEvent::CommandSubmitted { command }
+            | AuditEvent::ContentGenerated { command, .. }
+            | AuditEvent::GuardVerdict { command, .. }
+            | AuditEvent::AgentResult { command, .. }
+            | AuditEvent::Confirmation { command, .. }
+            | AuditEvent::QuarantineRejected { command, .. }
+            | AuditEvent::ExecutionFinished { command, .. } => command,
+        }
+    }
+}
+
+/// One line of the log. `hash` covers every other field, including the previous entry's hash,
+/// so editing, reordering or removing any entry breaks the chain from that point on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub timestamp_ms: u64,
+    pub prev_hash: String,
+    pub event: AuditEvent,
+    pub hash: String,
+}
+
+impl AuditEntry {
+    pub fn at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.timestamp_ms)
+    }
+
+    fn compute_hash(seq: u64, timestamp_ms: u64, prev_hash: &str, event: &AuditEvent) -> Result<String, MatrixError> {
+        let event = serde_json::to_vec(event).map_err(|e| MatrixError::serialization("Audit event serialization failed", e))?;
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(b"agent-matrix audit v1");
+        hasher.update(&seq.to_be_bytes());
+        hasher.update(&timestamp_ms.to_be_bytes());
+        hasher.update(prev_hash.as_bytes());
+        hasher.update(&event);
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+}
+
+// The chain head lives beside the log so truncating trailing entries is detectable:
+// a shortened log still chains correctly but no longer ends at the recorded head. The chain
+// itself is unkeyed, so the head is signed with the operator key: without it a rewritten log
+// could simply be rehashed and given a new head.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AuditHead {
+    seq: u64,
+    hash: String,
+    #[serde(default)]
+    signature: String,
+}
+
+impl AuditHead {
+    fn signed(seq: u64, hash: String, key: &OperatorKey) -> Self {
+        let signature = key.sign_hex(&Self::message(seq, &hash));
+        Self { seq, hash, signature }
+    }
+
+    fn message(seq: u64, hash: &str) -> Vec<u8> {
+        [b"agent-matrix audit head v1".as_slice(), &seq.to_be_bytes(), hash.as_bytes()].concat()
+    }
+
+    fn verify(&self, public_key: &str) -> Result<(), MatrixError> {
+        receipt::verify_signature(public_key, &Self::message(self.seq, &self.hash), &self.signature)
+            .map_err(|e| MatrixError::Integrity(format!("Audit head at entry {} is not signed by the operator key: {}", self.seq, e)))
+    }
+}
+
+/// Filters for `AuditLog::query`; unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct AuditQuery {
+    pub since: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+    /// Substring of the command text.
+    pub command: Option<String>,
+    /// Only guard verdict events with this verdict.
+    pub verdict: Option<Verdict>,
+}
+
+impl AuditQuery {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        let at = entry.at();
+        self.since.is_none_or(|since| at >= since)
+            && self.until.is_none_or(|until| at <= until)
+            && self.command.as_ref().is_none_or(|c| entry.event.command().contains(c.as_str()))
+            && self.verdict.is_none_or(|v| matches!(&entry.event, AuditEvent::GuardVerdict { verdict, .. } if *verdict == v))
+    }
+}
+
+/// Append-only, BLAKE3 hash-chained event log stored as one JSON entry per line, whose head is
+/// signed by the operator key. Whoever holds that key can still rewrite the log, so for review
+/// against a compromised machine verify with a public key kept off-box.
+pub struct AuditLog {
+    path: PathBuf,
+    key: Arc<OperatorKey>,
+    // The next entry's seq and prev_hash, not a signed head
+    head: Mutex<AuditHead>,
+}
+
+impl AuditLog {
+    /// `~/.config/agent-matrix/audit.log`
+    pub fn default_path() -> PathBuf {
+        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+        PathBuf::from(home).join(".config").join("agent-matrix").join("audit.log")
+    }
+
+    /// Opens the log at `path`, creating it if needed, and resumes the chain from its last entry.
+    /// Refuses a log that no longer ends at its recorded head, or whose head `key` did not sign,
+    /// rather than extending it.
+    pub fn open(path: &Path, key: Arc<OperatorKey>) -> Result<Self, MatrixError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| MatrixError::io("Cannot create audit directory", e))?;
+        }
+        let entries = read_entries(path)?;
+        let recorded = read_head(path)?;
+        if let Some(head) = &recorded {
+            head.verify(&key.public_key_hex())?;
+        }
+        let head = match (entries.last(), &recorded) {
+            (None, None) => AuditHead { seq: 0, hash: GENESIS_HASH.to_string(), signature: String::new() },
+            (Some(last), Some(head)) if last.seq == head.seq && last.hash == head.hash => {
+                AuditHead { seq: last.seq + 1, hash: last.hash.clone(), signature: String::new() }
+            },
+            // `append` writes the entry before the head, so a crash in between leaves one sealed entry past it
+            (Some(last), _) if follows_head(last, recorded.as_ref())? => {
+                write_head(path, &AuditHead::signed(last.seq, last.hash.clone(), &key))?;
+                AuditHead { seq: last.seq + 1, hash: last.hash.clone(), signature: String::new() }
+            },
+            (last, _) => {
+                return Err(MatrixError::Integrity(format!(
+                    "Audit log ends at entry {} but the head records entry {}: refusing to append",
+                    last.map(|l| l.seq.to_string()).unwrap_or_else(|| "none".to_string()),
+                    recorded.as_ref().map(|h| h.seq.to_string()).unwrap_or_else(|| "none".to_string())
+                )));
+            },
+        };
+        Ok(Self { path: path.to_path_buf(), key, head: Mutex::new(head) })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn append(&self, event: AuditEvent) -> Result<AuditEntry, MatrixError> {
+        // Held across the write so concurrent appends cannot fork the chain
+        let mut head = self.head.lock().await;
+        let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+        let hash = AuditEntry::compute_hash(head.seq, timestamp_ms, &head.hash, &event)?;
+        let entry = AuditEntry { seq: head.seq, timestamp_ms, prev_hash: head.hash.clone(), event, hash };
+
+        let mut line = serde_json::to_vec(&entry).map_err(|e| MatrixError::serialization("Audit entry serialization failed", e))?;
+        line.push(b'\n');
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| MatrixError::io("Cannot open audit log", e))?;
+        file.write_all(&line).map_err(|e| MatrixError::io("Cannot append to audit log", e))?;
+        file.sync_data().map_err(|e| MatrixError::io("Cannot flush audit log", e))?;
+
+        write_head(&self.path, &AuditHead::signed(entry.seq, entry.hash.clone(), &self.key))?;
+        *head = AuditHead { seq: entry.seq + 1, hash: entry.hash.clone(), signature: String::new() };
+        Ok(entry)
+    }
+
+    /// Entries matching `query`, oldest first. Does not verify the chain; call `verify` for that.
+    pub fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEntry>, MatrixError> {
+        self::query(&self.path, query)
+    }
+}
+
+/// Entries of the log at `path` matching `query`, oldest first. Reads a damaged log too, so
+/// it can still be inspected after `open` has refused to extend it.
+pub fn query(path: &Path, query: &AuditQuery) -> Result<Vec<AuditEntry>, MatrixError> {
+    Ok(read_entries(path)?.into_iter().filter(|e| query.matches(e)).collect())
+}
+
+/// Checks every link of the chain at `path` and that it ends at the recorded head, signed by the
+/// hex-encoded `trusted_key`. Returns the number of entries on success.
+pub fn verify(path: &Path, trusted_key: &str) -> Result<u64, MatrixError> {
+    let entries = read_entries(path)?;
+    let mut prev_hash = GENESIS_HASH.to_string();
+    for (expected_seq, entry) in entries.iter().enumerate() {
+        if entry.seq != expected_seq as u64 {
+            return Err(MatrixError::Integrity(format!("Audit entry {} found where {} was expected: entries removed or reordered", entry.seq, expected_seq)));
+        }
+        if entry.prev_hash != prev_hash {
+            return Err(MatrixError::Integrity(format!("Audit entry {} does not chain to its predecessor", entry.seq)));
+        }
+        if AuditEntry::compute_hash(entry.seq, entry.timestamp_ms, &entry.prev_hash, &entry.event)? != entry.hash {
+            return Err(MatrixError::Integrity(format!("Audit entry {} has been modified", entry.seq)));
+        }
+        prev_hash = entry.hash.clone();
+    }
+
+    match (read_head(path)?, entries.last()) {
+        (None, None) => {},
+        (None, Some(_)) => return Err(MatrixError::Integrity("Audit log head is missing".to_string())),
+        (Some(head), last) => {
+            head.verify(trusted_key)?;
+            let ends_at_head = last.is_some_and(|last| last.seq == head.seq && last.hash == head.hash);
+            if !ends_at_head {
+                return Err(MatrixError::Integrity(format!(
+                    "Audit log ends at entry {} but the head records entry {}: log truncated",
+                    last.map(|l| l.seq.to_string()).unwrap_or_else(|| "none".to_string()),
+                    head.seq
+                )));
+            }
+        },
+    }
+    Ok(entries.len() as u64)
+}
+
+// Whether `entry` is the intact entry right after `head`, or the first entry when there is no head yet
+fn follows_head(entry: &AuditEntry, head: Option<&AuditHead>) -> Result<bool, MatrixError> {
+    let (seq, prev_hash) = head.map_or((0, GENESIS_HASH), |h| (h.seq + 1, h.hash.as_str()));
+    Ok(entry.seq == seq
+        && entry.prev_hash == prev_hash
+        && AuditEntry::compute_hash(entry.seq, entry.timestamp_ms, &entry.prev_hash, &entry.event)? == entry.hash)
+}
+
+fn head_path(path: &Path) -> PathBuf {
+    path.with_extension("head")
+}
+
+fn read_entries(path: &Path) -> Result<Vec<AuditEntry>, MatrixError> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(MatrixError::io("Cannot read audit log", e)),
+    };
+    let mut entries = vec![];
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| MatrixError::io("Cannot read audit log", e))?;
+        if line.is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line)
+            .map_err(|e| MatrixError::serialization(&format!("Malformed audit entry on line {}", number + 1), e))?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn read_head(path: &Path) -> Result<Option<AuditHead>, MatrixError> {
+    match std::fs::read(head_path(path)) {
+        Ok(raw) => serde_json::from_slice(&raw).map(Some).map_err(|e| MatrixError::serialization("Malformed audit head", e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(MatrixError::io("Cannot read audit head", e)),
+    }
+}
+
+fn write_head(path: &Path, head: &AuditHead) -> Result<(), MatrixError> {
+    let json = serde_json::to_vec(head).map_err(|e| MatrixError::serialization("Audit head serialization failed", e))?;
+    // Write-then-rename so the head is never observed half-written
+    let target = head_path(path);
+    let tmp = target.with_extension("head.tmp");
+    std::fs::write(&tmp, json).map_err(|e| MatrixError::io("Cannot write audit head", e))?;
+    std::fs::rename(&tmp, &target).map_err(|e| MatrixError::io("Cannot write audit head", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::LazyLock;
+
+    static KEY: LazyLock<Arc<OperatorKey>> = LazyLock::new(|| Arc::new(OperatorKey::generate("auditor")));
+
+    fn key() -> Arc<OperatorKey> {
+        KEY.clone()
+    }
+
+    fn trusted() -> String {
+        KEY.public_key_hex()
+    }
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("agent-matrix-audit-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("audit.log")
+    }
+
+    async fn log_with(path: &Path, commands: &[&str]) -> AuditLog {
+        let log = AuditLog::open(path, key()).unwrap();
+        for command in commands {
+            log.append(AuditEvent::CommandSubmitted { command: command.to_string() }).await.unwrap();
+        }
+        log
+    }
+
+    fn lines(path: &Path) -> Vec<String> {
+        std::fs::read_to_string(path).unwrap().lines().map(str::to_string).collect()
+    }
+
+    #[tokio::test]
+    async fn chain_survives_reopening() {
+        let path = scratch("reopen");
+        log_with(&path, &["ls", "pwd"]).await;
+        let log = log_with(&path, &["whoami"]).await;
+
+        assert_eq!(verify(&path, &trusted()).unwrap(), 3);
+        let entries = log.query(&AuditQuery::default()).unwrap();
+        assert_eq!(entries.iter().map(|e| e.seq).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(entries[2].prev_hash, entries[1].hash);
+    }
+
+    #[tokio::test]
+    async fn verify_detects_edits_and_reordering() {
+        let path = scratch("edit");
+        log_with(&path, &["ls", "pwd", "id"]).await;
+        let original = lines(&path);
+
+        std::fs::write(&path, original.join("\n").replace("\"pwd\"", "\"rm\"") + "\n").unwrap();
+        assert!(matches!(verify(&path, &trusted()), Err(MatrixError::Integrity(m)) if m.contains("modified")));
+
+        let swapped = [original[0].clone(), original[2].clone(), original[1].clone()];
+        std::fs::write(&path, swapped.join("\n") + "\n").unwrap();
+        assert!(matches!(verify(&path, &trusted()), Err(MatrixError::Integrity(_))));
+    }
+
+    #[tokio::test]
+    async fn truncated_log_fails_verification_and_refuses_appends() {
+        let path = scratch("truncate");
+        log_with(&path, &["ls", "pwd", "id"]).await;
+        let original = lines(&path);
+        std::fs::write(&path, original[..2].join("\n") + "\n").unwrap();
+
+        assert!(matches!(verify(&path, &trusted()), Err(MatrixError::Integrity(m)) if m.contains("truncated")));
+        assert!(matches!(AuditLog::open(&path, key()), Err(MatrixError::Integrity(_))));
+
+        std::fs::write(&path, "").unwrap();
+        assert!(matches!(AuditLog::open(&path, key()), Err(MatrixError::Integrity(_))));
+    }
+
+    #[tokio::test]
+    async fn missing_head_refuses_appends() {
+        let path = scratch("headless");
+        log_with(&path, &["ls", "pwd"]).await;
+        std::fs::remove_file(head_path(&path)).unwrap();
+
+        assert!(matches!(verify(&path, &trusted()), Err(MatrixError::Integrity(_))));
+        assert!(matches!(AuditLog::open(&path, key()), Err(MatrixError::Integrity(_))));
+    }
+
+    #[tokio::test]
+    async fn resumes_after_a_crash_between_entry_and_head() {
+        let path = scratch("crash");
+        log_with(&path, &["ls"]).await;
+        let head = std::fs::read(head_path(&path)).unwrap();
+        log_with(&path, &["pwd"]).await;
+        // As if the process died after writing the second entry but before its head
+        std::fs::write(head_path(&path), head).unwrap();
+
+        log_with(&path, &["id"]).await;
+        assert_eq!(verify(&path, &trusted()).unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn rehashed_log_needs_the_operator_key() {
+        let path = scratch("rehash");
+        log_with(&path, &["ls", "rm -rf /", "id"]).await;
+        let entries = log_with(&path, &[]).await.query(&AuditQuery::default()).unwrap();
+
+        // Drop the middle entry and rebuild a consistent chain and head without the key
+        let mut prev_hash = GENESIS_HASH.to_string();
+        let mut forged = vec![];
+        for (seq, entry) in entries.into_iter().filter(|e| e.seq != 1).enumerate() {
+            let hash = AuditEntry::compute_hash(seq as u64, entry.timestamp_ms, &prev_hash, &entry.event).unwrap();
+            forged.push(AuditEntry { seq: seq as u64, prev_hash: std::mem::replace(&mut prev_hash, hash.clone()), hash, ..entry });
+        }
+        let lines: Vec<String> = forged.iter().map(|e| serde_json::to_string(e).unwrap()).collect();
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let last = forged.last().unwrap();
+
+        write_head(&path, &AuditHead { seq: last.seq, hash: last.hash.clone(), signature: String::new() }).unwrap();
+        assert!(matches!(verify(&path, &trusted()), Err(MatrixError::Integrity(m)) if m.contains("not signed")));
+        assert!(matches!(AuditLog::open(&path, key()), Err(MatrixError::Integrity(_))));
+
+        write_head(&path, &AuditHead::signed(last.seq, last.hash.clone(), &OperatorKey::generate("mallory"))).unwrap();
+        assert!(matches!(verify(&path, &trusted()), Err(MatrixError::Integrity(_))));
+        assert!(matches!(AuditLog::open(&path, key()), Err(MatrixError::Integrity(_))));
+
+        // Only the operator key can reseal it
+        write_head(&path, &AuditHead::signed(last.seq, last.hash.clone(), &KEY)).unwrap();
+        assert_eq!(verify(&path, &trusted()).unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn verify_requires_the_trusted_key() {
+        let path = scratch("other-key");
+        log_with(&path, &["ls"]).await;
+        assert!(matches!(verify(&path, &OperatorKey::generate("mallory").public_key_hex()), Err(MatrixError::Integrity(_))));
+    }
+}
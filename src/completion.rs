@@ -0,0 +1,882 @@
+use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use super::sandbox::SandboxProfile;
+use super::shell::Span;
+
+/// Most completions offered for one word.
+pub const MAX_COMPLETIONS: usize = 50;
+/// How long a `--help` run may take before the command is treated as having none.
+pub const HELP_TIMEOUT: Duration = Duration::from_secs(2);
+// $PATH is rescanned at most this often, or as soon as it changes
+const PATH_RESCAN: Duration = Duration::from_secs(60);
+const MAX_DIR_ENTRIES: usize = 2000;
+const HISTORY_CANDIDATES: usize = 500;
+const HELP_OUTPUT_LIMIT: usize = 64 * 1024;
+// Characters a shell would otherwise interpret inside a completed word
+const SHELL_SPECIAL: &str = " \t\n'\"\\$`&|;()<>*?[]{}!#";
+const OPERATORS: &str = "|&;()<>";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CompletionKind {
+    History,
+    Executable,
+    Path,
+    Variable,
+    GitRef,
+    Subcommand,
+    Flag,
+}
+
+impl CompletionKind {
+    /// How strongly a match of this kind ranks against the others. Candidates that only make
+    /// sense at this exact position outrank general ones.
+    pub fn weight(self) -> f64 {
+        match self {
+            CompletionKind::Subcommand | CompletionKind::Flag | CompletionKind::GitRef => 1.5,
+            CompletionKind::Variable => 1.4,
+            CompletionKind::Executable | CompletionKind::Path => 1.2,
+            CompletionKind::History => 1.0,
+        }
+    }
+}
+
+impl std::fmt::Display for CompletionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompletionKind::History => write!(f, "history"),
+            CompletionKind::Executable => write!(f, "command"),
+            CompletionKind::Path => write!(f, "path"),
+            CompletionKind::Variable => write!(f, "variable"),
+            CompletionKind::GitRef => write!(f, "git"),
+            CompletionKind::Subcommand => write!(f, "subcommand"),
+            CompletionKind::Flag => write!(f, "flag"),
+        }
+    }
+}
+
+/// A candidate replacement for part of the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    /// Replaces `span` of the line when accepted, already quoted for the shell.
+    pub text: String,
+    pub span: Span,
+    pub kind: CompletionKind,
+    /// Shown beside the completion, e.g. a flag's description.
+    pub detail: Option<String>,
+    /// The provider's own preference among its candidates, multiplied into the score.
+    pub boost: f64,
+    /// Filled in by the engine when ranking.
+    pub score: f64,
+}
+
+impl Completion {
+    pub fn new(text: impl Into<String>, span: Span, kind: CompletionKind) -> Self {
+        Self { text: text.into(), span, kind, detail: None, boost: 1.0, score: 0.0 }
+    }
+
+    pub fn detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn boost(mut self, boost: f64) -> Self {
+        self.boost = boost;
+        self
+    }
+}
+
+/// The word under the cursor and the simple command it belongs to. Built with a forgiving
+/// scan rather than `shell::parse`, since a line being typed is rarely complete.
+pub struct CompletionContext<'a> {
+    pub line: &'a str,
+    pub cursor: usize,
+    /// The word under the cursor, which an accepted completion replaces.
+    pub word: Span,
+    /// The word's text before the cursor, with quoting removed.
+    pub prefix: String,
+    /// Earlier words of the current simple command, unquoted; leading assignments are skipped.
+    pub args: Vec<String>,
+    /// The word is the target of a redirection.
+    pub redirect: bool,
+    pub cwd: PathBuf,
+    /// Previous commands, most recent first.
+    pub history: &'a [String],
+    /// The operator pressed Tab, rather than the list refreshing as they type. Only then may a
+    /// provider run programs to learn about them.
+    pub requested: bool,
+}
+
+impl<'a> CompletionContext<'a> {
+    pub fn new(line: &'a str, cursor: usize, cwd: PathBuf, history: &'a [String]) -> Self {
+        let cursor = cursor.min(line.len());
+        let mut args: Vec<String> = vec![];
+        let mut word = String::new();
+        let mut start: Option<usize> = None;
+        let mut quote: Option<char> = None;
+        let mut escaped = false;
+        let mut redirect = false;
+        for (i, c) in line[..cursor].char_indices() {
+            if escaped {
+                word.push(c);
+                escaped = false;
+                continue;
+            }
+            match (quote, c) {
+                (Some('\''), '\'') | (Some('"'), '"') => quote = None,
+                (Some('"'), '\\') => escaped = true,
+                (Some(_), _) => word.push(c),
+                (None, '\\') => {
+                    start.get_or_insert(i);
+                    escaped = true;
+                },
+                (None, '\'' | '"') => {
+                    start.get_or_insert(i);
+                    quote = Some(c);
+                },
+                (None, c) if c.is_whitespace() || OPERATORS.contains(c) => {
+                    if start.take().is_some() {
+                        let finished = std::mem::take(&mut word);
+                        if redirect {
+                            redirect = false;
+                        } else if !(args.is_empty() && is_assignment(&finished)) {
+                            args.push(finished);
+                        }
+                    }
+                    match c {
+                        '<' | '>' => redirect = true,
+                        '\n' | '|' | '&' | ';' | '(' | ')' => {
+                            args.clear();
+                            redirect = false;
+                        },
+                        _ => {},
+                    }
+                },
+                (None, c) => {
+                    start.get_or_insert(i);
+                    word.push(c);
+                },
+            }
+        }
+        let end = line[cursor..]
+            .find(|c: char| c.is_whitespace() || OPERATORS.contains(c))
+            .map(|i| cursor + i)
+            .unwrap_or(line.len());
+        Self {
+            line,
+            cursor,
+            word: Span { start: start.unwrap_or(cursor), end },
+            prefix: word,
+            args,
+            redirect,
+            cwd,
+            history,
+            requested: false,
+        }
+    }
+
+    /// The current command's program name, without its directory.
+    pub fn program(&self) -> Option<&str> {
+        self.args.first().map(|p| p.rsplit('/').next().unwrap_or(p))
+    }
+
+    /// The first argument when it is not a flag, e.g. `commit` in `git commit -`.
+    pub fn subcommand(&self) -> Option<&str> {
+        self.args.get(1).map(String::as_str).filter(|a| !a.starts_with('-'))
+    }
+
+    /// Whether the word names the program to run.
+    pub fn command_position(&self) -> bool {
+        self.args.is_empty() && !self.redirect
+    }
+}
+
+/// A source of completions. Providers are asked on every repaint of the suggestions, so
+/// anything slow must be cached or done in the background.
+pub trait CompletionProvider: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Candidates for the word in `ctx`; the engine filters and ranks them against what is typed.
+    fn complete(&self, ctx: &CompletionContext) -> Vec<Completion>;
+}
+
+type ProviderFactory = fn() -> Arc<dyn CompletionProvider>;
+
+/// Every provider the terminal ships with. A new provider only needs a line here.
+const BUILTIN_PROVIDERS: &[ProviderFactory] = &[
+    || Arc::new(HistoryProvider),
+    || Arc::new(ExecutableProvider::default()),
+    || Arc::new(PathProvider),
+    || Arc::new(VariableProvider),
+    || Arc::new(GitRefProvider),
+    || Arc::new(FlagProvider::default()),
+];
+
+/// Ranks the candidates of every registered provider together.
+pub struct CompletionEngine {
+    providers: Vec<Arc<dyn CompletionProvider>>,
+    matcher: SkimMatcherV2,
+}
+
+impl Default for CompletionEngine {
+    fn default() -> Self {
+        Self { providers: vec![], matcher: SkimMatcherV2::default() }
+    }
+}
+
+impl CompletionEngine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An engine with every built-in provider.
+    pub fn builtin() -> Self {
+        let mut engine = Self::new();
+        for factory in BUILTIN_PROVIDERS {
+            engine.register(factory());
+        }
+        engine
+    }
+
+    pub fn register(&mut self, provider: Arc<dyn CompletionProvider>) {
+        self.providers.push(provider);
+    }
+
+    pub fn providers(&self) -> impl Iterator<Item = &str> {
+        self.providers.iter().map(|p| p.name())
+    }
+
+    /// Every provider's candidates that match what is typed, best first. The score multiplies
+    /// match quality, the kind's weight and the provider's boost.
+    pub fn complete(&self, ctx: &CompletionContext) -> Vec<Completion> {
+        let mut seen = HashSet::new();
+        let mut ranked = vec![];
+        for provider in &self.providers {
+            for mut completion in provider.complete(ctx) {
+                let Some(quality) = self.quality(&completion, ctx) else { continue };
+                if !seen.insert((completion.text.clone(), completion.span.start, completion.span.end)) {
+                    continue;
+                }
+                completion.score = quality * completion.kind.weight() * completion.boost;
+                ranked.push(completion);
+            }
+        }
+        ranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+        ranked.truncate(MAX_COMPLETIONS);
+        ranked
+    }
+
+    // A prefix match ranks a tier above a scattered fuzzy match, and the fuzzy score only
+    // orders within a tier; a candidate that is exactly what is typed offers nothing
+    fn quality(&self, completion: &Completion, ctx: &CompletionContext) -> Option<f64> {
+        let typed = ctx.line.get(completion.span.start..ctx.cursor).unwrap_or("");
+        if completion.text == typed {
+            return None;
+        }
+        if typed.is_empty() {
+            return Some(1.0);
+        }
+        let fuzzy = self.matcher.fuzzy_match(&completion.text, typed)? as f64;
+        let tier = if completion.text.starts_with(typed) { 2.0 } else { 1.0 };
+        Some(tier + fuzzy / (fuzzy + 100.0))
+    }
+}
+
+/// Whole previous command lines, favouring the most recent.
+pub struct HistoryProvider;
+
+impl CompletionProvider for HistoryProvider {
+    fn name(&self) -> &str {
+        "history"
+    }
+
+    fn complete(&self, ctx: &CompletionContext) -> Vec<Completion> {
+        let whole = Span { start: 0, end: ctx.line.len() };
+        ctx.history
+            .iter()
+            .take(HISTORY_CANDIDATES)
+            .enumerate()
+            .map(|(i, command)| Completion::new(command.clone(), whole, CompletionKind::History).boost(1.0 / (1.0 + i as f64 / 20.0)))
+            .collect()
+    }
+}
+
+/// Programs on `$PATH`, in command position.
+#[derive(Default)]
+pub struct ExecutableProvider {
+    scan: Mutex<Option<PathScan>>,
+}
+
+struct PathScan {
+    path: String,
+    at: Instant,
+    names: Arc<Vec<String>>,
+}
+
+impl ExecutableProvider {
+    fn executables(&self) -> Arc<Vec<String>> {
+        let path = std::env::var("PATH").unwrap_or_default();
+        let mut scan = self.scan.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(current) = scan.as_ref().filter(|s| s.path == path && s.at.elapsed() < PATH_RESCAN) {
+            return current.names.clone();
+        }
+        let mut names = BTreeSet::new();
+        for dir in std::env::split_paths(&path) {
+            let Ok(entries) = std::fs::read_dir(dir) else { continue };
+            for entry in entries.flatten() {
+                if is_executable(&entry.path()) {
+                    names.insert(entry.file_name().to_string_lossy().into_owned());
+                }
+            }
+        }
+        let names = Arc::new(names.into_iter().collect::<Vec<_>>());
+        *scan = Some(PathScan { path, at: Instant::now(), names: names.clone() });
+        names
+    }
+}
+
+impl CompletionProvider for ExecutableProvider {
+    fn name(&self) -> &str {
+        "executables"
+    }
+
+    fn complete(&self, ctx: &CompletionContext) -> Vec<Completion> {
+        if !ctx.command_position() || ctx.prefix.contains('/') {
+            return vec![];
+        }
+        self.executables()
+            .iter()
+            .map(|name| Completion::new(escape(name), ctx.word, CompletionKind::Executable))
+            .collect()
+    }
+}
+
+/// Files and directories, relative to the working directory unless the word is absolute or
+/// starts with `~`. In command position only directories and executables are offered, and
+/// only once the word contains a `/`; after `cd` only directories.
+pub struct PathProvider;
+
+impl CompletionProvider for PathProvider {
+    fn name(&self) -> &str {
+        "paths"
+    }
+
+    fn complete(&self, ctx: &CompletionContext) -> Vec<Completion> {
+        let command_position = ctx.command_position();
+        if (command_position && !ctx.prefix.contains('/')) || ctx.prefix.starts_with('-') || ctx.prefix.contains('$') {
+            return vec![];
+        }
+        let (dir_part, file_part) = match ctx.prefix.rfind('/') {
+            Some(i) => ctx.prefix.split_at(i + 1),
+            None => ("", ctx.prefix.as_str()),
+        };
+        let dir = match dir_part.strip_prefix('~') {
+            Some(rest) => PathBuf::from(format!("{}{}", std::env::var("HOME").unwrap_or_default(), rest)),
+            None => PathBuf::from(dir_part),
+        };
+        let Ok(entries) = std::fs::read_dir(ctx.cwd.join(&dir)) else { return vec![] };
+        let only_dirs = matches!(ctx.program(), Some("cd" | "pushd" | "rmdir"));
+
+        entries
+            .flatten()
+            .take(MAX_DIR_ENTRIES)
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with('.') && !file_part.starts_with('.') {
+                    return None;
+                }
+                let path = entry.path();
+                let is_dir = path.is_dir();
+                if (only_dirs || command_position) && !is_dir && (only_dirs || !is_executable(&path)) {
+                    return None;
+                }
+                let text = format!("{}{}{}", escape_path(dir_part), escape(&name), if is_dir { "/" } else { "" });
+                Some(Completion::new(text, ctx.word, CompletionKind::Path))
+            })
+            .collect()
+    }
+}
+
+/// Environment variable names after a `$`. Values are deliberately not shown; they often hold secrets.
+pub struct VariableProvider;
+
+impl CompletionProvider for VariableProvider {
+    fn name(&self) -> &str {
+        "variables"
+    }
+
+    fn complete(&self, ctx: &CompletionContext) -> Vec<Completion> {
+        let Some(dollar) = ctx.prefix.rfind('$') else { return vec![] };
+        let head = escape_path(&ctx.prefix[..dollar]);
+        let braced = ctx.prefix[dollar + 1..].starts_with('{');
+        std::env::vars_os()
+            .filter_map(|(name, _)| name.into_string().ok())
+            .map(|name| {
+                let text = if braced { format!("{}${{{}}}", head, name) } else { format!("{}${}", head, name) };
+                Completion::new(text, ctx.word, CompletionKind::Variable)
+            })
+            .collect()
+    }
+}
+
+// git subcommands whose arguments name refs, and those that take a remote
+const REF_SUBCOMMANDS: &[&str] = &[
+    "branch", "checkout", "cherry-pick", "diff", "log", "merge", "pull", "push", "rebase", "reset", "restore", "revert", "show", "switch", "tag", "worktree",
+];
+const REMOTE_SUBCOMMANDS: &[&str] = &["fetch", "pull", "push", "remote"];
+const REF_NAMESPACES: [(&str, &str); 3] = [("refs/heads/", "branch"), ("refs/tags/", "tag"), ("refs/remotes/", "remote branch")];
+
+/// Branches, tags and remotes of the repository around the working directory, read straight
+/// from its ref store so completing never runs git.
+pub struct GitRefProvider;
+
+impl CompletionProvider for GitRefProvider {
+    fn name(&self) -> &str {
+        "git refs"
+    }
+
+    fn complete(&self, ctx: &CompletionContext) -> Vec<Completion> {
+        if ctx.program() != Some("git") || ctx.prefix.starts_with('-') {
+            return vec![];
+        }
+        let Some(subcommand) = ctx.subcommand() else { return vec![] };
+        let Some(git_dir) = find_git_dir(&ctx.cwd) else { return vec![] };
+
+        let mut completions = vec![];
+        if REMOTE_SUBCOMMANDS.contains(&subcommand) {
+            // The remote usually comes first, right after the subcommand
+            let boost = if ctx.args.len() == 2 { 1.5 } else { 1.0 };
+            completions.extend(read_remotes(&git_dir).into_iter().map(|name| Completion::new(escape(&name), ctx.word, CompletionKind::GitRef).detail("remote").boost(boost)));
+        }
+        if REF_SUBCOMMANDS.contains(&subcommand) {
+            completions.extend(read_refs(&git_dir).into_iter().map(|(name, kind)| Completion::new(escape(&name), ctx.word, CompletionKind::GitRef).detail(kind)));
+        }
+        completions
+    }
+}
+
+// The directory holding refs and config, following `.git` files and worktree links
+fn find_git_dir(cwd: &Path) -> Option<PathBuf> {
+    let dir = cwd.ancestors().find_map(|dir| {
+        let dot_git = dir.join(".git");
+        if dot_git.is_dir() {
+            return Some(dot_git);
+        }
+        let pointer = std::fs::read_to_string(&dot_git).ok()?;
+        Some(dir.join(pointer.strip_prefix("gitdir:")?.trim()))
+    })?;
+    match std::fs::read_to_string(dir.join("commondir")) {
+        Ok(common) => Some(dir.join(common.trim())),
+        Err(_) => Some(dir),
+    }
+}
+
+fn read_refs(git_dir: &Path) -> Vec<(String, &'static str)> {
+    let mut refs = vec![];
+    for (namespace, kind) in REF_NAMESPACES {
+        walk_refs(&git_dir.join(namespace.trim_end_matches('/')), "", kind, &mut refs);
+    }
+    if let Ok(packed) = std::fs::read_to_string(git_dir.join("packed-refs")) {
+        for name in packed.lines().filter(|l| !l.starts_with(['#', '^'])).filter_map(|l| l.split_whitespace().nth(1)) {
+            let Some(entry) = REF_NAMESPACES.iter().find_map(|(namespace, kind)| Some((name.strip_prefix(namespace)?.to_string(), *kind))) else { continue };
+            if !refs.contains(&entry) {
+                refs.push(entry);
+            }
+        }
+    }
+    refs.retain(|(name, _)| !name.ends_with("/HEAD"));
+    refs
+}
+
+fn walk_refs(dir: &Path, prefix: &str, kind: &'static str, refs: &mut Vec<(String, &'static str)>) {
+    let Ok(entries) = std::fs::read_dir(dir) else { return };
+    for entry in entries.flatten() {
+        let name = format!("{}{}", prefix, entry.file_name().to_string_lossy());
+        if entry.path().is_dir() {
+            walk_refs(&entry.path(), &format!("{}/", name), kind, refs);
+        } else {
+            refs.push((name, kind));
+        }
+    }
+}
+
+fn read_remotes(git_dir: &Path) -> Vec<String> {
+    let Ok(config) = std::fs::read_to_string(git_dir.join("config")) else { return vec![] };
+    config
+        .lines()
+        .filter_map(|l| l.trim().strip_prefix("[remote \"")?.strip_suffix("\"]").map(str::to_string))
+        .collect()
+}
+
+/// A command's subcommands and flags, each with an optional description.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandSpec {
+    pub subcommands: Vec<(String, Option<String>)>,
+    pub flags: Vec<(String, Option<String>)>,
+}
+
+impl CommandSpec {
+    pub fn has_subcommand(&self, name: &str) -> bool {
+        self.subcommands.iter().any(|(s, _)| s == name)
+    }
+}
+
+enum Learned {
+    Pending,
+    Ready(Arc<CommandSpec>),
+    Unavailable,
+}
+
+/// Subcommands and flags from the bundled specs, or for other commands from their `--help`.
+/// Help is read only when the operator presses Tab on the command, once, in the background and
+/// inside a sandbox, so its flags show up from the next refresh on.
+#[derive(Default)]
+pub struct FlagProvider {
+    learned: Arc<Mutex<HashMap<String, Learned>>>,
+}
+
+impl FlagProvider {
+    // `None` while the help is still being read, or if there is none
+    fn spec(&self, argv: &[&str], may_learn: bool) -> Option<Arc<CommandSpec>> {
+        let key = argv.join(" ");
+        if let Some(spec) = bundled_spec(&key) {
+            return Some(Arc::new(spec));
+        }
+        let mut learned = self.learned.lock().unwrap_or_else(|e| e.into_inner());
+        match learned.get(&key) {
+            Some(Learned::Ready(spec)) => return Some(spec.clone()),
+            Some(_) => return None,
+            None if !may_learn => return None,
+            None => {},
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else { return None };
+        let Some(program) = find_executable(argv[0]) else {
+            learned.insert(key, Learned::Unavailable);
+            return None;
+        };
+        learned.insert(key.clone(), Learned::Pending);
+        let cache = self.learned.clone();
+        let args: Vec<String> = argv[1..].iter().map(|a| a.to_string()).collect();
+        runtime.spawn(async move {
+            let state = match read_help(&program, &args).await.map(|help| parse_help(&help)) {
+                Some(spec) if spec != CommandSpec::default() => Learned::Ready(Arc::new(spec)),
+                _ => Learned::Unavailable,
+            };
+            cache.lock().unwrap_or_else(|e| e.into_inner()).insert(key, state);
+        });
+        None
+    }
+}
+
+impl CompletionProvider for FlagProvider {
+    fn name(&self) -> &str {
+        "flags"
+    }
+
+    fn complete(&self, ctx: &CompletionContext) -> Vec<Completion> {
+        let Some(program) = ctx.program() else { return vec![] };
+        if ctx.redirect {
+            return vec![];
+        }
+        // Typing alone never runs anything. Only programs found on $PATH are asked for help:
+        // running `./script --help` would execute whatever is in the working directory
+        let may_learn = ctx.requested && !ctx.args[0].contains('/');
+        let Some(root) = self.spec(&[program], may_learn) else { return vec![] };
+        let subcommand = ctx.subcommand().filter(|s| root.has_subcommand(s));
+        let learned_root = bundled_spec(program).is_none();
+        let (spec, depth) = match subcommand.and_then(|s| self.spec(&[program, s], may_learn && learned_root)) {
+            Some(spec) => (spec, 2),
+            None => (root, if subcommand.is_some() { 2 } else { 1 }),
+        };
+
+        let entries = if ctx.prefix.starts_with('-') {
+            spec.flags.iter().map(|entry| (entry, CompletionKind::Flag)).collect::<Vec<_>>()
+        } else if ctx.args.len() == depth {
+            spec.subcommands.iter().map(|entry| (entry, CompletionKind::Subcommand)).collect()
+        } else {
+            vec![]
+        };
+        entries
+            .into_iter()
+            .map(|((name, description), kind)| {
+                let completion = Completion::new(name.clone(), ctx.word, kind);
+                match description {
+                    Some(description) => completion.detail(description.clone()),
+                    None => completion,
+                }
+            })
+            .collect()
+    }
+}
+
+// `--help` in the confined sandbox, with no input and a deadline
+async fn read_help(program: &Path, args: &[String]) -> Option<String> {
+    let mut profile = SandboxProfile::confined();
+    profile.name = "help".to_string();
+    profile.cpu_seconds = Some(HELP_TIMEOUT.as_secs());
+    let sandbox = profile.prepare().ok()?;
+
+    let mut command = tokio::process::Command::new(program);
+    command
+        .args(args)
+        .arg("--help")
+        .env("PAGER", "cat")
+        .env("MANPAGER", "cat")
+        .env("GIT_PAGER", "cat")
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    sandbox.install(&mut command);
+    let output = tokio::time::timeout(HELP_TIMEOUT, command.output()).await.ok()?.ok()?;
+    let text = if output.stdout.is_empty() { output.stderr } else { output.stdout };
+    Some(String::from_utf8_lossy(&text[..text.len().min(HELP_OUTPUT_LIMIT)]).into_owned())
+}
+
+/// Reads flags and subcommands out of `--help` output in the usual layouts: an option starts
+/// an indented line, and subcommands are listed one per line under a heading mentioning
+/// commands. Descriptions are whatever follows two or more spaces.
+pub fn parse_help(text: &str) -> CommandSpec {
+    let mut spec = CommandSpec::default();
+    let mut in_commands = false;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if trimmed.len() == line.len() {
+            in_commands = trimmed.to_lowercase().contains("command") && (trimmed.ends_with(':') || !trimmed.chars().any(|c| c.is_lowercase()));
+            continue;
+        }
+        let (synopsis, description) = match trimmed.find("  ").or_else(|| trimmed.find('\t')) {
+            Some(i) => (&trimmed[..i], Some(trimmed[i..].trim().to_string()).filter(|d| !d.is_empty())),
+            None => (trimmed, None),
+        };
+        if synopsis.starts_with('-') {
+            for flag in synopsis.split([',', ' ', '|']).filter_map(flag_name) {
+                if !spec.flags.iter().any(|(f, _)| *f == flag) {
+                    spec.flags.push((flag, description.clone()));
+                }
+            }
+        } else if in_commands {
+            let Some(name) = synopsis.split_whitespace().next() else { continue };
+            let plausible = name.starts_with(|c: char| c.is_ascii_lowercase()) && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-');
+            if plausible && (description.is_some() || synopsis == name) && !spec.has_subcommand(name) {
+                spec.subcommands.push((name.to_string(), description));
+            }
+        }
+    }
+    spec
+}
+
+// `--color[=WHEN]` and `-o<file>` name the flags `--color` and `-o`
+fn flag_name(token: &str) -> Option<String> {
+    let name = token.split(['=', '[', '<']).next()?;
+    let body = name.trim_start_matches('-');
+    let dashes = name.len() - body.len();
+    (matches!(dashes, 1 | 2) && !body.is_empty() && body.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')).then(|| name.to_string())
+}
+
+struct BundledSpec {
+    command: &'static str,
+    subcommands: &'static [&'static str],
+    flags: &'static [&'static str],
+}
+
+const CARGO_BUILD_FLAGS: &[&str] = &[
+    "--release", "-p", "--package", "--workspace", "--all-targets", "--lib", "--bin", "--bins", "--example", "--examples", "--tests", "--benches",
+    "--features", "-F", "--all-features", "--no-default-features", "--target", "--target-dir", "--profile", "-j", "--jobs", "--locked", "--offline",
+    "-v", "--verbose", "-q", "--quiet",
+];
+
+/// Specs for commands whose `--help` is unhelpful to parse or slow (git opens a man page).
+const BUNDLED_SPECS: &[BundledSpec] = &[
+    BundledSpec {
+        command: "git",
+        subcommands: &[
+            "add", "bisect", "blame", "branch", "checkout", "cherry-pick", "clone", "commit", "config", "diff", "fetch", "grep", "init", "log", "merge",
+            "mv", "pull", "push", "rebase", "remote", "reset", "restore", "revert", "rm", "show", "stash", "status", "switch", "tag", "worktree",
+        ],
+        flags: &["--version", "--help", "-C", "-c", "--no-pager", "--git-dir", "--work-tree"],
+    },
+    BundledSpec { command: "git add", subcommands: &[], flags: &["-A", "--all", "-p", "--patch", "-u", "--update", "-n", "--dry-run", "-f", "--force", "-v", "--verbose", "-N", "--intent-to-add"] },
+    BundledSpec {
+        command: "git branch",
+        subcommands: &[],
+        flags: &["-a", "--all", "-d", "--delete", "-D", "-m", "--move", "-r", "--remotes", "-v", "--verbose", "--list", "--show-current", "-u", "--set-upstream-to", "--merged", "--no-merged", "--contains", "--sort"],
+    },
+    BundledSpec { command: "git checkout", subcommands: &[], flags: &["-b", "-B", "--track", "--detach", "-f", "--force", "--orphan", "-p", "--patch"] },
+    BundledSpec {
+        command: "git commit",
+        subcommands: &[],
+        flags: &["-a", "--all", "-m", "--message", "--amend", "--no-edit", "-S", "--gpg-sign", "-s", "--signoff", "--fixup", "--squash", "-v", "--verbose", "-n", "--no-verify", "--allow-empty", "-p", "--patch"],
+    },
+    BundledSpec { command: "git diff", subcommands: &[], flags: &["--cached", "--staged", "--stat", "--name-only", "--name-status", "--word-diff", "--no-index", "-w", "--ignore-all-space", "--color-words"] },
+    BundledSpec { command: "git fetch", subcommands: &[], flags: &["--all", "-p", "--prune", "--tags", "--depth", "--unshallow", "--dry-run"] },
+    BundledSpec {
+        command: "git log",
+        subcommands: &[],
+        flags: &["--oneline", "--graph", "--all", "--decorate", "--stat", "-p", "--patch", "-n", "--max-count", "--since", "--until", "--author", "--grep", "--follow", "--reverse", "--pretty", "--format"],
+    },
+    BundledSpec { command: "git merge", subcommands: &[], flags: &["--no-ff", "--ff-only", "--squash", "--abort", "--continue", "-m", "--no-edit"] },
+    BundledSpec { command: "git pull", subcommands: &[], flags: &["--rebase", "--no-rebase", "--ff-only", "--all", "--prune", "--tags", "-v", "--verbose"] },
+    BundledSpec { command: "git push", subcommands: &[], flags: &["-u", "--set-upstream", "-f", "--force", "--force-with-lease", "--tags", "--all", "--delete", "-n", "--dry-run", "--no-verify"] },
+    BundledSpec { command: "git rebase", subcommands: &[], flags: &["-i", "--interactive", "--onto", "--continue", "--abort", "--skip", "--autosquash", "--autostash", "--root"] },
+    BundledSpec { command: "git reset", subcommands: &[], flags: &["--soft", "--mixed", "--hard", "--keep", "--merge", "-p", "--patch"] },
+    BundledSpec { command: "git restore", subcommands: &[], flags: &["-s", "--source", "-S", "--staged", "-W", "--worktree", "-p", "--patch"] },
+    BundledSpec {
+        command: "git stash",
+        subcommands: &["apply", "branch", "clear", "drop", "list", "pop", "push", "show"],
+        flags: &["-p", "--patch", "-u", "--include-untracked", "-m", "--message", "--keep-index"],
+    },
+    BundledSpec { command: "git status", subcommands: &[], flags: &["-s", "--short", "-b", "--branch", "--porcelain", "-u", "--untracked-files", "--ignored"] },
+    BundledSpec { command: "git switch", subcommands: &[], flags: &["-c", "--create", "-C", "--force-create", "-d", "--detach", "--discard-changes", "-t", "--track", "--orphan"] },
+    BundledSpec { command: "git tag", subcommands: &[], flags: &["-a", "--annotate", "-d", "--delete", "-l", "--list", "-m", "--message", "-s", "--sign", "-f", "--force"] },
+    BundledSpec {
+        command: "cargo",
+        subcommands: &[
+            "add", "bench", "build", "check", "clean", "clippy", "doc", "fetch", "fix", "fmt", "init", "install", "metadata", "new", "publish", "remove", "run",
+            "search", "test", "tree", "uninstall", "update",
+        ],
+        flags: &["--version", "--list", "--help", "-v", "--verbose", "-q", "--quiet", "--color", "--offline", "--locked", "--frozen", "-Z"],
+    },
+    BundledSpec { command: "cargo build", subcommands: &[], flags: CARGO_BUILD_FLAGS },
+    BundledSpec { command: "cargo check", subcommands: &[], flags: CARGO_BUILD_FLAGS },
+    BundledSpec { command: "cargo clippy", subcommands: &[], flags: CARGO_BUILD_FLAGS },
+    BundledSpec {
+        command: "cargo run",
+        subcommands: &[],
+        flags: &["--release", "-p", "--package", "--bin", "--example", "--features", "-F", "--all-features", "--no-default-features", "--target", "--profile", "-q", "--quiet"],
+    },
+    BundledSpec {
+        command: "cargo test",
+        subcommands: &[],
+        flags: &[
+            "--release", "-p", "--package", "--workspace", "--lib", "--bin", "--test", "--tests", "--doc", "--examples", "--no-run", "--no-fail-fast", "--features",
+            "-F", "--all-features", "--no-default-features", "-j", "-q", "--quiet",
+        ],
+    },
+    BundledSpec {
+        command: "docker",
+        subcommands: &["build", "compose", "exec", "images", "inspect", "kill", "logs", "network", "ps", "pull", "push", "rm", "rmi", "run", "start", "stop", "system", "tag", "volume"],
+        flags: &["--help", "--version", "-H", "--host", "--context", "-D", "--debug", "-l", "--log-level"],
+    },
+    BundledSpec {
+        command: "docker run",
+        subcommands: &[],
+        flags: &[
+            "-d", "--detach", "-i", "--interactive", "-t", "--tty", "--rm", "--name", "-e", "--env", "--env-file", "-p", "--publish", "-v", "--volume", "--mount",
+            "-w", "--workdir", "--network", "--entrypoint", "-u", "--user", "--platform", "--read-only", "--cpus", "-m", "--memory",
+        ],
+    },
+];
+
+fn bundled_spec(key: &str) -> Option<CommandSpec> {
+    let bundled = BUNDLED_SPECS.iter().find(|s| s.command == key)?;
+    Some(CommandSpec {
+        subcommands: bundled.subcommands.iter().map(|s| (s.to_string(), None)).collect(),
+        flags: bundled.flags.iter().map(|f| (f.to_string(), None)).collect(),
+    })
+}
+
+fn find_executable(name: &str) -> Option<PathBuf> {
+    std::env::split_paths(&std::env::var_os("PATH")?).map(|dir| dir.join(name)).find(|p| is_executable(p))
+}
+
+fn is_executable(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'))
+}
+
+/// Backslash-escapes characters the shell would otherwise interpret.
+pub fn escape(word: &str) -> String {
+    let mut escaped = String::with_capacity(word.len());
+    for c in word.chars() {
+        if SHELL_SPECIAL.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+// Like `escape`, but keeps a leading `~` so the shell still expands it
+fn escape_path(path: &str) -> String {
+    match path.strip_prefix('~') {
+        Some(rest) => format!("~{}", escape(rest)),
+        None => escape(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_help_reads_flags_and_descriptions() {
+        let help = "Usage: tool [OPTIONS] <FILE>\n\nOptions:\n  -v, --verbose          Print more\n  -o<file>, --output=FILE  Write to FILE\n      --color[=WHEN]     Colorize output\n  -h, --help\n";
+        let spec = parse_help(help);
+        assert_eq!(
+            spec.flags,
+            vec![
+                ("-v".to_string(), Some("Print more".to_string())),
+                ("--verbose".to_string(), Some("Print more".to_string())),
+                ("-o".to_string(), Some("Write to FILE".to_string())),
+                ("--output".to_string(), Some("Write to FILE".to_string())),
+                ("--color".to_string(), Some("Colorize output".to_string())),
+                ("-h".to_string(), None),
+                ("--help".to_string(), None),
+            ]
+        );
+        assert!(spec.subcommands.is_empty());
+    }
+
+    #[test]
+    fn parse_help_reads_subcommands_under_a_commands_heading() {
+        let help = "Usage: tool <COMMAND>\n\nCommands:\n  build    Compile the project\n  run-all  Run everything\n  help\n  Build    Not a subcommand\n\nArguments:\n  input    Not a subcommand either\n";
+        let spec = parse_help(help);
+        assert_eq!(
+            spec.subcommands,
+            vec![("build".to_string(), Some("Compile the project".to_string())), ("run-all".to_string(), Some("Run everything".to_string())), ("help".to_string(), None)]
+        );
+
+        let spec = parse_help("COMMANDS\n  init  Create a repository\n");
+        assert!(spec.has_subcommand("init"));
+    }
+
+    #[tokio::test]
+    async fn reads_help_only_when_requested() {
+        let provider = FlagProvider::default();
+        let cwd = std::env::current_dir().unwrap();
+        let history = Vec::new();
+
+        let typed = CompletionContext::new("ls -", 4, cwd.clone(), &history);
+        assert!(provider.complete(&typed).is_empty());
+        assert!(provider.learned.lock().unwrap().is_empty());
+
+        let local = CompletionContext { requested: true, ..CompletionContext::new("./ls -", 6, cwd.clone(), &history) };
+        provider.complete(&local);
+        assert!(provider.learned.lock().unwrap().is_empty());
+
+        let tab = CompletionContext { requested: true, ..CompletionContext::new("ls -", 4, cwd, &history) };
+        provider.complete(&tab);
+        assert!(provider.learned.lock().unwrap().contains_key("ls"));
+    }
+
+    #[test]
+    fn bundled_specs_need_no_help() {
+        let provider = FlagProvider::default();
+        let history = Vec::new();
+        let ctx = CompletionContext::new("git commit --am", 15, std::env::current_dir().unwrap(), &history);
+        let completions = provider.complete(&ctx);
+        assert!(completions.iter().any(|c| c.text == "--amend"));
+        assert!(provider.learned.lock().unwrap().is_empty());
+    }
+}
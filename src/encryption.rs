@@ -1,8 +1,11 @@
 use aes_gcm::{Aes256Gcm, Key, Nonce};
-use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
 use rand::Rng;
 use tokio::task;
 use blake3::Hasher;
+use super::envelope::{Envelope, NONCE_SIZE};
+use super::error::MatrixError;
+use super::keystore::KeyStore;
 
 // HASH_SIZE is 32 bytes for BLAKE3
 const HASH_SIZE: usize = 32;
@@ -13,73 +16,67 @@ pub fn generate_hash(data: &str) -> [u8; HASH_SIZE] {
     *hasher.finalize().as_bytes()
 }
 
-pub async fn encrypt_command_with_integrity(cmd: &str) -> Vec<u8> {
-    // 1. Generate hash of original plaintext
-    let original_hash = generate_hash(cmd);
+/// Encrypts `plaintext` under the keystore's current key, authenticating `aad` alongside it.
+pub async fn seal_envelope(keys: &KeyStore, plaintext: Vec<u8>, aad: Vec<u8>) -> Envelope {
+    let (key_id, key) = keys.current_key();
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let mut nonce_bytes = [0u8; NONCE_SIZE];
+    rand::rng().fill(&mut nonce_bytes);
+
+    let (aad, ciphertext) = task::spawn_blocking(move || {
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce_bytes), Payload { msg: &plaintext, aad: &aad })
+            .expect("Encryption failure");
+        (aad, ciphertext)
+    }).await.expect("Task failed");
 
-    // 2. Encrypt the command
-    let key = Key::from_slice(b"an example very very secret key.");
-    let cipher = Aes256Gcm::new(key);
-    let mut nonce_bytes = [0u8; 12];
-    rand::thread_rng().fill(&mut nonce_bytes);
-    let nonce = Nonce::from_slice(&nonce_bytes);
+    Envelope::new(key_id, nonce_bytes, aad, ciphertext)
+}
 
-    let ciphertext = task::spawn_blocking(move || {
-        cipher.encrypt(nonce, cmd.as_bytes().as_ref()).expect("Encryption failure")
-    }).await.expect("Task failed");
+/// Decrypts an envelope with whichever key its header names.
+pub async fn open_envelope(keys: &KeyStore, envelope: &Envelope) -> Result<Vec<u8>, MatrixError> {
+    let key = keys.get(envelope.key_id).ok_or_else(|| MatrixError::KeyStore(format!("Unknown key id {}", envelope.key_id)))?;
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+    let nonce = envelope.nonce;
+    let aad = envelope.aad.clone();
+    let ciphertext = envelope.ciphertext.clone();
+
+    task::spawn_blocking(move || {
+        cipher
+            .decrypt(Nonce::from_slice(&nonce), Payload { msg: &ciphertext, aad: &aad })
+            .map_err(|e| MatrixError::Crypto(format!("Decryption failed: {:?}", e)))
+    }).await.map_err(|e| MatrixError::Crypto(format!("Decryption task failed: {}", e)))?
+}
 
-    // 3. Prepend the original hash and nonce to the ciphertext
-    [original_hash.to_vec(), nonce_bytes.to_vec(), ciphertext].concat()
+pub async fn encrypt_command_with_integrity(keys: &KeyStore, cmd: &str) -> Vec<u8> {
+    // The plaintext hash rides in the AAD, so AES-GCM authenticates it with the ciphertext
+    let original_hash = generate_hash(cmd);
+    seal_envelope(keys, cmd.as_bytes().to_vec(), original_hash.to_vec()).await.to_bytes()
 }
 
-pub async fn decrypt_command_with_integrity(data: &[u8]) -> Result<String, String> {
-    // 1. Deconstruct the data packet
-    let (original_hash_bytes, rest) = data.split_at(HASH_SIZE);
-    let (nonce_bytes, ciphertext) = rest.split_at(12);
-    let nonce = Nonce::from_slice(nonce_bytes);
+pub async fn decrypt_command_with_integrity(keys: &KeyStore, data: &[u8]) -> Result<String, MatrixError> {
+    // 1. Parse the envelope
+    let envelope = Envelope::from_bytes(data)?;
 
     // 2. Decrypt the ciphertext
-    let key = Key::from_slice(b"an example very very secret key.");
-    let cipher = Aes256Gcm::new(key);
-    let plaintext_bytes = task::spawn_blocking(move || {
-        cipher.decrypt(nonce, ciphertext).map_err(|e| format!("Decryption failed: {:?}", e))
-    }).await.map_err(|e| format!("Task failed: {:?}", e))??;
-
-    let decrypted_cmd = String::from_utf8(plaintext_bytes).map_err(|e| format!("Invalid UTF-8: {:?}", e))?;
+    let plaintext_bytes = open_envelope(keys, &envelope).await?;
+    let decrypted_cmd = String::from_utf8(plaintext_bytes).map_err(|e| MatrixError::Integrity(format!("Invalid UTF-8: {}", e)))?;
 
     // 3. Verify integrity
     let decrypted_hash_bytes = generate_hash(&decrypted_cmd);
-    if original_hash_bytes == decrypted_hash_bytes {
+    if envelope.aad == decrypted_hash_bytes {
         Ok(decrypted_cmd)
     } else {
-        Err("Integrity check failed: Hashes do not match.".to_string())
+        Err(MatrixError::Integrity("Hashes do not match.".to_string()))
     }
 }
 
-pub async fn encrypt_command(cmd: &str) -> Vec<u8> {
-    let key = Key::from_slice(b"an example very very secret key.");
-    let cipher = Aes256Gcm::new(key);
-    let mut nonce_bytes = [0u8; 12];
-    rand::thread_rng().fill(&mut nonce_bytes);
-    let nonce = Nonce::from_slice(&nonce_bytes);
-
-    let plaintext = cmd.as_bytes();
-    let ciphertext = task::spawn_blocking(move || {
-        cipher.encrypt(nonce, plaintext.as_ref()).expect("Encryption failure")
-    }).await.expect("Task failed");
-
-    [nonce_bytes.to_vec(), ciphertext].concat()
+pub async fn encrypt_command(keys: &KeyStore, cmd: &str) -> Vec<u8> {
+    seal_envelope(keys, cmd.as_bytes().to_vec(), Vec::new()).await.to_bytes()
 }
 
-pub async fn decrypt_command(data: &[u8]) -> Result<String, String> {
-    let key = Key::from_slice(b"an example very very secret key.");
-    let cipher = Aes256Gcm::new(key);
-    let (nonce_bytes, ciphertext) = data.split_at(12);
-    let nonce = Nonce::from_slice(nonce_bytes);
-
-    let plaintext = task::spawn_blocking(move || {
-        cipher.decrypt(nonce, ciphertext).map_err(|e| format!("Decryption failed: {:?}", e))
-    }).await.map_err(|e| format!("Task failed: {:?}", e))?;
-
-    String::from_utf8(plaintext).map_err(|e| format!("Invalid UTF-8: {:?}", e))
-}
\ No newline at end of file
+pub async fn decrypt_command(keys: &KeyStore, data: &[u8]) -> Result<String, MatrixError> {
+    let envelope = Envelope::from_bytes(data)?;
+    let plaintext = open_envelope(keys, &envelope).await?;
+    String::from_utf8(plaintext).map_err(|e| MatrixError::Integrity(format!("Invalid UTF-8: {}", e)))
+}
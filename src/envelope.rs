@@ -0,0 +1,227 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use super::keystore::KeyId;
+
+// Binary layout (all integers big-endian):
+//   magic "AXMX" (4) | version (1) | algorithm (1) | key_id (4) | nonce (12)
+//   | aad_len (4) | aad | ciphertext_len (4) | ciphertext
+pub const MAGIC: [u8; 4] = *b"AXMX";
+pub const VERSION: u8 = 1;
+pub const NONCE_SIZE: usize = 12;
+const FIXED_HEADER_SIZE: usize = 4 + 1 + 1 + 4 + NONCE_SIZE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Algorithm {
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+}
+
+impl Algorithm {
+    pub fn id(self) -> u8 {
+        match self {
+            Algorithm::Aes256Gcm => 1,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Result<Self, EnvelopeError> {
+        match id {
+            1 => Ok(Algorithm::Aes256Gcm),
+            other => Err(EnvelopeError::UnknownAlgorithm(other)),
+        }
+    }
+}
+
+/// Self-describing encrypted packet exchanged between agent-matrix versions.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub version: u8,
+    pub algorithm: Algorithm,
+    pub key_id: KeyId,
+    pub nonce: [u8; NONCE_SIZE],
+    pub aad: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvelopeError {
+    Truncated { needed: usize, available: usize },
+    BadMagic([u8; 4]),
+    UnsupportedVersion(u8),
+    UnknownAlgorithm(u8),
+    TrailingBytes(usize),
+    Json(String),
+}
+
+impl fmt::Display for EnvelopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvelopeError::Truncated { needed, available } => write!(f, "Truncated envelope: needed {} bytes, got {}", needed, available),
+            EnvelopeError::BadMagic(magic) => write!(f, "Not an agent-matrix envelope (magic {:02x?})", magic),
+            EnvelopeError::UnsupportedVersion(v) => write!(f, "Unsupported envelope version {}", v),
+            EnvelopeError::UnknownAlgorithm(id) => write!(f, "Unknown envelope algorithm id {}", id),
+            EnvelopeError::TrailingBytes(n) => write!(f, "Envelope has {} trailing bytes", n),
+            EnvelopeError::Json(e) => write!(f, "Malformed envelope JSON: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for EnvelopeError {}
+
+// Bounds-checked cursor so short input surfaces as `Truncated` rather than a panic
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], EnvelopeError> {
+        let available = self.data.len() - self.pos;
+        if available < n {
+            return Err(EnvelopeError::Truncated { needed: self.pos.saturating_add(n), available: self.data.len() });
+        }
+        let slice = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, EnvelopeError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, EnvelopeError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().expect("4-byte slice")))
+    }
+
+    fn length_prefixed(&mut self) -> Result<&'a [u8], EnvelopeError> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+}
+
+impl Envelope {
+    pub fn new(key_id: KeyId, nonce: [u8; NONCE_SIZE], aad: Vec<u8>, ciphertext: Vec<u8>) -> Self {
+        Self { version: VERSION, algorithm: Algorithm::Aes256Gcm, key_id, nonce, aad, ciphertext }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(FIXED_HEADER_SIZE + 8 + self.aad.len() + self.ciphertext.len());
+        out.extend_from_slice(&MAGIC);
+        out.push(self.version);
+        out.push(self.algorithm.id());
+        out.extend_from_slice(&self.key_id.to_be_bytes());
+        out.extend_from_slice(&self.nonce);
+        out.extend_from_slice(&(self.aad.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.aad);
+        out.extend_from_slice(&(self.ciphertext.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.ciphertext);
+        out
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, EnvelopeError> {
+        let mut reader = Reader { data, pos: 0 };
+        let magic: [u8; 4] = reader.take(4)?.try_into().expect("4-byte slice");
+        if magic != MAGIC {
+            return Err(EnvelopeError::BadMagic(magic));
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(EnvelopeError::UnsupportedVersion(version));
+        }
+        let algorithm = Algorithm::from_id(reader.u8()?)?;
+        let key_id = reader.u32()?;
+        let nonce: [u8; NONCE_SIZE] = reader.take(NONCE_SIZE)?.try_into().expect("nonce slice");
+        let aad = reader.length_prefixed()?.to_vec();
+        let ciphertext = reader.length_prefixed()?.to_vec();
+        if reader.pos != data.len() {
+            return Err(EnvelopeError::TrailingBytes(data.len() - reader.pos));
+        }
+        Ok(Self { version, algorithm, key_id, nonce, aad, ciphertext })
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Envelope serialization")
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, EnvelopeError> {
+        let envelope: Self = serde_json::from_str(json).map_err(|e| EnvelopeError::Json(e.to_string()))?;
+        if envelope.version != VERSION {
+            return Err(EnvelopeError::UnsupportedVersion(envelope.version));
+        }
+        Ok(envelope)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Envelope {
+        Envelope::new(7, [9; NONCE_SIZE], b"header".to_vec(), b"secret bytes".to_vec())
+    }
+
+    #[test]
+    fn round_trips_through_bytes_and_json() {
+        let envelope = sample();
+        assert_eq!(Envelope::from_bytes(&envelope.to_bytes()), Ok(envelope.clone()));
+        assert_eq!(Envelope::from_json(&envelope.to_json()), Ok(envelope));
+
+        let empty = Envelope::new(0, [0; NONCE_SIZE], vec![], vec![]);
+        assert_eq!(empty.to_bytes().len(), FIXED_HEADER_SIZE + 8);
+        assert_eq!(Envelope::from_bytes(&empty.to_bytes()), Ok(empty));
+    }
+
+    #[test]
+    fn every_truncation_is_an_error() {
+        let bytes = sample().to_bytes();
+        for len in 0..bytes.len() {
+            match Envelope::from_bytes(&bytes[..len]) {
+                Err(EnvelopeError::Truncated { needed, available }) => {
+                    assert_eq!(available, len);
+                    assert!(needed > len && needed <= bytes.len());
+                },
+                other => panic!("{} bytes parsed as {:?}", len, other),
+            }
+        }
+    }
+
+    #[test]
+    fn oversized_lengths_are_truncation_not_panics() {
+        let mut bytes = sample().to_bytes();
+        bytes[FIXED_HEADER_SIZE..FIXED_HEADER_SIZE + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(
+            Envelope::from_bytes(&bytes),
+            Err(EnvelopeError::Truncated { needed: FIXED_HEADER_SIZE + 4 + u32::MAX as usize, available: bytes.len() })
+        );
+
+        let mut bytes = sample().to_bytes();
+        let ciphertext_len = FIXED_HEADER_SIZE + 4 + b"header".len();
+        bytes[ciphertext_len..ciphertext_len + 4].copy_from_slice(&1000u32.to_be_bytes());
+        assert!(matches!(Envelope::from_bytes(&bytes), Err(EnvelopeError::Truncated { .. })));
+    }
+
+    #[test]
+    fn rejects_foreign_headers_and_trailing_bytes() {
+        let bytes = sample().to_bytes();
+
+        let mut bad = bytes.clone();
+        bad[..4].copy_from_slice(b"PK\x03\x04");
+        assert_eq!(Envelope::from_bytes(&bad), Err(EnvelopeError::BadMagic(*b"PK\x03\x04")));
+
+        let mut bad = bytes.clone();
+        bad[4] = VERSION + 1;
+        assert_eq!(Envelope::from_bytes(&bad), Err(EnvelopeError::UnsupportedVersion(VERSION + 1)));
+
+        let mut bad = bytes.clone();
+        bad[5] = 0;
+        assert_eq!(Envelope::from_bytes(&bad), Err(EnvelopeError::UnknownAlgorithm(0)));
+
+        let mut bad = bytes.clone();
+        bad.extend_from_slice(&[0, 0]);
+        assert_eq!(Envelope::from_bytes(&bad), Err(EnvelopeError::TrailingBytes(2)));
+
+        let mut json = sample();
+        json.version = VERSION + 1;
+        assert_eq!(Envelope::from_json(&json.to_json()), Err(EnvelopeError::UnsupportedVersion(VERSION + 1)));
+        assert!(matches!(Envelope::from_json("{"), Err(EnvelopeError::Json(_))));
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+use super::envelope::EnvelopeError;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Crate-wide error type. Callers match on the variant rather than the message text.
+#[derive(Debug)]
+pub enum MatrixError {
+    EthicsViolation { rule: String, reason: String },
+    ConfirmationRequired { rule: String, reason: String },
+    Quarantined { id: u64, rule: String, reason: String },
+    Policy(String),
+    Integrity(String),
+    Crypto(String),
+    Envelope(EnvelopeError),
+    KeyStore(String),
+    Session(String),
+    ModelLoad { context: String, source: BoxError },
+    Inference { context: String, source: BoxError },
+    AgentPanic(String),
+    CommandExit { command: String, code: Option<i32> },
+    Remote(String),
+    Workflow(String),
+    Registry(String),
+    Plugin(String),
+    StepTimeout { step: String, after: std::time::Duration },
+    AgentTimeout { agent: String, after: std::time::Duration },
+    Cancelled(String),
+    Transport { context: String, source: std::io::Error },
+    Network { context: String, source: BoxError },
+    Io { context: String, source: std::io::Error },
+    Serialization { context: String, source: serde_json::Error },
+}
+
+impl MatrixError {
+    pub fn model_load(context: &str, source: impl Into<BoxError>) -> Self {
+        MatrixError::ModelLoad { context: context.to_string(), source: source.into() }
+    }
+
+    pub fn inference(context: &str, source: impl Into<BoxError>) -> Self {
+        MatrixError::Inference { context: context.to_string(), source: source.into() }
+    }
+
+    pub fn network(context: &str, source: impl Into<BoxError>) -> Self {
+        MatrixError::Network { context: context.to_string(), source: source.into() }
+    }
+
+    pub fn transport(context: &str, source: std::io::Error) -> Self {
+        MatrixError::Transport { context: context.to_string(), source }
+    }
+
+    pub fn io(context: &str, source: std::io::Error) -> Self {
+        MatrixError::Io { context: context.to_string(), source }
+    }
+
+    pub fn serialization(context: &str, source: serde_json::Error) -> Self {
+        MatrixError::Serialization { context: context.to_string(), source }
+    }
+}
+
+impl fmt::Display for MatrixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatrixError::EthicsViolation { rule, reason } => write!(f, "Ethical violation [{}]: {}", rule, reason),
+            MatrixError::ConfirmationRequired { rule, reason } => write!(f, "Confirmation required [{}]: {}", rule, reason),
+            MatrixError::Quarantined { id, rule, reason } => write!(f, "Quarantined as #{} [{}]: {}", id, rule, reason),
+            MatrixError::Policy(reason) => write!(f, "Policy error: {}", reason),
+            MatrixError::Integrity(reason) => write!(f, "Integrity check failed: {}", reason),
+            MatrixError::Crypto(reason) => write!(f, "Cryptographic failure: {}", reason),
+            MatrixError::Envelope(e) => write!(f, "Invalid envelope: {}", e),
+            MatrixError::KeyStore(reason) => write!(f, "Keystore error: {}", reason),
+            MatrixError::Session(reason) => write!(f, "Session error: {}", reason),
+            MatrixError::ModelLoad { context, source } => write!(f, "Model load failed ({}): {}", context, source),
+            MatrixError::Inference { context, source } => write!(f, "Inference failed ({}): {}", context, source),
+            MatrixError::AgentPanic(reason) => write!(f, "Agent panic: {}", reason),
+            MatrixError::CommandExit { command, code: Some(code) } => write!(f, "Command '{}' exited with status {}", command, code),
+            MatrixError::CommandExit { command, code: None } => write!(f, "Command '{}' was terminated by a signal", command),
+            MatrixError::Remote(reason) => write!(f, "Remote agent error: {}", reason),
+            MatrixError::Workflow(reason) => write!(f, "Invalid workflow: {}", reason),
+            MatrixError::Registry(reason) => write!(f, "Agent registry error: {}", reason),
+            MatrixError::Plugin(reason) => write!(f, "Plugin error: {}", reason),
+            MatrixError::StepTimeout { step, after } => write!(f, "Step '{}' timed out after {:?}", step, after),
+            MatrixError::AgentTimeout { agent, after } => write!(f, "Agent '{}' gave no answer within {:?}", agent, after),
+            MatrixError::Cancelled(what) => write!(f, "Cancelled: {}", what),
+            MatrixError::Transport { context, source } => write!(f, "{}: {}", context, source),
+            MatrixError::Network { context, source } => write!(f, "{}: {}", context, source),
+            MatrixError::Io { context, source } => write!(f, "{}: {}", context, source),
+            MatrixError::Serialization { context, source } => write!(f, "{}: {}", context, source),
+        }
+    }
+}
+
+impl std::error::Error for MatrixError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MatrixError::Envelope(e) => Some(e),
+            MatrixError::ModelLoad { source, .. }
+            | MatrixError::Inference { source, .. }
+            | MatrixError::Network { source, .. } => Some(source.as_ref()),
+            MatrixError::Transport { source, .. } | MatrixError::Io { source, .. } => Some(source),
+            MatrixError::Serialization { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<EnvelopeError> for MatrixError {
+    fn from(e: EnvelopeError) -> Self {
+        MatrixError::Envelope(e)
+    }
+}
@@ -1,19 +1,192 @@
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::SystemTime;
 use tokio::sync::Mutex;
+use super::error::MatrixError;
+use super::policy::{Decision, Policy, Strictness, Verdict};
+use super::risk::{self, Finding};
+use super::sandbox::SandboxProfile;
+use super::shell::{self, ParseError, Script};
+
+/// A denied command held back (MODERATE) or let through with a note (LENIENT).
+#[derive(Debug, Clone)]
+pub struct GuardRecord {
+    pub id: u64,
+    pub command: String,
+    pub rule: String,
+    pub reason: String,
+    pub at: SystemTime,
+}
+
+/// Everything the guard learned about a command: its AST, the risks in it, the policy verdict
+/// and the sandbox profile it must run under, if any.
+#[derive(Debug, Clone)]
+pub struct Inspection {
+    pub script: Result<Script, ParseError>,
+    pub findings: Vec<Finding>,
+    pub decision: Decision,
+    pub sandbox: Option<SandboxProfile>,
+}
 
 pub struct EthicalGuard {
-    pub constraints: Arc<Mutex<Vec<String>>>,
+    pub policy: Arc<Mutex<Policy>>,
+    strictness: Mutex<Strictness>,
+    quarantine: Mutex<Vec<GuardRecord>>,
+    rejected: Mutex<Vec<GuardRecord>>,
+    lenient_log: Mutex<Vec<GuardRecord>>,
+    next_record_id: AtomicU64,
 }
 
 impl EthicalGuard {
-    pub async fn check_command(&self, cmd: &str) -> Result<(), String> {
-        let guards = self.constraints.lock().await;
-        if cmd.contains("bias_inducing_term") {
-            return Err("Ethical violation: Potential data disparity".to_string());
+    pub fn new(policy: Policy) -> Self {
+        let strictness = policy.strictness().unwrap_or_default();
+        Self {
+            policy: Arc::new(Mutex::new(policy)),
+            strictness: Mutex::new(strictness),
+            quarantine: Mutex::new(vec![]),
+            rejected: Mutex::new(vec![]),
+            lenient_log: Mutex::new(vec![]),
+            next_record_id: AtomicU64::new(1),
         }
-        if guards.contains(&"disparity_analysis".to_string()) && cmd.len() > 50 {
-            return Err("Disparity detected in command length".to_string());
+    }
+
+    /// Swaps in a new policy, e.g. after the operator changes directory or edits the file.
+    pub async fn reload(&self, policy: Policy) {
+        *self.policy.lock().await = policy;
+    }
+
+    pub async fn strictness(&self) -> Strictness {
+        *self.strictness.lock().await
+    }
+
+    pub async fn set_strictness(&self, strictness: Strictness) {
+        *self.strictness.lock().await = strictness;
+    }
+
+    pub async fn inspect(&self, cmd: &str) -> Inspection {
+        let script = shell::parse(cmd);
+        let findings = script.as_ref().map(risk::analyze).unwrap_or_default();
+        let cwd = std::env::current_dir().unwrap_or_else(|_| std::path::PathBuf::from("/"));
+        let policy = self.policy.lock().await;
+        let decision = policy.evaluate_script(cmd, script.as_ref().ok(), &findings, &cwd);
+        let sandbox = policy.sandbox_for(cmd, &findings, &decision);
+        Inspection { script, findings, decision, sandbox }
+    }
+
+    pub async fn evaluate(&self, cmd: &str) -> Decision {
+        self.inspect(cmd).await.decision
+    }
+
+    pub async fn check_command(&self, cmd: &str) -> Result<(), MatrixError> {
+        let decision = self.evaluate(cmd).await;
+        let rule = decision.rule_id.unwrap_or_default();
+        match decision.verdict {
+            Verdict::Allow => Ok(()),
+            Verdict::Deny => self.enforce(cmd, rule, decision.reason).await,
+            Verdict::Confirm => Err(MatrixError::ConfirmationRequired { rule, reason: decision.reason }),
         }
-        Ok(())
     }
-}
\ No newline at end of file
+
+    /// Applies the current strictness to a violation found by the policy or by an agent's own analysis.
+    pub async fn enforce(&self, cmd: &str, rule: String, reason: String) -> Result<(), MatrixError> {
+        let record = GuardRecord {
+            id: self.next_record_id.fetch_add(1, Ordering::Relaxed),
+            command: cmd.to_string(),
+            rule,
+            reason,
+            at: SystemTime::now(),
+        };
+        match self.strictness().await {
+            Strictness::Strict => Err(MatrixError::EthicsViolation { rule: record.rule, reason: record.reason }),
+            Strictness::Moderate => {
+                // The dry run and an agent may both enforce the same violation; it is held once
+                let mut quarantine = self.quarantine.lock().await;
+                let held = quarantine.iter().find(|r| r.command == record.command && r.rule == record.rule);
+                let id = held.map_or(record.id, |r| r.id);
+                let err = MatrixError::Quarantined { id, rule: record.rule.clone(), reason: record.reason.clone() };
+                if held.is_none() {
+                    quarantine.push(record);
+                }
+                Err(err)
+            },
+            Strictness::Lenient => {
+                self.lenient_log.lock().await.push(record);
+                Ok(())
+            },
+        }
+    }
+
+    pub async fn quarantined(&self) -> Vec<GuardRecord> {
+        self.quarantine.lock().await.clone()
+    }
+
+    /// Removes a quarantined command so the operator can run it; `None` if the id is unknown.
+    pub async fn release(&self, id: u64) -> Option<GuardRecord> {
+        let mut quarantine = self.quarantine.lock().await;
+        let index = quarantine.iter().position(|r| r.id == id)?;
+        Some(quarantine.remove(index))
+    }
+
+    /// Discards a quarantined command without running it. The record moves to the rejected list,
+    /// so it can never be released afterwards; `None` if the id is unknown.
+    pub async fn reject(&self, id: u64) -> Option<GuardRecord> {
+        let record = self.release(id).await?;
+        self.rejected.lock().await.push(record.clone());
+        Some(record)
+    }
+
+    /// Quarantined commands the operator turned down, oldest first.
+    pub async fn rejected(&self) -> Vec<GuardRecord> {
+        self.rejected.lock().await.clone()
+    }
+
+    pub async fn lenient_log(&self) -> Vec<GuardRecord> {
+        self.lenient_log.lock().await.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::{Rule, RuleKind};
+
+    async fn guard(strictness: Strictness) -> EthicalGuard {
+        let rules = vec![Rule { id: "no-curl".to_string(), reason: None, kind: RuleKind::RegexDeny { pattern: "curl".to_string() } }];
+        let guard = EthicalGuard::new(Policy::from_rules(rules).unwrap());
+        guard.set_strictness(strictness).await;
+        guard
+    }
+
+    #[tokio::test]
+    async fn strictness_decides_enforcement() {
+        assert!(matches!(guard(Strictness::Strict).await.check_command("curl x").await, Err(MatrixError::EthicsViolation { .. })));
+        assert!(matches!(guard(Strictness::Moderate).await.check_command("curl x").await, Err(MatrixError::Quarantined { .. })));
+
+        let lenient = guard(Strictness::Lenient).await;
+        assert!(lenient.check_command("curl x").await.is_ok());
+        assert_eq!(lenient.lenient_log().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn repeated_violations_are_quarantined_once() {
+        let guard = guard(Strictness::Moderate).await;
+        let Err(MatrixError::Quarantined { id: first, .. }) = guard.check_command("curl x").await else { panic!("expected quarantine") };
+        let Err(MatrixError::Quarantined { id: second, .. }) = guard.check_command("curl x").await else { panic!("expected quarantine") };
+        assert_eq!(first, second);
+        assert_eq!(guard.quarantined().await.len(), 1);
+        assert!(matches!(guard.check_command("curl y").await, Err(MatrixError::Quarantined { id, .. }) if id != first));
+    }
+
+    #[tokio::test]
+    async fn rejected_commands_cannot_be_released() {
+        let guard = guard(Strictness::Moderate).await;
+        let Err(MatrixError::Quarantined { id, .. }) = guard.check_command("curl x").await else { panic!("expected quarantine") };
+
+        let record = guard.reject(id).await.unwrap();
+        assert_eq!(record.command, "curl x");
+        assert!(guard.quarantined().await.is_empty());
+        assert_eq!(guard.rejected().await.len(), 1);
+        assert!(guard.release(id).await.is_none());
+        assert!(guard.reject(id).await.is_none());
+    }
+}
@@ -0,0 +1,252 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use super::encryption::{open_envelope, seal_envelope};
+use super::envelope::Envelope;
+use super::error::MatrixError;
+use super::keystore::KeyStore;
+
+// Authenticated with every record, so an envelope sealed for another purpose is rejected
+const HISTORY_AAD: &[u8] = b"agent-matrix/history/v1";
+
+/// How much more a run made in the searching directory counts than one made elsewhere.
+pub const CWD_BOOST: f64 = 2.0;
+
+/// One submitted command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// A later record with the same id supersedes the earlier one, e.g. once the command finishes.
+    pub id: u64,
+    pub command: String,
+    pub cwd: PathBuf,
+    /// `None` until the command exits, and for commands that never ran or died to a signal.
+    pub exit_code: Option<i32>,
+    pub duration_ms: Option<u64>,
+    pub timestamp_ms: u64,
+    /// The terminal session that submitted the command.
+    pub session: String,
+}
+
+impl HistoryEntry {
+    pub fn at(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(self.timestamp_ms)
+    }
+}
+
+/// Which runs a history search considers, by how they ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExitFilter {
+    #[default]
+    Any,
+    Succeeded,
+    Failed,
+}
+
+impl ExitFilter {
+    pub fn admits(self, entry: &HistoryEntry) -> bool {
+        match self {
+            ExitFilter::Any => true,
+            ExitFilter::Succeeded => entry.exit_code == Some(0),
+            ExitFilter::Failed => entry.exit_code.is_some_and(|code| code != 0),
+        }
+    }
+
+    /// The next filter in the cycle Any → Succeeded → Failed.
+    pub fn next(self) -> Self {
+        match self {
+            ExitFilter::Any => ExitFilter::Succeeded,
+            ExitFilter::Succeeded => ExitFilter::Failed,
+            ExitFilter::Failed => ExitFilter::Any,
+        }
+    }
+}
+
+impl std::fmt::Display for ExitFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExitFilter::Any => write!(f, "any"),
+            ExitFilter::Succeeded => write!(f, "succeeded"),
+            ExitFilter::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// A distinct command and its frecency: every admitted run adds a weight that falls with its age.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frecent {
+    pub command: String,
+    pub score: f64,
+    pub runs: u32,
+    /// Exit code of the most recent admitted run.
+    pub last_exit: Option<i32>,
+}
+
+/// The operator's command history, encrypted at rest under the keyring's current key. The file
+/// holds one sealed envelope per line and is only ever appended to.
+pub struct HistoryStore {
+    /// `None` keeps the history in memory only.
+    path: Option<PathBuf>,
+    keys: Arc<KeyStore>,
+    session: String,
+    entries: Vec<HistoryEntry>,
+}
+
+impl HistoryStore {
+    /// `~/.config/agent-matrix/history.log`
+    pub fn default_path() -> PathBuf {
+        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+        PathBuf::from(home).join(".config").join("agent-matrix").join("history.log")
+    }
+
+    /// Loads the history at `path`, creating its directory if needed, and starts a new session.
+    /// A record that fails to decrypt or authenticate fails the whole load.
+    pub async fn open(path: &Path, keys: Arc<KeyStore>) -> Result<Self, MatrixError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| MatrixError::io("Cannot create history directory", e))?;
+        }
+        let entries = read_entries(path, &keys).await?;
+        Ok(Self { path: Some(path.to_path_buf()), keys, session: new_session_id(), entries })
+    }
+
+    /// A history that lasts only as long as this process.
+    pub fn ephemeral() -> Self {
+        Self { path: None, keys: Arc::new(KeyStore::generate()), session: new_session_id(), entries: vec![] }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    /// Every entry, oldest first.
+    pub fn entries(&self) -> &[HistoryEntry] {
+        &self.entries
+    }
+
+    /// Distinct command texts, most recently used first, optionally only those run in `cwd`.
+    pub fn commands(&self, cwd: Option<&Path>) -> Vec<String> {
+        let mut seen = HashSet::new();
+        self.entries
+            .iter()
+            .rev()
+            .filter(|e| cwd.is_none_or(|cwd| e.cwd == cwd))
+            .filter(|e| seen.insert(e.command.as_str()))
+            .map(|e| e.command.clone())
+            .collect()
+    }
+
+    /// Distinct commands with a run admitted by `filter`, highest frecency first, ties going to
+    /// the most recently used. Runs made in `cwd` count `CWD_BOOST` times as much.
+    pub fn frecent(&self, cwd: &Path, filter: ExitFilter, now: SystemTime) -> Vec<Frecent> {
+        let mut ranked: Vec<Frecent> = vec![];
+        let mut positions: HashMap<&str, usize> = HashMap::new();
+        for entry in self.entries.iter().rev().filter(|e| filter.admits(e)) {
+            let index = *positions.entry(entry.command.as_str()).or_insert_with(|| {
+                ranked.push(Frecent { command: entry.command.clone(), score: 0.0, runs: 0, last_exit: entry.exit_code });
+                ranked.len() - 1
+            });
+            let boost = if entry.cwd == cwd { CWD_BOOST } else { 1.0 };
+            ranked[index].score += recency_weight(now.duration_since(entry.at()).unwrap_or_default()) * boost;
+            ranked[index].runs += 1;
+        }
+        ranked.sort_by(|a, b| b.score.total_cmp(&a.score));
+        ranked
+    }
+
+    /// Adds a submitted command; `finish` fills in how it ended once it has run.
+    pub async fn record(&mut self, command: &str, cwd: &Path) -> Result<(), MatrixError> {
+        let entry = HistoryEntry {
+            id: self.entries.iter().map(|e| e.id + 1).max().unwrap_or(0),
+            command: command.to_string(),
+            cwd: cwd.to_path_buf(),
+            exit_code: None,
+            duration_ms: None,
+            timestamp_ms: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0),
+            session: self.session.clone(),
+        };
+        self.append(&entry).await?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Records how `command` ended on the latest submission still without an outcome; a dry
+    /// run confirmed or a quarantine released later still finds the entry made at submission.
+    pub async fn finish(&mut self, command: &str, exit_code: Option<i32>, duration: Duration) -> Result<(), MatrixError> {
+        let open = self.entries.iter().rposition(|e| e.command == command && e.session == self.session && e.duration_ms.is_none());
+        let Some(index) = open else { return Ok(()) };
+        let mut entry = self.entries[index].clone();
+        entry.exit_code = exit_code;
+        entry.duration_ms = Some(duration.as_millis() as u64);
+        self.append(&entry).await?;
+        self.entries[index] = entry;
+        Ok(())
+    }
+
+    async fn append(&self, entry: &HistoryEntry) -> Result<(), MatrixError> {
+        let Some(path) = &self.path else { return Ok(()) };
+        let plaintext = serde_json::to_vec(entry).map_err(|e| MatrixError::serialization("History entry serialization failed", e))?;
+        let mut line = seal_envelope(&self.keys, plaintext, HISTORY_AAD.to_vec()).await.to_json();
+        line.push('\n');
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o600)
+            .open(path)
+            .map_err(|e| MatrixError::io("Cannot open history", e))?;
+        file.write_all(line.as_bytes()).map_err(|e| MatrixError::io("Cannot append to history", e))?;
+        file.sync_data().map_err(|e| MatrixError::io("Cannot flush history", e))
+    }
+}
+
+// A run from the last hour counts sixteen times one from last quarter
+fn recency_weight(age: Duration) -> f64 {
+    const HOUR: u64 = 60 * 60;
+    match age.as_secs() {
+        secs if secs < HOUR => 4.0,
+        secs if secs < 24 * HOUR => 2.0,
+        secs if secs < 7 * 24 * HOUR => 1.0,
+        secs if secs < 30 * 24 * HOUR => 0.5,
+        _ => 0.25,
+    }
+}
+
+fn new_session_id() -> String {
+    format!("{:016x}", rand::random::<u64>())
+}
+
+async fn read_entries(path: &Path, keys: &KeyStore) -> Result<Vec<HistoryEntry>, MatrixError> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(MatrixError::io("Cannot read history", e)),
+    };
+    let mut entries: Vec<HistoryEntry> = vec![];
+    let mut positions: HashMap<u64, usize> = HashMap::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| MatrixError::io("Cannot read history", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let envelope = Envelope::from_json(&line)?;
+        if envelope.aad != HISTORY_AAD {
+            return Err(MatrixError::Integrity(format!("History line {} was not sealed as a history record", number + 1)));
+        }
+        let plaintext = open_envelope(keys, &envelope).await?;
+        let entry: HistoryEntry = serde_json::from_slice(&plaintext).map_err(|e| MatrixError::serialization(&format!("Malformed history line {}", number + 1), e))?;
+        match positions.get(&entry.id) {
+            Some(&index) => entries[index] = entry,
+            None => {
+                positions.insert(entry.id, entries.len());
+                entries.push(entry);
+            },
+        }
+    }
+    Ok(entries)
+}
@@ -0,0 +1,267 @@
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use aes_gcm::aead::{Aead, KeyInit};
+use argon2::Argon2;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use super::error::MatrixError;
+
+pub const KEY_SIZE: usize = 32;
+const SALT_SIZE: usize = 16;
+const NONCE_SIZE: usize = 12;
+const KEYRING_VERSION: u8 = 1;
+
+/// Identifies a key inside the keyring; written into every ciphertext header.
+pub type KeyId = u32;
+
+/// Holds every key ever issued so old ciphertexts keep decrypting after rotation.
+pub struct KeyStore {
+    keys: BTreeMap<KeyId, [u8; KEY_SIZE]>,
+    current: KeyId,
+}
+
+// On-disk layout: the keyring contents are sealed with a wrapping key derived from the passphrase
+#[derive(Serialize, Deserialize)]
+struct KeyringFile {
+    version: u8,
+    salt: Vec<u8>,
+    nonce: Vec<u8>,
+    sealed: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeyringContents {
+    current: KeyId,
+    keys: Vec<(KeyId, Vec<u8>)>,
+}
+
+impl KeyStore {
+    /// Creates an in-memory keystore holding a single freshly generated key.
+    pub fn generate() -> Self {
+        let mut keys = BTreeMap::new();
+        keys.insert(1, Self::generate_key());
+        Self { keys, current: 1 }
+    }
+
+    /// Wraps an externally derived key (e.g. a session key) as the sole, current key.
+    pub fn from_key(id: KeyId, key: [u8; KEY_SIZE]) -> Self {
+        let mut keys = BTreeMap::new();
+        keys.insert(id, key);
+        Self { keys, current: id }
+    }
+
+    pub fn generate_key() -> [u8; KEY_SIZE] {
+        let mut key = [0u8; KEY_SIZE];
+        rand::rng().fill(&mut key);
+        key
+    }
+
+    /// `~/.config/agent-matrix/keyring.json`
+    pub fn default_path() -> PathBuf {
+        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+        PathBuf::from(home).join(".config").join("agent-matrix").join("keyring.json")
+    }
+
+    pub fn current_id(&self) -> KeyId {
+        self.current
+    }
+
+    pub fn current_key(&self) -> (KeyId, [u8; KEY_SIZE]) {
+        (self.current, self.keys[&self.current])
+    }
+
+    pub fn get(&self, id: KeyId) -> Option<[u8; KEY_SIZE]> {
+        self.keys.get(&id).copied()
+    }
+
+    pub fn key_ids(&self) -> Vec<KeyId> {
+        self.keys.keys().copied().collect()
+    }
+
+    /// Issues a new current key. Previous keys are retained for decryption only.
+    pub fn rotate(&mut self) -> KeyId {
+        let next = self.keys.keys().next_back().copied().unwrap_or(0) + 1;
+        self.keys.insert(next, Self::generate_key());
+        self.current = next;
+        next
+    }
+
+    /// Installs an externally derived key under `id` and makes it current.
+    pub fn install(&mut self, id: KeyId, key: [u8; KEY_SIZE]) {
+        self.keys.insert(id, key);
+        self.current = id;
+    }
+
+    /// Drops a retired key. The current key cannot be removed.
+    pub fn retire(&mut self, id: KeyId) -> Result<(), MatrixError> {
+        if id == self.current {
+            return Err(MatrixError::KeyStore(format!("Cannot retire current key {}", id)));
+        }
+        self.keys.remove(&id).map(|_| ()).ok_or_else(|| MatrixError::KeyStore(format!("Unknown key id {}", id)))
+    }
+
+    /// Opens an existing keyring, or creates a new one at `path` if none exists.
+    pub fn open_or_create(path: &Path, passphrase: &str) -> Result<Self, MatrixError> {
+        if path.exists() {
+            Self::load(path, passphrase)
+        } else {
+            let store = Self::generate();
+            store.save(path, passphrase)?;
+            Ok(store)
+        }
+    }
+
+    pub fn load(path: &Path, passphrase: &str) -> Result<Self, MatrixError> {
+        let raw = std::fs::read(path).map_err(|e| MatrixError::io("Cannot read keyring", e))?;
+        let file: KeyringFile = serde_json::from_slice(&raw).map_err(|e| MatrixError::serialization("Malformed keyring", e))?;
+        if file.version != KEYRING_VERSION {
+            return Err(MatrixError::KeyStore(format!("Unsupported keyring version {}", file.version)));
+        }
+        if file.nonce.len() != NONCE_SIZE {
+            return Err(MatrixError::KeyStore("Malformed keyring: bad nonce length".to_string()));
+        }
+
+        let wrapping_key = derive_wrapping_key(passphrase, &file.salt)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&wrapping_key));
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&file.nonce), file.sealed.as_ref())
+            .map_err(|_| MatrixError::KeyStore("Keyring unlock failed: wrong passphrase or corrupted file".to_string()))?;
+        let contents: KeyringContents = serde_json::from_slice(&plaintext).map_err(|e| MatrixError::serialization("Malformed keyring", e))?;
+
+        let mut keys = BTreeMap::new();
+        for (id, bytes) in contents.keys {
+            let key: [u8; KEY_SIZE] = bytes.try_into().map_err(|_| MatrixError::KeyStore(format!("Key {} has invalid length", id)))?;
+            keys.insert(id, key);
+        }
+        if !keys.contains_key(&contents.current) {
+            return Err(MatrixError::KeyStore(format!("Keyring current key {} is missing", contents.current)));
+        }
+        Ok(Self { keys, current: contents.current })
+    }
+
+    pub fn save(&self, path: &Path, passphrase: &str) -> Result<(), MatrixError> {
+        let contents = KeyringContents {
+            current: self.current,
+            keys: self.keys.iter().map(|(id, key)| (*id, key.to_vec())).collect(),
+        };
+        let plaintext = serde_json::to_vec(&contents).map_err(|e| MatrixError::serialization("Keyring serialization failed", e))?;
+
+        let mut salt = [0u8; SALT_SIZE];
+        let mut nonce = [0u8; NONCE_SIZE];
+        rand::rng().fill(&mut salt);
+        rand::rng().fill(&mut nonce);
+        let wrapping_key = derive_wrapping_key(passphrase, &salt)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&wrapping_key));
+        let sealed = cipher
+            .encrypt(Nonce::from_slice(&nonce), plaintext.as_ref())
+            .map_err(|e| MatrixError::Crypto(format!("Keyring encryption failed: {:?}", e)))?;
+
+        let file = KeyringFile { version: KEYRING_VERSION, salt: salt.to_vec(), nonce: nonce.to_vec(), sealed };
+        let json = serde_json::to_vec_pretty(&file).map_err(|e| MatrixError::serialization("Keyring serialization failed", e))?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| MatrixError::io("Cannot create keyring directory", e))?;
+        }
+        // Write-then-rename so a crash never leaves a half-written keyring behind
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, json).map_err(|e| MatrixError::io("Cannot write keyring", e))?;
+        restrict_permissions(&tmp)?;
+        std::fs::rename(&tmp, path).map_err(|e| MatrixError::io("Cannot write keyring", e))
+    }
+}
+
+fn derive_wrapping_key(passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_SIZE], MatrixError> {
+    let mut key = [0u8; KEY_SIZE];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| MatrixError::Crypto(format!("Key derivation failed: {}", e)))?;
+    Ok(key)
+}
+
+/// Makes `path` readable and writable by its owner only.
+#[cfg(unix)]
+pub(crate) fn restrict_permissions(path: &Path) -> Result<(), MatrixError> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .map_err(|e| MatrixError::io(&format!("Cannot restrict permissions on {}", path.display()), e))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn restrict_permissions(_path: &Path) -> Result<(), MatrixError> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::{open_envelope, seal_envelope};
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("agent-matrix-keystore-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir.join("keyring.json")
+    }
+
+    #[test]
+    fn keyring_round_trips() {
+        let path = scratch("round-trip");
+        let mut store = KeyStore::generate();
+        store.rotate();
+        store.save(&path, "correct horse").unwrap();
+
+        let loaded = KeyStore::load(&path, "correct horse").unwrap();
+        assert_eq!(loaded.key_ids(), vec![1, 2]);
+        assert_eq!(loaded.current_key(), store.current_key());
+        assert_eq!(loaded.get(1), store.get(1));
+        // Nothing left behind by the write-then-rename
+        assert!(!path.with_extension("tmp").exists());
+    }
+
+    #[test]
+    fn wrong_passphrase_and_corruption_are_errors() {
+        let path = scratch("wrong");
+        KeyStore::open_or_create(&path, "correct horse").unwrap();
+        assert!(matches!(KeyStore::load(&path, "battery staple"), Err(MatrixError::KeyStore(m)) if m.contains("wrong passphrase")));
+
+        let mut file: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        file["nonce"] = serde_json::json!([0, 1, 2]);
+        std::fs::write(&path, serde_json::to_vec(&file).unwrap()).unwrap();
+        assert!(matches!(KeyStore::load(&path, "correct horse"), Err(MatrixError::KeyStore(_))));
+
+        std::fs::write(&path, b"{").unwrap();
+        assert!(KeyStore::load(&path, "correct horse").is_err());
+    }
+
+    #[tokio::test]
+    async fn rotated_out_keys_still_open_old_envelopes() {
+        let path = scratch("rotate");
+        let mut store = KeyStore::open_or_create(&path, "correct horse").unwrap();
+        let old = seal_envelope(&store, b"ls -la".to_vec(), vec![]).await;
+
+        assert_eq!(store.rotate(), 2);
+        store.save(&path, "correct horse").unwrap();
+        let store = KeyStore::open_or_create(&path, "correct horse").unwrap();
+        assert_eq!(store.current_id(), 2);
+        assert_eq!(open_envelope(&store, &old).await.unwrap(), b"ls -la");
+        assert_eq!(seal_envelope(&store, b"pwd".to_vec(), vec![]).await.key_id, 2);
+    }
+
+    #[test]
+    fn current_key_cannot_be_retired() {
+        let mut store = KeyStore::generate();
+        store.rotate();
+        assert!(store.retire(2).is_err());
+        store.retire(1).unwrap();
+        assert_eq!(store.key_ids(), vec![2]);
+        assert!(store.retire(1).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keyring_is_private_to_its_owner() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = scratch("mode");
+        KeyStore::generate().save(&path, "correct horse").unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+}
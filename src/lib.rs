@@ -1,8 +1,29 @@
 pub mod agents;
+pub mod audit;
+pub mod completion;
 pub mod encryption;
+pub mod envelope;
+pub mod error;
+pub mod keystore;
 pub mod integrity;
 pub mod ethics;
 pub mod gpu;
+pub mod history;
 pub mod orchestration;
+pub mod plugin;
+pub mod policy;
+pub mod pty;
+pub mod receipt;
+pub mod registry;
+pub mod remote;
+pub mod risk;
+pub mod sandbox;
+pub mod screen;
+pub mod session;
+pub mod shell;
+pub mod simulation;
+pub mod suggest;
 pub mod ux;
+pub mod watch;
+pub mod workflow;
 pub mod ui;
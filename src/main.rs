@@ -1,30 +1,43 @@
-use agent_matrix::agents::{EthicalAgent, ComputeAgent};
+use agent_matrix::agents::Consensus;
+use agent_matrix::audit::{self, AuditLog, AuditQuery};
+use agent_matrix::error::MatrixError;
 use agent_matrix::ethics::EthicalGuard;
+use agent_matrix::history::HistoryStore;
+use agent_matrix::keystore::KeyStore;
+use agent_matrix::plugin;
+use agent_matrix::policy::{Policy, Strictness, Verdict};
 use agent_matrix::gpu::init_vulkan;
+use agent_matrix::orchestration::{execute_command, OutputLine};
+use agent_matrix::suggest::{Architecture, SamplingConfig, Suggester};
 use agent_matrix::ux::UXEngine;
 use agent_matrix::ui::MatrixUI;
-use clap::Parser;
+use agent_matrix::receipt::{OperatorKey, Receipt};
+use agent_matrix::registry::{AgentContext, AgentRegistry};
+use agent_matrix::remote::{self, Endpoint, PeerIdentity, RemoteAgent, TrustedPeers};
+use agent_matrix::workflow::{StepStatus, Workflow};
+use clap::{Parser, Subcommand};
+use std::sync::Arc;
 
 // Sovereign environment bootstrap - Downloads and verifies model integrity
-async fn bootstrap_sovereign_environment() -> Result<(), String> {
+async fn bootstrap_sovereign_environment() -> Result<(), MatrixError> {
     let model_path = std::path::Path::new("model.safetensors");
     if !model_path.exists() {
         println!("🔒 Sovereign Model Acquisition: Downloading from verified source...");
         let response = reqwest::get("https://huggingface.co/distilbert-base-uncased/resolve/main/model.safetensors?download=true")
-            .await.map_err(|e| format!("Network failure: {}", e))?;
+            .await.map_err(|e| MatrixError::network("Network failure", e))?;
 
         let mut dest = std::fs::File::create(&model_path)
-            .map_err(|e| format!("File creation failed: {}", e))?;
-        let content = response.bytes().await.map_err(|e| format!("Content retrieval failed: {}", e))?;
+            .map_err(|e| MatrixError::io("File creation failed", e))?;
+        let content = response.bytes().await.map_err(|e| MatrixError::network("Content retrieval failed", e))?;
         std::io::copy(&mut content.as_ref(), &mut dest)
-            .map_err(|e| format!("Write failed: {}", e))?;
+            .map_err(|e| MatrixError::io("Write failed", e))?;
 
         println!("📦 Model acquired. Performing cryptographic verification...");
     }
 
     // Zero-trust integrity verification
     let model_bytes = std::fs::read(&model_path)
-        .map_err(|e| format!("Cannot read model: {}", e))?;
+        .map_err(|e| MatrixError::io("Cannot read model", e))?;
     let mut hasher = blake3::Hasher::new();
     hasher.update(&model_bytes);
     let actual_hash = hasher.finalize().to_hex().to_string();
@@ -32,7 +45,7 @@ async fn bootstrap_sovereign_environment() -> Result<(), String> {
     // In production, this would be a hardcoded verified checksum
     const EXPECTED_CHECKSUM: &str = "placeholder_integrity_hash";
     if actual_hash != EXPECTED_CHECKSUM {
-        return Err(format!("🚨 CRITICAL: Model integrity compromised! Expected: {}, Got: {}", EXPECTED_CHECKSUM, actual_hash));
+        return Err(MatrixError::Integrity(format!("🚨 CRITICAL: Model integrity compromised! Expected: {}, Got: {}", EXPECTED_CHECKSUM, actual_hash)));
     }
 
     println!("✅ Sovereign environment verified. All systems operational.");
@@ -48,50 +61,458 @@ async fn bootstrap_sovereign_environment() -> Result<(), String> {
 struct Args {
     #[arg(short, long, help = "UI theme (dark/light)")]
     theme: Option<String>,
+
+    #[arg(long, help = "Guard policy file (TOML/JSON); defaults to the nearest .agent-matrix/policy.toml")]
+    policy: Option<std::path::PathBuf>,
+
+    #[arg(long, help = "Guard strictness: strict blocks, moderate quarantines, lenient logs (overrides the policy file)")]
+    strictness: Option<Strictness>,
+
+    #[arg(long, default_value = "unanimous", help = "How agent votes combine: unanimous, majority or weighted")]
+    consensus: Consensus,
+
+    #[arg(long, help = "Audit log file; defaults to ~/.config/agent-matrix/audit.log")]
+    audit_log: Option<std::path::PathBuf>,
+
+    #[arg(long, help = "Ed25519 operator key used to sign execution receipts; defaults to ~/.config/agent-matrix/operator.key")]
+    operator_key: Option<std::path::PathBuf>,
+
+    #[arg(long, help = "Encrypted command history; defaults to ~/.config/agent-matrix/history.log")]
+    history: Option<std::path::PathBuf>,
+
+    #[arg(long, help = "Keyring sealing the history, unlocked with $AGENT_MATRIX_PASSPHRASE; defaults to ~/.config/agent-matrix/keyring.json")]
+    keyring: Option<std::path::PathBuf>,
+
+    #[arg(long, help = "Directory of agent plugins; defaults to ~/.config/agent-matrix/plugins")]
+    plugins_dir: Option<std::path::PathBuf>,
+
+    #[arg(long, help = "Remote agent daemon to include in the matrix (unix:<path> or tcp:<host:port>)")]
+    remote: Vec<Endpoint>,
+
+    #[arg(long, help = "Operator keys allowed on either end of a remote session; defaults to ~/.config/agent-matrix/trusted_peers")]
+    trusted_peers: Option<std::path::PathBuf>,
+
+    #[arg(long, help = "Quantized GGUF causal model for command suggestions (Ctrl+Space); runs locally on the CPU")]
+    model: Option<std::path::PathBuf>,
+
+    #[arg(long, help = "tokenizer.json for --model; defaults to the one beside the model file")]
+    tokenizer: Option<std::path::PathBuf>,
+
+    #[arg(long, default_value = "auto", help = "Model family of --model: auto, llama, phi or phi2")]
+    model_arch: Architecture,
+
+    #[arg(long, default_value_t = 0.2, help = "Suggestion sampling temperature; 0 is greedy")]
+    temperature: f64,
+
+    #[arg(long, default_value_t = 0.9, help = "Suggestion nucleus sampling threshold")]
+    top_p: f64,
+
+    #[arg(long, default_value_t = 1500, help = "Longest a suggestion may take, in milliseconds")]
+    suggestion_deadline_ms: u64,
+
+    #[command(subcommand)]
+    command: Option<Commands>,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Serve this machine's agents to remote terminals over an encrypted socket
+    Serve {
+        #[arg(long, help = "Listen endpoint (unix:<path> or tcp:<host:port>); defaults to unix:$XDG_RUNTIME_DIR/agent-matrix.sock")]
+        listen: Option<Endpoint>,
+    },
+    /// Inspect the hash-chained audit trail
+    Audit {
+        #[command(subcommand)]
+        action: AuditAction,
+    },
+    /// Work with signed execution receipts
+    Receipt {
+        #[command(subcommand)]
+        action: ReceiptAction,
+    },
+    /// Inspect installed agent plugins
+    Plugin {
+        #[command(subcommand)]
+        action: PluginAction,
+    },
+    /// Try the policy's sandbox profiles outside the TUI
+    Sandbox {
+        #[command(subcommand)]
+        action: SandboxAction,
+    },
+    /// Run a task through a workflow of agent steps and print each step's outcome
+    Workflow {
+        #[arg(help = "TOML file of [[step]] tables naming registered agents")]
+        file: std::path::PathBuf,
+        #[arg(required = true, trailing_var_arg = true, help = "The task every step starts from")]
+        task: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum PluginAction {
+    /// Check every plugin's manifest, API version and pinned hash
+    List,
+    /// Print the BLAKE3 pin for a plugin executable
+    Hash { executable: std::path::PathBuf },
+}
+
+#[derive(Subcommand)]
+enum SandboxAction {
+    /// List the profiles the policy defines
+    List,
+    /// Run a command under a profile, bypassing the guard, and report how it exited
+    Run {
+        #[arg(long, default_value = "confined", help = "Profile name from the policy's [sandbox] section")]
+        profile: String,
+        #[arg(required = true, trailing_var_arg = true, help = "Command line, passed to sh -c")]
+        command: Vec<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ReceiptAction {
+    /// Check a receipt's signature and command hash
+    Verify {
+        receipt: std::path::PathBuf,
+        #[arg(long, help = "Hex-encoded operator public key the receipt must be signed by; defaults to the local operator key")]
+        trusted_key: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum AuditAction {
+    /// Check the chain for tampering or truncation
+    Verify {
+        #[arg(long, help = "Hex-encoded operator public key the head must be signed by; defaults to the local operator key. Keep a copy off-box")]
+        trusted_key: Option<String>,
+    },
+    /// Print entries matching the filters, oldest first
+    Query {
+        #[arg(long, help = "Only entries from the last N seconds")]
+        since_secs: Option<u64>,
+        #[arg(long, help = "Only entries whose command contains this text")]
+        command: Option<String>,
+        #[arg(long, help = "Only guard verdicts of this kind (allow/deny/confirm)")]
+        verdict: Option<Verdict>,
+    },
+}
+
+fn run_receipt_action(action: ReceiptAction, operator_key_path: &std::path::Path) -> Result<(), MatrixError> {
+    match action {
+        ReceiptAction::Verify { receipt, trusted_key } => {
+            let loaded = Receipt::load(&receipt)?;
+            // Without an explicit key, only receipts this operator signed are trusted
+            let trusted_key = match trusted_key {
+                Some(key) => key,
+                None => OperatorKey::load(operator_key_path)
+                    .map_err(|e| MatrixError::Integrity(format!("No trusted key: pass --trusted-key or provide an operator key ({})", e)))?
+                    .public_key_hex(),
+            };
+            loaded.verify(&trusted_key)?;
+            let body = &loaded.body;
+            println!("✅ Receipt verified: `{}`", body.command);
+            println!("   operator: {} ({})", body.operator, body.operator_key);
+            println!("   exit status: {}", body.exit_code.map(|c| c.to_string()).unwrap_or_else(|| "killed by signal".to_string()));
+            println!("   guard: {:?} {}", body.guard_verdict.verdict, body.guard_verdict.rule_id.as_deref().unwrap_or(""));
+        },
+    }
+    Ok(())
+}
+
+async fn run_sandbox_action(policy: &Policy, action: SandboxAction) -> Result<(), MatrixError> {
+    match action {
+        SandboxAction::List => {
+            for profile in policy.sandbox_profiles() {
+                println!("{}", profile.describe());
+            }
+        },
+        SandboxAction::Run { profile, command } => {
+            let profile = policy
+                .sandbox_profile(&profile)
+                .ok_or_else(|| MatrixError::Policy(format!("Unknown sandbox profile '{}'", profile)))?;
+            let mut execution = execute_command(&command.join(" "), &None, Some(profile)).await?;
+            while let Some(line) = execution.next_line().await {
+                match line {
+                    OutputLine::Stdout(text) => println!("{}", text),
+                    OutputLine::Stderr(text) => eprintln!("{}", text),
+                }
+            }
+            let record = execution.wait().await?;
+            match record.exit_code {
+                Some(code) => println!("🧱 Exited with status {} in sandbox '{}'", code, profile.name),
+                None => println!("🧱 Killed by a signal in sandbox '{}' (a resource limit may have been hit)", profile.name),
+            }
+        },
+    }
+    Ok(())
+}
+
+fn run_plugin_action(dir: &std::path::Path, action: PluginAction) -> Result<(), MatrixError> {
+    match action {
+        PluginAction::List => {
+            for plugin in plugin::discover(dir) {
+                match plugin {
+                    Ok(plugin) => {
+                        let manifest = plugin.manifest();
+                        println!("✅ {} {} ({})", manifest.name, manifest.version, manifest.executable);
+                    },
+                    Err(e) => println!("⛔ {}", e),
+                }
+            }
+        },
+        PluginAction::Hash { executable } => {
+            let bytes = std::fs::read(&executable).map_err(|e| MatrixError::io(&format!("Cannot read {}", executable.display()), e))?;
+            println!("{}", blake3::hash(&bytes).to_hex());
+        },
+    }
+    Ok(())
+}
+
+fn load_policy(path: Option<&std::path::Path>) -> Result<Policy, MatrixError> {
+    match path {
+        Some(path) => Policy::load(path),
+        None => std::env::current_dir()
+            .map_err(|e| MatrixError::io("Cannot determine working directory", e))
+            .and_then(|cwd| Policy::discover(&cwd)),
+    }
+}
+
+// Without a passphrase there is no key to seal history with, so it is kept for this run only
+async fn open_history(args: &Args) -> HistoryStore {
+    let Ok(passphrase) = std::env::var("AGENT_MATRIX_PASSPHRASE") else {
+        println!("⚠️  History not persisted: set AGENT_MATRIX_PASSPHRASE to keep it encrypted across runs");
+        return HistoryStore::ephemeral();
+    };
+    let keyring_path = args.keyring.clone().unwrap_or_else(KeyStore::default_path);
+    let history_path = args.history.clone().unwrap_or_else(HistoryStore::default_path);
+    let keys = match KeyStore::open_or_create(&keyring_path, &passphrase) {
+        Ok(keys) => Arc::new(keys),
+        Err(e) => {
+            eprintln!("💀 Keyring unavailable: {}", e);
+            std::process::exit(1);
+        }
+    };
+    match HistoryStore::open(&history_path, keys).await {
+        Ok(history) => history,
+        Err(e) => {
+            eprintln!("💀 History unavailable: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn run_audit_action(path: &std::path::Path, action: AuditAction, operator_key_path: &std::path::Path) -> Result<(), MatrixError> {
+    match action {
+        AuditAction::Verify { trusted_key } => {
+            let trusted_key = match trusted_key {
+                Some(key) => key,
+                None => OperatorKey::load(operator_key_path)
+                    .map_err(|e| MatrixError::Integrity(format!("No trusted key: pass --trusted-key or provide an operator key ({})", e)))?
+                    .public_key_hex(),
+            };
+            let count = audit::verify(path, &trusted_key)?;
+            println!("✅ Audit trail intact: {} entries in {}", count, path.display());
+        },
+        AuditAction::Query { since_secs, command, verdict } => {
+            let query = AuditQuery {
+                since: since_secs.map(|secs| std::time::SystemTime::now() - std::time::Duration::from_secs(secs)),
+                until: None,
+                command,
+                verdict,
+            };
+            for entry in audit::query(path, &query)? {
+                let line = serde_json::to_string(&entry).map_err(|e| MatrixError::serialization("Audit entry serialization failed", e))?;
+                println!("{}", line);
+            }
+        },
+    }
+    Ok(())
+}
+
+async fn run_workflow(registry: &AgentRegistry, file: &std::path::Path, task: &str) -> Result<(), MatrixError> {
+    let report = Workflow::load(file, registry)?.run(task).await?;
+    for step in &report.steps {
+        let outcome = match &step.status {
+            StepStatus::Succeeded(output) => format!("✅ {}: {}", output.vote, output.payload()),
+            StepStatus::FellBack { output, error } => format!("🟡 fell back to `{}` after: {}", output, error),
+            StepStatus::Failed(error) => format!("⛔ {}", error),
+            StepStatus::Skipped => "↩️ skipped: a dependency failed".to_string(),
+        };
+        println!("{} ({} attempt(s), {} ms) {}", step.id, step.attempts, step.elapsed.as_millis(), outcome);
+    }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
+    let args = Args::parse();
+    let audit_path = args.audit_log.clone().unwrap_or_else(AuditLog::default_path);
+    let operator_key_path = args.operator_key.clone().unwrap_or_else(OperatorKey::default_path);
+
+    // Audit review must work even when the rest of the environment does not
+    if let Some(Commands::Audit { action }) = args.command {
+        if let Err(e) = run_audit_action(&audit_path, action, &operator_key_path) {
+            eprintln!("💀 Audit check failed: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(Commands::Receipt { action }) = args.command {
+        if let Err(e) = run_receipt_action(action, &operator_key_path) {
+            eprintln!("💀 Receipt rejected: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let plugins_dir = args.plugins_dir.clone().unwrap_or_else(plugin::default_dir);
+    if let Some(Commands::Plugin { action }) = args.command {
+        if let Err(e) = run_plugin_action(&plugins_dir, action) {
+            eprintln!("💀 Plugin check failed: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if let Some(Commands::Sandbox { action }) = args.command {
+        let result = match load_policy(args.policy.as_deref()) {
+            Ok(policy) => run_sandbox_action(&policy, action).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            eprintln!("💀 Sandbox check failed: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // Sovereign bootstrap (this is the highest-level security operation)
     if let Err(e) = bootstrap_sovereign_environment().await {
         eprintln!("💀 FATAL SECURITY VIOLATION: {}", e);
         std::process::exit(1);
     }
 
-    let _args = Args::parse();
-
     // Initialize sovereign AI agents
-    let ethical_guard = Arc::new(EthicalGuard {
-        constraints: Arc::new(tokio::sync::Mutex::new(vec![
-            "bias_check".to_string(),
-            "disparity_analysis".to_string(),
-            "toxicity_filter".to_string(),
-        ])),
-    });
-
-    let ethical_agent = Arc::new(EthicalAgent::new(ethical_guard));
+    let policy = match load_policy(args.policy.as_deref()) {
+        Ok(policy) => policy,
+        Err(e) => {
+            eprintln!("💀 Guard policy rejected: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let safety_plugins = policy.safety_plugins().to_vec();
+    let ethical_guard = Arc::new(EthicalGuard::new(policy));
+    if let Some(strictness) = args.strictness {
+        ethical_guard.set_strictness(strictness).await;
+    }
 
     // Initialize GPU-accelerated compute (prioritized over CPU)
-    let (compute_agent, vulkan_context) = match init_vulkan() {
+    let vulkan_context = match init_vulkan() {
         Ok(gpu_context) => {
             println!("⚡ GPU acceleration initialized successfully");
             // In real impl, also verify GPU integrity
-            (Arc::new(ComputeAgent::new(Some(gpu_context.clone()))), Some(gpu_context))
+            Some(gpu_context)
         },
         Err(e) => {
             println!("⚠️  GPU unavailable: {}. Falling back to verified CPU compute", e);
-            (Arc::new(ComputeAgent::new(None)), None)
+            None
         }
     };
 
     // Assemble sovereign agent matrix
-    let agents = vec![ethical_agent, compute_agent];
+    let context = AgentContext { guard: ethical_guard.clone(), vulkan: vulkan_context.clone() };
+    let mut registry = match AgentRegistry::builtin(&context) {
+        Ok(registry) => registry,
+        Err(e) => {
+            eprintln!("💀 Agent matrix unavailable: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // A plugin that fails its checks stays out of the matrix; the rest still load. Only the
+    // policy, never the plugin's own manifest, makes it a safety agent
+    for plugin in plugin::discover(&plugins_dir) {
+        let plugin = plugin.map(|p| if safety_plugins.contains(&p.manifest().name) { p.grant_safety() } else { p });
+        if let Err(e) = plugin.and_then(|p| registry.register(Arc::new(p))) {
+            println!("⚠️  Plugin skipped: {}", e);
+        }
+    }
+
+    let operator_key = match OperatorKey::open_or_create(&operator_key_path) {
+        Ok(key) => Arc::new(key),
+        Err(e) => {
+            eprintln!("💀 Operator key unavailable: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // The operator key doubles as this machine's identity in remote sessions
+    let trusted_peers_path = args.trusted_peers.clone().unwrap_or_else(TrustedPeers::default_path);
+    let identity = match TrustedPeers::load(&trusted_peers_path) {
+        Ok(trusted) => PeerIdentity { key: operator_key.clone(), trusted: Arc::new(trusted) },
+        Err(e) => {
+            eprintln!("💀 Trusted peers unavailable: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(Commands::Serve { listen }) = args.command {
+        let listen = listen.unwrap_or_else(Endpoint::default_local);
+        if let Err(e) = remote::serve(&listen, Arc::new(registry), identity).await {
+            eprintln!("💀 Agent daemon failure: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    // Remote agents join the matrix alongside the local ones
+    for endpoint in &args.remote {
+        if let Err(e) = registry.register(Arc::new(RemoteAgent::new(endpoint.clone(), identity.clone()))) {
+            eprintln!("💀 Remote agent rejected: {}", e);
+            std::process::exit(1);
+        }
+    }
+    let agents = Arc::new(registry);
+
+    if let Some(Commands::Workflow { file, task }) = args.command {
+        if let Err(e) = run_workflow(&agents, &file, &task.join(" ")).await {
+            eprintln!("💀 Workflow failed: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
 
     // Initialize AI-enhanced UX engine
-    let ux_engine = Arc::new(UXEngine::new());
+    let mut ux_engine = UXEngine::new();
+    if let Some(model) = &args.model {
+        let tokenizer = args.tokenizer.clone().unwrap_or_else(|| Suggester::default_tokenizer_path(model));
+        let sampling = SamplingConfig::default()
+            .temperature(args.temperature)
+            .top_p(Some(args.top_p))
+            .deadline(std::time::Duration::from_millis(args.suggestion_deadline_ms));
+        match Suggester::load(model, &tokenizer, args.model_arch, sampling) {
+            Ok(suggester) => ux_engine = ux_engine.with_suggester(suggester),
+            Err(e) => {
+                eprintln!("💀 Suggestion model unavailable: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    let ux_engine = Arc::new(ux_engine);
+
+    let audit_log = match AuditLog::open(&audit_path, operator_key.clone()) {
+        Ok(log) => Arc::new(log),
+        Err(e) => {
+            eprintln!("💀 Audit log unavailable: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let history = open_history(&args).await;
 
     // Launch the Sovereign AI Terminal - UI is now the top architecture priority
-    let mut terminal_interface = MatrixUI::new(agents, ux_engine, vulkan_context);
+    let mut terminal_interface = MatrixUI::new(agents, ethical_guard, audit_log, operator_key, ux_engine, vulkan_context, history);
+    terminal_interface.set_consensus(args.consensus);
     terminal_interface.run_event_loop().await?;
 
     println!("👑 Agent Matrix shutdown complete. Sovereign integrity maintained.");
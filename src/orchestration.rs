@@ -1,24 +1,276 @@
 use vulkano::instance::Instance;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
 use tokio::process::Command;
-use pqcrypto_kyber::kyber1024::{keypair, encapsulate, decapsulate};
+use tokio::sync::{mpsc, oneshot, watch};
+use std::process::Stdio;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use super::error::MatrixError;
+use super::sandbox::SandboxProfile;
 
 // Branded for @Devdollzai Alexis Adams @AxiomHive #AxiomHive
 
-pub async fn execute_command(cmd: &str, _vulkan: &Option<Arc<Instance>>) {
-    // Quantum-secure key exchange proof (Kyber KEM)
-    let (pk, sk) = keypair();
-    let (ct, ss) = encapsulate(&pk);
-    let _dec_ss = decapsulate(&ct, &sk);
+/// How long a cancelled command gets to exit after SIGINT before it is killed.
+pub const DEFAULT_CANCEL_GRACE: Duration = Duration::from_secs(3);
+/// How long output from background jobs is still read after `sh` exits, before each escalation:
+/// SIGHUP to the group, then SIGKILL, then the pipes are abandoned.
+pub const ORPHAN_GRACE: Duration = Duration::from_secs(1);
 
-    let output = Command::new("sh")
+/// What a finished command left behind, in the form a receipt commits to.
+#[derive(Debug, Clone)]
+pub struct ExecutionRecord {
+    /// `None` if the command was killed by a signal.
+    pub exit_code: Option<i32>,
+    pub stdout_digest: String,
+    pub stderr_digest: String,
+    pub started_at: SystemTime,
+    pub finished_at: SystemTime,
+    /// Name of the sandbox profile the command ran under, if any.
+    pub sandbox: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputLine {
+    Stdout(String),
+    Stderr(String),
+}
+
+/// What `Execution::next_event` saw.
+#[derive(Debug)]
+pub enum ExecutionEvent {
+    Line(OutputLine),
+    Finished(Result<ExecutionRecord, MatrixError>),
+}
+
+/// A running command. Output arrives line by line as the process writes it; the final
+/// record is available once both streams are drained and the process has exited.
+pub struct Execution {
+    command: String,
+    output: mpsc::UnboundedReceiver<OutputLine>,
+    cancel: Option<oneshot::Sender<Duration>>,
+    finished: oneshot::Receiver<Result<ExecutionRecord, MatrixError>>,
+}
+
+impl Execution {
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// The next output line, or `None` once the process has closed both streams.
+    pub async fn next_line(&mut self) -> Option<OutputLine> {
+        self.output.recv().await
+    }
+
+    /// Non-blocking variant of `next_line` for render loops.
+    pub fn try_next_line(&mut self) -> Option<OutputLine> {
+        self.output.try_recv().ok()
+    }
+
+    /// The next output line or, once both streams are drained, the final record. Cancel-safe,
+    /// so it can sit in a `select!`; must not be called again after `Finished`.
+    pub async fn next_event(&mut self) -> ExecutionEvent {
+        if let Some(line) = self.output.recv().await {
+            return ExecutionEvent::Line(line);
+        }
+        ExecutionEvent::Finished((&mut self.finished).await.unwrap_or_else(|_| Err(supervisor_gone())))
+    }
+
+    /// Sends SIGINT to the command's process group, then SIGKILL if it is still running after `grace`.
+    /// Later calls are no-ops.
+    pub fn cancel(&mut self, grace: Duration) {
+        if let Some(cancel) = self.cancel.take() {
+            let _ = cancel.send(grace);
+        }
+    }
+
+    /// The final record, if the command has finished. Returns `Some` exactly once.
+    pub fn try_wait(&mut self) -> Option<Result<ExecutionRecord, MatrixError>> {
+        self.finished.try_recv().ok()
+    }
+
+    /// Waits for the command to finish, discarding any output not yet read.
+    pub async fn wait(self) -> Result<ExecutionRecord, MatrixError> {
+        self.finished.await.unwrap_or_else(|_| Err(supervisor_gone()))
+    }
+}
+
+fn supervisor_gone() -> MatrixError {
+    MatrixError::AgentPanic("Execution supervisor exited without a result".to_string())
+}
+
+/// Starts `cmd` under `sh -c` in its own process group, confined by `sandbox` if given, and
+/// returns a handle to it.
+pub async fn execute_command(cmd: &str, _vulkan: &Option<Arc<Instance>>, sandbox: Option<&SandboxProfile>) -> Result<Execution, MatrixError> {
+    let mut command = Command::new("sh");
+    command
         .arg("-c")
         .arg(cmd)
-        .output()
-        .await
-        .expect("Failed to execute command");
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .kill_on_drop(true);
+    if let Some(profile) = sandbox {
+        profile.prepare()?.install(&mut command);
+    }
+    let sandbox = sandbox.map(|p| p.name.clone());
+
+    let started_at = SystemTime::now();
+    let mut child = command.spawn().map_err(|e| spawn_error(cmd, sandbox.as_deref(), e))?;
+    // Kept apart from `child`, which forgets its pid once reaped but the group may live on
+    let pgid = child.id();
 
-    println!("Output: {}", String::from_utf8_lossy(&output.stdout));
+    let (line_tx, output) = mpsc::unbounded_channel();
+    let (stop_tx, stop) = watch::channel(false);
+    let stdout = child.stdout.take().map(|s| tokio::spawn(stream_lines(s, line_tx.clone(), OutputLine::Stdout, stop.clone())));
+    let stderr = child.stderr.take().map(|s| tokio::spawn(stream_lines(s, line_tx, OutputLine::Stderr, stop)));
+    let (cancel, cancel_rx) = oneshot::channel();
+    let (finished_tx, finished) = oneshot::channel();
+
+    let command = cmd.to_string();
+    tokio::spawn(async move {
+        let status = supervise(&mut child, cancel_rx).await;
+        let digest = |handle: Option<tokio::task::JoinHandle<String>>| async move {
+            match handle {
+                Some(handle) => handle.await.unwrap_or_default(),
+                None => blake3::hash(b"").to_hex().to_string(),
+            }
+        };
+        let readers = async { (digest(stdout).await, digest(stderr).await) };
+        tokio::pin!(readers);
+        // `sh` has exited, but jobs it left in the background (`make &`) still hold the pipes.
+        // Hang them up as a closing terminal would, then kill what ignores that
+        let mut drained = None;
+        for signal in [None, Some(libc::SIGHUP), Some(libc::SIGKILL)] {
+            if let Some(signal) = signal {
+                signal_group(pgid, signal);
+            }
+            if let Ok(digests) = tokio::time::timeout(ORPHAN_GRACE, &mut readers).await {
+                drained = Some(digests);
+                break;
+            }
+        }
+        // Only a process that left the group (`setsid`) can still be writing: stop listening
+        let (stdout_digest, stderr_digest) = match drained {
+            Some(digests) => digests,
+            None => {
+                let _ = stop_tx.send(true);
+                readers.await
+            },
+        };
+        let result = status
+            .map(|status| ExecutionRecord { exit_code: status.code(), stdout_digest, stderr_digest, started_at, finished_at: SystemTime::now(), sandbox })
+            .map_err(|e| MatrixError::io(&format!("Lost track of '{}'", command), e));
+        let _ = finished_tx.send(result);
+    });
+
+    Ok(Execution { command: cmd.to_string(), output, cancel: Some(cancel), finished })
+}
+
+/// Names the sandbox when it is the likely culprit, e.g. unprivileged user namespaces are disabled.
+pub fn spawn_error(cmd: &str, sandbox: Option<&str>, e: std::io::Error) -> MatrixError {
+    match sandbox {
+        Some(profile) => MatrixError::io(&format!("Failed to start '{}' in sandbox '{}'", cmd, profile), e),
+        None => MatrixError::io(&format!("Failed to start '{}'", cmd), e),
+    }
+}
+
+async fn supervise(child: &mut tokio::process::Child, cancel: oneshot::Receiver<Duration>) -> std::io::Result<std::process::ExitStatus> {
+    let grace = tokio::select! {
+        status = child.wait() => return status,
+        grace = cancel => match grace {
+            Ok(grace) => grace,
+            // The handle was dropped without cancelling: let the command run to completion
+            Err(_) => return child.wait().await,
+        },
+    };
+
+    signal_group(child.id(), libc::SIGINT);
+    match tokio::time::timeout(grace, child.wait()).await {
+        Ok(status) => status,
+        Err(_) => {
+            signal_group(child.id(), libc::SIGKILL);
+            child.wait().await
+        },
+    }
+}
+
+// The child leads its own process group, so this reaches everything `sh -c` started
+fn signal_group(pgid: Option<u32>, signal: libc::c_int) {
+    if let Some(pid) = pgid {
+        unsafe {
+            libc::kill(-(pid as libc::pid_t), signal);
+        }
+    }
 }
 
-// #AxiomHive Orchestration/Kyber Coalesced
\ No newline at end of file
+// Forwards each line as it arrives and returns the BLAKE3 digest of the raw stream, or of as
+// much of it as arrived before `stop`
+async fn stream_lines<R: AsyncRead + Unpin>(stream: R, lines: mpsc::UnboundedSender<OutputLine>, wrap: fn(String) -> OutputLine, mut stop: watch::Receiver<bool>) -> String {
+    let mut reader = BufReader::new(stream);
+    let mut hasher = blake3::Hasher::new();
+    let mut buf = vec![];
+    loop {
+        buf.clear();
+        let read = tokio::select! {
+            read = reader.read_until(b'\n', &mut buf) => read,
+            _ = stop.wait_for(|stop| *stop) => break,
+        };
+        match read {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                hasher.update(&buf);
+                let line = String::from_utf8_lossy(&buf).trim_end_matches(['\n', '\r']).to_string();
+                // The receiver may be gone; keep draining so the digest covers the whole stream
+                let _ = lines.send(wrap(line));
+            },
+        }
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+// #AxiomHive Orchestration/Kyber Coalesced
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn run(cmd: &str) -> (Vec<OutputLine>, ExecutionRecord) {
+        let mut execution = execute_command(cmd, &None, None).await.unwrap();
+        let mut lines = vec![];
+        loop {
+            match tokio::time::timeout(Duration::from_secs(10), execution.next_event()).await.expect("command finished") {
+                ExecutionEvent::Line(line) => lines.push(line),
+                ExecutionEvent::Finished(record) => return (lines, record.unwrap()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_output_and_exit_status() {
+        let (lines, record) = run("echo out; echo err >&2; exit 3").await;
+        assert!(lines.contains(&OutputLine::Stdout("out".to_string())));
+        assert!(lines.contains(&OutputLine::Stderr("err".to_string())));
+        assert_eq!(record.exit_code, Some(3));
+        assert_eq!(record.stdout_digest, blake3::hash(b"out\n").to_hex().to_string());
+    }
+
+    #[tokio::test]
+    async fn finishes_despite_background_jobs() {
+        let (lines, record) = run("sleep 30 & echo hi").await;
+        assert_eq!(lines, vec![OutputLine::Stdout("hi".to_string())]);
+        assert_eq!(record.exit_code, Some(0));
+
+        // A job that ignores the hangup is killed
+        let (_, record) = run("(trap '' HUP; sleep 30) & echo hi").await;
+        assert_eq!(record.exit_code, Some(0));
+    }
+
+    #[tokio::test]
+    async fn cancel_interrupts_the_group() {
+        let mut execution = execute_command("sleep 30", &None, None).await.unwrap();
+        execution.cancel(Duration::from_secs(1));
+        let record = tokio::time::timeout(Duration::from_secs(10), execution.wait()).await.unwrap().unwrap();
+        assert_eq!(record.exit_code, None);
+    }
+}
@@ -0,0 +1,356 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::sync::Mutex;
+use super::agents::{Agent, AgentDescriptor, AgentOutput, Capability, InputKind};
+use super::error::MatrixError;
+
+/// Protocol version a plugin must declare in its manifest and confirm in `initialize`.
+pub const PLUGIN_API_VERSION: u32 = 1;
+
+const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+const PLUGIN_DEADLINE_MARGIN_MS: u64 = 1_000;
+const MAX_RESPONSE_SIZE: usize = 16 * 1024 * 1024;
+const SPAWN_ATTEMPTS: u32 = 5;
+
+/// `plugin.toml`, one per directory under the plugins directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginManifest {
+    pub name: String,
+    pub version: String,
+    pub api_version: u32,
+    /// Path of the program relative to the plugin directory.
+    pub executable: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// BLAKE3 of the executable, hex-encoded. The program is hashed again before every launch, and
+    /// what runs is a private copy of the bytes that were hashed.
+    pub blake3: String,
+    /// `safety` is ignored here: only the operator's policy can make a plugin a safety agent.
+    #[serde(default)]
+    pub capabilities: Vec<Capability>,
+    #[serde(default = "default_cost")]
+    pub cost: u32,
+    #[serde(default = "default_inputs")]
+    pub inputs: Vec<InputKind>,
+    #[serde(default)]
+    pub programs: Vec<String>,
+    /// Limit on a single `execute` call.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+fn default_cost() -> u32 {
+    10
+}
+
+fn default_inputs() -> Vec<InputKind> {
+    vec![InputKind::ShellCommand]
+}
+
+/// `~/.config/agent-matrix/plugins`
+pub fn default_dir() -> PathBuf {
+    let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    PathBuf::from(home).join(".config").join("agent-matrix").join("plugins")
+}
+
+/// Loads every `<dir>/*/plugin.toml`. A missing directory means no plugins; a plugin that
+/// fails its checks is reported in place without affecting the others.
+pub fn discover(dir: &Path) -> Vec<Result<PluginAgent, MatrixError>> {
+    let Ok(entries) = std::fs::read_dir(dir) else { return vec![] };
+    let mut dirs: Vec<PathBuf> = entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.join("plugin.toml").is_file()).collect();
+    dirs.sort();
+    dirs.iter().map(|d| PluginAgent::load(d)).collect()
+}
+
+/// An agent implemented by an external program speaking JSON-RPC 2.0 over stdio, one message
+/// per line. The host calls `initialize` once after launch, then `execute` with `{"task"}`,
+/// which answers with an `AgentOutput` object or a plain string. Since the program runs from a
+/// copy, it finds its own directory in `AGENT_MATRIX_PLUGIN_DIR`.
+pub struct PluginAgent {
+    manifest: PluginManifest,
+    dir: PathBuf,
+    executable: PathBuf,
+    safety: bool,
+    process: Mutex<Option<PluginProcess>>,
+}
+
+struct PluginProcess {
+    // Held so the program is killed when the connection is dropped
+    _child: Child,
+    // An interpreter opens a script by path after exec, so the copy outlives the launch
+    _copy: PrivateCopy,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    next_id: u64,
+}
+
+impl PluginAgent {
+    /// Reads and checks the manifest in `dir`, including the executable's pinned hash.
+    pub fn load(dir: &Path) -> Result<Self, MatrixError> {
+        let path = dir.join("plugin.toml");
+        let text = std::fs::read_to_string(&path).map_err(|e| MatrixError::io(&format!("Cannot read {}", path.display()), e))?;
+        let manifest: PluginManifest = toml::from_str(&text).map_err(|e| MatrixError::Plugin(format!("Malformed {}: {}", path.display(), e)))?;
+        if manifest.api_version != PLUGIN_API_VERSION {
+            return Err(MatrixError::Plugin(format!(
+                "Plugin '{}' targets API version {}, this build speaks {}",
+                manifest.name, manifest.api_version, PLUGIN_API_VERSION
+            )));
+        }
+        // The program must live inside the plugin directory the pin was taken for
+        let relative = Path::new(&manifest.executable);
+        if relative.is_absolute() || relative.components().any(|c| matches!(c, Component::ParentDir)) {
+            return Err(MatrixError::Plugin(format!("Plugin '{}' executable must be a path inside {}", manifest.name, dir.display())));
+        }
+        let agent = Self { dir: dir.to_path_buf(), executable: dir.join(relative), manifest, safety: false, process: Mutex::new(None) };
+        agent.verified_bytes()?;
+        Ok(agent)
+    }
+
+    pub fn manifest(&self) -> &PluginManifest {
+        &self.manifest
+    }
+
+    /// Lets the plugin act as a safety agent, which sees every task. For operator config only.
+    pub fn grant_safety(mut self) -> Self {
+        self.safety = true;
+        self
+    }
+
+    // Read once: the file may be swapped after it is hashed, so only these bytes may run
+    fn verified_bytes(&self) -> Result<Vec<u8>, MatrixError> {
+        let bytes = std::fs::read(&self.executable).map_err(|e| MatrixError::io(&format!("Cannot read {}", self.executable.display()), e))?;
+        let actual = blake3::hash(&bytes).to_hex().to_string();
+        if !actual.eq_ignore_ascii_case(&self.manifest.blake3) {
+            return Err(MatrixError::Integrity(format!(
+                "Plugin '{}' executable {} does not match its pin. Expected: {}, Got: {}",
+                self.manifest.name,
+                self.executable.display(),
+                self.manifest.blake3,
+                actual
+            )));
+        }
+        Ok(bytes)
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.manifest.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS))
+    }
+
+    async fn launch(&self) -> Result<PluginProcess, MatrixError> {
+        let copy = PrivateCopy::new(&self.manifest.name, &self.executable, &self.verified_bytes()?)?;
+        let mut command = Command::new(&copy.executable);
+        command
+            .args(&self.manifest.args)
+            .env("AGENT_MATRIX_PLUGIN_DIR", &self.dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            // A plugin writing to stderr would scribble over the TUI
+            .stderr(Stdio::null())
+            .kill_on_drop(true);
+        let mut attempt = 1;
+        let mut child = loop {
+            match command.spawn() {
+                Ok(child) => break child,
+                // Another thread forking while the copy was still open for writing holds it busy
+                Err(e) if e.raw_os_error() == Some(libc::ETXTBSY) && attempt < SPAWN_ATTEMPTS => {
+                    attempt += 1;
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                },
+                Err(e) => return Err(MatrixError::io(&format!("Failed to start plugin '{}'", self.manifest.name), e)),
+            }
+        };
+        let stdin = child.stdin.take().expect("Piped stdin");
+        let stdout = BufReader::new(child.stdout.take().expect("Piped stdout"));
+        let mut process = PluginProcess { _child: child, _copy: copy, stdin, stdout, next_id: 1 };
+
+        // A plugin that never answers must not hold the agent lock forever
+        let initialize = process.call("initialize", json!({ "api_version": PLUGIN_API_VERSION, "host_version": env!("CARGO_PKG_VERSION") }));
+        let reply = tokio::time::timeout(self.timeout(), initialize)
+            .await
+            .map_err(|_| MatrixError::AgentTimeout { agent: format!("plugin:{}", self.manifest.name), after: self.timeout() })???;
+        let api_version = reply.get("api_version").and_then(Value::as_u64);
+        if api_version != Some(PLUGIN_API_VERSION as u64) {
+            return Err(MatrixError::Plugin(format!("Plugin '{}' answered initialize with API version {:?}", self.manifest.name, api_version)));
+        }
+        Ok(process)
+    }
+}
+
+/// The verified program, written to a directory only this user can enter. Removed on drop.
+struct PrivateCopy {
+    dir: PathBuf,
+    executable: PathBuf,
+}
+
+impl PrivateCopy {
+    fn new(name: &str, original: &Path, bytes: &[u8]) -> Result<Self, MatrixError> {
+        // Created fresh, never reused: a directory someone else made first is an error
+        let nonce = blake3::hash(format!("{:?}{:?}", std::time::SystemTime::now(), std::thread::current().id()).as_bytes()).to_hex();
+        let dir = std::env::temp_dir().join(format!("agent-matrix-run-{}-{}", std::process::id(), &nonce[..16]));
+        std::fs::DirBuilder::new().mode(0o700).create(&dir).map_err(|e| MatrixError::io(&format!("Cannot create {}", dir.display()), e))?;
+        let copy = Self { executable: dir.join(original.file_name().unwrap_or(std::ffi::OsStr::new(name))), dir };
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o700)
+            .open(&copy.executable)
+            .map_err(|e| MatrixError::io(&format!("Cannot create {}", copy.executable.display()), e))?;
+        file.write_all(bytes).map_err(|e| MatrixError::io(&format!("Cannot write {}", copy.executable.display()), e))?;
+        Ok(copy)
+    }
+}
+
+impl Drop for PrivateCopy {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl PluginProcess {
+    /// The outer error means the connection is unusable; the inner one is an error the plugin
+    /// reported itself.
+    async fn call(&mut self, method: &str, params: Value) -> Result<Result<Value, MatrixError>, MatrixError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut request = serde_json::to_vec(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+            .map_err(|e| MatrixError::serialization("Serialization failed", e))?;
+        request.push(b'\n');
+        self.stdin.write_all(&request).await.map_err(|e| MatrixError::io("Plugin write failed", e))?;
+        self.stdin.flush().await.map_err(|e| MatrixError::io("Plugin write failed", e))?;
+
+        let mut line = String::new();
+        let read = (&mut self.stdout).take(MAX_RESPONSE_SIZE as u64).read_line(&mut line).await.map_err(|e| MatrixError::io("Plugin read failed", e))?;
+        if read == 0 {
+            return Err(MatrixError::Plugin("Plugin exited".to_string()));
+        }
+        if !line.ends_with('\n') {
+            return Err(MatrixError::Plugin(format!("Plugin response exceeds {} bytes", MAX_RESPONSE_SIZE)));
+        }
+        let response: Value = serde_json::from_str(&line).map_err(|e| MatrixError::serialization("Malformed plugin response", e))?;
+        if response.get("id").and_then(Value::as_u64) != Some(id) {
+            return Err(MatrixError::Plugin(format!("Plugin answered out of order (expected id {})", id)));
+        }
+        if let Some(error) = response.get("error") {
+            let message = error.get("message").and_then(Value::as_str).unwrap_or("unspecified error");
+            return Ok(Err(MatrixError::Plugin(message.to_string())));
+        }
+        response.get("result").cloned().map(Ok).ok_or_else(|| MatrixError::Plugin("Plugin response has neither result nor error".to_string()))
+    }
+}
+
+#[async_trait]
+impl Agent for PluginAgent {
+    fn descriptor(&self) -> AgentDescriptor {
+        AgentDescriptor {
+            name: format!("plugin:{}", self.manifest.name),
+            capabilities: self
+                .manifest
+                .capabilities
+                .iter()
+                .filter(|c| **c != Capability::Safety)
+                .cloned()
+                .chain(self.safety.then_some(Capability::Safety))
+                .collect(),
+            cost: self.manifest.cost,
+            inputs: self.manifest.inputs.clone(),
+            programs: self.manifest.programs.clone(),
+            // A little over the call limit, so the plugin is restarted cleanly before the dry run gives up
+            deadline_ms: Some(self.timeout().as_millis() as u64 + PLUGIN_DEADLINE_MARGIN_MS),
+        }
+    }
+
+    async fn execute(&self, task: &str) -> Result<AgentOutput, MatrixError> {
+        let mut guard = self.process.lock().await;
+        if guard.is_none() {
+            *guard = Some(self.launch().await?);
+        }
+        let process = guard.as_mut().expect("Plugin launched");
+
+        let call = tokio::time::timeout(self.timeout(), process.call("execute", json!({ "task": task }))).await;
+        match call {
+            // A bare string is an opinion-free result
+            Ok(Ok(Ok(Value::String(output)))) => Ok(AgentOutput::abstain(output)),
+            Ok(Ok(Ok(other))) => serde_json::from_value(other)
+                .map_err(|e| MatrixError::Plugin(format!("Plugin '{}' returned a malformed output: {}", self.manifest.name, e))),
+            // Reported in-band: the plugin is still healthy
+            Ok(Ok(Err(e))) => Err(e),
+            // Broke protocol or stalled: restart, and re-verify, on the next call
+            Ok(Err(e)) => {
+                *guard = None;
+                Err(e)
+            },
+            Err(_) => {
+                *guard = None;
+                Err(MatrixError::AgentTimeout { agent: format!("plugin:{}", self.manifest.name), after: self.timeout() })
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    // A plugin directory whose executable is `script`, pinned correctly
+    fn install(name: &str, script: &str, extra: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("agent-matrix-plugin-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let executable = dir.join("run.sh");
+        std::fs::write(&executable, script).unwrap();
+        std::fs::set_permissions(&executable, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let pin = blake3::hash(script.as_bytes()).to_hex();
+        let manifest = format!("name = \"{}\"\nversion = \"1.0\"\napi_version = 1\nexecutable = \"run.sh\"\nblake3 = \"{}\"\n{}", name, pin, extra);
+        std::fs::write(dir.join("plugin.toml"), manifest).unwrap();
+        dir
+    }
+
+    #[test]
+    fn manifest_cannot_claim_safety() {
+        let dir = install("claims-safety", "#!/bin/sh\n", "capabilities = [\"safety\", \"git\"]\n");
+        let plugin = PluginAgent::load(&dir).unwrap();
+        assert_eq!(plugin.descriptor().capabilities, vec![Capability::Git]);
+        assert!(plugin.grant_safety().descriptor().has(&Capability::Safety));
+    }
+
+    #[test]
+    fn rejects_a_modified_executable() {
+        let dir = install("tampered", "#!/bin/sh\n", "");
+        std::fs::write(dir.join("run.sh"), "#!/bin/sh\nrm -rf ~\n").unwrap();
+        assert!(matches!(PluginAgent::load(&dir), Err(MatrixError::Integrity(_))));
+    }
+
+    #[tokio::test]
+    async fn runs_the_verified_copy() {
+        let script = "#!/bin/sh\nread line\necho '{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{\"api_version\":1}}'\nread line\nprintf '{\"jsonrpc\":\"2.0\",\"id\":2,\"result\":\"%s %s\"}\\n' \"$0\" \"$AGENT_MATRIX_PLUGIN_DIR\"\nsleep 60\n";
+        let dir = install("copied", script, "");
+        let plugin = PluginAgent::load(&dir).unwrap();
+        let output = plugin.execute("ls").await.unwrap();
+        let (program, plugin_dir) = output.rationale.split_once(' ').unwrap();
+        assert_eq!(plugin_dir, dir.to_str().unwrap());
+        // Swapping the original after the hash cannot change what runs
+        assert!(!Path::new(program).starts_with(&dir));
+        assert_eq!(std::fs::read(program).unwrap(), script.as_bytes());
+        let mode = std::fs::metadata(Path::new(program).parent().unwrap()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        drop(plugin);
+        assert!(!Path::new(program).exists());
+    }
+
+    #[tokio::test]
+    async fn initialize_is_bounded_by_the_timeout() {
+        let dir = install("silent", "#!/bin/sh\nexec sleep 60\n", "timeout_ms = 200\n");
+        let plugin = PluginAgent::load(&dir).unwrap();
+        let started = std::time::Instant::now();
+        assert!(matches!(plugin.execute("ls").await, Err(MatrixError::AgentTimeout { .. })));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
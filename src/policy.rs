@@ -0,0 +1,809 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+use super::error::MatrixError;
+use super::risk::{self, Finding, Severity};
+use super::sandbox::SandboxProfile;
+use super::shell::{self, Script, SimpleCommand, Word};
+
+// Upper bound on the working directories tracked through `cd` chains in one command line
+const MAX_TRACKED_DIRS: usize = 16;
+
+/// What a matched rule asks the guard to do with the command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    Allow,
+    Deny,
+    Confirm,
+}
+
+impl std::str::FromStr for Verdict {
+    type Err = MatrixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "allow" => Ok(Verdict::Allow),
+            "deny" => Ok(Verdict::Deny),
+            "confirm" => Ok(Verdict::Confirm),
+            other => Err(MatrixError::Policy(format!("Unknown verdict '{}': expected allow, deny or confirm", other))),
+        }
+    }
+}
+
+/// How hard the guard enforces a `Deny`: block it, quarantine it for review, or log and proceed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Strictness {
+    #[default]
+    Strict,
+    Moderate,
+    Lenient,
+}
+
+impl std::str::FromStr for Strictness {
+    type Err = MatrixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(Strictness::Strict),
+            "moderate" => Ok(Strictness::Moderate),
+            "lenient" => Ok(Strictness::Lenient),
+            other => Err(MatrixError::Policy(format!("Unknown strictness '{}': expected strict, moderate or lenient", other))),
+        }
+    }
+}
+
+impl std::fmt::Display for Strictness {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Strictness::Strict => write!(f, "STRICT"),
+            Strictness::Moderate => write!(f, "MODERATE"),
+            Strictness::Lenient => write!(f, "LENIENT"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RuleKind {
+    /// Deny when the raw command matches `pattern`.
+    RegexDeny { pattern: String },
+    /// Allow when the raw command matches `pattern`, short-circuiting later rules.
+    RegexAllow { pattern: String },
+    /// Deny when any simple command in the line runs a binary not in `binaries`. A bare name
+    /// allows that name as looked up through `PATH`; an explicit path must be the same file.
+    BinaryAllowlist { binaries: Vec<String> },
+    /// Deny when any argument or redirection target, resolved against the working directory,
+    /// falls under one of `paths`.
+    PathRestriction { paths: Vec<String> },
+    /// Deny commands longer than `max` characters.
+    MaxLength { max: usize },
+    /// Hold commands matching `pattern` until the operator confirms them.
+    RequireConfirmation { pattern: String },
+}
+
+/// A single policy entry as written in the policy file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub id: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(flatten)]
+    pub kind: RuleKind,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PolicyFile {
+    #[serde(default)]
+    strictness: Option<Strictness>,
+    /// Risk findings at or above this severity are denied unless a rule explicitly allows the command.
+    #[serde(default)]
+    deny_risk_at: Option<Severity>,
+    #[serde(default)]
+    rules: Vec<Rule>,
+    #[serde(default)]
+    sandbox: SandboxSection,
+    /// Plugins the operator trusts as safety agents; a manifest cannot grant itself the capability.
+    #[serde(default)]
+    safety_plugins: Vec<String>,
+}
+
+/// Runs commands matching `pattern` under the named sandbox profile.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SandboxAssignment {
+    pub pattern: String,
+    pub profile: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SandboxSection {
+    /// Pick `risky_profile` for commands the guard flags; assignments apply either way.
+    #[serde(default = "default_true")]
+    auto: bool,
+    #[serde(default)]
+    risky_at: Option<Severity>,
+    #[serde(default)]
+    risky_profile: Option<String>,
+    #[serde(default)]
+    profiles: Vec<SandboxProfile>,
+    #[serde(default)]
+    assign: Vec<SandboxAssignment>,
+}
+
+impl Default for SandboxSection {
+    fn default() -> Self {
+        Self { auto: true, risky_at: None, risky_profile: None, profiles: vec![], assign: vec![] }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// The outcome of evaluating a command: the verdict and, if any, the rule that produced it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decision {
+    pub verdict: Verdict,
+    pub rule_id: Option<String>,
+    pub reason: String,
+}
+
+impl Decision {
+    fn allow() -> Self {
+        Self { verdict: Verdict::Allow, rule_id: None, reason: "No rule matched".to_string() }
+    }
+}
+
+struct CompiledRule {
+    rule: Rule,
+    regex: Option<Regex>,
+}
+
+/// An ordered rule set. Rules are evaluated top to bottom and the first match decides.
+pub struct Policy {
+    rules: Vec<CompiledRule>,
+    strictness: Option<Strictness>,
+    deny_risk_at: Severity,
+    source: Option<PathBuf>,
+    sandbox: SandboxRules,
+    safety_plugins: Vec<String>,
+}
+
+// Medium would confine routine `rm -rf build` and `chmod 777` runs
+const DEFAULT_RISKY_AT: Severity = Severity::High;
+
+struct SandboxRules {
+    profiles: Vec<SandboxProfile>,
+    assign: Vec<(Regex, String)>,
+    risky_at: Severity,
+    /// `None` disables automatic sandboxing.
+    risky_profile: Option<String>,
+}
+
+impl Default for SandboxRules {
+    fn default() -> Self {
+        Self { profiles: vec![SandboxProfile::confined()], assign: vec![], risky_at: DEFAULT_RISKY_AT, risky_profile: Some("confined".to_string()) }
+    }
+}
+
+impl Default for Policy {
+    // The guard's historical built-in checks, expressed as data
+    fn default() -> Self {
+        Self::from_rules(vec![
+            Rule {
+                id: "bias_check".to_string(),
+                reason: Some("Potential data disparity".to_string()),
+                kind: RuleKind::RegexDeny { pattern: "bias_inducing_term".to_string() },
+            },
+            Rule {
+                id: "disparity_analysis".to_string(),
+                reason: Some("Disparity detected in command length".to_string()),
+                kind: RuleKind::MaxLength { max: 50 },
+            },
+        ]).expect("Built-in policy compiles")
+    }
+}
+
+impl Policy {
+    pub fn from_rules(rules: Vec<Rule>) -> Result<Self, MatrixError> {
+        let rules = rules
+            .into_iter()
+            .map(|rule| {
+                let pattern = match &rule.kind {
+                    RuleKind::RegexDeny { pattern } | RuleKind::RegexAllow { pattern } | RuleKind::RequireConfirmation { pattern } => Some(pattern),
+                    _ => None,
+                };
+                let regex = pattern
+                    .map(|p| Regex::new(p).map_err(|e| MatrixError::Policy(format!("Rule '{}' has an invalid pattern: {}", rule.id, e))))
+                    .transpose()?;
+                Ok(CompiledRule { rule, regex })
+            })
+            .collect::<Result<Vec<_>, MatrixError>>()?;
+        Ok(Self { rules, strictness: None, deny_risk_at: Severity::High, source: None, sandbox: SandboxRules::default(), safety_plugins: vec![] })
+    }
+
+    /// Parses a policy from TOML, or JSON when the text starts with `{`.
+    pub fn parse(text: &str) -> Result<Self, MatrixError> {
+        let file: PolicyFile = if text.trim_start().starts_with('{') {
+            serde_json::from_str(text).map_err(|e| MatrixError::serialization("Malformed policy JSON", e))?
+        } else {
+            toml::from_str(text).map_err(|e| MatrixError::Policy(format!("Malformed policy TOML: {}", e)))?
+        };
+        let mut policy = Self::from_rules(file.rules)?;
+        policy.strictness = file.strictness;
+        if let Some(severity) = file.deny_risk_at {
+            policy.deny_risk_at = severity;
+        }
+        policy.sandbox = SandboxRules::compile(file.sandbox)?;
+        policy.safety_plugins = file.safety_plugins;
+        Ok(policy)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MatrixError> {
+        let text = std::fs::read_to_string(path).map_err(|e| MatrixError::io(&format!("Cannot read policy {}", path.display()), e))?;
+        let mut policy = Self::parse(&text)?;
+        policy.source = Some(path.to_path_buf());
+        Ok(policy)
+    }
+
+    /// Finds the policy governing `dir`: the nearest `.agent-matrix/policy.{toml,json}` walking
+    /// up from `dir`, then `~/.config/agent-matrix/policy.toml`, then the built-in default.
+    pub fn discover(dir: &Path) -> Result<Self, MatrixError> {
+        for ancestor in dir.ancestors() {
+            for name in ["policy.toml", "policy.json"] {
+                let candidate = ancestor.join(".agent-matrix").join(name);
+                if candidate.is_file() {
+                    return Self::load(&candidate);
+                }
+            }
+        }
+        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+        let user_policy = PathBuf::from(home).join(".config").join("agent-matrix").join("policy.toml");
+        if user_policy.is_file() {
+            return Self::load(&user_policy);
+        }
+        Ok(Self::default())
+    }
+
+    /// The strictness the policy file asks for, if it names one.
+    pub fn strictness(&self) -> Option<Strictness> {
+        self.strictness
+    }
+
+    pub fn source(&self) -> Option<&Path> {
+        self.source.as_deref()
+    }
+
+    pub fn rules(&self) -> impl Iterator<Item = &Rule> {
+        self.rules.iter().map(|r| &r.rule)
+    }
+
+    pub fn deny_risk_at(&self) -> Severity {
+        self.deny_risk_at
+    }
+
+    /// Names of the plugins allowed to act as safety agents.
+    pub fn safety_plugins(&self) -> &[String] {
+        &self.safety_plugins
+    }
+
+    /// Every sandbox profile the policy defines, including the built-in `confined`.
+    pub fn sandbox_profiles(&self) -> &[SandboxProfile] {
+        &self.sandbox.profiles
+    }
+
+    pub fn sandbox_profile(&self, name: &str) -> Option<&SandboxProfile> {
+        self.sandbox.profiles.iter().find(|p| p.name == name)
+    }
+
+    /// The profile `cmd` should run under: the first matching assignment, otherwise the risky
+    /// profile when the guard did not simply allow it or a finding reaches `risky_at`. Commands
+    /// that escalate privileges are never confined automatically: no_new_privs stops sudo outright.
+    pub fn sandbox_for(&self, cmd: &str, findings: &[Finding], decision: &Decision) -> Option<SandboxProfile> {
+        let assigned = self.sandbox.assign.iter().find(|(re, _)| re.is_match(cmd)).map(|(_, name)| name);
+        let escalates = findings.iter().any(|f| f.id == "privilege_escalation");
+        let risky = !escalates && (decision.verdict != Verdict::Allow || findings.iter().any(|f| f.severity >= self.sandbox.risky_at));
+        let name = assigned.or(self.sandbox.risky_profile.as_ref().filter(|_| risky))?;
+        self.sandbox_profile(name).cloned()
+    }
+
+    /// Parses and risk-analyzes `cmd`, then evaluates it. Prefer `evaluate_script` when the
+    /// caller already holds the AST and findings.
+    pub fn evaluate(&self, cmd: &str) -> Decision {
+        let script = shell::parse(cmd).ok();
+        let findings = script.as_ref().map(risk::analyze).unwrap_or_default();
+        let cwd = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/"));
+        self.evaluate_script(cmd, script.as_ref(), &findings, &cwd)
+    }
+
+    /// Rules are checked first; if none matches, the most severe risk finding at or above
+    /// `deny_risk_at` denies the command. `script` is `None` when the command did not parse,
+    /// in which case argv-level rules fail closed. Relative paths resolve against `cwd`.
+    pub fn evaluate_script(&self, cmd: &str, script: Option<&Script>, findings: &[Finding], cwd: &Path) -> Decision {
+        let commands = script.map(|s| s.simple_commands()).unwrap_or_default();
+        for compiled in &self.rules {
+            let rule = &compiled.rule;
+            let argv_rule = matches!(rule.kind, RuleKind::BinaryAllowlist { .. } | RuleKind::PathRestriction { .. });
+            let hit = match &rule.kind {
+                _ if argv_rule && script.is_none() => Some((Verdict::Deny, "command could not be parsed for argv checks".to_string())),
+                RuleKind::RegexDeny { .. } => compiled.regex.as_ref().filter(|re| re.is_match(cmd))
+                    .map(|re| (Verdict::Deny, format!("matches /{}/", re.as_str()))),
+                RuleKind::RegexAllow { .. } => compiled.regex.as_ref().filter(|re| re.is_match(cmd))
+                    .map(|re| (Verdict::Allow, format!("allowed by /{}/", re.as_str()))),
+                RuleKind::RequireConfirmation { .. } => compiled.regex.as_ref().filter(|re| re.is_match(cmd))
+                    .map(|re| (Verdict::Confirm, format!("matches /{}/", re.as_str()))),
+                RuleKind::MaxLength { max } => (cmd.chars().count() > *max)
+                    .then(|| (Verdict::Deny, format!("longer than {} characters", max))),
+                RuleKind::BinaryAllowlist { binaries } => commands
+                    .iter()
+                    .find_map(|c| unlisted_binary(c, binaries, cwd))
+                    .map(|detail| (Verdict::Deny, detail)),
+                RuleKind::PathRestriction { paths } => script
+                    .and_then(|s| restricted_access(s, paths, cwd))
+                    .map(|(arg, p)| (Verdict::Deny, format!("'{}' touches restricted path {}", arg, p))),
+            };
+            if let Some((verdict, detail)) = hit {
+                let reason = match &rule.reason {
+                    Some(reason) => format!("{} ({})", reason, detail),
+                    None => detail,
+                };
+                return Decision { verdict, rule_id: Some(rule.id.clone()), reason };
+            }
+        }
+        match findings.iter().filter(|f| f.severity >= self.deny_risk_at).max_by_key(|f| f.severity) {
+            Some(finding) => Decision {
+                verdict: Verdict::Deny,
+                rule_id: Some(format!("risk:{}", finding.id)),
+                reason: format!("{} risk: {}", finding.severity, finding.message),
+            },
+            None => Decision::allow(),
+        }
+    }
+}
+
+impl SandboxRules {
+    // Profiles in the file override built-ins of the same name; every referenced name must exist
+    fn compile(section: SandboxSection) -> Result<Self, MatrixError> {
+        let mut profiles = section.profiles;
+        if !profiles.iter().any(|p| p.name == "confined") {
+            profiles.push(SandboxProfile::confined());
+        }
+        let known = |name: &str| {
+            if profiles.iter().any(|p| p.name == name) {
+                Ok(())
+            } else {
+                Err(MatrixError::Policy(format!("Unknown sandbox profile '{}'", name)))
+            }
+        };
+        let assign = section
+            .assign
+            .into_iter()
+            .map(|a| {
+                known(&a.profile)?;
+                let regex = Regex::new(&a.pattern).map_err(|e| MatrixError::Policy(format!("Sandbox pattern '{}' is invalid: {}", a.pattern, e)))?;
+                Ok((regex, a.profile))
+            })
+            .collect::<Result<Vec<_>, MatrixError>>()?;
+        let risky_profile = match section.auto {
+            true => Some(section.risky_profile.unwrap_or_else(|| "confined".to_string())),
+            false => None,
+        };
+        if let Some(name) = &risky_profile {
+            known(name)?;
+        }
+        Ok(Self { profiles, assign, risky_at: section.risky_at.unwrap_or(DEFAULT_RISKY_AT), risky_profile })
+    }
+}
+
+// Both the program and whatever a wrapper such as `sudo` runs must be listed. Overriding PATH
+// would change what every bare name resolves to, so that is refused outright
+fn unlisted_binary(command: &SimpleCommand, binaries: &[String], cwd: &Path) -> Option<String> {
+    if command.assignments.iter().any(|(name, _)| name == "PATH") {
+        return Some("command overrides PATH".to_string());
+    }
+    command
+        .argv
+        .first()
+        .into_iter()
+        .chain(risk::effective_argv(command).first())
+        .map(|w| w.text.as_str())
+        .find(|program| !allowlisted(program, binaries, cwd))
+        .map(|program| format!("binary '{}' is not allowlisted", program))
+}
+
+fn allowlisted(program: &str, binaries: &[String], cwd: &Path) -> bool {
+    if binaries.iter().any(|b| b == program) {
+        return true;
+    }
+    // Otherwise compare the files that would actually run, so `/tmp/evil/ls` does not pass as `ls`
+    let Some(target) = resolve_binary(program, cwd) else { return false };
+    binaries.iter().any(|b| resolve_binary(b, cwd).is_some_and(|p| p == target))
+}
+
+// Directory symlinks are resolved but the file name is kept, so multi-call binaries such as
+// busybox do not make every applet interchangeable
+fn resolve_binary(name: &str, cwd: &Path) -> Option<PathBuf> {
+    let path = if name.contains('/') {
+        normalize(&cwd.join(name))
+    } else {
+        let search = std::env::var_os("PATH")?;
+        std::env::split_paths(&search).map(|dir| dir.join(name)).find(|p| p.is_file())?
+    };
+    if !path.is_file() {
+        return None;
+    }
+    Some(std::fs::canonicalize(path.parent()?).ok()?.join(path.file_name()?))
+}
+
+// Relative arguments resolve against the starting directory and every `cd` target seen so far:
+// whether a given `cd` ran (`false && cd /etc`) is not known statically. After a `cd` to an
+// expanded target any relative path could be restricted
+fn restricted_access<'a>(script: &'a Script, paths: &'a [String], cwd: &Path) -> Option<(&'a str, &'a str)> {
+    let mut dirs = vec![normalize(cwd)];
+    let mut anywhere = false;
+    let check = |word: &'a Word, dirs: &[PathBuf], anywhere: bool| {
+        let arg = word.text.as_str();
+        // `--output=/etc/x` and `of=/dev/sda` name a path after the `=`
+        let whole = Some(arg).filter(|a| !a.starts_with('-'));
+        let value = arg.split_once('=').map(|(_, value)| value);
+        whole
+            .into_iter()
+            .chain(value)
+            .find_map(|candidate| {
+                if anywhere && !candidate.starts_with(['/', '~', '$', '`']) {
+                    return paths.first();
+                }
+                paths.iter().find(|p| dirs.iter().any(|dir| may_touch_path(candidate, word, p, dir)))
+            })
+            .map(|p| (arg, p.as_str()))
+    };
+    for command in script.simple_commands() {
+        if let Some(hit) = command.argv.iter().chain(command.redirects.iter().map(|r| &r.target)).find_map(|w| check(w, &dirs, anywhere)) {
+            return Some(hit);
+        }
+        if command.program_name() == Some("cd") {
+            anywhere |= command.argv.get(1).is_some_and(|w| w.glob || !w.variables.is_empty() || !w.substitutions.is_empty());
+            let target = command.argv.get(1).map(|w| expand_home(&w.text)).unwrap_or_else(|| expand_home("~"));
+            let next: Vec<PathBuf> = dirs.iter().map(|dir| normalize(&dir.join(&target))).collect();
+            for dir in next {
+                if !dirs.contains(&dir) && dirs.len() < MAX_TRACKED_DIRS {
+                    dirs.push(dir);
+                }
+            }
+        }
+    }
+    // Redirections on `{ ...; }` and `( ... )` belong to no simple command
+    script.redirects().into_iter().find_map(|r| check(&r.target, &dirs, anywhere))
+}
+
+// A word the shell expands is judged by what it could become, failing closed like the binary
+// allowlist: a glob by matching its pattern against the restricted path, anything with
+// variables or substitutions by the directory its fixed prefix names. `$HOME` is expanded like `~`
+fn may_touch_path(arg: &str, word: &Word, restricted: &str, cwd: &Path) -> bool {
+    let (arg, expanded) = match word.variables.first().filter(|v| *v == "HOME") {
+        Some(_) => match arg.strip_prefix("$HOME").or_else(|| arg.strip_prefix("${HOME}")) {
+            Some(rest) => (format!("~{}", rest), 1),
+            None => (arg.to_string(), 0),
+        },
+        None => (arg.to_string(), 0),
+    };
+    if word.variables.len() > expanded || !word.substitutions.is_empty() {
+        // Nothing fixed to go by: it could be any path at all
+        let Some(end) = arg.find(['$', '`']).filter(|end| *end > 0) else { return true };
+        let prefix = &arg[..end];
+        let dir = normalize(&cwd.join(expand_home(&prefix[..prefix.rfind('/').map_or(0, |i| i + 1)])));
+        let restricted = normalize(&cwd.join(expand_home(restricted)));
+        return restricted.starts_with(&dir) || dir.starts_with(&restricted);
+    }
+    if word.glob {
+        return glob_touches_path(&arg, restricted, cwd);
+    }
+    touches_path(&arg, restricted, cwd)
+}
+
+// Whether some path the pattern matches lies inside `restricted`
+fn glob_touches_path(pattern: &str, restricted: &str, cwd: &Path) -> bool {
+    let pattern = normalize(&cwd.join(expand_home(pattern)));
+    let restricted = normalize(&cwd.join(expand_home(restricted)));
+    let (pattern, restricted): (Vec<_>, Vec<_>) = (pattern.components().collect(), restricted.components().collect());
+    pattern.len() >= restricted.len()
+        && pattern.iter().zip(&restricted).all(|(p, r)| glob_match(&p.as_os_str().to_string_lossy(), &r.as_os_str().to_string_lossy()))
+}
+
+// `*`, `?` and `[...]` (with `!` or `^` negation and ranges) against one path component
+fn glob_match(pattern: &str, name: &str) -> bool {
+    fn matches(p: &[char], n: &[char]) -> bool {
+        match p.first() {
+            None => n.is_empty(),
+            Some('*') => (0..=n.len()).any(|i| matches(&p[1..], &n[i..])),
+            Some('?') => !n.is_empty() && matches(&p[1..], &n[1..]),
+            Some('[') if p.iter().skip(2).any(|c| *c == ']') => {
+                let close = 2 + p[2..].iter().position(|c| *c == ']').expect("checked above");
+                let (negated, set) = match p[1] {
+                    '!' | '^' => (true, &p[2..close]),
+                    _ => (false, &p[1..close]),
+                };
+                let Some(&c) = n.first() else { return false };
+                let mut hit = false;
+                let mut i = 0;
+                while i < set.len() {
+                    if i + 2 < set.len() && set[i + 1] == '-' {
+                        hit |= (set[i]..=set[i + 2]).contains(&c);
+                        i += 3;
+                    } else {
+                        hit |= set[i] == c;
+                        i += 1;
+                    }
+                }
+                hit != negated && matches(&p[close + 1..], &n[1..])
+            },
+            Some(c) => n.first() == Some(c) && matches(&p[1..], &n[1..]),
+        }
+    }
+    let (pattern, name): (Vec<char>, Vec<char>) = (pattern.chars().collect(), name.chars().collect());
+    matches(&pattern, &name)
+}
+
+fn touches_path(arg: &str, restricted: &str, cwd: &Path) -> bool {
+    let target = normalize(&cwd.join(expand_home(arg)));
+    let restricted = normalize(&cwd.join(expand_home(restricted)));
+    if target.starts_with(&restricted) {
+        return true;
+    }
+    // Symlinks can lead into the restricted tree from elsewhere, so compare real locations too
+    match (resolve_existing(&target), resolve_existing(&restricted)) {
+        (Some(target), Some(restricted)) => target.starts_with(restricted),
+        _ => false,
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = std::env::var("HOME").unwrap_or_default();
+            PathBuf::from(format!("{}{}", home, rest))
+        },
+        _ => PathBuf::from(path),
+    }
+}
+
+// Lexical clean-up: `//etc`, `/etc/./x` and `/etc/../etc/x` all become `/etc/...`
+fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => {
+                out.pop();
+            },
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+// Resolves symlinks in the longest existing prefix of an already normalized path
+fn resolve_existing(path: &Path) -> Option<PathBuf> {
+    let mut rest = vec![];
+    for ancestor in path.ancestors() {
+        if let Ok(real) = std::fs::canonicalize(ancestor) {
+            return Some(rest.iter().rev().fold(real, |acc, name| acc.join(name)));
+        }
+        rest.push(ancestor.file_name()?);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(kind: RuleKind) -> Policy {
+        Policy::from_rules(vec![Rule { id: "rule".to_string(), reason: None, kind }]).unwrap()
+    }
+
+    fn verdict_in(policy: &Policy, cmd: &str, cwd: &str) -> Verdict {
+        let script = shell::parse(cmd).ok();
+        let findings = script.as_ref().map(risk::analyze).unwrap_or_default();
+        policy.evaluate_script(cmd, script.as_ref(), &findings, Path::new(cwd)).verdict
+    }
+
+    fn verdict(policy: &Policy, cmd: &str) -> Verdict {
+        verdict_in(policy, cmd, "/home/operator/project")
+    }
+
+    fn allowlist(binaries: &[&str]) -> Policy {
+        policy(RuleKind::BinaryAllowlist { binaries: binaries.iter().map(|b| b.to_string()).collect() })
+    }
+
+    fn restrict(paths: &[&str]) -> Policy {
+        policy(RuleKind::PathRestriction { paths: paths.iter().map(|p| p.to_string()).collect() })
+    }
+
+    #[test]
+    fn first_matching_rule_decides() {
+        let policy = Policy::from_rules(vec![
+            Rule { id: "ok".to_string(), reason: None, kind: RuleKind::RegexAllow { pattern: "^git status$".to_string() } },
+            Rule { id: "no-git".to_string(), reason: None, kind: RuleKind::RegexDeny { pattern: "^git".to_string() } },
+            Rule { id: "push".to_string(), reason: None, kind: RuleKind::RequireConfirmation { pattern: "push".to_string() } },
+        ])
+        .unwrap();
+        assert_eq!(verdict(&policy, "git status"), Verdict::Allow);
+        let decision = policy.evaluate("git push");
+        assert_eq!(decision.verdict, Verdict::Deny);
+        assert_eq!(decision.rule_id.as_deref(), Some("no-git"));
+        assert_eq!(verdict(&policy, "hg push"), Verdict::Confirm);
+        assert_eq!(verdict(&policy, "ls"), Verdict::Allow);
+    }
+
+    #[test]
+    fn max_length_counts_characters() {
+        let policy = policy(RuleKind::MaxLength { max: 5 });
+        assert_eq!(verdict(&policy, "échos"), Verdict::Allow);
+        assert_eq!(verdict(&policy, "echo hi"), Verdict::Deny);
+    }
+
+    #[test]
+    fn risk_findings_deny_at_threshold() {
+        let policy = Policy::from_rules(vec![]).unwrap();
+        let decision = policy.evaluate("rm -rf /");
+        assert_eq!(decision.verdict, Verdict::Deny);
+        assert_eq!(decision.rule_id.as_deref(), Some("risk:recursive_delete_root"));
+        assert_eq!(verdict(&policy, "rm -rf build"), Verdict::Allow);
+    }
+
+    #[test]
+    fn confines_risky_commands_but_not_privileged_ones() {
+        let policy = policy(RuleKind::RequireConfirmation { pattern: "^sudo ".to_string() });
+        let profile = |cmd: &str| {
+            let findings = risk::analyze(&shell::parse(cmd).unwrap());
+            policy.sandbox_for(cmd, &findings, &policy.evaluate(cmd)).map(|p| p.name)
+        };
+        assert_eq!(profile("ls"), None);
+        assert_eq!(profile("rm -rf build"), None);
+        assert_eq!(profile("cp tool /usr/local/bin/").as_deref(), Some("confined"));
+        assert_eq!(profile("sudo apt update"), None);
+        assert_eq!(profile("sudo cp tool /usr/local/bin/"), None);
+    }
+
+    #[test]
+    fn invalid_pattern_is_rejected() {
+        let rules = vec![Rule { id: "bad".to_string(), reason: None, kind: RuleKind::RegexDeny { pattern: "(".to_string() } }];
+        assert!(matches!(Policy::from_rules(rules), Err(MatrixError::Policy(_))));
+    }
+
+    #[test]
+    fn parses_toml_policy() {
+        let policy = Policy::parse(
+            r#"
+            strictness = "moderate"
+            deny_risk_at = "critical"
+
+            [[rules]]
+            id = "no-curl"
+            type = "regex_deny"
+            pattern = "curl"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(policy.strictness(), Some(Strictness::Moderate));
+        assert_eq!(policy.deny_risk_at(), Severity::Critical);
+        assert_eq!(verdict(&policy, "curl example.com"), Verdict::Deny);
+    }
+
+    #[test]
+    fn allowlist_matches_bare_names_only_by_name() {
+        let policy = allowlist(&["ls", "sudo"]);
+        assert_eq!(verdict(&policy, "ls -la"), Verdict::Allow);
+        assert_eq!(verdict(&policy, "/tmp/evil/ls"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "./ls"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "PATH=/tmp/evil ls"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "sudo ls"), Verdict::Allow);
+        assert_eq!(verdict(&policy, "sudo rm -r build"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "ls | sh"), Verdict::Deny);
+    }
+
+    #[test]
+    fn allowlist_accepts_the_path_a_name_resolves_to() {
+        let cwd = Path::new("/");
+        let Some(sh) = resolve_binary("sh", cwd) else { return };
+        let policy = allowlist(&["sh"]);
+        assert_eq!(verdict(&policy, &format!("{} -c true", sh.display())), Verdict::Allow);
+    }
+
+    #[test]
+    fn allowlist_fails_closed_on_unparseable_input() {
+        assert_eq!(verdict(&allowlist(&["echo"]), "echo 'unterminated"), Verdict::Deny);
+    }
+
+    #[test]
+    fn path_restriction_normalizes_paths() {
+        let policy = restrict(&["/etc"]);
+        assert_eq!(verdict(&policy, "cat /etc/shadow"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cat /etc/../etc/shadow"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cat //etc/shadow"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cat /etc/./shadow"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cat /etcetera/notes"), Verdict::Allow);
+        assert_eq!(verdict(&policy, "cat ../../../etc/passwd"), Verdict::Deny);
+    }
+
+    #[test]
+    fn path_restriction_resolves_against_cwd() {
+        let policy = restrict(&["/etc"]);
+        assert_eq!(verdict_in(&policy, "cat shadow", "/etc"), Verdict::Deny);
+        assert_eq!(verdict_in(&policy, "cat notes", "/home/operator"), Verdict::Allow);
+        assert_eq!(verdict(&policy, "cd / && cat etc/shadow"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "false && cd / ; cat etc/shadow"), Verdict::Deny);
+    }
+
+    #[test]
+    fn path_restriction_checks_redirects_and_assignments() {
+        let policy = restrict(&["/etc"]);
+        assert_eq!(verdict(&policy, "echo x 2>/etc/hosts"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "{ echo x; } >> /etc/hosts"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "dd if=/etc/shadow of=out"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "grep --file=/etc/shadow x"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "ls -la"), Verdict::Allow);
+    }
+
+    #[test]
+    fn path_restriction_expands_home() {
+        let home = std::env::var("HOME").unwrap_or_default();
+        if home.is_empty() {
+            return;
+        }
+        let policy = restrict(&["~/.ssh"]);
+        assert_eq!(verdict(&policy, "cat ~/.ssh/id_ed25519"), Verdict::Deny);
+        assert_eq!(verdict(&policy, &format!("cat {}/.ssh/id_ed25519", home)), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cat ~/notes"), Verdict::Allow);
+    }
+
+    #[test]
+    fn path_restriction_fails_closed_on_expansions() {
+        let policy = restrict(&["/etc"]);
+        assert_eq!(verdict(&policy, "cat /e*/shadow"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cat /et?/shadow"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cat /[a-f]tc/shadow"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cat /u*/shadow /[!e]tc/x"), Verdict::Allow);
+        assert_eq!(verdict(&policy, "cat $(echo /etc)/shadow"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cat `echo /etc`/shadow"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cat $DIR/shadow"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cat /e$X/shadow"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cat /home/$USER/notes"), Verdict::Allow);
+        assert_eq!(verdict(&policy, "cat *.txt"), Verdict::Allow);
+        assert_eq!(verdict(&policy, "cat '/e*/shadow'"), Verdict::Allow);
+        assert_eq!(verdict(&policy, "cd $(echo /etc) && cat shadow"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cd /e* && cat shadow"), Verdict::Deny);
+    }
+
+    #[test]
+    fn path_restriction_expands_home_variable() {
+        let home = std::env::var("HOME").unwrap_or_default();
+        if home.is_empty() || home == "/" {
+            return;
+        }
+        let policy = restrict(&["~/.ssh"]);
+        assert_eq!(verdict(&policy, "cat $HOME/.ssh/id_rsa"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cat \"${HOME}/.ssh/id_rsa\""), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cat ~/.s*/id_rsa"), Verdict::Deny);
+        assert_eq!(verdict(&policy, "cat $HOME/notes"), Verdict::Allow);
+    }
+
+    #[test]
+    fn glob_matches_single_components() {
+        assert!(glob_match("e*", "etc"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("?tc", "etc"));
+        assert!(!glob_match("?tc", "tc"));
+        assert!(glob_match("[^a]tc", "etc"));
+        assert!(!glob_match("[!e]tc", "etc"));
+        assert!(glob_match("[", "["));
+    }
+
+    #[test]
+    fn normalize_is_lexical() {
+        assert_eq!(normalize(Path::new("//etc/./a/../b")), PathBuf::from("/etc/b"));
+        assert_eq!(normalize(Path::new("/../..")), PathBuf::from("/"));
+    }
+}
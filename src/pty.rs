@@ -0,0 +1,364 @@
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::process::{ExitStatus, Stdio};
+use std::time::SystemTime;
+use tokio::process::Command;
+use tokio::sync::mpsc::{self, error::TryRecvError};
+use super::error::MatrixError;
+use super::orchestration::{self, ExecutionRecord};
+use super::sandbox::SandboxProfile;
+use super::screen::VirtualTerminal;
+use super::shell::Script;
+
+/// Programs that need a controlling terminal to be usable.
+pub const INTERACTIVE_PROGRAMS: &[&str] = &[
+    "vi", "vim", "nvim", "nano", "emacs", "htop", "top", "less", "more", "man", "ssh", "tmux", "screen", "watch", "git",
+];
+
+/// Interpreters that only need a terminal when started as a REPL, i.e. without arguments.
+pub const REPL_PROGRAMS: &[&str] = &["sh", "bash", "zsh", "python", "python3", "ipython", "node", "irb", "psql", "mysql", "sqlite3"];
+
+/// Whether `script` should run in an embedded terminal rather than with piped output.
+pub fn wants_tty(script: &Script) -> bool {
+    script.simple_commands().iter().any(|c| match c.program_name() {
+        Some(p) => INTERACTIVE_PROGRAMS.contains(&p) || (REPL_PROGRAMS.contains(&p) && c.args().is_empty()),
+        None => false,
+    })
+}
+
+/// One approved command running under its own pseudo-terminal.
+pub struct PtySession {
+    id: u64,
+    command: String,
+    writer: std::fs::File,
+    output: mpsc::UnboundedReceiver<Vec<u8>>,
+    child: tokio::process::Child,
+    terminal: VirtualTerminal,
+    // A PTY merges stdout and stderr, so the receipt digests the one transcript
+    transcript: blake3::Hasher,
+    started_at: SystemTime,
+    sandbox: Option<String>,
+    exit: Option<ExitStatus>,
+    output_closed: bool,
+    finished: bool,
+    scroll: usize,
+}
+
+impl PtySession {
+    pub fn spawn(id: u64, cmd: &str, rows: u16, cols: u16, sandbox: Option<&SandboxProfile>) -> Result<Self, MatrixError> {
+        let (master, slave) = open_pty(rows, cols)?;
+        let slave_io = |fd: &OwnedFd| fd.try_clone().map(Stdio::from).map_err(|e| MatrixError::io("Cannot share the pseudo-terminal", e));
+
+        let child = {
+            let mut command = Command::new("sh");
+            command
+                .arg("-c")
+                .arg(cmd)
+                .env("TERM", "xterm-256color")
+                .stdin(slave_io(&slave)?)
+                .stdout(slave_io(&slave)?)
+                .stderr(Stdio::from(slave))
+                .kill_on_drop(true);
+            // The child starts a new session with the PTY as its controlling terminal
+            unsafe {
+                command.pre_exec(|| {
+                    if libc::setsid() == -1 || libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    Ok(())
+                });
+            }
+            // Hooks run in order, so the sandbox is entered after the session is set up
+            if let Some(profile) = sandbox {
+                profile.prepare()?.install(&mut command);
+            }
+            // Dropping `command` closes our copies of the slave so reads see EOF once the child exits
+            command.spawn().map_err(|e| orchestration::spawn_error(cmd, sandbox.map(|p| p.name.as_str()), e))?
+        };
+
+        let mut reader = std::fs::File::from(master.try_clone().map_err(|e| MatrixError::io("Cannot share the pseudo-terminal", e))?);
+        let (tx, output) = mpsc::unbounded_channel();
+        // Blocking reads on a dedicated thread: the master fd is not registered with the runtime
+        std::thread::spawn(move || {
+            let mut buf = [0u8; 8192];
+            loop {
+                match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        if tx.send(buf[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    },
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    // EIO once every slave descriptor is closed
+                    Err(_) => break,
+                }
+            }
+        });
+
+        Ok(Self {
+            id,
+            command: cmd.to_string(),
+            writer: std::fs::File::from(master),
+            output,
+            child,
+            terminal: VirtualTerminal::new(rows as usize, cols as usize),
+            transcript: blake3::Hasher::new(),
+            started_at: SystemTime::now(),
+            sandbox: sandbox.map(|p| p.name.clone()),
+            exit: None,
+            output_closed: false,
+            finished: false,
+            scroll: 0,
+        })
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    pub fn terminal(&self) -> &VirtualTerminal {
+        &self.terminal
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.exit.is_none()
+    }
+
+    /// Lines scrolled back into history; 0 follows the live screen.
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    pub fn scroll_by(&mut self, delta: isize) {
+        let max = self.terminal.screen().scrollback_len();
+        self.scroll = self.scroll.saturating_add_signed(delta).min(max);
+    }
+
+    /// Sends keystrokes to the program. Writing snaps the view back to the live screen.
+    pub fn write_input(&mut self, bytes: &[u8]) -> Result<(), MatrixError> {
+        self.scroll = 0;
+        self.writer.write_all(bytes).map_err(|e| MatrixError::io("Cannot write to the pseudo-terminal", e))
+    }
+
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        if self.terminal.screen().size() == (rows as usize, cols as usize) {
+            return;
+        }
+        let size = window_size(rows, cols);
+        // Delivers SIGWINCH to the foreground process group
+        unsafe {
+            libc::ioctl(self.writer.as_raw_fd(), libc::TIOCSWINSZ as _, &size);
+        }
+        self.terminal.resize(rows as usize, cols as usize);
+    }
+
+    /// Sends SIGHUP to the session, as closing a terminal window would.
+    pub fn hang_up(&mut self) {
+        if let Some(pid) = self.child.id() {
+            unsafe {
+                libc::kill(-(pid as libc::pid_t), libc::SIGHUP);
+            }
+        }
+    }
+
+    /// Waits for output, feeding it to the screen model, or for the program to exit once its
+    /// output has closed. Cancel-safe; returns at once when there is nothing left to wait for.
+    pub async fn changed(&mut self) {
+        if !self.output_closed {
+            match self.output.recv().await {
+                Some(bytes) => {
+                    self.transcript.update(&bytes);
+                    self.terminal.feed(&bytes);
+                },
+                None => self.output_closed = true,
+            }
+        } else if self.exit.is_none() {
+            // An error surfaces through `try_finish`
+            if let Ok(status) = self.child.wait().await {
+                self.exit = Some(status);
+            }
+        }
+    }
+
+    /// Feeds any pending output to the screen model.
+    pub fn pump(&mut self) {
+        loop {
+            match self.output.try_recv() {
+                Ok(bytes) => {
+                    self.transcript.update(&bytes);
+                    self.terminal.feed(&bytes);
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.output_closed = true;
+                    break;
+                },
+            }
+        }
+    }
+
+    /// The final record once the program has exited and its output is drained. Returns `Some` exactly once.
+    pub fn try_finish(&mut self) -> Option<Result<ExecutionRecord, MatrixError>> {
+        self.pump();
+        if self.finished {
+            return None;
+        }
+        if self.exit.is_none() {
+            match self.child.try_wait() {
+                Ok(Some(status)) => self.exit = Some(status),
+                Ok(None) => return None,
+                Err(e) => {
+                    self.finished = true;
+                    return Some(Err(MatrixError::io(&format!("Lost track of '{}'", self.command), e)));
+                },
+            }
+        }
+        if !self.output_closed {
+            return None;
+        }
+        self.finished = true;
+        Some(Ok(ExecutionRecord {
+            exit_code: self.exit.and_then(|s| s.code()),
+            stdout_digest: self.transcript.finalize().to_hex().to_string(),
+            stderr_digest: blake3::hash(b"").to_hex().to_string(),
+            started_at: self.started_at,
+            finished_at: SystemTime::now(),
+            sandbox: self.sandbox.clone(),
+        }))
+    }
+}
+
+/// Owns every embedded terminal session and tracks which one the TUI shows.
+#[derive(Default)]
+pub struct PtyManager {
+    sessions: Vec<PtySession>,
+    active: Option<u64>,
+    next_id: u64,
+}
+
+impl PtyManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts `cmd` in a new session and makes it the active one.
+    pub fn spawn(&mut self, cmd: &str, rows: u16, cols: u16, sandbox: Option<&SandboxProfile>) -> Result<u64, MatrixError> {
+        self.next_id += 1;
+        let session = PtySession::spawn(self.next_id, cmd, rows, cols, sandbox)?;
+        self.sessions.push(session);
+        self.active = Some(self.next_id);
+        Ok(self.next_id)
+    }
+
+    pub fn sessions(&self) -> &[PtySession] {
+        &self.sessions
+    }
+
+    pub fn get_mut(&mut self, id: u64) -> Option<&mut PtySession> {
+        self.sessions.iter_mut().find(|s| s.id == id)
+    }
+
+    pub fn active(&self) -> Option<&PtySession> {
+        self.active.and_then(|id| self.sessions.iter().find(|s| s.id == id))
+    }
+
+    pub fn active_mut(&mut self) -> Option<&mut PtySession> {
+        self.active.and_then(|id| self.sessions.iter_mut().find(|s| s.id == id))
+    }
+
+    pub fn set_active(&mut self, id: Option<u64>) {
+        self.active = id;
+    }
+
+    /// Drops a session, killing its program if it is still running.
+    pub fn remove(&mut self, id: u64) -> Option<PtySession> {
+        let index = self.sessions.iter().position(|s| s.id == id)?;
+        if self.active == Some(id) {
+            self.active = None;
+        }
+        Some(self.sessions.remove(index))
+    }
+}
+
+fn window_size(rows: u16, cols: u16) -> libc::winsize {
+    libc::winsize { ws_row: rows, ws_col: cols, ws_xpixel: 0, ws_ypixel: 0 }
+}
+
+fn open_pty(rows: u16, cols: u16) -> Result<(OwnedFd, OwnedFd), MatrixError> {
+    let mut master: libc::c_int = -1;
+    let mut slave: libc::c_int = -1;
+    let size = window_size(rows, cols);
+    let rc = unsafe { libc::openpty(&mut master, &mut slave, std::ptr::null_mut(), std::ptr::null(), &size) };
+    if rc != 0 {
+        return Err(MatrixError::io("Cannot allocate a pseudo-terminal", std::io::Error::last_os_error()));
+    }
+    let (master, slave) = unsafe { (OwnedFd::from_raw_fd(master), OwnedFd::from_raw_fd(slave)) };
+    // The child must not inherit the master side, or the PTY never reports EOF
+    unsafe {
+        libc::fcntl(master.as_raw_fd(), libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+    Ok((master, slave))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    async fn finish(session: &mut PtySession) -> ExecutionRecord {
+        let wait = async {
+            loop {
+                if let Some(record) = session.try_finish() {
+                    return record;
+                }
+                session.changed().await;
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(10), wait).await.expect("Program finished").unwrap()
+    }
+
+    #[test]
+    fn repls_and_full_screen_programs_want_a_tty() {
+        let wants = |cmd: &str| wants_tty(&crate::shell::parse(cmd).unwrap());
+        assert!(wants("vim notes.txt"));
+        assert!(wants("python3"));
+        assert!(!wants("python3 script.py"));
+        assert!(!wants("ls -la | grep x"));
+    }
+
+    #[tokio::test]
+    async fn output_reaches_the_screen_and_the_record() {
+        // Nothing to test where no pseudo-terminal can be allocated
+        if open_pty(24, 80).is_err() {
+            return;
+        }
+        let mut session = PtySession::spawn(1, "printf 'hello \\033[1mworld\\n'; exit 3", 24, 80, None).unwrap();
+        let record = finish(&mut session).await;
+
+        assert_eq!(record.exit_code, Some(3));
+        assert_eq!(session.terminal().screen().contents().lines().next(), Some("hello world"));
+        assert!(session.terminal().screen().view(0)[0][6].pen.bold);
+        // The line discipline turns the newline into CRLF
+        assert_eq!(record.stdout_digest, blake3::hash(b"hello \x1b[1mworld\r\n").to_hex().to_string());
+        assert!(!session.is_running());
+        assert!(session.try_finish().is_none());
+    }
+
+    #[tokio::test]
+    async fn input_is_echoed_back_through_the_terminal() {
+        if open_pty(24, 80).is_err() {
+            return;
+        }
+        let mut session = PtySession::spawn(1, "read line; printf 'got %s' \"$line\"", 24, 80, None).unwrap();
+        session.write_input(b"ping\n").unwrap();
+        let record = finish(&mut session).await;
+
+        assert_eq!(record.exit_code, Some(0));
+        assert_eq!(session.terminal().screen().contents().lines().take(2).collect::<Vec<_>>(), vec!["ping", "got ping"]);
+    }
+}
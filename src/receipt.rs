@@ -0,0 +1,297 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::agents::Vote;
+use super::error::MatrixError;
+use super::keystore::restrict_permissions;
+use super::orchestration::ExecutionRecord;
+use super::policy::{Decision, Verdict};
+
+const RECEIPT_VERSION: u8 = 1;
+
+/// The operator's Ed25519 identity. Receipts signed with it attest who approved each run.
+pub struct OperatorKey {
+    operator: String,
+    signing: SigningKey,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OperatorKeyFile {
+    operator: String,
+    secret: Vec<u8>,
+}
+
+impl OperatorKey {
+    pub fn generate(operator: &str) -> Self {
+        let mut secret = [0u8; 32];
+        rand::rng().fill(&mut secret);
+        Self { operator: operator.to_string(), signing: SigningKey::from_bytes(&secret) }
+    }
+
+    /// `~/.config/agent-matrix/operator.key`
+    pub fn default_path() -> PathBuf {
+        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+        PathBuf::from(home).join(".config").join("agent-matrix").join("operator.key")
+    }
+
+    /// Loads the key at `path`, or creates one for the current `$USER` if none exists.
+    pub fn open_or_create(path: &Path) -> Result<Self, MatrixError> {
+        if path.exists() {
+            return Self::load(path);
+        }
+        let operator = std::env::var("USER").unwrap_or_else(|_| "operator".to_string());
+        let key = Self::generate(&operator);
+        key.save(path)?;
+        Ok(key)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MatrixError> {
+        let raw = std::fs::read(path).map_err(|e| MatrixError::io("Cannot read operator key", e))?;
+        let file: OperatorKeyFile = serde_json::from_slice(&raw).map_err(|e| MatrixError::serialization("Malformed operator key", e))?;
+        let secret: [u8; 32] = file.secret.try_into().map_err(|_| MatrixError::Crypto("Operator key has invalid length".to_string()))?;
+        Ok(Self { operator: file.operator, signing: SigningKey::from_bytes(&secret) })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), MatrixError> {
+        let file = OperatorKeyFile { operator: self.operator.clone(), secret: self.signing.to_bytes().to_vec() };
+        let json = serde_json::to_vec_pretty(&file).map_err(|e| MatrixError::serialization("Operator key serialization failed", e))?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| MatrixError::io("Cannot create operator key directory", e))?;
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, json).map_err(|e| MatrixError::io("Cannot write operator key", e))?;
+        restrict_permissions(&tmp)?;
+        std::fs::rename(&tmp, path).map_err(|e| MatrixError::io("Cannot write operator key", e))
+    }
+
+    pub fn operator(&self) -> &str {
+        &self.operator
+    }
+
+    /// Hex-encoded public key, as recorded in receipts.
+    pub fn public_key_hex(&self) -> String {
+        to_hex(self.signing.verifying_key().as_bytes())
+    }
+
+    /// Hex-encoded signature over `message`.
+    pub fn sign_hex(&self, message: &[u8]) -> String {
+        to_hex(&self.signing.sign(message).to_bytes())
+    }
+}
+
+/// Checks a hex-encoded Ed25519 `signature` over `message` against a hex-encoded public key.
+pub fn verify_signature(public_key: &str, message: &[u8], signature: &str) -> Result<(), MatrixError> {
+    let public: [u8; 32] = from_hex(public_key)?
+        .try_into()
+        .map_err(|_| MatrixError::Crypto("Public key has invalid length".to_string()))?;
+    let verifying = VerifyingKey::from_bytes(&public).map_err(|e| MatrixError::Crypto(format!("Invalid public key: {}", e)))?;
+    let signature: [u8; 64] = from_hex(signature)?
+        .try_into()
+        .map_err(|_| MatrixError::Crypto("Signature has invalid length".to_string()))?;
+    verifying
+        .verify(message, &Signature::from_bytes(&signature))
+        .map_err(|_| MatrixError::Integrity("Signature does not verify".to_string()))
+}
+
+/// How the run was approved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Approval {
+    /// The operator confirmed the dry run.
+    DryRunConfirmed,
+    /// The operator released the command from quarantine.
+    QuarantineReleased { id: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GuardVerdict {
+    pub verdict: Verdict,
+    pub rule_id: Option<String>,
+    pub reason: String,
+}
+
+impl From<&Decision> for GuardVerdict {
+    fn from(decision: &Decision) -> Self {
+        Self { verdict: decision.verdict, rule_id: decision.rule_id.clone(), reason: decision.reason.clone() }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AgentVerdict {
+    pub agent: String,
+    pub ok: bool,
+    /// Absent when the agent failed instead of voting, and in receipts that predate votes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vote: Option<Vote>,
+    pub detail: String,
+}
+
+/// The signed statement: what ran, when, with what outcome, and under which verdicts.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReceiptBody {
+    pub version: u8,
+    pub command: String,
+    pub command_hash: String,
+    pub operator: String,
+    pub operator_key: String,
+    pub started_at_ms: u64,
+    pub finished_at_ms: u64,
+    pub exit_code: Option<i32>,
+    pub stdout_digest: String,
+    pub stderr_digest: String,
+    /// Sandbox profile the command ran under. Omitted when unconfined, so older receipts still verify.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<String>,
+    pub approval: Approval,
+    pub guard_verdict: GuardVerdict,
+    pub agent_verdicts: Vec<AgentVerdict>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub body: ReceiptBody,
+    /// Hex-encoded Ed25519 signature over the JSON serialization of `body`.
+    pub signature: String,
+}
+
+impl Receipt {
+    pub fn issue(
+        key: &OperatorKey,
+        command: &str,
+        execution: &ExecutionRecord,
+        approval: Approval,
+        guard_verdict: GuardVerdict,
+        agent_verdicts: Vec<AgentVerdict>,
+    ) -> Result<Self, MatrixError> {
+        let body = ReceiptBody {
+            version: RECEIPT_VERSION,
+            command: command.to_string(),
+            command_hash: blake3::hash(command.as_bytes()).to_hex().to_string(),
+            operator: key.operator.clone(),
+            operator_key: key.public_key_hex(),
+            started_at_ms: unix_ms(execution.started_at),
+            finished_at_ms: unix_ms(execution.finished_at),
+            exit_code: execution.exit_code,
+            stdout_digest: execution.stdout_digest.clone(),
+            stderr_digest: execution.stderr_digest.clone(),
+            sandbox: execution.sandbox.clone(),
+            approval,
+            guard_verdict,
+            agent_verdicts,
+        };
+        let signature = key.signing.sign(&signed_bytes(&body)?);
+        Ok(Self { body, signature: to_hex(&signature.to_bytes()) })
+    }
+
+    /// Checks that the receipt was signed by `trusted_key` and that the command hash matches the
+    /// command. The key embedded in the receipt proves nothing on its own: anyone can sign with theirs.
+    pub fn verify(&self, trusted_key: &str) -> Result<(), MatrixError> {
+        if self.body.version != RECEIPT_VERSION {
+            return Err(MatrixError::Integrity(format!("Unsupported receipt version {}", self.body.version)));
+        }
+        if blake3::hash(self.body.command.as_bytes()).to_hex().as_str() != self.body.command_hash {
+            return Err(MatrixError::Integrity("Receipt command does not match its hash".to_string()));
+        }
+        if !trusted_key.eq_ignore_ascii_case(&self.body.operator_key) {
+            return Err(MatrixError::Integrity(format!("Receipt was signed by {}, not the trusted operator key", self.body.operator_key)));
+        }
+        verify_signature(&self.body.operator_key, &signed_bytes(&self.body)?, &self.signature)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, MatrixError> {
+        let raw = std::fs::read(path).map_err(|e| MatrixError::io(&format!("Cannot read receipt {}", path.display()), e))?;
+        serde_json::from_slice(&raw).map_err(|e| MatrixError::serialization("Malformed receipt", e))
+    }
+
+    /// Writes the receipt into `dir` as `<finished_at_ms>-<command hash prefix>.json`.
+    pub fn save(&self, dir: &Path) -> Result<PathBuf, MatrixError> {
+        std::fs::create_dir_all(dir).map_err(|e| MatrixError::io("Cannot create receipt directory", e))?;
+        let path = dir.join(format!("{}-{}.json", self.body.finished_at_ms, &self.body.command_hash[..16]));
+        let json = serde_json::to_vec_pretty(self).map_err(|e| MatrixError::serialization("Receipt serialization failed", e))?;
+        std::fs::write(&path, json).map_err(|e| MatrixError::io("Cannot write receipt", e))?;
+        Ok(path)
+    }
+}
+
+/// `~/.config/agent-matrix/receipts`
+pub fn default_dir() -> PathBuf {
+    let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+    PathBuf::from(home).join(".config").join("agent-matrix").join("receipts")
+}
+
+fn signed_bytes(body: &ReceiptBody) -> Result<Vec<u8>, MatrixError> {
+    let mut bytes = b"agent-matrix receipt v1\0".to_vec();
+    bytes.extend(serde_json::to_vec(body).map_err(|e| MatrixError::serialization("Receipt serialization failed", e))?);
+    Ok(bytes)
+}
+
+fn unix_ms(at: SystemTime) -> u64 {
+    at.duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(text: &str) -> Result<Vec<u8>, MatrixError> {
+    if !text.len().is_multiple_of(2) {
+        return Err(MatrixError::Crypto("Odd-length hex string".to_string()));
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            text.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| MatrixError::Crypto(format!("Invalid hex at offset {}", i)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn receipt(key: &OperatorKey, command: &str) -> Receipt {
+        let now = SystemTime::now();
+        let execution = ExecutionRecord {
+            exit_code: Some(0),
+            stdout_digest: String::new(),
+            stderr_digest: String::new(),
+            started_at: now,
+            finished_at: now,
+            sandbox: None,
+        };
+        let verdict = GuardVerdict { verdict: Verdict::Allow, rule_id: None, reason: "No rule matched".to_string() };
+        Receipt::issue(key, command, &execution, Approval::DryRunConfirmed, verdict, vec![]).unwrap()
+    }
+
+    #[test]
+    fn verifies_against_the_trusted_key() {
+        let key = OperatorKey::generate("alice");
+        let receipt = receipt(&key, "ls");
+        assert!(receipt.verify(&key.public_key_hex()).is_ok());
+        assert!(receipt.verify(&key.public_key_hex().to_uppercase()).is_ok());
+    }
+
+    #[test]
+    fn rejects_receipts_signed_by_an_untrusted_key() {
+        let trusted = OperatorKey::generate("alice");
+        // A self-consistent receipt: valid signature, but by a key nobody trusts
+        let forged = receipt(&OperatorKey::generate("mallory"), "rm -rf /srv");
+        assert!(matches!(forged.verify(&trusted.public_key_hex()), Err(MatrixError::Integrity(_))));
+    }
+
+    #[test]
+    fn rejects_tampered_receipts() {
+        let key = OperatorKey::generate("alice");
+        let mut tampered = receipt(&key, "ls");
+        tampered.body.exit_code = Some(1);
+        assert!(tampered.verify(&key.public_key_hex()).is_err());
+
+        let mut renamed = receipt(&key, "ls");
+        renamed.body.command = "rm -rf /".to_string();
+        assert!(renamed.verify(&key.public_key_hex()).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use vulkano::instance::Instance as VulkanInstance;
+use super::agents::{Agent, AgentDescriptor, Capability, ComputeAgent, EthicalAgent, InputKind};
+use super::error::MatrixError;
+use super::ethics::EthicalGuard;
+use super::shell;
+
+/// Shared services a built-in agent may need at construction.
+#[derive(Clone)]
+pub struct AgentContext {
+    pub guard: Arc<EthicalGuard>,
+    pub vulkan: Option<Arc<VulkanInstance>>,
+}
+
+type AgentFactory = fn(&AgentContext) -> Result<Arc<dyn Agent>, MatrixError>;
+
+/// Every agent the terminal ships with. A new agent only needs a line here.
+const BUILTIN_AGENTS: &[AgentFactory] = &[
+    |ctx| Ok(Arc::new(EthicalAgent::new(ctx.guard.clone())?)),
+    |ctx| Ok(Arc::new(ComputeAgent::new(ctx.vulkan.clone()))),
+];
+
+/// Default ceiling on the summed cost of the optional agents routed to one task.
+pub const DEFAULT_COST_BUDGET: u32 = 50;
+
+/// The agents available to the matrix, keyed by descriptor name.
+pub struct AgentRegistry {
+    agents: Vec<(AgentDescriptor, Arc<dyn Agent>)>,
+    budget: u32,
+}
+
+impl Default for AgentRegistry {
+    fn default() -> Self {
+        Self { agents: vec![], budget: DEFAULT_COST_BUDGET }
+    }
+}
+
+impl AgentRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry holding every built-in agent.
+    pub fn builtin(ctx: &AgentContext) -> Result<Self, MatrixError> {
+        let mut registry = Self::new();
+        for factory in BUILTIN_AGENTS {
+            registry.register(factory(ctx)?)?;
+        }
+        Ok(registry)
+    }
+
+    /// Adds an agent; its descriptor is read once, here.
+    pub fn register(&mut self, agent: Arc<dyn Agent>) -> Result<(), MatrixError> {
+        let descriptor = agent.descriptor();
+        if self.get(&descriptor.name).is_some() {
+            return Err(MatrixError::Registry(format!("An agent named '{}' is already registered", descriptor.name)));
+        }
+        if descriptor.inputs.is_empty() {
+            return Err(MatrixError::Registry(format!("Agent '{}' accepts no input kinds", descriptor.name)));
+        }
+        self.agents.push((descriptor, agent));
+        Ok(())
+    }
+
+    pub fn set_budget(&mut self, budget: u32) {
+        self.budget = budget;
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn Agent>> {
+        self.agents.iter().find(|(d, _)| d.name == name).map(|(_, a)| a)
+    }
+
+    pub fn descriptors(&self) -> impl Iterator<Item = &AgentDescriptor> {
+        self.agents.iter().map(|(d, _)| d)
+    }
+
+    pub fn len(&self) -> usize {
+        self.agents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.agents.is_empty()
+    }
+
+    /// Picks the agents to invoke for `task`, in registration order. Safety agents that accept
+    /// the task always run; the rest must accept its input kind and, if they specialize, one
+    /// of the programs it runs. Optional agents are then admitted cheapest first while their
+    /// summed cost stays within the budget.
+    pub fn route(&self, task: &str) -> Vec<Arc<dyn Agent>> {
+        let script = shell::parse(task).ok();
+        let kind = if script.is_some() { InputKind::ShellCommand } else { InputKind::NaturalLanguage };
+        let programs: HashSet<String> = script
+            .iter()
+            .flat_map(|s| s.simple_commands())
+            .filter_map(|c| c.program_name().map(str::to_string))
+            .collect();
+
+        let eligible: Vec<&(AgentDescriptor, Arc<dyn Agent>)> = self
+            .agents
+            .iter()
+            .filter(|(d, _)| d.inputs.contains(&kind))
+            .filter(|(d, _)| d.has(&Capability::Safety) || d.programs.is_empty() || d.programs.iter().any(|p| programs.contains(p)))
+            .collect();
+
+        let mut optional: Vec<&AgentDescriptor> = eligible.iter().map(|(d, _)| d).filter(|d| !d.has(&Capability::Safety)).collect();
+        optional.sort_by_key(|d| d.cost);
+        let mut spent = 0u32;
+        let admitted: HashSet<&str> = optional
+            .into_iter()
+            .take_while(|d| {
+                spent = spent.saturating_add(d.cost);
+                spent <= self.budget
+            })
+            .map(|d| d.name.as_str())
+            .collect();
+
+        eligible
+            .into_iter()
+            .filter(|(d, _)| d.has(&Capability::Safety) || admitted.contains(d.name.as_str()))
+            .map(|(_, a)| a.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::AgentOutput;
+    use async_trait::async_trait;
+
+    struct Described(AgentDescriptor);
+
+    #[async_trait]
+    impl Agent for Described {
+        fn descriptor(&self) -> AgentDescriptor {
+            self.0.clone()
+        }
+
+        async fn execute(&self, _task: &str) -> Result<AgentOutput, MatrixError> {
+            Ok(AgentOutput::abstain(self.0.name.clone()))
+        }
+    }
+
+    fn registry(budget: u32, descriptors: Vec<AgentDescriptor>) -> AgentRegistry {
+        let mut registry = AgentRegistry::new();
+        registry.set_budget(budget);
+        for descriptor in descriptors {
+            registry.register(Arc::new(Described(descriptor))).unwrap();
+        }
+        registry
+    }
+
+    fn routed(registry: &AgentRegistry, task: &str) -> Vec<String> {
+        registry.route(task).iter().map(|a| a.descriptor().name).collect()
+    }
+
+    #[test]
+    fn optional_agents_are_admitted_cheapest_first_within_the_budget() {
+        let registry = registry(
+            10,
+            vec![
+                AgentDescriptor::new("review", &[Capability::Summary], 8),
+                AgentDescriptor::new("lint", &[Capability::Testing], 3),
+                AgentDescriptor::new("docs", &[Capability::Docs], 5),
+            ],
+        );
+        // Registration order is kept; the most expensive agent no longer fits
+        assert_eq!(routed(&registry, "cargo test"), vec!["lint", "docs"]);
+
+        let registry = registry_with_costs(10, &[5, 5, 1]);
+        assert_eq!(routed(&registry, "ls"), vec!["agent-0", "agent-2"]);
+        // Spending exactly the budget is allowed
+        let registry = registry_with_costs(10, &[5, 5]);
+        assert_eq!(routed(&registry, "ls"), vec!["agent-0", "agent-1"]);
+    }
+
+    fn registry_with_costs(budget: u32, costs: &[u32]) -> AgentRegistry {
+        registry(budget, costs.iter().enumerate().map(|(i, cost)| AgentDescriptor::new(&format!("agent-{}", i), &[Capability::Compute], *cost)).collect())
+    }
+
+    #[test]
+    fn safety_agents_run_whatever_the_budget() {
+        let registry = registry(
+            0,
+            vec![
+                AgentDescriptor::new("guard", &[Capability::Safety], 100),
+                AgentDescriptor::new("cheap", &[Capability::Compute], 1),
+                // Specializing does not narrow what a safety agent sees
+                AgentDescriptor::new("git-guard", &[Capability::Safety], 1).programs(&["git"]),
+                // But it must still be able to read the task
+                AgentDescriptor::new("nl-guard", &[Capability::Safety], 1).inputs(&[InputKind::NaturalLanguage]),
+            ],
+        );
+        assert_eq!(routed(&registry, "rm -rf build"), vec!["guard", "git-guard"]);
+        assert_eq!(routed(&registry, "what's in this directory"), vec!["guard", "git-guard", "nl-guard"]);
+    }
+
+    #[test]
+    fn exhausted_budget_admits_no_optional_agent() {
+        let registry = registry_with_costs(3, &[4, 7]);
+        assert!(routed(&registry, "ls").is_empty());
+        let registry = registry_with_costs(5, &[3, 3]);
+        assert_eq!(routed(&registry, "ls"), vec!["agent-0"]);
+    }
+
+    #[test]
+    fn agents_match_input_kind_and_programs() {
+        let registry = registry(
+            DEFAULT_COST_BUDGET,
+            vec![
+                AgentDescriptor::new("git", &[Capability::Git], 1).programs(&["git"]),
+                AgentDescriptor::new("shell", &[Capability::Compute], 1).inputs(&[InputKind::ShellCommand]),
+                AgentDescriptor::new("chat", &[Capability::Summary], 1).inputs(&[InputKind::NaturalLanguage]),
+            ],
+        );
+        assert_eq!(routed(&registry, "git status | less"), vec!["git", "shell"]);
+        assert_eq!(routed(&registry, "ls -la"), vec!["shell"]);
+        assert_eq!(routed(&registry, "what's using port 80"), vec!["chat"]);
+    }
+
+    #[test]
+    fn register_rejects_duplicates_and_agents_without_inputs() {
+        let mut registry = registry(DEFAULT_COST_BUDGET, vec![AgentDescriptor::new("one", &[], 1)]);
+        assert!(matches!(registry.register(Arc::new(Described(AgentDescriptor::new("one", &[], 1)))), Err(MatrixError::Registry(_))));
+        assert!(matches!(registry.register(Arc::new(Described(AgentDescriptor::new("two", &[], 1).inputs(&[])))), Err(MatrixError::Registry(_))));
+        assert_eq!(registry.len(), 1);
+    }
+}
@@ -0,0 +1,485 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::Mutex;
+use super::agents::{orchestrate, Agent, AgentDescriptor, AgentOutput, Capability, Consensus, DEFAULT_AGENT_DEADLINE};
+use super::registry::AgentRegistry;
+use super::envelope::Envelope;
+use super::error::MatrixError;
+use super::keystore::restrict_permissions;
+use super::receipt::{verify_signature, OperatorKey};
+use super::session::{self, ClientHello, Initiator, ServerHello, Session};
+
+// Frames are length-prefixed (u32 big-endian); anything larger is treated as hostile
+const MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+/// Time allowed for key exchange and peer authentication before the connection is dropped.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// A connected peer that sends no request for this long is disconnected.
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+/// Longest the daemon spends on one task, however slow the agents it routes to say they are.
+pub const REMOTE_TASK_LIMIT: Duration = Duration::from_secs(60);
+// The client waits a little longer than the daemon, so the daemon's own timeout is what it hears
+const REMOTE_DEADLINE_MARGIN: Duration = Duration::from_secs(5);
+// Distinct labels so a client's proof can never be replayed as the daemon's, or vice versa
+const CLIENT_AUTH_LABEL: &[u8] = b"agent-matrix peer auth v1: client\0";
+const DAEMON_AUTH_LABEL: &[u8] = b"agent-matrix peer auth v1: daemon\0";
+
+/// Where an `agent-matrix serve` daemon listens: `unix:/path/to.sock` or `tcp:host:port`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Unix(PathBuf),
+    Tcp(String),
+}
+
+impl std::str::FromStr for Endpoint {
+    type Err = MatrixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            Ok(Endpoint::Unix(PathBuf::from(path)))
+        } else if let Some(addr) = s.strip_prefix("tcp:") {
+            Ok(Endpoint::Tcp(addr.to_string()))
+        } else {
+            Err(MatrixError::Remote(format!("Unrecognized endpoint '{}': expected unix:<path> or tcp:<host:port>", s)))
+        }
+    }
+}
+
+impl Endpoint {
+    /// `unix:$XDG_RUNTIME_DIR/agent-matrix.sock`, or `~/.config/agent-matrix/agent-matrix.sock`
+    /// when there is no per-user runtime directory.
+    pub fn default_local() -> Self {
+        let dir = match std::env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => {
+                let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+                PathBuf::from(home).join(".config").join("agent-matrix")
+            }
+        };
+        Endpoint::Unix(dir.join("agent-matrix.sock"))
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+            Endpoint::Tcp(addr) => write!(f, "tcp:{}", addr),
+        }
+    }
+}
+
+/// Ed25519 identity keys this end will talk to: one hex key per line, `#` starts a comment.
+/// The daemon refuses clients whose operator key is not listed, and clients refuse daemons likewise.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedPeers {
+    keys: HashSet<String>,
+}
+
+impl TrustedPeers {
+    /// `~/.config/agent-matrix/trusted_peers`
+    pub fn default_path() -> PathBuf {
+        let home = std::env::var("HOME").unwrap_or_else(|_| ".".to_string());
+        PathBuf::from(home).join(".config").join("agent-matrix").join("trusted_peers")
+    }
+
+    /// A missing file trusts nobody, so every remote connection is refused until keys are added.
+    pub fn load(path: &Path) -> Result<Self, MatrixError> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Ok(Self::from_keys(text.lines().filter_map(|line| line.split('#').next()?.split_whitespace().next()))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(MatrixError::io(&format!("Cannot read {}", path.display()), e)),
+        }
+    }
+
+    pub fn from_keys<'a>(keys: impl IntoIterator<Item = &'a str>) -> Self {
+        Self { keys: keys.into_iter().map(|k| k.to_ascii_lowercase()).collect() }
+    }
+
+    pub fn trusts(&self, public_key: &str) -> bool {
+        self.keys.contains(&public_key.to_ascii_lowercase())
+    }
+}
+
+/// Identity this end presents during the handshake and the peers it accepts in return.
+#[derive(Clone)]
+pub struct PeerIdentity {
+    pub key: Arc<OperatorKey>,
+    pub trusted: Arc<TrustedPeers>,
+}
+
+// Proof that the sender holds its identity key, bound to this session's transcript so a relay
+// that ran separate handshakes with each side cannot forward it
+#[derive(Serialize, Deserialize)]
+struct PeerAuth {
+    public_key: String,
+    signature: String,
+}
+
+impl PeerIdentity {
+    fn prove(&self, label: &[u8], session: &Session) -> PeerAuth {
+        let message = [label, session.transcript_hash().as_slice()].concat();
+        PeerAuth { public_key: self.key.public_key_hex(), signature: self.key.sign_hex(&message) }
+    }
+
+    fn check(&self, auth: &PeerAuth, label: &[u8], session: &Session) -> Result<(), MatrixError> {
+        if !self.trusted.trusts(&auth.public_key) {
+            return Err(MatrixError::Remote(format!("Peer key {} is not in trusted_peers", auth.public_key)));
+        }
+        let message = [label, session.transcript_hash().as_slice()].concat();
+        verify_signature(&auth.public_key, &message, &auth.signature)
+            .map_err(|e| MatrixError::Remote(format!("Peer {} failed authentication: {}", auth.public_key, e)))
+    }
+}
+
+trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+#[derive(Serialize, Deserialize)]
+struct RemoteRequest {
+    task: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RemoteResponse {
+    result: Result<AgentOutput, String>,
+}
+
+async fn write_frame<S: AsyncWrite + Unpin>(stream: &mut S, payload: &[u8]) -> Result<(), MatrixError> {
+    stream.write_all(&(payload.len() as u32).to_be_bytes()).await.map_err(|e| MatrixError::transport("Socket write failed", e))?;
+    stream.write_all(payload).await.map_err(|e| MatrixError::transport("Socket write failed", e))?;
+    stream.flush().await.map_err(|e| MatrixError::transport("Socket write failed", e))
+}
+
+async fn read_frame<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>, MatrixError> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).await.map_err(|e| MatrixError::transport("Socket read failed", e))?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(MatrixError::Remote(format!("Frame of {} bytes exceeds limit", len)));
+    }
+    let mut payload = vec![0u8; len];
+    stream.read_exact(&mut payload).await.map_err(|e| MatrixError::transport("Socket read failed", e))?;
+    Ok(payload)
+}
+
+async fn send_sealed<S: AsyncWrite + Unpin, T: Serialize>(stream: &mut S, session: &mut Session, message: &T) -> Result<(), MatrixError> {
+    let plaintext = serde_json::to_vec(message).map_err(|e| MatrixError::serialization("Serialization failed", e))?;
+    let envelope = session.seal(&plaintext).await;
+    write_frame(stream, &envelope.to_bytes()).await
+}
+
+async fn recv_sealed<S: AsyncRead + Unpin, T: for<'de> Deserialize<'de>>(stream: &mut S, session: &mut Session) -> Result<T, MatrixError> {
+    let frame = read_frame(stream).await?;
+    let envelope = Envelope::from_bytes(&frame)?;
+    let plaintext = session.open(&envelope).await?;
+    serde_json::from_slice(&plaintext).map_err(|e| MatrixError::serialization("Malformed message", e))
+}
+
+// Initiator side: key exchange, then each end proves its identity inside the new session
+async fn client_handshake<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, identity: &PeerIdentity) -> Result<Session, MatrixError> {
+    let (initiator, hello) = Initiator::start();
+    let hello = serde_json::to_vec(&hello).map_err(|e| MatrixError::serialization("Serialization failed", e))?;
+    write_frame(stream, &hello).await?;
+    let reply: ServerHello = serde_json::from_slice(&read_frame(stream).await?)
+        .map_err(|e| MatrixError::serialization("Malformed handshake reply", e))?;
+    let mut session = initiator.finish(&reply)?;
+
+    let proof = identity.prove(CLIENT_AUTH_LABEL, &session);
+    send_sealed(stream, &mut session, &proof).await?;
+    let daemon: PeerAuth = recv_sealed(stream, &mut session).await?;
+    identity.check(&daemon, DAEMON_AUTH_LABEL, &session)?;
+    Ok(session)
+}
+
+async fn daemon_handshake<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut S, identity: &PeerIdentity) -> Result<Session, MatrixError> {
+    let hello: ClientHello = serde_json::from_slice(&read_frame(stream).await?)
+        .map_err(|e| MatrixError::serialization("Malformed handshake", e))?;
+    let (mut session, reply) = session::respond(&hello)?;
+    let reply = serde_json::to_vec(&reply).map_err(|e| MatrixError::serialization("Serialization failed", e))?;
+    write_frame(stream, &reply).await?;
+
+    // The client is checked before the daemon reveals anything about itself
+    let client: PeerAuth = recv_sealed(stream, &mut session).await?;
+    identity.check(&client, CLIENT_AUTH_LABEL, &session)?;
+    let proof = identity.prove(DAEMON_AUTH_LABEL, &session);
+    send_sealed(stream, &mut session, &proof).await?;
+    Ok(session)
+}
+
+fn handshake_timed_out() -> MatrixError {
+    MatrixError::Remote(format!("Handshake not completed within {:?}", HANDSHAKE_TIMEOUT))
+}
+
+struct Connection {
+    stream: Box<dyn Transport>,
+    session: Session,
+}
+
+/// An `Agent` whose work happens in a remote `agent-matrix serve` daemon.
+pub struct RemoteAgent {
+    endpoint: Endpoint,
+    identity: PeerIdentity,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl RemoteAgent {
+    pub fn new(endpoint: Endpoint, identity: PeerIdentity) -> Self {
+        Self { endpoint, identity, connection: Mutex::new(None) }
+    }
+
+    pub fn endpoint(&self) -> &Endpoint {
+        &self.endpoint
+    }
+
+    async fn connect(&self) -> Result<Connection, MatrixError> {
+        let context = format!("Cannot reach {}", self.endpoint);
+        let mut stream: Box<dyn Transport> = match &self.endpoint {
+            Endpoint::Unix(path) => Box::new(UnixStream::connect(path).await.map_err(|e| MatrixError::transport(&context, e))?),
+            Endpoint::Tcp(addr) => Box::new(TcpStream::connect(addr).await.map_err(|e| MatrixError::transport(&context, e))?),
+        };
+        let session = tokio::time::timeout(HANDSHAKE_TIMEOUT, client_handshake(&mut stream, &self.identity))
+            .await
+            .map_err(|_| handshake_timed_out())??;
+        Ok(Connection { stream, session })
+    }
+}
+
+#[async_trait]
+impl Agent for RemoteAgent {
+    // The daemon routes the task among its own agents, so this stands in for all of them
+    fn descriptor(&self) -> AgentDescriptor {
+        AgentDescriptor::new(&format!("remote:{}", self.endpoint), &[Capability::Compute], 20).deadline(REMOTE_TASK_LIMIT + REMOTE_DEADLINE_MARGIN)
+    }
+
+    async fn execute(&self, task: &str) -> Result<AgentOutput, MatrixError> {
+        let mut guard = self.connection.lock().await;
+        // Out of the slot until the reply is in: if this call is dropped mid-exchange (a dry-run
+        // timeout, a cancel), the connection goes with it instead of handing this task's late
+        // reply to the next one. Any failure likewise makes the next call renegotiate
+        let mut conn = match guard.take() {
+            Some(conn) => conn,
+            None => self.connect().await?,
+        };
+        send_sealed(&mut conn.stream, &mut conn.session, &RemoteRequest { task: task.to_string() }).await?;
+        let response: RemoteResponse = recv_sealed(&mut conn.stream, &mut conn.session).await?;
+        *guard = Some(conn);
+        response.result.map_err(MatrixError::Remote)
+    }
+}
+
+async fn handle_connection<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, registry: Arc<AgentRegistry>, identity: PeerIdentity) -> Result<(), MatrixError> {
+    let mut session = tokio::time::timeout(HANDSHAKE_TIMEOUT, daemon_handshake(&mut stream, &identity))
+        .await
+        .map_err(|_| handshake_timed_out())??;
+
+    loop {
+        let request: RemoteRequest = match tokio::time::timeout(IDLE_TIMEOUT, recv_sealed(&mut stream, &mut session)).await {
+            Ok(Ok(request)) => request,
+            // Peer hung up between requests, or went quiet for too long
+            Ok(Err(MatrixError::Transport { source, .. })) if source.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Ok(Err(e)) => return Err(e),
+            Err(_) => return Ok(()),
+        };
+        // The daemon answers as one agent: any local deny denies. Errors cross the wire as
+        // text; the client surfaces them as `MatrixError::Remote`
+        let agents = registry.route(&request.task);
+        let limit = task_limit(&agents);
+        let result = match tokio::time::timeout(limit, orchestrate(agents, &request.task, Consensus::Unanimous)).await {
+            Ok(outcome) => outcome.map(|tally| tally.into_output()).map_err(|e| e.to_string()),
+            Err(_) => Err(MatrixError::AgentTimeout { agent: "remote matrix".to_string(), after: limit }.to_string()),
+        };
+        send_sealed(&mut stream, &mut session, &RemoteResponse { result }).await?;
+    }
+}
+
+// As long as the slowest routed agent may take, up to `REMOTE_TASK_LIMIT`
+fn task_limit(agents: &[Arc<dyn Agent>]) -> Duration {
+    agents.iter().map(|agent| agent.descriptor().time_limit()).max().unwrap_or(DEFAULT_AGENT_DEADLINE).min(REMOTE_TASK_LIMIT)
+}
+
+// A stale socket from a previous run would make bind fail, but a live daemon's socket or
+// anything that is not a socket at all is left alone
+fn remove_stale_socket(path: &Path) -> Result<(), MatrixError> {
+    use std::os::unix::fs::FileTypeExt;
+    match std::fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Err(MatrixError::Remote(format!("Another daemon is already listening on {}", path.display())));
+            }
+            std::fs::remove_file(path).map_err(|e| MatrixError::io(&format!("Cannot remove stale socket {}", path.display()), e))
+        },
+        Ok(_) => Err(MatrixError::Remote(format!("{} exists and is not a socket; refusing to replace it", path.display()))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(MatrixError::io(&format!("Cannot inspect {}", path.display()), e)),
+    }
+}
+
+/// Runs the `agent-matrix serve` daemon, answering each task with the agents the registry routes it to.
+/// Only clients whose key is in `identity.trusted` get past the handshake.
+pub async fn serve(endpoint: &Endpoint, registry: Arc<AgentRegistry>, identity: PeerIdentity) -> Result<(), MatrixError> {
+    match endpoint {
+        Endpoint::Unix(path) => {
+            if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                std::fs::create_dir_all(parent).map_err(|e| MatrixError::io("Cannot create socket directory", e))?;
+            }
+            remove_stale_socket(path)?;
+            let listener = UnixListener::bind(path).map_err(|e| MatrixError::transport(&format!("Cannot bind {}", endpoint), e))?;
+            restrict_permissions(path)?;
+            println!("🛰️  Agent Matrix serving on {} as {}", endpoint, identity.key.public_key_hex());
+            loop {
+                let (stream, _) = listener.accept().await.map_err(|e| MatrixError::transport("Accept failed", e))?;
+                let registry = registry.clone();
+                let identity = identity.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, registry, identity).await {
+                        eprintln!("⚠️  Remote session ended: {}", e);
+                    }
+                });
+            }
+        },
+        Endpoint::Tcp(addr) => {
+            let listener = TcpListener::bind(addr).await.map_err(|e| MatrixError::transport(&format!("Cannot bind {}", endpoint), e))?;
+            println!("🛰️  Agent Matrix serving on {} as {}", endpoint, identity.key.public_key_hex());
+            loop {
+                let (stream, peer) = listener.accept().await.map_err(|e| MatrixError::transport("Accept failed", e))?;
+                let registry = registry.clone();
+                let identity = identity.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, registry, identity).await {
+                        eprintln!("⚠️  Remote session with {} ended: {}", peer, e);
+                    }
+                });
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity(key: OperatorKey, trusted: &[String]) -> PeerIdentity {
+        PeerIdentity { key: Arc::new(key), trusted: Arc::new(TrustedPeers::from_keys(trusted.iter().map(String::as_str))) }
+    }
+
+    async fn handshake(client: PeerIdentity, daemon: PeerIdentity) -> (Result<Session, MatrixError>, Result<Session, MatrixError>) {
+        let (mut a, mut b) = tokio::io::duplex(64 * 1024);
+        tokio::join!(
+            async move { client_handshake(&mut a, &client).await },
+            async move { daemon_handshake(&mut b, &daemon).await },
+        )
+    }
+
+    #[tokio::test]
+    async fn mutually_trusted_peers_share_a_session() {
+        let (client_key, daemon_key) = (OperatorKey::generate("alice"), OperatorKey::generate("daemon"));
+        let (client_public, daemon_public) = (client_key.public_key_hex(), daemon_key.public_key_hex());
+        let client = identity(client_key, &[daemon_public]);
+        let daemon = identity(daemon_key, &[client_public]);
+
+        let (client, daemon) = handshake(client, daemon).await;
+        assert_eq!(client.unwrap().id(), daemon.unwrap().id());
+    }
+
+    #[tokio::test]
+    async fn daemon_refuses_unknown_client() {
+        let daemon_key = OperatorKey::generate("daemon");
+        let client = identity(OperatorKey::generate("mallory"), &[daemon_key.public_key_hex()]);
+        let daemon = identity(daemon_key, &[]);
+
+        let (client, daemon) = handshake(client, daemon).await;
+        assert!(matches!(daemon, Err(MatrixError::Remote(_))));
+        assert!(client.is_err());
+    }
+
+    #[tokio::test]
+    async fn client_refuses_unpinned_daemon() {
+        let client_key = OperatorKey::generate("alice");
+        let daemon = identity(OperatorKey::generate("impostor"), &[client_key.public_key_hex()]);
+        let client = identity(client_key, &[]);
+
+        let (client, _) = handshake(client, daemon).await;
+        assert!(matches!(client, Err(MatrixError::Remote(_))));
+    }
+
+    #[tokio::test]
+    async fn abandoned_request_does_not_answer_the_next() {
+        let path = std::env::temp_dir().join(format!("agent-matrix-remote-cancel-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let (client_key, daemon_key) = (OperatorKey::generate("alice"), OperatorKey::generate("daemon"));
+        let (client_public, daemon_public) = (client_key.public_key_hex(), daemon_key.public_key_hex());
+        let client = identity(client_key, &[daemon_public]);
+        let daemon = identity(daemon_key, &[client_public]);
+
+        // Echoes each task back as the rationale, taking its time over "slow"
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let daemon = daemon.clone();
+                tokio::spawn(async move {
+                    let mut session = daemon_handshake(&mut stream, &daemon).await.unwrap();
+                    while let Ok(request) = recv_sealed::<_, RemoteRequest>(&mut stream, &mut session).await {
+                        if request.task == "slow" {
+                            tokio::time::sleep(Duration::from_millis(300)).await;
+                        }
+                        let response = RemoteResponse { result: Ok(AgentOutput::approve(1.0, request.task)) };
+                        if send_sealed(&mut stream, &mut session, &response).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        let agent = RemoteAgent::new(Endpoint::Unix(path.clone()), client);
+        assert!(tokio::time::timeout(Duration::from_millis(50), agent.execute("slow")).await.is_err());
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(agent.execute("fast").await.unwrap().rationale, "fast");
+        assert_eq!(agent.execute("again").await.unwrap().rationale, "again");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn remote_deadline_outlasts_the_daemon() {
+        let agent = RemoteAgent::new(Endpoint::default_local(), identity(OperatorKey::generate("alice"), &[]));
+        assert!(agent.descriptor().time_limit() > REMOTE_TASK_LIMIT);
+        assert_eq!(task_limit(&[]), DEFAULT_AGENT_DEADLINE);
+    }
+
+    #[test]
+    fn trusted_peers_file_ignores_comments_and_case() {
+        let dir = std::env::temp_dir().join(format!("agent-matrix-peers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("trusted_peers");
+        std::fs::write(&path, "# laptop\nABCDEF  alice@laptop\n\n  0123 # build box\n").unwrap();
+
+        let peers = TrustedPeers::load(&path).unwrap();
+        assert!(peers.trusts("abcdef"));
+        assert!(peers.trusts("0123"));
+        assert!(!peers.trusts("alice@laptop"));
+        assert!(!TrustedPeers::load(&dir.join("missing")).unwrap().trusts("abcdef"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_unlink_non_socket() {
+        let path = std::env::temp_dir().join(format!("agent-matrix-not-a-socket-{}", std::process::id()));
+        std::fs::write(&path, b"precious").unwrap();
+        assert!(remove_stale_socket(&path).is_err());
+        assert!(path.exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn parses_endpoints() {
+        assert_eq!("unix:/run/a.sock".parse::<Endpoint>().unwrap(), Endpoint::Unix(PathBuf::from("/run/a.sock")));
+        assert_eq!("tcp:127.0.0.1:7000".parse::<Endpoint>().unwrap(), Endpoint::Tcp("127.0.0.1:7000".to_string()));
+        assert!("/tmp/a.sock".parse::<Endpoint>().is_err());
+    }
+}
@@ -0,0 +1,504 @@
+use serde::{Deserialize, Serialize};
+use super::shell::{self, Command, Redirect, RedirectOp, Script, SimpleCommand, Span, Word};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Low => write!(f, "LOW"),
+            Severity::Medium => write!(f, "MEDIUM"),
+            Severity::High => write!(f, "HIGH"),
+            Severity::Critical => write!(f, "CRITICAL"),
+        }
+    }
+}
+
+/// A risky construct found in a parsed command, with the span the TUI should highlight.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub id: &'static str,
+    pub severity: Severity,
+    pub message: String,
+    pub span: Span,
+}
+
+const DOWNLOADERS: &[&str] = &["curl", "wget", "fetch", "aria2c"];
+const INTERPRETERS: &[&str] = &["sh", "bash", "zsh", "dash", "ksh", "fish", "python", "python3", "perl", "ruby", "node", "php"];
+const SHELLS: &[&str] = &["sh", "bash", "zsh", "dash", "ksh"];
+const SYSTEM_PATHS: &[&str] = &["/etc", "/boot", "/usr", "/bin", "/sbin", "/lib", "/lib64", "/sys", "/proc"];
+// Fallback prefixes for device nodes that do not exist on this machine
+const BLOCK_DEVICES: &[&str] = &["/dev/sd", "/dev/hd", "/dev/vd", "/dev/xvd", "/dev/nvme", "/dev/mmcblk", "/dev/disk/", "/dev/dm-", "/dev/md", "/dev/mapper/", "/dev/loop"];
+
+/// A command that runs another command given as its operands.
+struct Wrapper {
+    name: &'static str,
+    /// Options that take the following word as their value.
+    valued: &'static [&'static str],
+    /// Operands between the options and the wrapped command, e.g. `timeout`'s duration.
+    operands: usize,
+}
+
+const WRAPPERS: &[Wrapper] = &[
+    Wrapper {
+        name: "sudo",
+        valued: &["-u", "-g", "-h", "-p", "-C", "-D", "-R", "-r", "-t", "-T", "-U", "--user", "--group", "--host", "--prompt", "--close-from", "--chdir", "--chroot", "--role", "--type", "--command-timeout", "--other-user"],
+        operands: 0,
+    },
+    Wrapper { name: "doas", valued: &["-u", "-C"], operands: 0 },
+    Wrapper { name: "env", valued: &["-u", "-C", "-S", "--unset", "--chdir", "--split-string"], operands: 0 },
+    Wrapper { name: "nice", valued: &["-n", "--adjustment"], operands: 0 },
+    Wrapper { name: "ionice", valued: &["-c", "-n", "-p", "-P", "-u", "--class", "--classdata"], operands: 0 },
+    Wrapper { name: "nohup", valued: &[], operands: 0 },
+    Wrapper { name: "setsid", valued: &[], operands: 0 },
+    Wrapper { name: "time", valued: &["-f", "-o", "--format", "--output"], operands: 0 },
+    Wrapper { name: "xargs", valued: &["-a", "-d", "-E", "-I", "-L", "-n", "-P", "-s", "--arg-file", "--delimiter", "--max-args", "--max-procs", "--max-chars", "--max-lines"], operands: 0 },
+    Wrapper { name: "timeout", valued: &["-k", "-s", "--kill-after", "--signal"], operands: 1 },
+    Wrapper { name: "exec", valued: &["-a"], operands: 0 },
+    Wrapper { name: "command", valued: &[], operands: 0 },
+    Wrapper { name: "builtin", valued: &[], operands: 0 },
+    Wrapper { name: "stdbuf", valued: &["-i", "-o", "-e", "--input", "--output", "--error"], operands: 0 },
+    Wrapper { name: "chroot", valued: &["--userspec", "--groups"], operands: 1 },
+    Wrapper { name: "taskset", valued: &[], operands: 1 },
+];
+
+pub fn analyze(script: &Script) -> Vec<Finding> {
+    let mut findings = vec![];
+    for command in script.simple_commands() {
+        analyze_simple(command, &mut findings);
+    }
+    for pipeline in script.pipelines() {
+        // `curl ... | sh`: downloaded content executed without inspection
+        let mut saw_download = None;
+        for command in &pipeline.commands {
+            if let Command::Simple(c) = command {
+                let name = effective_program(c);
+                if name.is_some_and(|n| DOWNLOADERS.contains(&n)) {
+                    saw_download = Some(c.span);
+                } else if let (Some(download), Some(n)) = (saw_download, name) {
+                    if INTERPRETERS.contains(&n) {
+                        findings.push(Finding {
+                            id: "remote_code_execution",
+                            severity: Severity::Critical,
+                            message: format!("Downloaded content is piped straight into {}", n),
+                            span: Span { start: download.start, end: c.span.end },
+                        });
+                    }
+                }
+            }
+        }
+    }
+    for redirect in script.redirects() {
+        if !redirect.writes_file() {
+            continue;
+        }
+        let target = &redirect.target.text;
+        if block_device(target) {
+            findings.push(Finding {
+                id: "raw_device_write",
+                severity: Severity::Critical,
+                message: format!("Redirection overwrites block device {}", target),
+                span: redirect.span,
+            });
+        } else if let Some(root) = system_path(target) {
+            findings.push(Finding {
+                id: "system_path_write",
+                severity: Severity::High,
+                message: format!("Redirection writes into {}", root),
+                span: redirect.span,
+            });
+        }
+    }
+    findings.sort_by(|a, b| b.severity.cmp(&a.severity).then(a.span.start.cmp(&b.span.start)));
+    findings
+}
+
+// Looks through `sudo`, `env` and friends to the program that actually runs
+fn effective_program(command: &SimpleCommand) -> Option<&str> {
+    effective_argv(command).first().map(|w| basename(&w.text))
+}
+
+/// The argv that actually runs once wrappers such as `sudo`, `nice -n 10` or `timeout 5` are
+/// looked through.
+pub fn effective_argv(command: &SimpleCommand) -> &[Word] {
+    let mut argv = command.argv.as_slice();
+    while let Some(wrapper) = argv.first().and_then(|w| WRAPPERS.iter().find(|x| x.name == basename(&w.text))) {
+        argv = &argv[1..];
+        while let Some(arg) = argv.first() {
+            let text = arg.text.as_str();
+            let skip = if text == "--" {
+                argv = &argv[1..];
+                break;
+            } else if text.starts_with('-') && text.len() > 1 {
+                // `-n 10` takes the next word; `-n10` and `--adjustment=10` carry their value
+                if wrapper.valued.contains(&text) { 2 } else { 1 }
+            } else if wrapper.name == "env" && text.contains('=') {
+                1
+            } else {
+                break;
+            };
+            argv = &argv[skip.min(argv.len())..];
+        }
+        argv = &argv[wrapper.operands.min(argv.len())..];
+    }
+    argv
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+// Writes to /dev/null, /dev/stdout or a terminal are harmless; only disks are destroyed
+fn block_device(path: &str) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    if !path.starts_with("/dev/") {
+        return false;
+    }
+    match std::fs::metadata(path) {
+        Ok(meta) => meta.file_type().is_block_device(),
+        Err(_) => BLOCK_DEVICES.iter().any(|prefix| path.starts_with(prefix)),
+    }
+}
+
+// `bash -c '...'` and `eval '...'` run a string as a command line of its own, and so does a
+// shell reading its script from `<<EOF` or `<<<'...'`
+fn nested_command_line(program: &str, args: &[Word], redirects: &[Redirect]) -> Option<(String, Span)> {
+    if program == "eval" {
+        let span = Span { start: args.first()?.span.start, end: args.last()?.span.end };
+        return Some((args.iter().map(|a| a.text.as_str()).collect::<Vec<_>>().join(" "), span));
+    }
+    if !SHELLS.contains(&program) {
+        return None;
+    }
+    // `-c` may be bundled with other flags, as in `bash -lc`; the string is the first operand
+    if let Some(at) = args.iter().position(|a| a.text.starts_with('-') && !a.text.starts_with("--") && a.text.contains('c')) {
+        let script = args[at + 1..].iter().find(|a| !a.text.starts_with('-'))?;
+        return Some((script.text.clone(), script.span));
+    }
+    // Without a script file, or with `-s`, the script is whatever arrives on stdin
+    if args.iter().any(|a| !a.text.starts_with('-')) && !args.iter().any(|a| a.text == "-s") {
+        return None;
+    }
+    redirects.iter().filter(|r| r.fd.is_none_or(|fd| fd == 0)).find_map(|r| match r.op {
+        RedirectOp::HereDoc => Some((r.body.clone()?, r.span)),
+        RedirectOp::HereString => Some((r.target.text.clone(), r.span)),
+        _ => None,
+    })
+}
+
+fn system_path(target: &str) -> Option<&'static str> {
+    SYSTEM_PATHS.iter().copied().find(|root| target == *root || target.starts_with(&format!("{}/", root)))
+}
+
+fn analyze_simple(command: &SimpleCommand, findings: &mut Vec<Finding>) {
+    let argv = effective_argv(command);
+    let wrappers = &command.argv[..command.argv.len() - argv.len()];
+    if let Some(wrapper) = wrappers.iter().find(|w| matches!(basename(&w.text), "sudo" | "doas")) {
+        findings.push(Finding {
+            id: "privilege_escalation",
+            severity: Severity::Medium,
+            message: format!("Runs with elevated privileges via {}", basename(&wrapper.text)),
+            span: wrapper.span,
+        });
+    }
+
+    let Some(program) = argv.first().map(|w| basename(&w.text)) else { return };
+    let args = &argv[1..];
+
+    // Whatever the inner command line would be flagged for, flag here, pointing at the string
+    if let Some((line, span)) = nested_command_line(program, args, &command.redirects) {
+        if let Ok(script) = shell::parse(&line) {
+            findings.extend(analyze(&script).into_iter().map(|finding| Finding { span, ..finding }));
+        }
+    }
+
+    match program {
+        "rm" => analyze_rm(command, args, findings),
+        "chmod" => {
+            let recursive = args.iter().any(|a| a.text == "-R" || a.text == "--recursive");
+            if let Some(mode) = args.iter().find(|a| world_writable_mode(&a.text)) {
+                findings.push(Finding {
+                    id: "world_writable",
+                    severity: if recursive { Severity::High } else { Severity::Medium },
+                    message: format!("chmod {} makes files writable by every user", mode.text),
+                    span: mode.span,
+                });
+            }
+        },
+        "dd" => {
+            if let Some(of) = args.iter().find(|a| a.text.strip_prefix("of=").is_some_and(block_device)) {
+                findings.push(Finding {
+                    id: "raw_device_write",
+                    severity: Severity::Critical,
+                    message: format!("dd writes directly to {}", &of.text[3..]),
+                    span: of.span,
+                });
+            }
+        },
+        p if p.starts_with("mkfs") => findings.push(Finding {
+            id: "filesystem_format",
+            severity: Severity::Critical,
+            message: format!("{} formats a filesystem", p),
+            span: command.span,
+        }),
+        "tee" | "cp" | "mv" | "install" | "ln" | "rsync" => {
+            // For tee every argument is a destination; for the rest, the last one is
+            let destinations: Vec<&Word> = if program == "tee" {
+                args.iter().filter(|a| !a.text.starts_with('-')).collect()
+            } else {
+                args.iter().rev().find(|a| !a.text.starts_with('-')).into_iter().collect()
+            };
+            for dest in destinations {
+                if let Some(root) = system_path(&dest.text) {
+                    findings.push(Finding {
+                        id: "system_path_write",
+                        severity: Severity::High,
+                        message: format!("{} writes into {}", program, root),
+                        span: dest.span,
+                    });
+                }
+            }
+        },
+        p if INTERPRETERS.contains(&p) => {
+            // `sh -c "$(curl ...)"`: the same download-and-run pattern without a pipe
+            for arg in args {
+                let downloads = arg.substitutions.iter().flat_map(|s| s.simple_commands())
+                    .any(|c| effective_program(c).is_some_and(|n| DOWNLOADERS.contains(&n)));
+                if downloads {
+                    findings.push(Finding {
+                        id: "remote_code_execution",
+                        severity: Severity::Critical,
+                        message: format!("{} executes downloaded content", p),
+                        span: command.span,
+                    });
+                }
+            }
+        },
+        "eval" => findings.push(Finding {
+            id: "eval",
+            severity: Severity::Medium,
+            message: "eval executes dynamically constructed code".to_string(),
+            span: command.span,
+        }),
+        _ => {},
+    }
+}
+
+fn analyze_rm(command: &SimpleCommand, args: &[Word], findings: &mut Vec<Finding>) {
+    let mut recursive = false;
+    let mut force = false;
+    let mut targets = vec![];
+    let mut options_done = false;
+    for arg in args {
+        if !options_done && arg.text == "--" {
+            options_done = true;
+        } else if !options_done && arg.text.starts_with("--") {
+            recursive |= arg.text == "--recursive";
+            force |= arg.text == "--force";
+        } else if !options_done && arg.text.starts_with('-') && arg.text.len() > 1 {
+            recursive |= arg.text.contains('r') || arg.text.contains('R');
+            force |= arg.text.contains('f');
+        } else {
+            targets.push(arg);
+        }
+    }
+    if !recursive {
+        return;
+    }
+
+    for target in &targets {
+        let text = target.text.trim_end_matches('/');
+        let catastrophic = text.is_empty()
+            || text == "/*"
+            || text == "~"
+            || text == "$HOME"
+            || text == "${HOME}"
+            || text == "*"
+            || text == "."
+            || system_path(&target.text).is_some_and(|root| text == root);
+        if catastrophic {
+            findings.push(Finding {
+                id: "recursive_delete_root",
+                severity: Severity::Critical,
+                message: format!("rm -r{} targets {}", if force { "f" } else { "" }, target.text),
+                span: command.span,
+            });
+            return;
+        }
+        // `rm -rf $DIR/` becomes `rm -rf /` when DIR is unset or empty
+        if let Some(var) = target.unquoted_variables.first() {
+            if target.text.starts_with('$') {
+                findings.push(Finding {
+                    id: "unguarded_variable_delete",
+                    severity: Severity::High,
+                    message: format!("rm -r on ${} deletes from / if the variable is empty", var),
+                    span: target.span,
+                });
+                return;
+            }
+        }
+    }
+
+    if force {
+        findings.push(Finding {
+            id: "recursive_force_delete",
+            severity: Severity::Medium,
+            message: "rm -rf deletes without confirmation".to_string(),
+            span: command.span,
+        });
+    }
+}
+
+fn world_writable_mode(mode: &str) -> bool {
+    // Octal modes of any width, e.g. 777, 0666 or 1777 with the sticky bit
+    if !mode.is_empty() && mode.chars().all(|c| ('0'..='7').contains(&c)) {
+        return u32::from_str_radix(mode, 8).is_ok_and(|bits| bits <= 0o7777 && bits & 0o002 != 0);
+    }
+    // Symbolic modes: a+w, o+w, +w, a=rwx ...
+    mode.split(',').any(|clause| {
+        let (who, perms) = match clause.find(['+', '=']) {
+            Some(i) => (&clause[..i], &clause[i + 1..]),
+            None => return false,
+        };
+        (who.is_empty() || who.contains('a') || who.contains('o')) && perms.contains('w')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn findings(src: &str) -> Vec<(&'static str, Severity)> {
+        analyze(&shell::parse(src).unwrap()).into_iter().map(|f| (f.id, f.severity)).collect()
+    }
+
+    fn flags(src: &str, id: &str) -> bool {
+        findings(src).iter().any(|(found, _)| *found == id)
+    }
+
+    #[test]
+    fn grades_recursive_deletes() {
+        assert_eq!(findings("rm -rf /"), vec![("recursive_delete_root", Severity::Critical)]);
+        assert_eq!(findings("rm -r --no-preserve-root ~/"), vec![("recursive_delete_root", Severity::Critical)]);
+        assert_eq!(findings("rm -rf $BUILD/"), vec![("unguarded_variable_delete", Severity::High)]);
+        assert_eq!(findings("rm -rf target"), vec![("recursive_force_delete", Severity::Medium)]);
+        assert!(findings("rm -f notes.txt").is_empty());
+        assert!(findings("rm -- -rf").is_empty());
+    }
+
+    #[test]
+    fn sees_through_compound_commands() {
+        for src in [
+            "if :; then rm -rf /; fi",
+            "while true; do rm -rf /; done",
+            "for d in a b; do rm -rf /; done",
+            "case $x in *) rm -rf / ;; esac",
+            "f() { rm -rf /; }",
+            "(cd /tmp && rm -rf /)",
+            "echo $(rm -rf /)",
+        ] {
+            assert!(flags(src, "recursive_delete_root"), "{}", src);
+        }
+    }
+
+    #[test]
+    fn sees_through_wrappers() {
+        for src in [
+            "nice -n 10 rm -rf /",
+            "nice -n10 rm -rf /",
+            "ionice -c 3 rm -rf /",
+            "timeout 5 rm -rf /",
+            "timeout -s KILL 5 rm -rf /",
+            "exec rm -rf /",
+            "command rm -rf /",
+            "stdbuf -o L rm -rf /",
+            "chroot /mnt rm -rf /",
+            "env -u HOME FOO=1 rm -rf /",
+            "nohup setsid /bin/rm -rf /",
+            "xargs -n 1 rm -rf /",
+            "sudo -- rm -rf /",
+        ] {
+            assert!(flags(src, "recursive_delete_root"), "{}", src);
+        }
+    }
+
+    #[test]
+    fn flags_privilege_escalation_behind_other_wrappers() {
+        let found = findings("sudo -u root rm -rf /");
+        assert!(found.contains(&("recursive_delete_root", Severity::Critical)));
+        assert!(found.contains(&("privilege_escalation", Severity::Medium)));
+        assert!(flags("nice -n 5 doas ls", "privilege_escalation"));
+        assert!(!flags("echo sudo", "privilege_escalation"));
+    }
+
+    #[test]
+    fn inspects_nested_command_lines() {
+        assert!(flags("bash -c 'rm -rf /'", "recursive_delete_root"));
+        assert!(flags("sh -lc \"rm -rf /\"", "recursive_delete_root"));
+        assert!(flags("sudo sh -c 'curl https://x | sh'", "remote_code_execution"));
+        let found = findings("eval rm -rf /");
+        assert!(found.contains(&("recursive_delete_root", Severity::Critical)));
+        assert!(found.contains(&("eval", Severity::Medium)));
+        assert!(findings("bash script.sh").is_empty());
+        assert!(flags("sh <<<'rm -rf ~'", "recursive_delete_root"));
+        assert!(flags("sudo bash -s arg <<EOF\nrm -rf /\nEOF\n", "recursive_delete_root"));
+        assert!(flags("bash -x <<-'EOF'\n\tcurl https://x | sh\n\tEOF\n", "remote_code_execution"));
+        assert!(findings("bash script.sh <<EOF\nrm -rf /\nEOF\n").is_empty());
+        assert!(findings("cat <<<'rm -rf /'").is_empty());
+        assert!(flags("echo ${x:-$(rm -rf /)}", "recursive_delete_root"));
+        assert!(flags("echo $(( $(rm -rf /) ))", "recursive_delete_root"));
+        assert!(flags("cat <<EOF\n$(rm -rf /)\nEOF\n", "recursive_delete_root"));
+    }
+
+    #[test]
+    fn nested_findings_point_at_the_string() {
+        let src = "bash -c 'rm -rf /'";
+        let finding = analyze(&shell::parse(src).unwrap()).remove(0);
+        assert_eq!(&src[finding.span.start..finding.span.end], "'rm -rf /'");
+    }
+
+    #[test]
+    fn flags_downloads_piped_into_interpreters() {
+        assert_eq!(findings("curl -fsSL https://x | sh"), vec![("remote_code_execution", Severity::Critical)]);
+        assert!(flags("wget -qO- https://x | sudo bash", "remote_code_execution"));
+        assert!(flags("bash -c \"$(curl -fsSL https://x)\"", "remote_code_execution"));
+        assert!(findings("curl https://x | jq .").is_empty());
+    }
+
+    #[test]
+    fn flags_world_writable_modes() {
+        assert_eq!(findings("chmod 777 f"), vec![("world_writable", Severity::Medium)]);
+        assert_eq!(findings("chmod -R 0777 dir"), vec![("world_writable", Severity::High)]);
+        for mode in ["1777", "2777", "4777", "0666", "o+w", "a=rwx", "u+x,o+w"] {
+            assert!(flags(&format!("chmod {} f", mode), "world_writable"), "{}", mode);
+        }
+        for mode in ["755", "0644", "1755", "4755", "u+w", "o-w", "17777"] {
+            assert!(!flags(&format!("chmod {} f", mode), "world_writable"), "{}", mode);
+        }
+    }
+
+    #[test]
+    fn limits_device_writes_to_block_devices() {
+        assert_eq!(findings("dd if=img of=/dev/sda bs=4M"), vec![("raw_device_write", Severity::Critical)]);
+        assert_eq!(findings("echo x > /dev/nvme0n1"), vec![("raw_device_write", Severity::Critical)]);
+        assert!(findings("dd if=/dev/zero of=/dev/null count=1").is_empty());
+        assert!(findings("cmd > /dev/null 2>&1").is_empty());
+        assert!(findings("echo hi > /dev/stderr").is_empty());
+    }
+
+    #[test]
+    fn flags_system_path_writes() {
+        assert_eq!(findings("echo x >> /etc/hosts"), vec![("system_path_write", Severity::High)]);
+        assert_eq!(findings("cp tool /usr/local/bin/"), vec![("system_path_write", Severity::High)]);
+        assert!(flags("echo x | tee -a /etc/profile", "system_path_write"));
+        assert!(findings("cp a /etcetera/b").is_empty());
+        assert_eq!(findings("mkfs.ext4 /dev/sdb1"), vec![("filesystem_format", Severity::Critical)]);
+    }
+}
@@ -0,0 +1,403 @@
+use serde::{Deserialize, Serialize};
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+use super::error::MatrixError;
+
+/// Confinement applied to a command before it execs. Every limit is optional; a profile with
+/// nothing set still isolates the mount namespace and installs the syscall filter.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SandboxProfile {
+    pub name: String,
+    /// RLIMIT_CPU, in seconds of CPU time.
+    #[serde(default)]
+    pub cpu_seconds: Option<u64>,
+    /// RLIMIT_AS, in MiB of address space.
+    #[serde(default)]
+    pub memory_mb: Option<u64>,
+    /// RLIMIT_NOFILE.
+    #[serde(default)]
+    pub open_files: Option<u64>,
+    /// Run in a fresh network namespace with no interfaces up.
+    #[serde(default = "default_true")]
+    pub network: bool,
+    /// Paths bind-mounted read-only inside the sandbox; `~` expands to `$HOME` and `.` to the
+    /// working directory.
+    #[serde(default)]
+    pub read_only: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl SandboxProfile {
+    /// The profile the built-in policy applies to risky commands. Only scratch space such as
+    /// /tmp stays writable; the operator's home and working directory do not.
+    pub fn confined() -> Self {
+        Self {
+            name: "confined".to_string(),
+            cpu_seconds: Some(60),
+            memory_mb: Some(2048),
+            open_files: Some(256),
+            network: false,
+            read_only: ["/etc", "/usr", "/boot", "/bin", "/sbin", "/lib", "/lib64", "/opt", "/var", "~", "."].iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    /// One-line summary for the dry-run panel.
+    pub fn describe(&self) -> String {
+        let mut parts = vec![];
+        if let Some(cpu) = self.cpu_seconds {
+            parts.push(format!("cpu {}s", cpu));
+        }
+        if let Some(memory) = self.memory_mb {
+            parts.push(format!("memory {} MiB", memory));
+        }
+        if let Some(files) = self.open_files {
+            parts.push(format!("{} open files", files));
+        }
+        if !self.network {
+            parts.push("no network".to_string());
+        }
+        if !self.read_only.is_empty() {
+            parts.push(format!("read-only {}", self.read_only.join(" ")));
+        }
+        format!("{} ({})", self.name, if parts.is_empty() { "namespaces + seccomp".to_string() } else { parts.join(", ") })
+    }
+
+    /// Resolves everything the child needs up front: after fork only raw syscalls are safe.
+    #[cfg(target_os = "linux")]
+    pub fn prepare(&self) -> Result<PreparedSandbox, MatrixError> {
+        let home = std::env::var("HOME").unwrap_or_default();
+        let cwd = std::env::current_dir().map_err(|e| MatrixError::io("Cannot resolve the sandbox working directory", e))?;
+        let read_only = self
+            .read_only
+            .iter()
+            .map(|p| match p.strip_prefix('~') {
+                Some(rest) => PathBuf::from(format!("{}{}", home, rest)),
+                None if p == "." || p.starts_with("./") => cwd.join(p),
+                None => PathBuf::from(p),
+            })
+            // Nothing to protect at a path that does not exist
+            .filter(|p| p.exists())
+            .map(|p| CString::new(p.to_string_lossy().into_owned()).map_err(|_| MatrixError::Policy(format!("Sandbox path {} contains a NUL byte", p.display()))))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let mut limits = vec![];
+        if let Some(cpu) = self.cpu_seconds {
+            limits.push((libc::RLIMIT_CPU, cpu));
+        }
+        if let Some(memory) = self.memory_mb {
+            limits.push((libc::RLIMIT_AS, memory.saturating_mul(1024 * 1024)));
+        }
+        if let Some(files) = self.open_files {
+            limits.push((libc::RLIMIT_NOFILE, files));
+        }
+
+        Ok(PreparedSandbox {
+            limits,
+            network: self.network,
+            read_only,
+            uid_map: CString::new(format!("{} {} 1\n", uid, uid)).expect("No NUL in a number"),
+            gid_map: CString::new(format!("{} {} 1\n", gid, gid)).expect("No NUL in a number"),
+            filter: seccomp::syscall_filter(),
+        })
+    }
+
+    /// Namespaces and seccomp are Linux-only; elsewhere a profile cannot be honoured, so refuse.
+    #[cfg(not(target_os = "linux"))]
+    pub fn prepare(&self) -> Result<PreparedSandbox, MatrixError> {
+        Err(MatrixError::Policy(format!("Sandbox '{}' needs Linux namespaces and seccomp", self.name)))
+    }
+}
+
+/// A profile reduced to plain data the post-fork hook can apply without allocating.
+#[cfg(target_os = "linux")]
+pub struct PreparedSandbox {
+    limits: Vec<(libc::__rlimit_resource_t, u64)>,
+    network: bool,
+    read_only: Vec<CString>,
+    uid_map: CString,
+    gid_map: CString,
+    filter: Vec<libc::sock_filter>,
+}
+
+/// Never constructed off Linux: `prepare` refuses instead.
+#[cfg(not(target_os = "linux"))]
+pub enum PreparedSandbox {}
+
+#[cfg(not(target_os = "linux"))]
+impl PreparedSandbox {
+    pub fn install(self, _command: &mut tokio::process::Command) {
+        match self {}
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl PreparedSandbox {
+    /// Installs the sandbox as a `pre_exec` hook on `command`.
+    pub fn install(self, command: &mut tokio::process::Command) {
+        unsafe {
+            command.pre_exec(move || self.enter());
+        }
+    }
+
+    // Runs in the forked child: rlimits, then an unprivileged user namespace that owns new
+    // mount (and optionally network) namespaces, then no_new_privs and the seccomp filter.
+    fn enter(&self) -> std::io::Result<()> {
+        let check = |rc: libc::c_int| if rc == -1 { Err(std::io::Error::last_os_error()) } else { Ok(()) };
+        unsafe {
+            for &(resource, value) in &self.limits {
+                let limit = libc::rlimit { rlim_cur: value, rlim_max: value };
+                check(libc::setrlimit(resource, &limit))?;
+            }
+
+            let mut flags = libc::CLONE_NEWUSER | libc::CLONE_NEWNS;
+            if !self.network {
+                flags |= libc::CLONE_NEWNET;
+            }
+            check(libc::unshare(flags))?;
+            write_proc(c"/proc/self/setgroups", c"deny")?;
+            write_proc(c"/proc/self/uid_map", &self.uid_map)?;
+            write_proc(c"/proc/self/gid_map", &self.gid_map)?;
+
+            // Keep our mounts from propagating back to the host
+            check(libc::mount(std::ptr::null(), c"/".as_ptr(), std::ptr::null(), libc::MS_REC | libc::MS_PRIVATE, std::ptr::null()))?;
+            for path in &self.read_only {
+                check(libc::mount(path.as_ptr(), path.as_ptr(), std::ptr::null(), libc::MS_BIND | libc::MS_REC, std::ptr::null()))?;
+                // A user namespace may not clear nosuid/nodev/noexec inherited from the host
+                let mut stat: libc::statvfs = std::mem::zeroed();
+                check(libc::statvfs(path.as_ptr(), &mut stat))?;
+                let locked = stat.f_flag & (libc::ST_NOSUID | libc::ST_NODEV | libc::ST_NOEXEC | libc::ST_NOATIME | libc::ST_NODIRATIME | libc::ST_RELATIME);
+                let remount = libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY | locked;
+                check(libc::mount(std::ptr::null(), path.as_ptr(), std::ptr::null(), remount, std::ptr::null()))?;
+            }
+
+            check(libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0))?;
+            let program = libc::sock_fprog { len: self.filter.len() as libc::c_ushort, filter: self.filter.as_ptr() as *mut libc::sock_filter };
+            check(libc::prctl(libc::PR_SET_SECCOMP, libc::SECCOMP_MODE_FILTER, &program as *const libc::sock_fprog))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn write_proc(path: &std::ffi::CStr, contents: &std::ffi::CStr) -> std::io::Result<()> {
+    let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+    if fd == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let bytes = contents.to_bytes();
+    let written = libc::write(fd, bytes.as_ptr() as *const libc::c_void, bytes.len());
+    libc::close(fd);
+    if written != bytes.len() as isize {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// The seccomp filter, in classic BPF (linux/filter.h, linux/seccomp.h)
+#[cfg(target_os = "linux")]
+mod seccomp {
+    const BPF_LD_W_ABS: u16 = 0x20;
+    const BPF_JMP_JEQ_K: u16 = 0x15;
+    const BPF_JMP_JGE_K: u16 = 0x35;
+    const BPF_JMP_JSET_K: u16 = 0x45;
+    const BPF_RET_K: u16 = 0x06;
+    const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+    const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+    const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+    // Offsets into struct seccomp_data; the low half of args[0] on little-endian targets
+    const SECCOMP_DATA_NR: u32 = 0;
+    const SECCOMP_DATA_ARCH: u32 = 4;
+    const SECCOMP_DATA_ARG0: u32 = 16;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+    // x32 syscalls share AUDIT_ARCH_X86_64 but are numbered from this bit up
+    #[cfg(target_arch = "x86_64")]
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    /// Syscalls that let a process escape or tamper with the sandbox or the host kernel.
+    const DENIED_SYSCALLS: &[libc::c_long] = &[
+        libc::SYS_ptrace,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_kexec_load,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+    ];
+
+    /// `clone` flags that would create namespaces, the same escape `unshare` offers.
+    const NAMESPACE_FLAGS: libc::c_int = libc::CLONE_NEWNS
+        | libc::CLONE_NEWCGROUP
+        | libc::CLONE_NEWUTS
+        | libc::CLONE_NEWIPC
+        | libc::CLONE_NEWUSER
+        | libc::CLONE_NEWPID
+        | libc::CLONE_NEWNET;
+
+    fn bpf(code: u16, jt: u8, jf: u8, k: u32) -> libc::sock_filter {
+        libc::sock_filter { code, jt, jf, k }
+    }
+
+    // Denied syscalls fail with EPERM; a foreign architecture or the x32 ABI is killed outright,
+    // since its syscall numbers would bypass the list
+    pub(super) fn syscall_filter() -> Vec<libc::sock_filter> {
+        let deny = bpf(BPF_RET_K, 0, 0, SECCOMP_RET_ERRNO | libc::EPERM as u32);
+        let mut filter = vec![
+            bpf(BPF_LD_W_ABS, 0, 0, SECCOMP_DATA_ARCH),
+            bpf(BPF_JMP_JEQ_K, 1, 0, AUDIT_ARCH),
+            bpf(BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS),
+            bpf(BPF_LD_W_ABS, 0, 0, SECCOMP_DATA_NR),
+        ];
+        #[cfg(target_arch = "x86_64")]
+        filter.extend([bpf(BPF_JMP_JGE_K, 0, 1, X32_SYSCALL_BIT), bpf(BPF_RET_K, 0, 0, SECCOMP_RET_KILL_PROCESS)]);
+        for &nr in DENIED_SYSCALLS {
+            filter.push(bpf(BPF_JMP_JEQ_K, 0, 1, nr as u32));
+            filter.push(deny);
+        }
+        // clone3 passes its flags behind a pointer the filter cannot read; ENOSYS makes libc
+        // fall back to clone, whose flags it can
+        filter.push(bpf(BPF_JMP_JEQ_K, 0, 1, libc::SYS_clone3 as u32));
+        filter.push(bpf(BPF_RET_K, 0, 0, SECCOMP_RET_ERRNO | libc::ENOSYS as u32));
+        filter.extend([
+            bpf(BPF_JMP_JEQ_K, 0, 3, libc::SYS_clone as u32),
+            bpf(BPF_LD_W_ABS, 0, 0, SECCOMP_DATA_ARG0),
+            bpf(BPF_JMP_JSET_K, 0, 1, NAMESPACE_FLAGS as u32),
+            deny,
+        ]);
+        filter.push(bpf(BPF_RET_K, 0, 0, SECCOMP_RET_ALLOW));
+        filter
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+    use std::process::ExitStatus;
+
+    // Unprivileged user namespaces are often disabled in containers and CI; nothing to test then
+    fn namespaces_available() -> bool {
+        let mut probe = std::process::Command::new("true");
+        unsafe {
+            probe.pre_exec(|| if libc::unshare(libc::CLONE_NEWUSER) == -1 { Err(std::io::Error::last_os_error()) } else { Ok(()) });
+        }
+        probe.status().is_ok_and(|s| s.success())
+    }
+
+    fn profile() -> SandboxProfile {
+        SandboxProfile { name: "test".to_string(), cpu_seconds: None, memory_mb: None, open_files: None, network: true, read_only: vec![] }
+    }
+
+    async fn run(profile: &SandboxProfile, script: &str) -> std::process::Output {
+        let mut command = tokio::process::Command::new("sh");
+        command.arg("-c").arg(script);
+        profile.prepare().unwrap().install(&mut command);
+        command.output().await.unwrap()
+    }
+
+    // Runs `probe` in the child once the sandbox is in place; its error fails the spawn
+    async fn probe(profile: &SandboxProfile, probe: impl FnMut() -> std::io::Result<()> + Send + Sync + 'static) -> std::io::Result<ExitStatus> {
+        let mut command = tokio::process::Command::new("true");
+        profile.prepare().unwrap().install(&mut command);
+        unsafe {
+            command.pre_exec(probe);
+        }
+        command.status().await
+    }
+
+    fn connect_to(port: u16) -> impl FnMut() -> std::io::Result<()> + Send + Sync + 'static {
+        move || unsafe {
+            let address = libc::sockaddr_in {
+                sin_family: libc::AF_INET as libc::sa_family_t,
+                sin_port: port.to_be(),
+                sin_addr: libc::in_addr { s_addr: u32::from_be_bytes([127, 0, 0, 1]).to_be() },
+                sin_zero: [0; 8],
+            };
+            let fd = libc::socket(libc::AF_INET, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0);
+            if fd == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let rc = libc::connect(fd, &address as *const libc::sockaddr_in as *const libc::sockaddr, std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t);
+            let result = if rc == -1 { Err(std::io::Error::last_os_error()) } else { Ok(()) };
+            libc::close(fd);
+            result
+        }
+    }
+
+    #[tokio::test]
+    async fn network_namespace_has_no_route_out() {
+        if !namespaces_available() {
+            return;
+        }
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert!(probe(&profile(), connect_to(port)).await.unwrap().success());
+        let offline = SandboxProfile { network: false, ..profile() };
+        let error = probe(&offline, connect_to(port)).await.unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::ENETUNREACH));
+    }
+
+    #[tokio::test]
+    async fn read_only_paths_reject_writes() {
+        if !namespaces_available() {
+            return;
+        }
+        let base = std::env::temp_dir().join(format!("agent-matrix-sandbox-{}", std::process::id()));
+        let (locked, open) = (base.join("locked"), base.join("open"));
+        std::fs::create_dir_all(&locked).unwrap();
+        std::fs::create_dir_all(&open).unwrap();
+        let sandbox = SandboxProfile { read_only: vec![locked.display().to_string()], ..profile() };
+
+        let output = run(&sandbox, &format!("echo x > {}/file", locked.display())).await;
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("Read-only file system"));
+        assert!(!locked.join("file").exists());
+
+        assert!(run(&sandbox, &format!("echo x > {}/file", open.display())).await.status.success());
+        assert!(open.join("file").exists());
+        let _ = std::fs::remove_dir_all(&base);
+    }
+
+    #[tokio::test]
+    async fn resource_limits_are_applied() {
+        if !namespaces_available() {
+            return;
+        }
+        let limited = SandboxProfile { cpu_seconds: Some(5), memory_mb: Some(512), open_files: Some(64), ..profile() };
+        let output = run(&limited, "ulimit -n; ulimit -t; ulimit -v").await;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "64\n5\n524288\n");
+    }
+
+    #[tokio::test]
+    async fn denied_syscalls_fail_with_eperm() {
+        if !namespaces_available() {
+            return;
+        }
+        // The sandbox owns its user namespace, so only the filter can refuse a new UTS namespace
+        let unshare = || if unsafe { libc::unshare(libc::CLONE_NEWUTS) } == -1 { Err(std::io::Error::last_os_error()) } else { Ok(()) };
+        let error = probe(&profile(), unshare).await.unwrap_err();
+        assert_eq!(error.raw_os_error(), Some(libc::EPERM));
+    }
+}
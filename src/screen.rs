@@ -0,0 +1,664 @@
+use std::collections::VecDeque;
+use unicode_width::UnicodeWidthChar;
+use vte::{Params, Perform};
+
+/// Lines kept above the visible screen on the primary buffer.
+pub const DEFAULT_SCROLLBACK: usize = 5000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TermColor {
+    #[default]
+    Default,
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// SGR attributes applied to printed characters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pen {
+    pub fg: TermColor,
+    pub bg: TermColor,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub ch: char,
+    /// Columns the character occupies; 0 marks the right half of a wide character.
+    pub width: u8,
+    pub pen: Pen,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self { ch: ' ', width: 1, pen: Pen::default() }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Cursor {
+    pub row: usize,
+    pub col: usize,
+}
+
+// The primary buffer parked while a full-screen program owns the alternate one
+struct SavedPrimary {
+    grid: Vec<Vec<Cell>>,
+    cursor: Cursor,
+    pen: Pen,
+}
+
+/// The cell grid a VT100/xterm-compatible program draws into.
+pub struct Screen {
+    rows: usize,
+    cols: usize,
+    grid: Vec<Vec<Cell>>,
+    cursor: Cursor,
+    pen: Pen,
+    // Writing into the last column defers the wrap until the next printable character
+    pending_wrap: bool,
+    saved_cursor: Option<(Cursor, Pen)>,
+    scroll_top: usize,
+    scroll_bottom: usize,
+    scrollback: VecDeque<Vec<Cell>>,
+    scrollback_limit: usize,
+    primary: Option<SavedPrimary>,
+    cursor_visible: bool,
+    application_cursor: bool,
+    title: String,
+}
+
+impl Screen {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        let rows = rows.max(1);
+        let cols = cols.max(1);
+        Self {
+            rows,
+            cols,
+            grid: vec![vec![Cell::default(); cols]; rows],
+            cursor: Cursor::default(),
+            pen: Pen::default(),
+            pending_wrap: false,
+            saved_cursor: None,
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            scrollback: VecDeque::new(),
+            scrollback_limit: DEFAULT_SCROLLBACK,
+            primary: None,
+            cursor_visible: true,
+            application_cursor: false,
+            title: String::new(),
+        }
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    pub fn cursor(&self) -> Cursor {
+        self.cursor
+    }
+
+    pub fn cursor_visible(&self) -> bool {
+        self.cursor_visible
+    }
+
+    /// Whether the program asked for SS3 (`ESC O A`) rather than CSI arrow keys.
+    pub fn application_cursor(&self) -> bool {
+        self.application_cursor
+    }
+
+    pub fn alternate_screen(&self) -> bool {
+        self.primary.is_some()
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn scrollback_len(&self) -> usize {
+        self.scrollback.len()
+    }
+
+    /// The rows to display when scrolled `offset` lines back into history.
+    pub fn view(&self, offset: usize) -> Vec<&[Cell]> {
+        let offset = offset.min(self.scrollback.len());
+        let start = self.scrollback.len() - offset;
+        self.scrollback
+            .iter()
+            .skip(start)
+            .chain(self.grid.iter())
+            .take(self.rows)
+            .map(|row| row.as_slice())
+            .collect()
+    }
+
+    /// The visible screen as plain text, one line per row with trailing blanks trimmed.
+    pub fn contents(&self) -> String {
+        self.grid
+            .iter()
+            .map(|row| row.iter().filter(|c| c.width > 0).map(|c| c.ch).collect::<String>().trim_end().to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        let rows = rows.max(1);
+        let cols = cols.max(1);
+        if (rows, cols) == (self.rows, self.cols) {
+            return;
+        }
+        // Keep the cursor line on screen by pushing the top of the screen into history
+        if self.cursor.row >= rows {
+            let excess = self.cursor.row + 1 - rows;
+            for line in self.grid.drain(..excess) {
+                if self.primary.is_none() {
+                    push_bounded(&mut self.scrollback, line, self.scrollback_limit);
+                }
+            }
+            self.cursor.row -= excess;
+        }
+        resize_grid(&mut self.grid, rows, cols);
+        // The parked primary screen keeps its cursor line the same way, or the prompt is lost
+        if let Some(primary) = &mut self.primary {
+            if primary.cursor.row >= rows {
+                let excess = primary.cursor.row + 1 - rows;
+                for line in primary.grid.drain(..excess) {
+                    push_bounded(&mut self.scrollback, line, self.scrollback_limit);
+                }
+                primary.cursor.row -= excess;
+            }
+            resize_grid(&mut primary.grid, rows, cols);
+            primary.cursor.col = primary.cursor.col.min(cols - 1);
+        }
+        self.rows = rows;
+        self.cols = cols;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        self.cursor.col = self.cursor.col.min(cols - 1);
+        self.pending_wrap = false;
+    }
+
+    fn blank(&self) -> Cell {
+        // Erased cells take the current background, as xterm does
+        Cell { pen: Pen { bg: self.pen.bg, ..Pen::default() }, ..Cell::default() }
+    }
+
+    fn blank_line(&self) -> Vec<Cell> {
+        vec![self.blank(); self.cols]
+    }
+
+    fn scroll_up(&mut self, count: usize) {
+        for _ in 0..count.min(self.scroll_bottom + 1 - self.scroll_top) {
+            let line = self.grid.remove(self.scroll_top);
+            if self.scroll_top == 0 && self.primary.is_none() {
+                push_bounded(&mut self.scrollback, line, self.scrollback_limit);
+            }
+            self.grid.insert(self.scroll_bottom, self.blank_line());
+        }
+    }
+
+    // Deleted lines are gone, not history
+    fn scroll_up_without_history(&mut self, count: usize) {
+        for _ in 0..count.min(self.scroll_bottom + 1 - self.scroll_top) {
+            self.grid.remove(self.scroll_top);
+            self.grid.insert(self.scroll_bottom, self.blank_line());
+        }
+    }
+
+    fn scroll_down(&mut self, count: usize) {
+        for _ in 0..count.min(self.scroll_bottom + 1 - self.scroll_top) {
+            self.grid.remove(self.scroll_bottom);
+            self.grid.insert(self.scroll_top, self.blank_line());
+        }
+    }
+
+    fn linefeed(&mut self) {
+        if self.cursor.row == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.cursor.row < self.rows - 1 {
+            self.cursor.row += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        if self.cursor.row == self.scroll_top {
+            self.scroll_down(1);
+        } else {
+            self.cursor.row = self.cursor.row.saturating_sub(1);
+        }
+    }
+
+    fn move_to(&mut self, row: usize, col: usize) {
+        self.cursor = Cursor { row: row.min(self.rows - 1), col: col.min(self.cols - 1) };
+        self.pending_wrap = false;
+    }
+
+    fn erase(&mut self, row: usize, cols: std::ops::Range<usize>) {
+        let blank = self.blank();
+        let end = cols.end.min(self.cols);
+        for cell in &mut self.grid[row][cols.start.min(end)..end] {
+            *cell = blank;
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved_cursor = Some((self.cursor, self.pen));
+    }
+
+    fn restore_cursor(&mut self) {
+        let (cursor, pen) = self.saved_cursor.unwrap_or_default();
+        self.pen = pen;
+        self.move_to(cursor.row, cursor.col);
+    }
+
+    fn enter_alternate(&mut self) {
+        if self.primary.is_none() {
+            let grid = std::mem::replace(&mut self.grid, vec![vec![Cell::default(); self.cols]; self.rows]);
+            self.primary = Some(SavedPrimary { grid, cursor: self.cursor, pen: self.pen });
+        }
+    }
+
+    fn leave_alternate(&mut self) {
+        if let Some(primary) = self.primary.take() {
+            self.grid = primary.grid;
+            self.pen = primary.pen;
+            self.move_to(primary.cursor.row, primary.cursor.col);
+        }
+    }
+
+    fn reset(&mut self) {
+        let scrollback = std::mem::take(&mut self.scrollback);
+        *self = Self::new(self.rows, self.cols);
+        self.scrollback = scrollback;
+    }
+
+    fn set_private_mode(&mut self, mode: u16, enabled: bool) {
+        match mode {
+            1 => self.application_cursor = enabled,
+            25 => self.cursor_visible = enabled,
+            47 | 1047 => if enabled { self.enter_alternate() } else { self.leave_alternate() },
+            1049 => {
+                if enabled {
+                    self.save_cursor();
+                    self.enter_alternate();
+                } else {
+                    self.leave_alternate();
+                    self.restore_cursor();
+                }
+            },
+            _ => {},
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        if params.is_empty() {
+            self.pen = Pen::default();
+            return;
+        }
+        let mut i = 0;
+        while i < params.len() {
+            match params[i] {
+                0 => self.pen = Pen::default(),
+                1 => self.pen.bold = true,
+                2 => self.pen.dim = true,
+                3 => self.pen.italic = true,
+                4 => self.pen.underline = true,
+                7 => self.pen.inverse = true,
+                22 => {
+                    self.pen.bold = false;
+                    self.pen.dim = false;
+                },
+                23 => self.pen.italic = false,
+                24 => self.pen.underline = false,
+                27 => self.pen.inverse = false,
+                n @ 30..=37 => self.pen.fg = TermColor::Indexed((n - 30) as u8),
+                39 => self.pen.fg = TermColor::Default,
+                n @ 40..=47 => self.pen.bg = TermColor::Indexed((n - 40) as u8),
+                49 => self.pen.bg = TermColor::Default,
+                n @ 90..=97 => self.pen.fg = TermColor::Indexed((n - 90 + 8) as u8),
+                n @ 100..=107 => self.pen.bg = TermColor::Indexed((n - 100 + 8) as u8),
+                n @ (38 | 48) => {
+                    // 38;5;n (256-color) or 38;2;r;g;b (truecolor)
+                    let (color, used) = match params.get(i + 1) {
+                        Some(5) => (params.get(i + 2).map(|&c| TermColor::Indexed(c as u8)), 2),
+                        Some(2) => match (params.get(i + 2), params.get(i + 3), params.get(i + 4)) {
+                            (Some(&r), Some(&g), Some(&b)) => (Some(TermColor::Rgb(r as u8, g as u8, b as u8)), 4),
+                            _ => (None, 4),
+                        },
+                        _ => (None, 0),
+                    };
+                    if let Some(color) = color {
+                        if n == 38 { self.pen.fg = color } else { self.pen.bg = color }
+                    }
+                    i += used;
+                },
+                _ => {},
+            }
+            i += 1;
+        }
+    }
+}
+
+impl Perform for Screen {
+    fn print(&mut self, c: char) {
+        let width = c.width().unwrap_or(0);
+        if width == 0 {
+            // Combining marks are dropped; the base character already holds the cell
+            return;
+        }
+        if self.pending_wrap || self.cursor.col + width > self.cols {
+            self.cursor.col = 0;
+            self.linefeed();
+            self.pending_wrap = false;
+        }
+        let Cursor { row, col } = self.cursor;
+        self.grid[row][col] = Cell { ch: c, width: width as u8, pen: self.pen };
+        if width == 2 && col + 1 < self.cols {
+            self.grid[row][col + 1] = Cell { ch: ' ', width: 0, pen: self.pen };
+        }
+        if col + width >= self.cols {
+            self.cursor.col = self.cols - 1;
+            self.pending_wrap = true;
+        } else {
+            self.cursor.col = col + width;
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            0x08 => {
+                self.cursor.col = self.cursor.col.saturating_sub(1);
+                self.pending_wrap = false;
+            },
+            0x09 => {
+                let next_stop = (self.cursor.col / 8 + 1) * 8;
+                self.move_to(self.cursor.row, next_stop);
+            },
+            0x0A..=0x0C => {
+                self.linefeed();
+                self.pending_wrap = false;
+            },
+            0x0D => {
+                self.cursor.col = 0;
+                self.pending_wrap = false;
+            },
+            _ => {},
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &Params, intermediates: &[u8], _ignore: bool, action: char) {
+        let values: Vec<u16> = params.iter().flat_map(|p| p.iter().copied()).collect();
+        // Movement counts and 1-based positions treat a missing or zero parameter as 1
+        let arg = |i: usize| values.get(i).copied().filter(|v| *v != 0).unwrap_or(1) as usize;
+        let Cursor { row, col } = self.cursor;
+
+        if intermediates.first() == Some(&b'?') {
+            match action {
+                'h' => values.iter().for_each(|&m| self.set_private_mode(m, true)),
+                'l' => values.iter().for_each(|&m| self.set_private_mode(m, false)),
+                _ => {},
+            }
+            return;
+        }
+
+        match action {
+            'A' => self.move_to(row.saturating_sub(arg(0)), col),
+            'B' | 'e' => self.move_to(row + arg(0), col),
+            'C' | 'a' => self.move_to(row, col + arg(0)),
+            'D' => self.move_to(row, col.saturating_sub(arg(0))),
+            'E' => self.move_to(row + arg(0), 0),
+            'F' => self.move_to(row.saturating_sub(arg(0)), 0),
+            'G' | '`' => self.move_to(row, arg(0) - 1),
+            'd' => self.move_to(arg(0) - 1, col),
+            'H' | 'f' => self.move_to(arg(0) - 1, arg(1) - 1),
+            'J' => match values.first().copied().unwrap_or(0) {
+                0 => {
+                    self.erase(row, col..self.cols);
+                    (row + 1..self.rows).for_each(|r| self.erase(r, 0..self.cols));
+                },
+                1 => {
+                    (0..row).for_each(|r| self.erase(r, 0..self.cols));
+                    self.erase(row, 0..col + 1);
+                },
+                mode => {
+                    (0..self.rows).for_each(|r| self.erase(r, 0..self.cols));
+                    if mode == 3 {
+                        self.scrollback.clear();
+                    }
+                },
+            },
+            'K' => match values.first().copied().unwrap_or(0) {
+                0 => self.erase(row, col..self.cols),
+                1 => self.erase(row, 0..col + 1),
+                _ => self.erase(row, 0..self.cols),
+            },
+            'L' | 'M' if (self.scroll_top..=self.scroll_bottom).contains(&row) => {
+                // Insert/delete lines inside the scroll region, starting at the cursor line
+                let saved_top = self.scroll_top;
+                self.scroll_top = row;
+                if action == 'L' { self.scroll_down(arg(0)) } else { self.scroll_up_without_history(arg(0)) }
+                self.scroll_top = saved_top;
+                self.move_to(row, 0);
+            },
+            '@' => {
+                let blank = self.blank();
+                let line = &mut self.grid[row];
+                for _ in 0..arg(0).min(self.cols - col) {
+                    line.pop();
+                    line.insert(col, blank);
+                }
+            },
+            'P' => {
+                let blank = self.blank();
+                let line = &mut self.grid[row];
+                for _ in 0..arg(0).min(self.cols - col) {
+                    line.remove(col);
+                    line.push(blank);
+                }
+            },
+            'X' => self.erase(row, col..col + arg(0)),
+            'S' => self.scroll_up(arg(0)),
+            'T' => self.scroll_down(arg(0)),
+            'm' => self.select_graphic_rendition(&values),
+            'r' => {
+                let top = arg(0) - 1;
+                let bottom = values.get(1).copied().filter(|v| *v != 0).map(|v| v as usize - 1).unwrap_or(self.rows - 1);
+                if top < bottom && bottom < self.rows {
+                    self.scroll_top = top;
+                    self.scroll_bottom = bottom;
+                    self.move_to(0, 0);
+                }
+            },
+            's' => self.save_cursor(),
+            'u' => self.restore_cursor(),
+            _ => {},
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], _ignore: bool, byte: u8) {
+        if !intermediates.is_empty() {
+            return;
+        }
+        match byte {
+            b'7' => self.save_cursor(),
+            b'8' => self.restore_cursor(),
+            b'D' => self.linefeed(),
+            b'E' => {
+                self.linefeed();
+                self.cursor.col = 0;
+            },
+            b'M' => self.reverse_index(),
+            b'c' => self.reset(),
+            _ => {},
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]], _bell_terminated: bool) {
+        if let [kind, title, ..] = params {
+            if *kind == b"0" || *kind == b"2" {
+                self.title = String::from_utf8_lossy(title).into_owned();
+            }
+        }
+    }
+}
+
+/// A screen plus the escape-sequence parser feeding it.
+pub struct VirtualTerminal {
+    parser: vte::Parser,
+    screen: Screen,
+}
+
+impl VirtualTerminal {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Self { parser: vte::Parser::new(), screen: Screen::new(rows, cols) }
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.parser.advance(&mut self.screen, *byte);
+        }
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        self.screen.resize(rows, cols);
+    }
+}
+
+fn push_bounded(scrollback: &mut VecDeque<Vec<Cell>>, line: Vec<Cell>, limit: usize) {
+    if scrollback.len() == limit {
+        scrollback.pop_front();
+    }
+    scrollback.push_back(line);
+}
+
+fn resize_grid(grid: &mut Vec<Vec<Cell>>, rows: usize, cols: usize) {
+    grid.resize_with(rows, || vec![Cell::default(); cols]);
+    for line in grid.iter_mut() {
+        line.resize(cols, Cell::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn terminal(rows: usize, cols: usize, bytes: &str) -> VirtualTerminal {
+        let mut terminal = VirtualTerminal::new(rows, cols);
+        terminal.feed(bytes.as_bytes());
+        terminal
+    }
+
+    fn cursor(terminal: &VirtualTerminal) -> (usize, usize) {
+        let Cursor { row, col } = terminal.screen().cursor();
+        (row, col)
+    }
+
+    #[test]
+    fn cursor_position_and_erase() {
+        let mut t = terminal(3, 10, "abcdef\r\nghijkl\r\nmnopqr");
+        t.feed(b"\x1b[H");
+        assert_eq!(cursor(&t), (0, 0));
+        t.feed(b"\x1b[99;99H");
+        assert_eq!(cursor(&t), (2, 9));
+
+        // ED 0 clears from the cursor to the end of the screen
+        t.feed(b"\x1b[2;3H\x1b[J");
+        assert_eq!(t.screen().contents(), "abcdef\ngh\n");
+        assert_eq!(cursor(&t), (1, 2));
+        // EL 1 clears up to and including the cursor, EL 0 from it
+        t.feed(b"\x1b[1;3H\x1b[1K");
+        assert_eq!(t.screen().contents(), "   def\ngh\n");
+        t.feed(b"\x1b[1;5H\x1b[K");
+        assert_eq!(t.screen().contents(), "   d\ngh\n");
+        t.feed(b"\x1b[2J");
+        assert_eq!(t.screen().contents(), "\n\n");
+        assert_eq!(cursor(&t), (0, 4));
+    }
+
+    #[test]
+    fn wrap_is_deferred_and_wide_characters_move_whole() {
+        let mut t = terminal(2, 5, "abcde");
+        assert_eq!(cursor(&t), (0, 4));
+        assert_eq!(t.screen().contents(), "abcde\n");
+        t.feed(b"f");
+        assert_eq!(t.screen().contents(), "abcde\nf");
+
+        // Too wide for the last column: the character wraps rather than splitting
+        let t = terminal(2, 5, "abcd世");
+        assert_eq!(t.screen().contents(), "abcd\n世");
+        assert_eq!(cursor(&t), (1, 2));
+        let row = t.screen().view(0)[1];
+        assert_eq!((row[0].ch, row[0].width, row[1].width), ('世', 2, 0));
+
+        let mut t = terminal(2, 5, "abc世");
+        assert_eq!(t.screen().contents(), "abc世\n");
+        t.feed(b"x");
+        assert_eq!(t.screen().contents(), "abc世\nx");
+
+        // Wrapping off the bottom scrolls into history
+        let t = terminal(2, 5, "abcdefghijk");
+        assert_eq!(t.screen().contents(), "fghij\nk");
+        assert_eq!(t.screen().scrollback_len(), 1);
+    }
+
+    #[test]
+    fn scroll_region_confines_inserted_and_deleted_lines() {
+        let mut t = terminal(5, 10, "1\r\n2\r\n3\r\n4\r\n5");
+        t.feed(b"\x1b[2;4r");
+        assert_eq!(cursor(&t), (0, 0));
+
+        t.feed(b"\x1b[3H\x1b[L");
+        assert_eq!(t.screen().contents(), "1\n2\n\n3\n5");
+        t.feed(b"\x1b[2H\x1b[2M");
+        assert_eq!(t.screen().contents(), "1\n3\n\n\n5");
+
+        // Outside the region IL does nothing; a linefeed at its bottom scrolls only the region
+        t.feed(b"\x1b[5H\x1b[L");
+        assert_eq!(t.screen().contents(), "1\n3\n\n\n5");
+        t.feed(b"\x1b[2Hx\x1b[4H\n");
+        assert_eq!(t.screen().contents(), "1\n\n\n\n5");
+        assert_eq!(t.screen().scrollback_len(), 0);
+    }
+
+    #[test]
+    fn alternate_screen_restores_the_primary() {
+        let mut t = terminal(3, 10, "$ vim");
+        t.feed(b"\x1b[?1049h");
+        assert!(t.screen().alternate_screen());
+        assert_eq!(t.screen().contents(), "\n\n");
+
+        // Nothing drawn on the alternate screen reaches history
+        t.feed(b"\x1b[Ha\r\nb\r\nc\r\nd\r\ne");
+        assert_eq!(t.screen().contents(), "c\nd\ne");
+        assert_eq!(t.screen().scrollback_len(), 0);
+
+        t.feed(b"\x1b[?1049l");
+        assert!(!t.screen().alternate_screen());
+        assert_eq!(t.screen().contents(), "$ vim\n\n");
+        assert_eq!(cursor(&t), (0, 5));
+    }
+
+    #[test]
+    fn shrinking_in_the_alternate_screen_keeps_the_prompt() {
+        let lines: String = (0..8).map(|i| format!("line{}\r\n", i)).collect();
+        let mut t = terminal(10, 20, &format!("{}$ ", lines));
+        t.feed(b"\x1b[?1049h\x1b[Htop");
+        t.resize(4, 10);
+        assert_eq!(t.screen().size(), (4, 10));
+        assert_eq!(t.screen().contents(), "top\n\n\n");
+
+        t.feed(b"\x1b[?1049l");
+        assert_eq!(t.screen().contents(), "line5\nline6\nline7\n$");
+        assert_eq!(cursor(&t), (3, 2));
+        assert_eq!(t.screen().scrollback_len(), 5);
+    }
+}
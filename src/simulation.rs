@@ -0,0 +1,467 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinHandle;
+use super::agents::{execute_timed, Agent, AgentOutput, Consensus, Vote};
+use super::error::MatrixError;
+use super::ethics::{EthicalGuard, Inspection};
+use super::policy::Verdict;
+use super::shell::{Command, Connector, RedirectOp, Script, SimpleCommand, Span, Word};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EffectKind {
+    Create,
+    Modify,
+    Remove,
+}
+
+impl std::fmt::Display for EffectKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EffectKind::Create => write!(f, "create"),
+            EffectKind::Modify => write!(f, "modify"),
+            EffectKind::Remove => write!(f, "remove"),
+        }
+    }
+}
+
+/// A predicted file-system change. `certain` is false when the path depends on a glob,
+/// variable or command substitution the shell resolves only at run time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsEffect {
+    pub kind: EffectKind,
+    pub path: PathBuf,
+    pub recursive: bool,
+    pub certain: bool,
+    pub span: Span,
+}
+
+/// The Pre-simulation Protocol's output: what the system thinks the command means and
+/// does, shown to the operator before anything runs. The guard's part is ready at once; agent
+/// opinions arrive while the operator reads it, see `next_opinion`.
+pub struct Simulation {
+    pub command: String,
+    pub inspection: Inspection,
+    /// The guard's decision under its strictness: an error when STRICT blocks or MODERATE
+    /// quarantines a denied command; LENIENT logs it and lets it through.
+    pub guard_outcome: Result<(), MatrixError>,
+    pub interpretation: Vec<String>,
+    pub effects: Vec<FsEffect>,
+    /// Answers so far, in the order they arrived.
+    pub agent_opinions: Vec<(String, Result<AgentOutput, MatrixError>)>,
+    /// How the agents' votes combine once they have all answered.
+    pub consensus: Consensus,
+    pending: Vec<PendingAgent>,
+}
+
+struct PendingAgent {
+    name: String,
+    started: Instant,
+    handle: JoinHandle<Result<AgentOutput, MatrixError>>,
+}
+
+impl Simulation {
+    /// The first agent verdict that forbids execution. A pending confirmation is not
+    /// blocking: the operator's confirm keystroke is that confirmation.
+    pub fn blocking_error(&self) -> Option<&MatrixError> {
+        self.agent_opinions.iter().find_map(|(_, opinion)| match opinion {
+            Err(MatrixError::ConfirmationRequired { .. }) | Ok(_) => None,
+            Err(e) => Some(e),
+        })
+    }
+
+    /// The combined vote of the agents that have answered, and its support.
+    pub fn verdict(&self) -> (Vote, f32) {
+        self.consensus.decide(self.agent_opinions.iter().filter_map(|(_, opinion)| opinion.as_ref().ok()))
+    }
+
+    /// The agents' consensus is a denial.
+    pub fn denied_by_consensus(&self) -> bool {
+        self.verdict().0 == Vote::Deny
+    }
+
+    /// Agents still working, with how long each has taken so far.
+    pub fn pending_agents(&self) -> impl Iterator<Item = (&str, Duration)> {
+        self.pending.iter().map(|p| (p.name.as_str(), p.started.elapsed()))
+    }
+
+    pub fn agents_done(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// The guard allowed the command, asked for the confirmation the operator's confirm
+    /// keystroke gives, or denied it under LENIENT. Otherwise it runs only once released from
+    /// quarantine.
+    pub fn guard_permits(&self) -> bool {
+        self.guard_outcome.is_ok()
+    }
+
+    pub fn can_execute(&self) -> bool {
+        self.agents_done()
+            && self.inspection.script.is_ok()
+            && self.guard_permits()
+            && self.blocking_error().is_none()
+            && !self.denied_by_consensus()
+    }
+
+    /// Waits for the next agent to answer and appends its opinion to `agent_opinions`. Cancel-safe;
+    /// never resolves once every agent has answered.
+    pub async fn next_opinion(&mut self) {
+        if self.pending.is_empty() {
+            return std::future::pending().await;
+        }
+        let (opinion, index, _) = futures::future::select_all(self.pending.iter_mut().map(|p| &mut p.handle)).await;
+        let agent = self.pending.remove(index);
+        self.agent_opinions.push((agent.name, opinion.unwrap_or_else(|e| Err(MatrixError::AgentPanic(e.to_string())))));
+    }
+
+    /// Abandons the agents still working; each is recorded as cancelled, which blocks execution.
+    /// Returns how many were cancelled.
+    pub fn cancel_agents(&mut self) -> usize {
+        let cancelled = self.pending.len();
+        for agent in self.pending.drain(..) {
+            agent.handle.abort();
+            self.agent_opinions.push((agent.name, Err(MatrixError::Cancelled("agent stopped by the operator".to_string()))));
+        }
+        cancelled
+    }
+}
+
+impl Drop for Simulation {
+    // A dismissed dry run must not leave agents computing in the background
+    fn drop(&mut self) {
+        for agent in &self.pending {
+            agent.handle.abort();
+        }
+    }
+}
+
+/// Dry-runs `cmd`: parses it, predicts its effects and takes the guard's verdict, then starts
+/// every agent concurrently under its descriptor's deadline and returns without waiting for them.
+/// Agents run here, once; confirming the simulation executes without consulting them again.
+pub async fn simulate(cmd: &str, guard: &EthicalGuard, agents: &[Arc<dyn Agent>], consensus: Consensus, cwd: &Path) -> Simulation {
+    let inspection = guard.inspect(cmd).await;
+    let decision = &inspection.decision;
+    let guard_outcome = match decision.verdict {
+        Verdict::Deny => guard.enforce(cmd, decision.rule_id.clone().unwrap_or_default(), decision.reason.clone()).await,
+        Verdict::Allow | Verdict::Confirm => Ok(()),
+    };
+    let (interpretation, effects) = match &inspection.script {
+        Ok(script) => (interpret(script), predict_effects(script, cwd)),
+        Err(e) => (vec![format!("Could not parse command: {}", e)], vec![]),
+    };
+
+    let pending = agents
+        .iter()
+        .map(|agent| {
+            let agent = agent.clone();
+            let task = cmd.to_string();
+            let descriptor = agent.descriptor();
+            let (name, limit) = (descriptor.name.clone(), descriptor.time_limit());
+            let handle = tokio::spawn(async move {
+                match tokio::time::timeout(limit, execute_timed(agent.as_ref(), &task)).await {
+                    Ok(opinion) => opinion,
+                    Err(_) => Err(MatrixError::AgentTimeout { agent: descriptor.name, after: limit }),
+                }
+            });
+            PendingAgent { name, started: Instant::now(), handle }
+        })
+        .collect();
+
+    Simulation { command: cmd.to_string(), inspection, guard_outcome, interpretation, effects, agent_opinions: vec![], consensus, pending }
+}
+
+/// One human-readable line per pipeline, noting how it is chained to the next.
+pub fn interpret(script: &Script) -> Vec<String> {
+    let mut lines = vec![];
+    for (i, item) in script.items.iter().enumerate() {
+        let stages: Vec<String> = item.pipeline.commands.iter().map(describe_command).collect();
+        let mut line = format!("{}. {}{}", i + 1, if item.pipeline.negated { "NOT " } else { "" }, stages.join("  ──▶  "));
+        match item.connector {
+            Some(Connector::And) => line.push_str("   (next runs only if this succeeds)"),
+            Some(Connector::Or) => line.push_str("   (next runs only if this fails)"),
+            Some(Connector::Background) => line.push_str("   (runs in the background)"),
+            Some(Connector::Sequence) | None => {},
+        }
+        lines.push(line);
+    }
+    lines
+}
+
+fn describe_command(command: &Command) -> String {
+    match command {
+        Command::Simple(c) => {
+            let mut parts = vec![];
+            for (name, value) in &c.assignments {
+                parts.push(format!("{}={}", name, value.text));
+            }
+            match c.argv.first() {
+                Some(program) => parts.push(format!("run `{}`", program.text)),
+                None => parts.push("set variables".to_string()),
+            }
+            if c.argv.len() > 1 {
+                let args: Vec<&str> = c.args().iter().map(|w| w.text.as_str()).collect();
+                parts.push(format!("with [{}]", args.join(", ")));
+            }
+            for redirect in &c.redirects {
+                parts.push(describe_redirect(redirect.op, &redirect.target.text));
+            }
+            parts.join(" ")
+        },
+        Command::Subshell { body, .. } => format!("subshell {{ {} }}", interpret(body).join("; ")),
+        Command::Group { body, .. } => format!("group {{ {} }}", interpret(body).join("; ")),
+        Command::Compound { kind, bodies, .. } => {
+            let bodies: Vec<String> = bodies.iter().map(|body| interpret(body).join("; ")).collect();
+            format!("{} {{ {} }}", kind, bodies.join(" | "))
+        },
+    }
+}
+
+fn describe_redirect(op: RedirectOp, target: &str) -> String {
+    match op {
+        RedirectOp::Read => format!("reading {}", target),
+        RedirectOp::Write => format!("overwriting {}", target),
+        RedirectOp::Append => format!("appending to {}", target),
+        RedirectOp::WriteAll => format!("sending all output to {}", target),
+        RedirectOp::Duplicate => format!("duplicating fd {}", target),
+        RedirectOp::HereDoc => "with inline here-document".to_string(),
+        RedirectOp::HereString => "with inline string input".to_string(),
+        RedirectOp::ReadWrite => format!("opening {} read-write", target),
+    }
+}
+
+/// Effects that can be read off the command statically. Commands not modelled here are
+/// simply absent from the prediction; the simulation never claims more than it knows.
+pub fn predict_effects(script: &Script, cwd: &Path) -> Vec<FsEffect> {
+    let mut effects = vec![];
+    for redirect in script.redirects() {
+        if redirect.writes_file() {
+            effects.push(write_effect(&redirect.target, cwd));
+        }
+    }
+    for command in script.simple_commands() {
+        predict_command(command, cwd, &mut effects);
+    }
+    effects
+}
+
+fn resolve(word: &Word, cwd: &Path) -> PathBuf {
+    let text = match word.text.strip_prefix('~') {
+        Some(rest) => format!("{}{}", std::env::var("HOME").unwrap_or_default(), rest),
+        None => word.text.clone(),
+    };
+    cwd.join(text)
+}
+
+fn is_dynamic(word: &Word) -> bool {
+    word.glob || !word.variables.is_empty() || !word.substitutions.is_empty()
+}
+
+fn effect(kind: EffectKind, word: &Word, cwd: &Path, recursive: bool) -> FsEffect {
+    FsEffect { kind, path: resolve(word, cwd), recursive, certain: !is_dynamic(word), span: word.span }
+}
+
+// Writing to a path creates it if absent, otherwise modifies it
+fn write_effect(word: &Word, cwd: &Path) -> FsEffect {
+    let path = resolve(word, cwd);
+    let kind = if path.exists() { EffectKind::Modify } else { EffectKind::Create };
+    effect(kind, word, cwd, false)
+}
+
+fn predict_command(command: &SimpleCommand, cwd: &Path, effects: &mut Vec<FsEffect>) {
+    let Some(program) = command.program_name() else { return };
+    let args = command.args();
+    let flags: Vec<&str> = args.iter().map(|w| w.text.as_str()).filter(|a| a.starts_with('-')).collect();
+    let operands: Vec<&Word> = args.iter().filter(|w| !w.text.starts_with('-')).collect();
+    let has_flag = |short: char, long: &str| flags.iter().any(|f| *f == long || (!f.starts_with("--") && f.contains(short)));
+
+    match program {
+        "rm" => {
+            let recursive = has_flag('r', "--recursive") || has_flag('R', "--recursive");
+            effects.extend(operands.iter().map(|w| effect(EffectKind::Remove, w, cwd, recursive)));
+        },
+        "rmdir" | "unlink" | "shred" => effects.extend(operands.iter().map(|w| effect(EffectKind::Remove, w, cwd, false))),
+        "mkdir" => effects.extend(operands.iter().map(|w| effect(EffectKind::Create, w, cwd, false))),
+        "touch" | "tee" => effects.extend(operands.iter().map(|w| write_effect(w, cwd))),
+        "chmod" | "chown" | "chgrp" | "truncate" => {
+            // The first operand is the mode/owner/size, not a file
+            let recursive = has_flag('R', "--recursive");
+            let files = if program == "truncate" { &operands[..] } else { operands.get(1..).unwrap_or(&[]) };
+            effects.extend(files.iter().map(|w| effect(EffectKind::Modify, w, cwd, recursive)));
+        },
+        "sed" | "perl" if flags.iter().any(|f| f.starts_with("-i")) => {
+            effects.extend(operands.iter().skip(1).map(|w| effect(EffectKind::Modify, w, cwd, false)));
+        },
+        "cp" | "mv" | "ln" | "install" => {
+            if let Some((dest, sources)) = operands.split_last() {
+                if program == "mv" {
+                    effects.extend(sources.iter().map(|w| effect(EffectKind::Remove, w, cwd, false)));
+                }
+                let dest_path = resolve(dest, cwd);
+                if dest_path.is_dir() {
+                    // Into a directory: one new entry per source
+                    for source in sources {
+                        let name = Path::new(&source.text).file_name().map(|n| n.to_os_string()).unwrap_or_default();
+                        let path = dest_path.join(name);
+                        let kind = if path.exists() { EffectKind::Modify } else { EffectKind::Create };
+                        effects.push(FsEffect { kind, path, recursive: false, certain: !is_dynamic(source) && !is_dynamic(dest), span: dest.span });
+                    }
+                } else {
+                    effects.push(write_effect(dest, cwd));
+                }
+            }
+        },
+        "dd" => {
+            if let Some(of) = args.iter().find(|a| a.text.starts_with("of=")) {
+                let target = Word { text: of.text[3..].to_string(), ..of.clone() };
+                effects.push(write_effect(&target, cwd));
+            }
+        },
+        _ => {},
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::{AgentDescriptor, Capability};
+    use crate::policy::{Policy, Rule, RuleKind, Strictness};
+    use crate::shell;
+    use async_trait::async_trait;
+
+    struct Voter {
+        name: &'static str,
+        vote: Vote,
+        delay: Duration,
+    }
+
+    #[async_trait]
+    impl Agent for Voter {
+        fn descriptor(&self) -> AgentDescriptor {
+            AgentDescriptor::new(self.name, &[Capability::Compute], 1)
+        }
+
+        async fn execute(&self, _task: &str) -> Result<AgentOutput, MatrixError> {
+            tokio::time::sleep(self.delay).await;
+            Ok(AgentOutput::new(self.vote, 0.9, self.name))
+        }
+    }
+
+    fn voter(name: &'static str, vote: Vote, delay: Duration) -> Arc<dyn Agent> {
+        Arc::new(Voter { name, vote, delay })
+    }
+
+    async fn guard(strictness: Strictness) -> EthicalGuard {
+        let rules = vec![Rule { id: "no-curl".to_string(), reason: None, kind: RuleKind::RegexDeny { pattern: "curl".to_string() } }];
+        let guard = EthicalGuard::new(Policy::from_rules(rules).unwrap());
+        guard.set_strictness(strictness).await;
+        guard
+    }
+
+    async fn dry_run(cmd: &str, guard: &EthicalGuard, agents: &[Arc<dyn Agent>]) -> Simulation {
+        let mut simulation = simulate(cmd, guard, agents, Consensus::Unanimous, Path::new("/")).await;
+        while !simulation.agents_done() {
+            simulation.next_opinion().await;
+        }
+        simulation
+    }
+
+    #[test]
+    fn predicts_file_system_effects() {
+        let cwd = std::env::temp_dir().join(format!("agent-matrix-effects-{}", std::process::id()));
+        std::fs::create_dir_all(cwd.join("dir")).unwrap();
+        std::fs::write(cwd.join("existing"), b"").unwrap();
+
+        let script = shell::parse("echo hi > existing; echo x >> new.txt; rm -r build; mv a.txt dir; touch existing fresh; rm $X/*").unwrap();
+        let effects: Vec<_> = predict_effects(&script, &cwd).into_iter().map(|e| (e.kind, e.path, e.recursive, e.certain)).collect();
+        assert_eq!(
+            effects,
+            vec![
+                (EffectKind::Modify, cwd.join("existing"), false, true),
+                (EffectKind::Create, cwd.join("new.txt"), false, true),
+                (EffectKind::Remove, cwd.join("build"), true, true),
+                (EffectKind::Remove, cwd.join("a.txt"), false, true),
+                (EffectKind::Create, cwd.join("dir/a.txt"), false, true),
+                (EffectKind::Modify, cwd.join("existing"), false, true),
+                (EffectKind::Create, cwd.join("fresh"), false, true),
+                (EffectKind::Remove, cwd.join("$X/*"), false, false),
+            ]
+        );
+        std::fs::remove_dir_all(&cwd).unwrap();
+    }
+
+    #[test]
+    fn interprets_each_pipeline() {
+        let script = shell::parse("make && ls -l | wc -l > out; sleep 1 &").unwrap();
+        assert_eq!(
+            interpret(&script),
+            vec![
+                "1. run `make`   (next runs only if this succeeds)",
+                "2. run `ls` with [-l]  ──▶  run `wc` with [-l] overwriting out",
+                "3. run `sleep` with [1]   (runs in the background)",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn executes_once_the_agents_approve() {
+        let guard = guard(Strictness::Strict).await;
+        let simulation = dry_run("echo hi", &guard, &[voter("a", Vote::Approve, Duration::ZERO), voter("b", Vote::Abstain, Duration::ZERO)]).await;
+        assert!(simulation.can_execute());
+        assert_eq!(simulation.agent_opinions.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn consensus_denial_blocks_execution() {
+        let guard = guard(Strictness::Strict).await;
+        let simulation = dry_run("echo hi", &guard, &[voter("a", Vote::Approve, Duration::ZERO), voter("b", Vote::Deny, Duration::ZERO)]).await;
+        assert!(simulation.denied_by_consensus());
+        assert!(!simulation.can_execute());
+    }
+
+    #[tokio::test]
+    async fn cancelled_agents_block_execution() {
+        let guard = guard(Strictness::Strict).await;
+        let agents = [voter("fast", Vote::Approve, Duration::ZERO), voter("slow", Vote::Approve, Duration::from_secs(60))];
+        let mut simulation = simulate("echo hi", &guard, &agents, Consensus::Unanimous, Path::new("/")).await;
+        simulation.next_opinion().await;
+        assert!(!simulation.can_execute());
+
+        assert_eq!(simulation.cancel_agents(), 1);
+        assert!(simulation.agents_done());
+        assert!(matches!(simulation.blocking_error(), Some(MatrixError::Cancelled(_))));
+        assert!(!simulation.can_execute());
+    }
+
+    #[tokio::test]
+    async fn strictness_decides_whether_a_denied_command_runs() {
+        let agents = [voter("a", Vote::Approve, Duration::ZERO)];
+
+        let strict = guard(Strictness::Strict).await;
+        let simulation = dry_run("curl x", &strict, &agents).await;
+        assert!(matches!(simulation.guard_outcome, Err(MatrixError::EthicsViolation { .. })));
+        assert!(!simulation.can_execute());
+
+        let moderate = guard(Strictness::Moderate).await;
+        let simulation = dry_run("curl x", &moderate, &agents).await;
+        assert!(matches!(simulation.guard_outcome, Err(MatrixError::Quarantined { .. })));
+        assert!(!simulation.can_execute());
+        assert_eq!(moderate.quarantined().await.len(), 1);
+
+        // LENIENT logs the violation and proceeds
+        let lenient = guard(Strictness::Lenient).await;
+        let simulation = dry_run("curl x", &lenient, &agents).await;
+        assert_eq!(simulation.inspection.decision.verdict, Verdict::Deny);
+        assert!(simulation.guard_permits());
+        assert!(simulation.can_execute());
+        assert_eq!(lenient.lenient_log().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn unparseable_commands_never_execute() {
+        let guard = guard(Strictness::Lenient).await;
+        let simulation = dry_run("echo 'open", &guard, &[voter("a", Vote::Approve, Duration::ZERO)]).await;
+        assert!(simulation.interpretation[0].starts_with("Could not parse command"));
+        assert!(!simulation.can_execute());
+    }
+}
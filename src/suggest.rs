@@ -0,0 +1,441 @@
+use candle_core::quantized::gguf_file;
+use candle_core::{DType, Device, Tensor};
+use candle_transformers::generation::LogitsProcessor;
+use candle_transformers::models::{mixformer, quantized_llama, quantized_mixformer};
+use candle_transformers::quantized_var_builder;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokenizers::Tokenizer;
+use tokio::sync::mpsc;
+use super::error::MatrixError;
+
+/// Default cap on the wall-clock time of one suggestion, prompt included.
+pub const DEFAULT_SUGGESTION_DEADLINE: Duration = Duration::from_millis(1500);
+pub const DEFAULT_MAX_TOKENS: usize = 48;
+// Prompts keep only their last tokens so the prefill stays fast on a CPU
+const MAX_PROMPT_TOKENS: usize = 512;
+const STREAM_BUFFER: usize = 64;
+// End-of-text markers across the Llama, Phi and Gemma tokenizers
+const STOP_TOKENS: &[&str] = &["</s>", "<|endoftext|>", "<|end|>", "<eos>"];
+
+/// A causal language model producing next-token logits. Implementations keep their own
+/// key/value cache: `position` 0 starts a new sequence and later calls feed only the new
+/// tokens, `position` being how many came before them.
+pub trait SuggestionModel: Send {
+    fn name(&self) -> &str;
+
+    fn forward(&mut self, tokens: &Tensor, position: usize) -> candle_core::Result<Tensor>;
+}
+
+/// Model family of a GGUF file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Architecture {
+    /// Taken from the file's `general.architecture` metadata.
+    #[default]
+    Auto,
+    /// Llama and its derivatives, e.g. Mistral, TinyLlama, CodeLlama.
+    Llama,
+    /// Phi-1.5.
+    Phi,
+    Phi2,
+}
+
+impl std::str::FromStr for Architecture {
+    type Err = MatrixError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "auto" => Ok(Architecture::Auto),
+            "llama" => Ok(Architecture::Llama),
+            "phi" | "phi-1.5" => Ok(Architecture::Phi),
+            "phi2" | "phi-2" => Ok(Architecture::Phi2),
+            other => Err(MatrixError::Policy(format!("Unknown model architecture '{}': expected auto, llama, phi or phi2", other))),
+        }
+    }
+}
+
+impl std::fmt::Display for Architecture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Architecture::Auto => write!(f, "auto"),
+            Architecture::Llama => write!(f, "llama"),
+            Architecture::Phi => write!(f, "phi"),
+            Architecture::Phi2 => write!(f, "phi2"),
+        }
+    }
+}
+
+pub struct QuantizedLlama {
+    weights: quantized_llama::ModelWeights,
+}
+
+impl SuggestionModel for QuantizedLlama {
+    fn name(&self) -> &str {
+        "llama"
+    }
+
+    fn forward(&mut self, tokens: &Tensor, position: usize) -> candle_core::Result<Tensor> {
+        self.weights.forward(tokens, position)
+    }
+}
+
+pub struct QuantizedPhi {
+    model: quantized_mixformer::MixFormerSequentialForCausalLM,
+    architecture: Architecture,
+}
+
+impl SuggestionModel for QuantizedPhi {
+    fn name(&self) -> &str {
+        if self.architecture == Architecture::Phi2 { "phi-2" } else { "phi-1.5" }
+    }
+
+    fn forward(&mut self, tokens: &Tensor, position: usize) -> candle_core::Result<Tensor> {
+        if position == 0 {
+            self.model.clear_kv_cache();
+        }
+        self.model.forward(tokens)
+    }
+}
+
+/// Loads a quantized GGUF model for CPU inference. Nothing is fetched: the file must be local.
+pub fn load_gguf(path: &Path, architecture: Architecture) -> Result<Box<dyn SuggestionModel>, MatrixError> {
+    let context = path.display().to_string();
+    let mut file = std::fs::File::open(path).map_err(|e| MatrixError::model_load(&context, e))?;
+    let content = gguf_file::Content::read(&mut file).map_err(|e| MatrixError::model_load(&context, e))?;
+    let architecture = match architecture {
+        Architecture::Auto => detect_architecture(&content)?,
+        chosen => chosen,
+    };
+    match architecture {
+        Architecture::Llama => {
+            let weights = quantized_llama::ModelWeights::from_gguf(content, &mut file).map_err(|e| MatrixError::model_load(&context, e))?;
+            Ok(Box::new(QuantizedLlama { weights }))
+        },
+        Architecture::Phi | Architecture::Phi2 => {
+            let config = if architecture == Architecture::Phi2 { mixformer::Config::v2() } else { mixformer::Config::v1_5() };
+            let vb = quantized_var_builder::VarBuilder::from_gguf(path).map_err(|e| MatrixError::model_load(&context, e))?;
+            let model = quantized_mixformer::MixFormerSequentialForCausalLM::new(&config, vb).map_err(|e| MatrixError::model_load(&context, e))?;
+            Ok(Box::new(QuantizedPhi { model, architecture }))
+        },
+        Architecture::Auto => unreachable!("resolved above"),
+    }
+}
+
+fn detect_architecture(content: &gguf_file::Content) -> Result<Architecture, MatrixError> {
+    let name = match content.metadata.get("general.architecture") {
+        Some(gguf_file::Value::String(name)) => name.as_str(),
+        _ => "",
+    };
+    match name {
+        "llama" | "mistral" => Ok(Architecture::Llama),
+        "phi2" => Ok(Architecture::Phi2),
+        "phi" | "mixformer" => Ok(Architecture::Phi),
+        other => Err(MatrixError::model_load(
+            "gguf architecture",
+            format!("'{}' is not supported; name one of llama, phi or phi2 explicitly", other),
+        )),
+    }
+}
+
+/// How tokens are drawn and when generation gives up.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplingConfig {
+    /// 0 samples greedily.
+    pub temperature: f64,
+    /// Nucleus sampling threshold; `None` samples from the whole distribution.
+    pub top_p: Option<f64>,
+    pub max_tokens: usize,
+    /// Generation stops once this much time has passed, keeping what it has.
+    pub deadline: Duration,
+    pub seed: u64,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self { temperature: 0.2, top_p: Some(0.9), max_tokens: DEFAULT_MAX_TOKENS, deadline: DEFAULT_SUGGESTION_DEADLINE, seed: 299_792_458 }
+    }
+}
+
+impl SamplingConfig {
+    pub fn temperature(mut self, temperature: f64) -> Self {
+        self.temperature = temperature;
+        self
+    }
+
+    pub fn top_p(mut self, top_p: Option<f64>) -> Self {
+        self.top_p = top_p;
+        self
+    }
+
+    pub fn max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = deadline;
+        self
+    }
+}
+
+/// Continues command lines with a local causal model. Inference runs on a blocking thread,
+/// one generation at a time.
+pub struct Suggester {
+    model: Arc<Mutex<Box<dyn SuggestionModel>>>,
+    tokenizer: Arc<Tokenizer>,
+    sampling: SamplingConfig,
+    stop: Vec<u32>,
+    name: String,
+}
+
+impl Suggester {
+    pub fn new(model: Box<dyn SuggestionModel>, tokenizer: Tokenizer, sampling: SamplingConfig) -> Self {
+        let stop = STOP_TOKENS.iter().filter_map(|t| tokenizer.token_to_id(t)).collect();
+        let name = model.name().to_string();
+        Self { model: Arc::new(Mutex::new(model)), tokenizer: Arc::new(tokenizer), sampling, stop, name }
+    }
+
+    /// Loads a GGUF model and its `tokenizer.json` from disk.
+    pub fn load(model: &Path, tokenizer: &Path, architecture: Architecture, sampling: SamplingConfig) -> Result<Self, MatrixError> {
+        let weights = load_gguf(model, architecture)?;
+        let tokenizer = Tokenizer::from_file(tokenizer).map_err(|e| MatrixError::model_load(&tokenizer.display().to_string(), e))?;
+        Ok(Self::new(weights, tokenizer, sampling))
+    }
+
+    /// `tokenizer.json` beside the model file.
+    pub fn default_tokenizer_path(model: &Path) -> PathBuf {
+        model.with_file_name("tokenizer.json")
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn sampling(&self) -> &SamplingConfig {
+        &self.sampling
+    }
+
+    /// Starts continuing `prompt` up to the end of its line. Text arrives on the stream as it
+    /// is generated; dropping the stream stops the model at its next token.
+    pub fn suggest(&self, prompt: &str) -> SuggestionStream {
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        let (model, tokenizer, sampling, stop, prompt) = (self.model.clone(), self.tokenizer.clone(), self.sampling.clone(), self.stop.clone(), prompt.to_string());
+        let started = Instant::now();
+        tokio::task::spawn_blocking(move || {
+            let mut model = model.lock().unwrap_or_else(|e| e.into_inner());
+            if let Err(e) = generate(model.as_mut(), &tokenizer, &sampling, &stop, &prompt, started, &tx) {
+                let _ = tx.blocking_send(Err(e));
+            }
+        });
+        SuggestionStream { rx }
+    }
+}
+
+/// Text of one suggestion, piece by piece.
+pub struct SuggestionStream {
+    rx: mpsc::Receiver<Result<String, MatrixError>>,
+}
+
+impl SuggestionStream {
+    /// The next piece of text, or `None` once generation has ended. Cancel-safe.
+    pub async fn next(&mut self) -> Option<Result<String, MatrixError>> {
+        self.rx.recv().await
+    }
+}
+
+fn generate(
+    model: &mut dyn SuggestionModel,
+    tokenizer: &Tokenizer,
+    sampling: &SamplingConfig,
+    stop: &[u32],
+    prompt: &str,
+    started: Instant,
+    tx: &mpsc::Sender<Result<String, MatrixError>>,
+) -> Result<(), MatrixError> {
+    let encoding = tokenizer.encode(prompt, true).map_err(|e| MatrixError::inference("tokenize", e))?;
+    let ids = encoding.get_ids();
+    let mut tokens = ids[ids.len().saturating_sub(MAX_PROMPT_TOKENS)..].to_vec();
+    let prompt_len = tokens.len();
+    if prompt_len == 0 {
+        return Ok(());
+    }
+    let decode = |tokens: &[u32]| tokenizer.decode(tokens, true).map_err(|e| MatrixError::inference("detokenize", e));
+    // Decoding from the last prompt token keeps the leading space SentencePiece drops from a
+    // sequence's first token
+    let anchor = decode(&tokens[prompt_len - 1..])?;
+    let temperature = (sampling.temperature > 0.0).then_some(sampling.temperature);
+    let mut processor = LogitsProcessor::new(sampling.seed, temperature, sampling.top_p);
+    let mut emitted = 0;
+
+    for step in 0..sampling.max_tokens {
+        if started.elapsed() >= sampling.deadline || tx.is_closed() {
+            break;
+        }
+        let (input, position) = match step {
+            0 => (&tokens[..], 0),
+            _ => (&tokens[tokens.len() - 1..], tokens.len() - 1),
+        };
+        let input = Tensor::new(input, &Device::Cpu).and_then(|t| t.unsqueeze(0)).map_err(|e| MatrixError::inference("input tensor", e))?;
+        let logits = model
+            .forward(&input, position)
+            .and_then(|l| l.flatten_all())
+            .and_then(|l| l.to_dtype(DType::F32))
+            .map_err(|e| MatrixError::inference("forward", e))?;
+        let next = processor.sample(&logits).map_err(|e| MatrixError::inference("sample", e))?;
+        if stop.contains(&next) {
+            break;
+        }
+        tokens.push(next);
+
+        // The whole continuation is decoded each step so characters spanning tokens come out whole
+        let decoded = decode(&tokens[prompt_len - 1..])?;
+        let Some(text) = decoded.strip_prefix(anchor.as_str()) else { continue };
+        if text.ends_with('\u{FFFD}') || text.len() <= emitted || !text.is_char_boundary(emitted) {
+            continue;
+        }
+        let fresh = &text[emitted..];
+        let (piece, line_done) = match fresh.find('\n') {
+            Some(i) => (&fresh[..i], true),
+            None => (fresh, false),
+        };
+        if !piece.is_empty() && tx.blocking_send(Ok(piece.to_string())).is_err() {
+            break;
+        }
+        if line_done {
+            break;
+        }
+        emitted = text.len();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const STOP: u32 = 1;
+    const A: u32 = 2;
+    const B: u32 = 3;
+    const NEWLINE: u32 = 4;
+    // 世 is E4 B8 96, one byte-fallback token per byte
+    const WORLD: [u32; 3] = [5, 6, 7];
+    const VOCAB_SIZE: usize = 8;
+
+    // Character-level BPE with byte fallback, so a character can span several tokens
+    fn tokenizer() -> Tokenizer {
+        let json = r#"{
+            "version": "1.0",
+            "truncation": null,
+            "padding": null,
+            "added_tokens": [{ "id": 1, "content": "</s>", "single_word": false, "lstrip": false, "rstrip": false, "normalized": false, "special": true }],
+            "normalizer": null,
+            "pre_tokenizer": null,
+            "post_processor": null,
+            "decoder": { "type": "Sequence", "decoders": [{ "type": "ByteFallback" }, { "type": "Fuse" }] },
+            "model": {
+                "type": "BPE",
+                "dropout": null,
+                "unk_token": "<unk>",
+                "continuing_subword_prefix": null,
+                "end_of_word_suffix": null,
+                "fuse_unk": false,
+                "byte_fallback": true,
+                "vocab": { "<unk>": 0, "</s>": 1, "a": 2, "b": 3, "<0x0A>": 4, "<0xE4>": 5, "<0xB8>": 6, "<0x96>": 7 },
+                "merges": []
+            }
+        }"#;
+        Tokenizer::from_str(json).unwrap()
+    }
+
+    // Answers each forward pass with the next scripted token, then `b` forever
+    struct Scripted {
+        tokens: Vec<u32>,
+        calls: Arc<AtomicUsize>,
+        delay: Duration,
+    }
+
+    impl Scripted {
+        fn new(tokens: &[u32]) -> Self {
+            Self { tokens: tokens.to_vec(), calls: Arc::new(AtomicUsize::new(0)), delay: Duration::ZERO }
+        }
+    }
+
+    impl SuggestionModel for Scripted {
+        fn name(&self) -> &str {
+            "scripted"
+        }
+
+        fn forward(&mut self, _tokens: &Tensor, _position: usize) -> candle_core::Result<Tensor> {
+            let call = self.calls.fetch_add(1, Ordering::SeqCst);
+            std::thread::sleep(self.delay);
+            let mut logits = vec![0f32; VOCAB_SIZE];
+            logits[*self.tokens.get(call).unwrap_or(&B) as usize] = 10.0;
+            Tensor::new(logits.as_slice(), &Device::Cpu)
+        }
+    }
+
+    fn greedy() -> SamplingConfig {
+        SamplingConfig::default().temperature(0.0)
+    }
+
+    fn pieces(model: &mut Scripted, sampling: &SamplingConfig) -> Vec<String> {
+        let (tx, mut rx) = mpsc::channel(STREAM_BUFFER);
+        generate(model, &tokenizer(), sampling, &[STOP], "a", Instant::now(), &tx).unwrap();
+        drop(tx);
+        std::iter::from_fn(|| rx.try_recv().ok()).map(|piece| piece.unwrap()).collect()
+    }
+
+    #[test]
+    fn stops_at_a_stop_token() {
+        let mut model = Scripted::new(&[B, A, STOP, B]);
+        assert_eq!(pieces(&mut model, &greedy()).concat(), "ba");
+        assert_eq!(model.calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn stops_at_the_end_of_the_line() {
+        let mut model = Scripted::new(&[B, NEWLINE, A]);
+        assert_eq!(pieces(&mut model, &greedy()), vec!["b"]);
+        assert_eq!(model.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn characters_spanning_tokens_come_out_whole() {
+        let mut model = Scripted::new(&[A, WORLD[0], WORLD[1], WORLD[2], B, STOP]);
+        assert_eq!(pieces(&mut model, &greedy()), vec!["a", "世", "b"]);
+    }
+
+    #[test]
+    fn generation_is_capped_by_max_tokens_and_the_deadline() {
+        let mut model = Scripted::new(&[]);
+        assert_eq!(pieces(&mut model, &greedy().max_tokens(5)).concat(), "bbbbb");
+        assert_eq!(model.calls.load(Ordering::SeqCst), 5);
+
+        let mut slow = Scripted { delay: Duration::from_millis(50), ..Scripted::new(&[]) };
+        let started = Instant::now();
+        let text = pieces(&mut slow, &greedy().deadline(Duration::from_millis(120))).concat();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(!text.is_empty() && text.len() < DEFAULT_MAX_TOKENS);
+        assert_eq!(text.len(), slow.calls.load(Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn dropping_the_stream_stops_the_model() {
+        let model = Scripted { delay: Duration::from_millis(10), ..Scripted::new(&[]) };
+        let calls = model.calls.clone();
+        let suggester = Suggester::new(Box::new(model), tokenizer(), greedy().max_tokens(1000).deadline(Duration::from_secs(60)));
+        assert_eq!(suggester.name(), "scripted");
+
+        let mut stream = suggester.suggest("a");
+        assert_eq!(stream.next().await.unwrap().unwrap(), "b");
+        drop(stream);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let stopped_at = calls.load(Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(calls.load(Ordering::SeqCst), stopped_at);
+        assert!(stopped_at < 1000);
+    }
+}
@@ -0,0 +1,519 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+const KILL_RING_SIZE: usize = 16;
+const UNDO_DEPTH: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Insert,
+    Delete,
+    KillForward,
+    KillBackward,
+    Yank,
+    Other,
+}
+
+#[derive(Debug, Clone)]
+struct Snapshot {
+    text: String,
+    cursor: usize,
+}
+
+/// The command line: an Emacs-style editor over possibly multi-line text. The cursor is a byte
+/// offset that always sits on a grapheme cluster boundary, so combining marks and emoji
+/// sequences move and delete as one character.
+#[derive(Debug, Default)]
+pub struct LineEditor {
+    text: String,
+    cursor: usize,
+    kill_ring: Vec<String>,
+    // Where the last yank started and which ring entry it inserted, for Alt+Y
+    yanked: Option<(usize, usize)>,
+    undo: Vec<Snapshot>,
+    redo: Vec<Snapshot>,
+    last_edit: Option<Edit>,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Byte offset of the cursor.
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Replaces `start..end` with `text` as one undoable edit, leaving the cursor after it.
+    pub fn replace(&mut self, start: usize, end: usize, text: &str) {
+        let (start, end) = (start.min(self.text.len()), end.min(self.text.len()));
+        if !self.text.is_char_boundary(start) || !self.text.is_char_boundary(end) || start > end {
+            return;
+        }
+        self.checkpoint(Edit::Other);
+        self.text.replace_range(start..end, text);
+        self.cursor = start + text.len();
+    }
+
+    /// Replaces the whole text, e.g. with a history entry, leaving the cursor at the end. Undoable.
+    pub fn set_text(&mut self, text: &str) {
+        if text == self.text {
+            return;
+        }
+        self.checkpoint(Edit::Other);
+        self.text = text.to_string();
+        self.cursor = self.text.len();
+    }
+
+    /// Empties the editor after a command is taken from it; unlike `set_text` this starts a
+    /// fresh undo history.
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+        self.undo.clear();
+        self.redo.clear();
+        self.last_edit = None;
+        self.yanked = None;
+    }
+
+    /// Inserts at the cursor. Consecutive single characters undo together, up to a space.
+    pub fn insert_str(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let word_char = text.chars().count() == 1 && !text.starts_with(char::is_whitespace);
+        self.checkpoint(if word_char { Edit::Insert } else { Edit::Other });
+        self.text.insert_str(self.cursor, text);
+        self.cursor += text.len();
+    }
+
+    /// Inserts pasted text as one undoable edit, normalizing line endings.
+    pub fn paste(&mut self, text: &str) {
+        let text = text.replace("\r\n", "\n").replace('\r', "\n");
+        self.checkpoint(Edit::Other);
+        self.text.insert_str(self.cursor, &text);
+        self.cursor += text.len();
+        self.last_edit = Some(Edit::Other);
+    }
+
+    /// Number of lines, at least one.
+    pub fn line_count(&self) -> usize {
+        self.text.split('\n').count()
+    }
+
+    /// Row and display column of the cursor, counting wide characters as two columns.
+    pub fn cursor_position(&self) -> (usize, usize) {
+        let before = &self.text[..self.cursor];
+        let row = before.matches('\n').count();
+        (row, before[self.line_start()..].width())
+    }
+
+    /// Applies an editing key. Returns false for keys the editor has no binding for, which
+    /// the caller may then handle itself.
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        if key.kind == KeyEventKind::Release {
+            return false;
+        }
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+        match key.code {
+            KeyCode::Enter if alt => self.insert_str("\n"),
+            KeyCode::Left if ctrl || alt => self.move_to(self.word_start()),
+            KeyCode::Right if ctrl || alt => self.move_to(self.word_end()),
+            KeyCode::Left => self.move_to(self.prev_boundary(self.cursor)),
+            KeyCode::Right => self.move_to(self.next_boundary(self.cursor)),
+            KeyCode::Home => self.move_to(self.line_start()),
+            KeyCode::End => self.move_to(self.line_end()),
+            KeyCode::Backspace if alt || ctrl => self.kill(self.word_start(), self.cursor),
+            KeyCode::Backspace => self.delete(self.prev_boundary(self.cursor), self.cursor),
+            KeyCode::Delete => self.delete(self.cursor, self.next_boundary(self.cursor)),
+            KeyCode::Char(c) if ctrl => match c {
+                'a' => self.move_to(self.line_start()),
+                'e' => self.move_to(self.line_end()),
+                'b' => self.move_to(self.prev_boundary(self.cursor)),
+                'f' => self.move_to(self.next_boundary(self.cursor)),
+                'd' => self.delete(self.cursor, self.next_boundary(self.cursor)),
+                'h' => self.delete(self.prev_boundary(self.cursor), self.cursor),
+                // At the end of a line Ctrl+K joins the next one, as in Emacs
+                'k' if self.cursor == self.line_end() && self.cursor < self.text.len() => self.kill(self.cursor, self.cursor + 1),
+                'k' => self.kill(self.cursor, self.line_end()),
+                'u' => self.kill(self.line_start(), self.cursor),
+                'w' => self.kill(self.whitespace_word_start(), self.cursor),
+                'y' => self.yank(),
+                'z' if key.modifiers.contains(KeyModifiers::SHIFT) => self.redo(),
+                // Terminals report Ctrl+_ as Ctrl+7
+                'z' | '_' | '7' | '/' => self.undo(),
+                _ => return false,
+            },
+            KeyCode::Char(c) if alt => match c {
+                'b' => self.move_to(self.word_start()),
+                'f' => self.move_to(self.word_end()),
+                'd' => self.kill(self.cursor, self.word_end()),
+                'y' => self.yank_pop(),
+                'z' => self.redo(),
+                _ => return false,
+            },
+            KeyCode::Char(c) => self.insert_str(c.encode_utf8(&mut [0; 4])),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Moves to the line above, keeping the display column where possible. Returns false on the first line.
+    pub fn line_up(&mut self) -> bool {
+        let start = self.line_start();
+        if start == 0 {
+            return false;
+        }
+        let (_, column) = self.cursor_position();
+        let above = self.text[..start - 1].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        self.move_to(self.offset_at_column(above, start - 1, column));
+        true
+    }
+
+    /// Moves to the line below, keeping the display column where possible. Returns false on the last line.
+    pub fn line_down(&mut self) -> bool {
+        let end = self.line_end();
+        if end == self.text.len() {
+            return false;
+        }
+        let (_, column) = self.cursor_position();
+        let below_end = self.text[end + 1..].find('\n').map(|i| end + 1 + i).unwrap_or(self.text.len());
+        self.move_to(self.offset_at_column(end + 1, below_end, column));
+        true
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(snapshot) = self.undo.pop() {
+            self.redo.push(self.snapshot());
+            self.restore(snapshot);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(snapshot) = self.redo.pop() {
+            self.undo.push(self.snapshot());
+            self.restore(snapshot);
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot { text: self.text.clone(), cursor: self.cursor }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.text = snapshot.text;
+        self.cursor = snapshot.cursor;
+        self.last_edit = None;
+        self.yanked = None;
+    }
+
+    // Saves the state before an edit unless it continues the previous edit of the same kind
+    fn checkpoint(&mut self, edit: Edit) {
+        let continues = self.last_edit == Some(edit) && matches!(edit, Edit::Insert | Edit::Delete | Edit::KillForward | Edit::KillBackward);
+        if !continues {
+            self.undo.push(self.snapshot());
+            if self.undo.len() > UNDO_DEPTH {
+                self.undo.remove(0);
+            }
+        }
+        self.redo.clear();
+        self.last_edit = Some(edit);
+        if edit != Edit::Yank {
+            self.yanked = None;
+        }
+    }
+
+    fn move_to(&mut self, offset: usize) {
+        self.cursor = offset;
+        self.last_edit = None;
+        self.yanked = None;
+    }
+
+    fn delete(&mut self, start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+        self.checkpoint(Edit::Delete);
+        self.text.replace_range(start..end, "");
+        self.cursor = start;
+    }
+
+    // Consecutive kills in one direction collect into a single ring entry
+    fn kill(&mut self, start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+        let edit = if end > self.cursor { Edit::KillForward } else { Edit::KillBackward };
+        let continues = self.last_edit == Some(edit);
+        self.checkpoint(edit);
+        let killed: String = self.text.drain(start..end).collect();
+        match self.kill_ring.last_mut() {
+            Some(last) if continues && edit == Edit::KillForward => last.push_str(&killed),
+            Some(last) if continues => last.insert_str(0, &killed),
+            _ => {
+                self.kill_ring.push(killed);
+                if self.kill_ring.len() > KILL_RING_SIZE {
+                    self.kill_ring.remove(0);
+                }
+            },
+        }
+        self.cursor = start;
+    }
+
+    fn yank(&mut self) {
+        let Some(text) = self.kill_ring.last().cloned() else { return };
+        self.checkpoint(Edit::Yank);
+        let start = self.cursor;
+        self.text.insert_str(start, &text);
+        self.cursor += text.len();
+        self.yanked = Some((start, self.kill_ring.len() - 1));
+    }
+
+    // Replaces the text just yanked with the next older kill
+    fn yank_pop(&mut self) {
+        let Some((start, index)) = self.yanked else { return };
+        let older = if index == 0 { self.kill_ring.len() - 1 } else { index - 1 };
+        let text = self.kill_ring[older].clone();
+        self.text.replace_range(start..self.cursor, &text);
+        self.cursor = start + text.len();
+        self.yanked = Some((start, older));
+    }
+
+    fn prev_boundary(&self, offset: usize) -> usize {
+        self.text[..offset].grapheme_indices(true).next_back().map(|(i, _)| i).unwrap_or(0)
+    }
+
+    fn next_boundary(&self, offset: usize) -> usize {
+        self.text[offset..].graphemes(true).next().map(|g| offset + g.len()).unwrap_or(self.text.len())
+    }
+
+    fn line_start(&self) -> usize {
+        self.text[..self.cursor].rfind('\n').map(|i| i + 1).unwrap_or(0)
+    }
+
+    fn line_end(&self) -> usize {
+        self.text[self.cursor..].find('\n').map(|i| self.cursor + i).unwrap_or(self.text.len())
+    }
+
+    // Start of the word before the cursor, words being runs of alphanumerics and underscores
+    fn word_start(&self) -> usize {
+        let graphemes: Vec<(usize, &str)> = self.text[..self.cursor].grapheme_indices(true).collect();
+        let mut i = graphemes.len();
+        while i > 0 && !is_word(graphemes[i - 1].1) {
+            i -= 1;
+        }
+        while i > 0 && is_word(graphemes[i - 1].1) {
+            i -= 1;
+        }
+        graphemes.get(i).map(|(offset, _)| *offset).unwrap_or(self.cursor)
+    }
+
+    fn word_end(&self) -> usize {
+        let mut graphemes = self.text[self.cursor..].grapheme_indices(true).peekable();
+        while graphemes.next_if(|(_, g)| !is_word(g)).is_some() {}
+        while graphemes.next_if(|(_, g)| is_word(g)).is_some() {}
+        graphemes.peek().map(|(offset, _)| self.cursor + offset).unwrap_or(self.text.len())
+    }
+
+    // Ctrl+W's notion of a word: everything back to the previous whitespace
+    fn whitespace_word_start(&self) -> usize {
+        let before = &self.text[..self.cursor];
+        let trimmed = before.trim_end_matches(char::is_whitespace);
+        trimmed.rfind(char::is_whitespace).map(|i| i + trimmed[i..].chars().next().map_or(1, char::len_utf8)).unwrap_or(0)
+    }
+
+    // The offset in `start..end` whose display column is closest to `column` without passing it
+    fn offset_at_column(&self, start: usize, end: usize, column: usize) -> usize {
+        let mut width = 0;
+        for (i, grapheme) in self.text[start..end].grapheme_indices(true) {
+            width += grapheme.width();
+            if width > column {
+                return start + i;
+            }
+        }
+        end
+    }
+}
+
+fn is_word(grapheme: &str) -> bool {
+    grapheme.chars().next().is_some_and(|c| c.is_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(editor: &mut LineEditor, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        editor.handle_key(KeyEvent::new(code, modifiers))
+    }
+
+    fn ctrl(editor: &mut LineEditor, c: char) -> bool {
+        press(editor, KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    fn alt(editor: &mut LineEditor, c: char) -> bool {
+        press(editor, KeyCode::Char(c), KeyModifiers::ALT)
+    }
+
+    fn type_str(editor: &mut LineEditor, text: &str) {
+        for c in text.chars() {
+            press(editor, KeyCode::Char(c), KeyModifiers::NONE);
+        }
+    }
+
+    #[test]
+    fn moves_and_deletes_whole_graphemes() {
+        let mut editor = LineEditor::new();
+        editor.insert_str("e\u{301}👨‍👩‍👧x");
+        press(&mut editor, KeyCode::Left, KeyModifiers::NONE);
+        press(&mut editor, KeyCode::Left, KeyModifiers::NONE);
+        assert_eq!(editor.cursor(), "e\u{301}".len());
+        press(&mut editor, KeyCode::Backspace, KeyModifiers::NONE);
+        assert_eq!(editor.text(), "👨‍👩‍👧x");
+        assert_eq!(editor.cursor(), 0);
+        press(&mut editor, KeyCode::Delete, KeyModifiers::NONE);
+        assert_eq!(editor.text(), "x");
+
+        editor.set_text("中a");
+        assert_eq!(editor.cursor_position(), (0, 3));
+    }
+
+    #[test]
+    fn word_motion_skips_punctuation() {
+        let mut editor = LineEditor::new();
+        editor.set_text("cargo build --release");
+        alt(&mut editor, 'b');
+        assert_eq!(editor.cursor(), "cargo build --".len());
+        alt(&mut editor, 'b');
+        assert_eq!(editor.cursor(), "cargo ".len());
+        press(&mut editor, KeyCode::Right, KeyModifiers::CONTROL);
+        assert_eq!(editor.cursor(), "cargo build".len());
+        alt(&mut editor, 'd');
+        assert_eq!(editor.text(), "cargo build");
+    }
+
+    #[test]
+    fn typed_words_undo_together() {
+        let mut editor = LineEditor::new();
+        type_str(&mut editor, "ls -la");
+        editor.undo();
+        assert_eq!(editor.text(), "ls ");
+        editor.undo();
+        assert_eq!(editor.text(), "ls");
+        editor.undo();
+        assert_eq!(editor.text(), "");
+        editor.redo();
+        assert_eq!(editor.text(), "ls");
+        assert_eq!(editor.cursor(), 2);
+
+        // A new edit drops what could have been redone
+        type_str(&mut editor, "!");
+        ctrl(&mut editor, 'z');
+        press(&mut editor, KeyCode::Char('z'), KeyModifiers::CONTROL | KeyModifiers::SHIFT);
+        assert_eq!(editor.text(), "ls!");
+        alt(&mut editor, 'z');
+        assert_eq!(editor.text(), "ls!");
+    }
+
+    #[test]
+    fn consecutive_kills_yank_as_one() {
+        let mut editor = LineEditor::new();
+        editor.set_text("echo one two three");
+        ctrl(&mut editor, 'w');
+        ctrl(&mut editor, 'w');
+        assert_eq!(editor.text(), "echo one ");
+        ctrl(&mut editor, 'y');
+        assert_eq!(editor.text(), "echo one two three");
+        editor.undo();
+        assert_eq!(editor.text(), "echo one ");
+    }
+
+    #[test]
+    fn yank_pop_cycles_through_the_ring() {
+        let mut editor = LineEditor::new();
+        editor.set_text("a b");
+        ctrl(&mut editor, 'a');
+        ctrl(&mut editor, 'k');
+        type_str(&mut editor, "c");
+        ctrl(&mut editor, 'u');
+        assert_eq!(editor.text(), "");
+
+        ctrl(&mut editor, 'y');
+        assert_eq!(editor.text(), "c");
+        alt(&mut editor, 'y');
+        assert_eq!(editor.text(), "a b");
+        alt(&mut editor, 'y');
+        assert_eq!(editor.text(), "c");
+
+        // Only straight after a yank
+        press(&mut editor, KeyCode::Left, KeyModifiers::NONE);
+        alt(&mut editor, 'y');
+        assert_eq!(editor.text(), "c");
+    }
+
+    #[test]
+    fn vertical_motion_keeps_the_display_column() {
+        let mut editor = LineEditor::new();
+        editor.set_text("abcdef\nxy\n中文字");
+        assert_eq!(editor.line_count(), 3);
+        assert_eq!(editor.cursor_position(), (2, 6));
+        assert!(editor.line_up());
+        assert_eq!(editor.cursor_position(), (1, 2));
+        assert!(editor.line_up());
+        assert_eq!(editor.cursor_position(), (0, 2));
+        assert!(!editor.line_up());
+        assert!(editor.line_down());
+        assert!(editor.line_down());
+        assert_eq!(editor.cursor(), "abcdef\nxy\n中".len());
+        assert_eq!(editor.cursor_position(), (2, 2));
+        assert!(!editor.line_down());
+    }
+
+    #[test]
+    fn kill_at_line_end_joins_lines() {
+        let mut editor = LineEditor::new();
+        editor.set_text("one\ntwo");
+        editor.line_up();
+        assert_eq!(editor.cursor(), 3);
+        ctrl(&mut editor, 'k');
+        assert_eq!(editor.text(), "onetwo");
+    }
+
+    #[test]
+    fn paste_and_replace_are_single_edits() {
+        let mut editor = LineEditor::new();
+        editor.paste("a\r\nb\rc");
+        assert_eq!(editor.text(), "a\nb\nc");
+        editor.undo();
+        assert_eq!(editor.text(), "");
+
+        editor.set_text("git sta");
+        editor.replace(4, 7, "status");
+        assert_eq!((editor.text(), editor.cursor()), ("git status", 10));
+        editor.undo();
+        assert_eq!(editor.text(), "git sta");
+
+        // Offsets inside a character are ignored
+        editor.set_text("é");
+        editor.replace(1, 2, "x");
+        assert_eq!(editor.text(), "é");
+    }
+
+    #[test]
+    fn leaves_unbound_keys_to_the_caller() {
+        let mut editor = LineEditor::new();
+        assert!(!ctrl(&mut editor, 'q'));
+        assert!(!press(&mut editor, KeyCode::Tab, KeyModifiers::NONE));
+        assert!(!editor.handle_key(KeyEvent::new_with_kind(KeyCode::Char('a'), KeyModifiers::NONE, KeyEventKind::Release)));
+        assert_eq!(editor.text(), "");
+        assert!(press(&mut editor, KeyCode::Enter, KeyModifiers::ALT));
+        assert_eq!(editor.text(), "\n");
+    }
+}
@@ -1,10 +1,41 @@
+mod editor;
+mod terminal;
+
 use std::sync::Arc;
 use ratatui::prelude::*;
-use ratatui::widgets::{Block, Borders, Paragraph, Tabs, List, ListItem, ListState};
-use crossterm::event::{Event, KeyCode, KeyEventKind};
-use agent_matrix::agents::Agent;
-use agent_matrix::ux::UXEngine;
-use agent_matrix::orchestration::execute_command;
+use ratatui::widgets::{Block, Borders, Paragraph, Tabs, List, ListItem, ListState, Wrap};
+use crossterm::event::{DisableBracketedPaste, EnableBracketedPaste, Event, EventStream, KeyCode, KeyEventKind, KeyModifiers};
+use futures::StreamExt;
+use crate::agents::{AgentOutput, Capability, Consensus, Vote};
+use crate::audit::{AuditEvent, AuditLog};
+use crate::completion::{Completion, CompletionKind};
+use crate::error::MatrixError;
+use crate::ethics::{EthicalGuard, GuardRecord};
+use crate::history::{ExitFilter, HistoryStore};
+use crate::policy::{Strictness, Verdict};
+use crate::pty::{self, PtyManager};
+use crate::risk::{self, Finding, Severity};
+use crate::sandbox::SandboxProfile;
+use crate::shell;
+use crate::simulation::{self, EffectKind, Simulation};
+use crate::suggest::SuggestionStream;
+use crate::ux::{HistoryMatch, UXEngine};
+use self::editor::LineEditor;
+use self::terminal::{key_to_bytes, TerminalView};
+use crate::orchestration::{execute_command, Execution, ExecutionEvent, ExecutionRecord, OutputLine, DEFAULT_CANCEL_GRACE};
+use crate::registry::AgentRegistry;
+use crate::receipt::{self, AgentVerdict, Approval, GuardVerdict, OperatorKey, Receipt};
+use crate::policy::Policy;
+use crate::watch::FileWatcher;
+
+const TAB_COUNT: usize = 5;
+const SUGGESTIONS_TAB: usize = 2;
+const QUARANTINE_TAB: usize = 4;
+const OUTPUT_SCROLLBACK: usize = 2000;
+/// Tallest the command input grows before it scrolls.
+const MAX_INPUT_ROWS: usize = 6;
+/// Repaint interval when nothing else happens, so timers and metrics stay current.
+const TICK: std::time::Duration = std::time::Duration::from_millis(250);
 
 /// Top-level UI application state - The Nexus of User Experience
 pub struct MatrixUI {
@@ -13,14 +44,88 @@ pub struct MatrixUI {
 }
 
 pub struct UIState {
-    pub input_buffer: String,
-    pub command_history: Vec<String>,
+    pub editor: LineEditor,
+    pub history: HistoryStore,
+    /// Ctrl+R search in progress; it replaces the risk panel while open.
+    pub history_search: Option<HistorySearch>,
+    /// Position of an Up/Down walk through the history, and the line being edited before it began.
+    pub history_cursor: Option<(usize, String)>,
     pub suggestion_list_state: ListState,
+    /// The model's continuation of the command line, if one was asked for.
+    pub model_suggestion: Option<ModelSuggestion>,
     pub active_tab: usize,
-    pub agents: Vec<Arc<dyn Agent>>,
+    pub agents: Arc<AgentRegistry>,
+    pub guard: Arc<EthicalGuard>,
+    pub audit: Arc<AuditLog>,
+    pub operator_key: Arc<OperatorKey>,
     pub ux_engine: Arc<UXEngine>,
-    pub vulkan_instance: Option<vulkano::instance::Instance>,
+    pub vulkan_instance: Option<Arc<vulkano::instance::Instance>>,
     pub live_metrics: LiveMetrics,
+    pub guard_view: GuardView,
+    /// Dry-run awaiting the operator's confirm keystroke; nothing executes while this is set.
+    pub pending_simulation: Option<Simulation>,
+    pub running: Option<RunningCommand>,
+    pub output: OutputPane,
+    pub terminals: PtyManager,
+    /// Keystrokes go to the embedded terminal rather than the command line.
+    pub terminal_focus: bool,
+    /// Inner size of the output pane at the last render, used to size new terminals.
+    pub terminal_size: (u16, u16),
+    /// How agent votes combine into the dry run's verdict.
+    pub consensus: Consensus,
+    /// Each agent's answer to the last dry run, for the Agent Matrix tab.
+    pub agent_votes: Vec<(String, Result<AgentOutput, String>)>,
+    /// The last completions, with the line and cursor they were computed for.
+    completion_cache: Option<(String, usize, Vec<Completion>)>,
+}
+
+/// A model continuation of the command line, drawn as ghost text after the cursor.
+pub struct ModelSuggestion {
+    /// The line it continues; any edit to the line discards it.
+    pub line: String,
+    pub text: String,
+    /// Still generating while set.
+    pub stream: Option<SuggestionStream>,
+}
+
+/// A reverse incremental history search. Abandoning it restores `draft` to the command line.
+pub struct HistorySearch {
+    pub query: String,
+    pub exit_filter: ExitFilter,
+    pub selected: usize,
+    pub draft: String,
+}
+
+pub enum Process {
+    Piped(Execution),
+    /// Running in the embedded terminal session with this id.
+    Terminal(u64),
+}
+
+/// An approved command in flight, with what its receipt will attest once it exits.
+pub struct RunningCommand {
+    pub process: Process,
+    pub approval: Approval,
+    pub guard_verdict: GuardVerdict,
+    pub agent_verdicts: Vec<AgentVerdict>,
+}
+
+/// Scrollback of the most recent command's output.
+pub struct OutputPane {
+    pub command: String,
+    /// Set when the command runs in an embedded terminal; its screen replaces `lines`.
+    pub terminal: Option<u64>,
+    pub lines: Vec<OutputLine>,
+    pub status: String,
+}
+
+/// Snapshot of the guard's state, refreshed after each command so rendering stays synchronous.
+pub struct GuardView {
+    pub strictness: Strictness,
+    pub quarantine: Vec<GuardRecord>,
+    pub quarantine_list_state: ListState,
+    pub lenient_log: Vec<GuardRecord>,
+    pub last_verdict: String,
 }
 
 pub struct LiveMetrics {
@@ -32,34 +137,73 @@ pub struct LiveMetrics {
 
 impl MatrixUI {
     pub fn new(
-        agents: Vec<Arc<dyn Agent>>,
+        agents: Arc<AgentRegistry>,
+        guard: Arc<EthicalGuard>,
+        audit: Arc<AuditLog>,
+        operator_key: Arc<OperatorKey>,
         ux_engine: Arc<UXEngine>,
-        vulkan_instance: Option<vulkano::instance::Instance>
+        vulkan_instance: Option<Arc<vulkano::instance::Instance>>,
+        history: HistoryStore,
     ) -> Self {
         let terminal = Terminal::new(CrosstermBackend::new(std::io::stdout())).unwrap();
         let mut ui = Self {
-            state: UIState::new(agents, ux_engine, vulkan_instance),
+            state: UIState::new(agents, guard, audit, operator_key, ux_engine, vulkan_instance, history),
             terminal,
         };
         let _ = ui.init_interface();
         ui
     }
 
+    pub fn set_consensus(&mut self, consensus: Consensus) {
+        self.state.consensus = consensus;
+    }
+
     fn init_interface(&mut self) -> std::io::Result<()> {
         crossterm::terminal::enable_raw_mode()?;
+        // Pastes then arrive whole instead of as keystrokes, so a pasted newline cannot submit
+        crossterm::execute!(std::io::stdout(), EnableBracketedPaste)?;
         self.terminal.clear()?;
         Ok(())
     }
 
+    /// Repaints after every keystroke, output line, agent answer, policy edit and tick; nothing
+    /// in here blocks, so background work shows up as it happens.
     pub async fn run_event_loop(&mut self) -> std::io::Result<()> {
+        self.state.refresh_guard_view().await;
+        let mut events = EventStream::new();
+        let mut tick = tokio::time::interval(TICK);
+        tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        let source = self.state.guard.policy.lock().await.source().map(|p| p.to_path_buf());
+        let mut policy_watcher = match source.as_deref().map(FileWatcher::new) {
+            Some(Ok(watcher)) => Some(watcher),
+            Some(Err(e)) => {
+                self.state.guard_view.last_verdict = format!("⚠️ Policy changes will not be picked up: {}", e);
+                None
+            },
+            None => None,
+        };
+
         loop {
             self.render_frame()?;
-            if let Event::Key(key) = crossterm::event::read()? {
-                if let Some(should_exit) = self.handle_input(key).await {
-                    if should_exit {
-                        break;
-                    }
-                }
+            tokio::select! {
+                event = events.next() => match event {
+                    Some(Ok(Event::Key(key))) => {
+                        if self.handle_input(key).await == Some(true) {
+                            break;
+                        }
+                    },
+                    Some(Ok(Event::Paste(text))) => self.handle_paste(&text),
+                    // Resizes and the rest only need the repaint
+                    Some(Ok(_)) => {},
+                    Some(Err(e)) => return Err(e),
+                    None => break,
+                },
+                activity = self.state.background() => self.state.apply(activity).await,
+                changed = watch_policy(&mut policy_watcher) => match changed {
+                    Some(path) => self.state.reload_policy(&path).await,
+                    None => policy_watcher = None,
+                },
+                _ = tick.tick() => self.state.refresh_guard_view().await,
             }
         }
         self.cleanup()
@@ -84,7 +228,7 @@ impl MatrixUI {
     }
 
     fn render_header(&self, f: &mut Frame, area: Rect) {
-        let tabs = vec!["Command Interface", "Agent Matrix", "AI Suggestions", "System Logs"];
+        let tabs = vec!["Command Interface", "Agent Matrix", "AI Suggestions", "System Logs", "Quarantine"];
         let tab_titles: Vec<&str> = tabs.iter().map(|&s| s).collect();
 
         let tabs_widget = Tabs::new(tab_titles)
@@ -103,65 +247,178 @@ impl MatrixUI {
         match self.state.active_tab {
             0 => self.render_command_interface(f, area),
             1 => self.render_agent_matrix(f, area),
-            2 => self.render_ai_suggestions(f, area),
+            SUGGESTIONS_TAB => self.render_ai_suggestions(f, area),
             3 => self.render_system_logs(f, area),
+            QUARANTINE_TAB => self.render_quarantine(f, area),
             _ => {}
         }
     }
 
-    fn render_command_interface(&self, f: &mut Frame, area: Rect) {
+    fn render_command_interface(&mut self, f: &mut Frame, area: Rect) {
+        let input_rows = self.state.editor.line_count().min(MAX_INPUT_ROWS) as u16;
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(input_rows + 2), // Input lines
+                Constraint::Percentage(45), // Risk findings or pending dry-run
+                Constraint::Min(0),         // Command output
+            ])
+            .split(area);
+
+        // Live risk analysis while typing; parse errors simply leave the line unhighlighted
+        let findings = shell::parse(self.state.editor.text())
+            .map(|script| risk::analyze(&script))
+            .unwrap_or_default();
+
+        let title = match &self.state.model_suggestion {
+            Some(ModelSuggestion { stream: Some(_), .. }) => "Command Input — ⏳ model suggesting…",
+            Some(_) if self.state.ghost().is_some() => "Command Input — →: accept suggestion",
+            _ => "Command Input",
+        };
         let input_block = Block::default()
             .borders(Borders::ALL)
-            .title("Command Input")
+            .title(title)
             .border_style(Style::default().fg(Color::Green));
 
-        let input_widget = Paragraph::new(self.state.input_buffer.as_str())
+        // Scroll so the cursor stays inside the box, vertically and horizontally
+        let inner = input_block.inner(chunks[0]);
+        let (row, column) = self.state.editor.cursor_position();
+        let scroll = (
+            (row + 1).saturating_sub(inner.height.max(1) as usize) as u16,
+            (column + 1).saturating_sub(inner.width.max(1) as usize) as u16,
+        );
+        let mut input_text = highlight_risks(self.state.editor.text(), &findings);
+        if let (Some(ghost), Some(last)) = (self.state.ghost(), input_text.lines.last_mut()) {
+            last.spans.push(ratatui::text::Span::styled(ghost.to_string(), Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC)));
+        }
+        let input_widget = Paragraph::new(input_text)
             .block(input_block)
-            .style(Style::default().fg(Color::White));
+            .style(Style::default().fg(Color::White))
+            .scroll(scroll);
+
+        f.render_widget(input_widget, chunks[0]);
+        let overlaid = self.state.history_search.is_some() || self.state.pending_simulation.is_some() || self.state.terminal_focus;
+        if !overlaid {
+            f.set_cursor_position(Position::new(inner.x + (column as u16 - scroll.1), inner.y + (row as u16 - scroll.0)));
+        }
+        let inner = Block::default().borders(Borders::ALL).inner(chunks[2]);
+        self.state.terminal_size = (inner.height.max(1), inner.width.max(1));
+        let session = self.state.output.terminal.and_then(|id| self.state.terminals.get_mut(id));
+        match session {
+            Some(session) => {
+                session.resize(inner.height.max(1), inner.width.max(1));
+                let screen_title = session.terminal().screen().title().to_string();
+                let title = format!(
+                    "Terminal — `{}` — {}{}",
+                    self.state.output.command,
+                    if screen_title.is_empty() { self.state.output.status.clone() } else { screen_title },
+                    if self.state.terminal_focus { " (Ctrl+]: release focus)" } else { " (Ctrl+]: focus)" }
+                );
+                let block = Block::default()
+                    .borders(Borders::ALL)
+                    .title(title)
+                    .border_style(Style::default().fg(if self.state.terminal_focus { Color::Cyan } else { Color::Yellow }));
+                f.render_widget(block, chunks[2]);
+                f.render_widget(TerminalView::new(session.terminal().screen(), session.scroll(), self.state.terminal_focus), inner);
+            },
+            None => render_output(f, chunks[2], &self.state.output, self.state.running.is_some()),
+        }
+
+        if let Some(search) = &self.state.history_search {
+            render_history_search(f, chunks[1], search, &self.state.search_matches());
+            return;
+        }
+        if let Some(simulation) = &self.state.pending_simulation {
+            render_simulation(f, chunks[1], simulation);
+            return;
+        }
+
+        let items: Vec<ListItem> = findings
+            .iter()
+            .map(|finding| {
+                let snippet = self.state.editor.text().get(finding.span.start..finding.span.end).unwrap_or("");
+                ListItem::new(format!("[{}] {} — `{}`", finding.severity, finding.message, snippet))
+                    .style(Style::default().fg(severity_color(finding.severity)))
+            })
+            .collect();
 
-        f.render_widget(input_widget, area);
+        let findings_block = Block::default()
+            .borders(Borders::ALL)
+            .title("Risk Analysis")
+            .border_style(Style::default().fg(Color::Green));
+
+        f.render_widget(List::new(items).block(findings_block), chunks[1]);
     }
 
     fn render_agent_matrix(&self, f: &mut Frame, area: Rect) {
-        let agent_info = format!(
-            "🛡️ Ethical Agent: Active\n⚡ Compute Agent: {}\n🧠 UX Agent: Online\n",
-            if self.state.vulkan_instance.is_some() { "GPU Accelerated" } else { "CPU Mode" }
-        );
+        let mut lines: Vec<Line> = self
+            .state
+            .agents
+            .descriptors()
+            .map(|d| {
+                let icon = if d.has(&Capability::Safety) { "🛡️" } else { "⚡" };
+                let capabilities: Vec<String> = d.capabilities.iter().map(|c| format!("{:?}", c).to_lowercase()).collect();
+                Line::raw(format!("{} {}: {} (cost {})", icon, d.name, capabilities.join(", "), d.cost))
+            })
+            .collect();
+        lines.push(Line::raw("🧠 UX Agent: Online"));
+        lines.push(Line::raw(format!("🖥️ Compute: {}", if self.state.vulkan_instance.is_some() { "GPU Accelerated" } else { "CPU Mode" })));
+
+        if !self.state.agent_votes.is_empty() {
+            lines.push(Line::raw(""));
+            lines.push(Line::styled(format!("Last dry run ({:?} consensus)", self.state.consensus), Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)));
+            for (name, vote) in &self.state.agent_votes {
+                lines.push(match vote {
+                    Ok(output) => {
+                        let (icon, color) = vote_style(output.vote);
+                        Line::styled(format!("  {} {}: {} {:.2} — {}", icon, name, output.vote, output.confidence, output.rationale), Style::default().fg(color))
+                    },
+                    Err(e) => Line::styled(format!("  ⛔ {}: {}", name, e), Style::default().fg(Color::Red)),
+                });
+            }
+        }
 
         let agents_block = Block::default()
             .borders(Borders::ALL)
             .title("Agent Status Matrix")
             .border_style(Style::default().fg(Color::Yellow));
 
-        let agents_widget = Paragraph::new(agent_info)
+        let agents_widget = Paragraph::new(lines)
             .block(agents_block)
-            .style(Style::default().fg(Color::White));
+            .style(Style::default().fg(Color::White))
+            .wrap(Wrap { trim: false });
 
         f.render_widget(agents_widget, area);
     }
 
     fn render_ai_suggestions(&mut self, f: &mut Frame, area: Rect) {
-        let suggestions = self.state.ux_engine.auto_complete(
-            &self.state.input_buffer,
-            &self.state.command_history
-        );
+        let selection = self.state.suggestion_list_state.selected().unwrap_or(0);
+        let suggestions = self.state.completions(false);
 
         let items: Vec<ListItem> = suggestions
             .iter()
             .enumerate()
             .map(|(i, s)| {
-                let style = if i == self.state.suggestion_list_state.selected().unwrap_or(0) {
+                let selected = i == selection;
+                let style = if selected {
                     Style::default().fg(Color::Black).bg(Color::White)
                 } else {
                     Style::default().fg(Color::White)
                 };
-                ListItem::new(s.as_str()).style(style)
+                let mut spans = vec![
+                    ratatui::text::Span::styled(format!("{:<10} ", s.kind), if selected { style } else { Style::default().fg(completion_color(s.kind)) }),
+                    ratatui::text::Span::raw(s.text.clone()),
+                ];
+                if let Some(detail) = &s.detail {
+                    spans.push(ratatui::text::Span::styled(format!("  {}", detail), if selected { style } else { Style::default().fg(Color::DarkGray) }));
+                }
+                ListItem::new(Line::from(spans)).style(style)
             })
             .collect();
 
         let suggestions_block = Block::default()
             .borders(Borders::ALL)
-            .title("AI-Powered Command Suggestions")
+            .title("Completions (↑/↓: Select | TAB: Accept)")
             .border_style(Style::default().fg(Color::Blue));
 
         let list = List::new(items)
@@ -173,11 +430,15 @@ impl MatrixUI {
 
     fn render_system_logs(&self, f: &mut Frame, area: Rect) {
         let log_content = format!(
-            "System Status: Online\nEthical Passes: {}\nGPU Savings: {:.1}% MB\nAvg Latency: {:.1} ms\nLast Command: {}\nIntegrity: ✅ Verified",
+            "System Status: Online\nEthical Passes: {}\nGPU Savings: {:.1}% MB\nAvg Latency: {:.1} ms\nLast Command: {}\nIntegrity: ✅ Verified\nGuard Strictness: {}\nLast Verdict: {}\nQuarantined: {}\nLenient Violations Logged: {}",
             self.state.live_metrics.ethical_passes,
             self.state.live_metrics.gpu_savings,
             self.state.live_metrics.avg_latency_ms,
-            self.state.live_metrics.last_command
+            self.state.live_metrics.last_command,
+            self.state.guard_view.strictness,
+            self.state.guard_view.last_verdict,
+            self.state.guard_view.quarantine.len(),
+            self.state.guard_view.lenient_log.len()
         );
 
         let logs_block = Block::default()
@@ -192,8 +453,41 @@ impl MatrixUI {
         f.render_widget(logs_widget, area);
     }
 
+    fn render_quarantine(&mut self, f: &mut Frame, area: Rect) {
+        let items: Vec<ListItem> = self.state.guard_view.quarantine
+            .iter()
+            .map(|r| ListItem::new(format!("#{} [{}] {} — {}", r.id, r.rule, r.command, r.reason)))
+            .collect();
+
+        let quarantine_block = Block::default()
+            .borders(Borders::ALL)
+            .title("Quarantined Commands (↑/↓ select | a: approve & run | r: reject)")
+            .border_style(Style::default().fg(Color::Red));
+
+        let list = List::new(items)
+            .block(quarantine_block)
+            .style(Style::default().fg(Color::White))
+            .highlight_style(Style::default().fg(Color::Black).bg(Color::White));
+
+        f.render_stateful_widget(list, area, &mut self.state.guard_view.quarantine_list_state);
+    }
+
     fn render_status_bar(&self, f: &mut Frame, area: Rect) {
-        let status = format!(" ESC: Switch Tabs | TAB: Select | ENTER: Execute | Ctrl+C: Quit | Buffer: {} chars", self.state.input_buffer.len());
+        let status = if let Some(search) = &self.state.history_search {
+            format!(" HISTORY SEARCH | Ctrl+R/↓: Next | ↑: Previous | TAB: Exit Filter ({}) | ENTER: Use | ESC: Cancel", search.exit_filter)
+        } else if self.state.terminal_focus {
+            " TERMINAL | Ctrl+]: Release Focus | Shift+PgUp/PgDn: Scrollback".to_string()
+        } else if self.state.pending_simulation.as_ref().is_some_and(|s| !s.agents_done()) {
+            " DRY RUN | Waiting for agents | Ctrl+C: Stop Agents | N/ESC: Cancel".to_string()
+        } else if self.state.pending_simulation.is_some() {
+            " DRY RUN | Y/ENTER: Confirm & Execute | T: Run in Terminal | N/ESC: Cancel".to_string()
+        } else if matches!(self.state.running, Some(RunningCommand { process: Process::Terminal(_), .. })) {
+            " RUNNING IN TERMINAL | Ctrl+]: Focus Terminal | Ctrl+C: Hang Up".to_string()
+        } else if self.state.running.is_some() {
+            " RUNNING | Ctrl+C: Cancel Command | ESC: Switch Tabs".to_string()
+        } else {
+            format!(" ESC: Switch Tabs | TAB: Complete | ↑/↓: History | Ctrl+R: Search | Ctrl+Space: Suggest | ENTER: Dry Run | Ctrl+C: Quit | Buffer: {} chars", self.state.editor.text().chars().count())
+        };
         let status_bar = Paragraph::new(status)
             .style(Style::default().fg(Color::DarkGray).bg(Color::Black))
             .alignment(Alignment::Center);
@@ -202,93 +496,937 @@ impl MatrixUI {
     }
 
     async fn handle_input(&mut self, key: crossterm::event::KeyEvent) -> Option<bool> {
+        if self.handle_terminal_input(key) {
+            return None;
+        }
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            match self.state.running.as_mut().map(|r| &mut r.process) {
+                Some(Process::Piped(execution)) => {
+                    execution.cancel(DEFAULT_CANCEL_GRACE);
+                    self.state.output.status = "Cancelling: SIGINT sent".to_string();
+                    return None;
+                },
+                Some(Process::Terminal(id)) => {
+                    let id = *id;
+                    if let Some(session) = self.state.terminals.get_mut(id) {
+                        session.hang_up();
+                    }
+                    self.state.output.status = "Hanging up: SIGHUP sent".to_string();
+                    return None;
+                },
+                None => {},
+            }
+            if self.state.pending_simulation.as_ref().is_some_and(|s| !s.agents_done()) {
+                self.state.stop_agents().await;
+                return None;
+            }
+        }
+        if self.state.pending_simulation.is_some() {
+            self.handle_simulation_input(key).await;
+            return None;
+        }
+        if self.state.history_search.is_some() {
+            self.handle_search_input(key);
+            return None;
+        }
+        if self.state.active_tab == QUARANTINE_TAB && self.handle_quarantine_input(key).await {
+            return None;
+        }
+        if self.state.active_tab == SUGGESTIONS_TAB && self.handle_suggestions_input(key) {
+            return None;
+        }
         match key.code {
-            KeyCode::Enter if key.kind == KeyEventKind::Press => {
+            KeyCode::Enter if key.kind == KeyEventKind::Press && !key.modifiers.contains(KeyModifiers::ALT) => {
+                self.state.history_cursor = None;
                 self.handle_command_execution().await;
             },
-            KeyCode::Char(c) => self.state.input_buffer.push(c),
-            KeyCode::Backspace => { self.state.input_buffer.pop(); },
+            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                let draft = self.state.history_cursor.take().map(|(_, draft)| draft).unwrap_or_else(|| self.state.editor.text().to_string());
+                self.state.history_search = Some(HistorySearch { query: String::new(), exit_filter: ExitFilter::Any, selected: 0, draft });
+                self.state.active_tab = 0;
+            },
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Some(true),
+            // Up and Down move between the lines of a multi-line command and reach the
+            // history only from its first or last line
+            KeyCode::Up if key.kind == KeyEventKind::Press => {
+                if !self.state.editor.line_up() {
+                    self.state.history_step(1);
+                }
+            },
+            KeyCode::Down if key.kind == KeyEventKind::Press => {
+                if !self.state.editor.line_down() {
+                    self.state.history_step(-1);
+                }
+            },
             KeyCode::Tab => self.handle_suggestion_selection(),
-            KeyCode::Esc => self.state.active_tab = (self.state.active_tab + 1) % 4,
-            KeyCode::Char('c') if key.modifiers.contains(crossterm::event::KeyModifiers::CONTROL) => return Some(true),
-            _ => {}
+            KeyCode::Esc => self.state.active_tab = (self.state.active_tab + 1) % TAB_COUNT,
+            KeyCode::Char(' ') if key.modifiers.contains(KeyModifiers::CONTROL) => self.state.request_suggestion(),
+            KeyCode::Right | KeyCode::End if key.modifiers.is_empty() && self.state.ghost().is_some() => self.state.accept_suggestion().await,
+            _ => {
+                let before = self.state.editor.text().to_string();
+                if self.state.editor.handle_key(key) && self.state.editor.text() != before {
+                    self.state.history_cursor = None;
+                    self.state.suggestion_list_state.select(Some(0));
+                    self.state.model_suggestion = None;
+                }
+            },
         }
         None
     }
 
+    // A bracketed paste lands wherever typing would: the focused terminal, the open search,
+    // or the command line as a single undoable edit
+    fn handle_paste(&mut self, text: &str) {
+        if self.state.terminal_focus {
+            if let Some(session) = self.state.output.terminal.and_then(|id| self.state.terminals.get_mut(id)) {
+                if let Err(e) = session.write_input(text.as_bytes()) {
+                    self.state.output.status = e.to_string();
+                }
+            }
+            return;
+        }
+        if let Some(search) = self.state.history_search.as_mut() {
+            search.query.extend(text.chars().filter(|c| !c.is_control()));
+            search.selected = 0;
+            return;
+        }
+        if self.state.pending_simulation.is_some() {
+            return;
+        }
+        self.state.history_cursor = None;
+        self.state.editor.paste(text);
+    }
+
+    // Typing refines the query, Ctrl+R/Down and Up move through the hits, Tab cycles the
+    // exit-status filter, Enter puts the hit on the command line and Esc restores the old line
+    fn handle_search_input(&mut self, key: crossterm::event::KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let hits = self.state.search_matches();
+        let Some(search) = self.state.history_search.as_mut() else { return };
+        match key.code {
+            KeyCode::Char('r') if ctrl => search.selected = (search.selected + 1).min(hits.len().saturating_sub(1)),
+            KeyCode::Down => search.selected = (search.selected + 1).min(hits.len().saturating_sub(1)),
+            KeyCode::Up => search.selected = search.selected.saturating_sub(1),
+            KeyCode::Tab => {
+                search.exit_filter = search.exit_filter.next();
+                search.selected = 0;
+            },
+            KeyCode::Enter => {
+                let chosen = hits.get(search.selected).map(|hit| hit.command.clone());
+                self.state.editor.set_text(&chosen.unwrap_or_else(|| search.draft.clone()));
+                self.state.history_search = None;
+            },
+            KeyCode::Esc => self.state.cancel_search(),
+            KeyCode::Char('g') | KeyCode::Char('c') if ctrl => self.state.cancel_search(),
+            KeyCode::Backspace => {
+                search.query.pop();
+                search.selected = 0;
+            },
+            KeyCode::Char(c) if !ctrl => {
+                search.query.push(c);
+                search.selected = 0;
+            },
+            _ => {},
+        }
+    }
+
+    // Ctrl+] toggles focus on a running embedded terminal; while focused every other key is
+    // forwarded to it. Returns whether the key was consumed.
+    fn handle_terminal_input(&mut self, key: crossterm::event::KeyEvent) -> bool {
+        if key.kind != KeyEventKind::Press {
+            return false;
+        }
+        let toggle = key.code == KeyCode::Char(']') && key.modifiers.contains(KeyModifiers::CONTROL);
+        let Some(session) = self.state.output.terminal.and_then(|id| self.state.terminals.get_mut(id)) else {
+            self.state.terminal_focus = false;
+            return false;
+        };
+        if !session.is_running() {
+            self.state.terminal_focus = false;
+            return false;
+        }
+        if toggle {
+            self.state.terminal_focus = !self.state.terminal_focus;
+            return true;
+        }
+        if !self.state.terminal_focus {
+            return false;
+        }
+        match key.code {
+            KeyCode::PageUp if key.modifiers.contains(KeyModifiers::SHIFT) => session.scroll_by(self.state.terminal_size.0 as isize / 2),
+            KeyCode::PageDown if key.modifiers.contains(KeyModifiers::SHIFT) => session.scroll_by(-(self.state.terminal_size.0 as isize / 2)),
+            _ => {
+                let application_cursor = session.terminal().screen().application_cursor();
+                if let Some(bytes) = key_to_bytes(key, application_cursor) {
+                    if let Err(e) = session.write_input(&bytes) {
+                        self.state.output.status = e.to_string();
+                    }
+                }
+            },
+        }
+        true
+    }
+
     async fn handle_command_execution(&mut self) {
-        if self.state.input_buffer.trim().is_empty() {
+        if self.state.editor.text().trim().is_empty() {
             return;
         }
 
         // Process the command through the agent matrix
-        let command = self.state.input_buffer.clone();
+        let command = self.state.editor.text().to_string();
         self.state.live_metrics.last_command = command.clone();
+        self.state.record(AuditEvent::CommandSubmitted { command: command.clone() }).await;
 
-        // Get AI suggestion
-        if let Ok(suggestion) = self.state.ux_engine.llm_suggest(&command).await {
-            self.state.command_history.push(format!("{} [AI: {}]", command, suggestion));
-        } else {
-            self.state.command_history.push(command.clone());
+        let cwd = std::env::current_dir().unwrap_or_default();
+        if let Err(e) = self.state.history.record(&command, &cwd).await {
+            self.state.guard_view.last_verdict = format!("⚠️ History not recorded: {}", e);
         }
 
-        // Execute command through orchestration
-        // Note: In a real implementation, this would go through the full encryption/integrity pipeline
-        let agent_results = self.state.execute_agents(&command).await;
+        self.state.model_suggestion = None;
+
+        // Pre-simulation Protocol: the agent matrix decides under the guard's strictness
+        // (STRICT blocks, MODERATE quarantines, LENIENT logs), then the operator confirms
+        let routed = self.state.agents.route(&command);
+        // Agents answer in the background; `background` files their opinions as they arrive
+        let simulation = simulation::simulate(&command, &self.state.guard, &routed, self.state.consensus, &cwd).await;
+        let decision = &simulation.inspection.decision;
+        self.state.record(AuditEvent::GuardVerdict {
+            command: command.clone(),
+            verdict: decision.verdict,
+            rule_id: decision.rule_id.clone(),
+            reason: decision.reason.clone(),
+        }).await;
+        self.state.agent_votes.clear();
+        self.state.guard_view.last_verdict = dry_run_verdict(&simulation);
+        self.state.pending_simulation = Some(simulation);
+        self.state.refresh_guard_view().await;
+    }
+
+    async fn handle_simulation_input(&mut self, key: crossterm::event::KeyEvent) {
+        if key.kind != KeyEventKind::Press {
+            return;
+        }
+        match key.code {
+            KeyCode::Char('y') | KeyCode::Char('Y') | KeyCode::Enter | KeyCode::Char('t') | KeyCode::Char('T') => {
+                if self.state.running.is_some() {
+                    self.state.guard_view.last_verdict = "⏳ Another command is still running".to_string();
+                    return;
+                }
+                let Some(simulation) = self.state.pending_simulation.take() else { return };
+                if !simulation.can_execute() {
+                    // A blocked dry run can only be dismissed; one still waiting on agents must finish first
+                    self.state.pending_simulation = Some(simulation);
+                    return;
+                }
+                // Nothing runs unless the approval made it into the audit trail
+                let approval = AuditEvent::Confirmation { command: simulation.command.clone(), approved: true, quarantine_id: None };
+                if !self.state.record(approval).await {
+                    return;
+                }
+                self.state.guard_view.last_verdict = "✅ Approved".to_string();
+                self.state.live_metrics.ethical_passes += 1;
+                let agent_verdicts = simulation.agent_opinions
+                    .iter()
+                    .map(|(agent, opinion)| AgentVerdict {
+                        agent: agent.clone(),
+                        ok: opinion.is_ok(),
+                        vote: opinion.as_ref().ok().map(|o| o.vote),
+                        detail: match opinion {
+                            Ok(output) => output.rationale.clone(),
+                            Err(e) => e.to_string(),
+                        },
+                    })
+                    .collect();
+                let guard_verdict = GuardVerdict::from(&simulation.inspection.decision);
+                // Interactive programs get a terminal automatically; 't' asks for one explicitly
+                let tty = matches!(key.code, KeyCode::Char('t') | KeyCode::Char('T'))
+                    || simulation.inspection.script.as_ref().is_ok_and(pty::wants_tty);
+                let sandbox = simulation.inspection.sandbox.as_ref();
+                self.state.run_approved(&simulation.command, Approval::DryRunConfirmed, guard_verdict, agent_verdicts, tty, sandbox).await;
+
+                // Update metrics (would use actual measurements in production)
+                self.state.live_metrics.gpu_savings += 0.5;
+                self.state.live_metrics.avg_latency_ms = 1.91;
 
-        // Update metrics (would use actual measurements in production)
-        self.state.live_metrics.ethical_passes += 1;
-        self.state.live_metrics.gpu_savings += 0.5;
-        self.state.live_metrics.avg_latency_ms = 1.91;
+                self.state.editor.clear();
+                self.state.refresh_guard_view().await;
+            },
+            KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                if let Some(mut simulation) = self.state.pending_simulation.take() {
+                    // A dry run still waiting on agents had not been blocked yet
+                    let undecided = simulation.can_execute() || !simulation.agents_done();
+                    let from = simulation.agent_opinions.len();
+                    simulation.cancel_agents();
+                    self.state.file_opinions(&simulation, from).await;
+                    if undecided {
+                        self.state.guard_view.last_verdict = "↩️ Dry run cancelled by operator".to_string();
+                        self.state.record(AuditEvent::Confirmation { command: simulation.command.clone(), approved: false, quarantine_id: None }).await;
+                    }
+                }
+            },
+            _ => {},
+        }
+    }
 
-        self.state.input_buffer.clear();
+    async fn handle_quarantine_input(&mut self, key: crossterm::event::KeyEvent) -> bool {
+        let view = &mut self.state.guard_view;
+        let selected = view.quarantine_list_state.selected().and_then(|i| view.quarantine.get(i)).map(|r| r.id);
+        match key.code {
+            KeyCode::Up => view.quarantine_list_state.select_previous(),
+            KeyCode::Down => view.quarantine_list_state.select_next(),
+            KeyCode::Char('a') => {
+                // Operator approval overrides the guard for this one command
+                if self.state.running.is_some() {
+                    self.state.guard_view.last_verdict = "⏳ Another command is still running".to_string();
+                    return true;
+                }
+                if let Some(id) = selected {
+                    if let Some(record) = self.state.guard.release(id).await {
+                        let approval = AuditEvent::Confirmation { command: record.command.clone(), approved: true, quarantine_id: Some(record.id) };
+                        if self.state.record(approval).await {
+                            self.state.guard_view.last_verdict = format!("✅ #{} approved by operator", record.id);
+                            self.state.live_metrics.last_command = record.command.clone();
+                            let guard_verdict = GuardVerdict { verdict: Verdict::Deny, rule_id: Some(record.rule.clone()), reason: record.reason.clone() };
+                            // A released command was denied, so the policy's risky profile applies
+                            let inspection = self.state.guard.inspect(&record.command).await;
+                            let tty = inspection.script.as_ref().is_ok_and(pty::wants_tty);
+                            let approval = Approval::QuarantineReleased { id: record.id };
+                            self.state.run_approved(&record.command, approval, guard_verdict, vec![], tty, inspection.sandbox.as_ref()).await;
+                        }
+                    }
+                }
+                self.state.refresh_guard_view().await;
+            },
+            KeyCode::Char('r') => {
+                if let Some(id) = selected {
+                    if let Some(record) = self.state.guard.reject(id).await {
+                        self.state.guard_view.last_verdict = format!("🗑️ #{} rejected by operator", record.id);
+                        let rejection = AuditEvent::QuarantineRejected { command: record.command, quarantine_id: record.id, rule: record.rule, reason: record.reason };
+                        self.state.record(rejection).await;
+                    }
+                }
+                self.state.refresh_guard_view().await;
+            },
+            _ => return false,
+        }
+        true
     }
 
     fn handle_suggestion_selection(&mut self) {
-        if let Some(selected) = self.state.suggestion_list_state.selected() {
-            let suggestions = self.state.ux_engine.auto_complete(&self.state.input_buffer, &self.state.command_history);
-            if let Some(suggestion) = suggestions.get(selected) {
-                self.state.input_buffer = suggestion.clone();
-            }
+        let selected = self.state.suggestion_list_state.selected().unwrap_or(0);
+        if let Some(completion) = self.state.completions(true).get(selected).cloned() {
+            self.state.editor.replace(completion.span.start, completion.span.end, &completion.text);
+            self.state.history_cursor = None;
+            self.state.suggestion_list_state.select(Some(0));
         }
     }
 
+    // Up/Down move through the completion list while it is on screen
+    fn handle_suggestions_input(&mut self, key: crossterm::event::KeyEvent) -> bool {
+        if key.kind != KeyEventKind::Press {
+            return false;
+        }
+        match key.code {
+            KeyCode::Up => self.state.suggestion_list_state.select_previous(),
+            KeyCode::Down => self.state.suggestion_list_state.select_next(),
+            _ => return false,
+        }
+        true
+    }
+
     fn cleanup(self) -> std::io::Result<()> {
+        crossterm::execute!(std::io::stdout(), DisableBracketedPaste)?;
         crossterm::terminal::disable_raw_mode()?;
         Ok(())
     }
 }
 
+// The dry-run report shown in place of the live risk list until the operator decides
+fn render_simulation(f: &mut Frame, area: Rect, simulation: &Simulation) {
+    let heading = |text: &'static str| Line::styled(text, Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD));
+    let mut lines = vec![heading("Interpretation")];
+    lines.extend(simulation.interpretation.iter().map(|l| Line::raw(format!("  {}", l))));
+
+    lines.push(heading("Predicted file-system effects"));
+    if simulation.effects.is_empty() {
+        lines.push(Line::raw("  none statically predictable"));
+    }
+    for effect in &simulation.effects {
+        let color = match effect.kind {
+            EffectKind::Create => Color::Green,
+            EffectKind::Modify => Color::Yellow,
+            EffectKind::Remove => Color::Red,
+        };
+        let mut text = format!("  {:<7} {}", effect.kind.to_string().to_uppercase(), effect.path.display());
+        if effect.recursive {
+            text.push_str(" (recursive)");
+        }
+        if !effect.certain {
+            text.push_str(" (resolved at run time)");
+        }
+        lines.push(Line::styled(text, Style::default().fg(color)));
+    }
+
+    lines.push(heading("Guard verdict"));
+    let decision = &simulation.inspection.decision;
+    let verdict_color = match decision.verdict {
+        Verdict::Allow => Color::Green,
+        Verdict::Confirm => Color::Yellow,
+        Verdict::Deny => Color::Red,
+    };
+    let rule = decision.rule_id.as_deref().map(|r| format!(" [{}]", r)).unwrap_or_default();
+    lines.push(Line::styled(format!("  {:?}{}: {}", decision.verdict, rule, decision.reason), Style::default().fg(verdict_color)));
+    for finding in &simulation.inspection.findings {
+        lines.push(Line::styled(format!("  [{}] {}", finding.severity, finding.message), Style::default().fg(severity_color(finding.severity))));
+    }
+
+    lines.push(heading("Sandbox"));
+    lines.push(match &simulation.inspection.sandbox {
+        Some(profile) => Line::styled(format!("  {}", profile.describe()), Style::default().fg(Color::Green)),
+        None => Line::styled("  none: runs with your full privileges", Style::default().fg(Color::Yellow)),
+    });
+
+    lines.push(heading("Agent votes"));
+    for (name, elapsed) in simulation.pending_agents() {
+        lines.push(Line::styled(format!("  ⏳ {}: thinking… {:.1}s", name, elapsed.as_secs_f32()), Style::default().fg(Color::DarkGray)));
+    }
+    for (name, opinion) in &simulation.agent_opinions {
+        match opinion {
+            Ok(output) => {
+                let (icon, color) = vote_style(output.vote);
+                let text = format!("  {} {}: {} {:.2} — {} ({} ms)", icon, name, output.vote, output.confidence, output.rationale, output.elapsed_ms);
+                lines.push(Line::styled(text, Style::default().fg(color)));
+                if let Some(rewrite) = &output.rewrite {
+                    lines.push(Line::styled(format!("      ↪ suggests `{}`", rewrite), Style::default().fg(Color::Cyan)));
+                }
+            },
+            Err(e @ MatrixError::ConfirmationRequired { .. }) => lines.push(Line::styled(format!("  ❔ {}: {}", name, e), Style::default().fg(Color::Yellow))),
+            Err(e) => lines.push(Line::styled(format!("  ⛔ {}: {}", name, e), Style::default().fg(Color::Red))),
+        }
+    }
+    if simulation.agents_done() {
+        let (vote, support) = simulation.verdict();
+        let (icon, color) = vote_style(vote);
+        lines.push(Line::styled(format!("  {} {:?} consensus: {} ({:.0}% support)", icon, simulation.consensus, vote, support * 100.0), Style::default().fg(color)));
+    }
+
+    let (title, border) = if !simulation.agents_done() {
+        ("Dry Run — waiting for agents | Ctrl+C: stop agents | n/Esc: cancel", Color::Yellow)
+    } else if simulation.can_execute() {
+        ("Dry Run — y/Enter: confirm & execute | n/Esc: cancel", Color::Yellow)
+    } else {
+        ("Dry Run — execution blocked | n/Esc: dismiss", Color::Red)
+    };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(title)
+        .border_style(Style::default().fg(border));
+
+    f.render_widget(Paragraph::new(lines).block(block).wrap(Wrap { trim: false }), area);
+}
+
+/// Progress made in the background while the event loop waited.
+enum Activity {
+    Output(ExecutionEvent),
+    /// The embedded terminal's screen or state changed.
+    Screen,
+    /// An agent answered the pending dry run.
+    Opinion,
+    /// More of the model's suggestion, or `None` when it is complete.
+    Suggestion(Option<Result<String, MatrixError>>),
+}
+
+// Resolves with the watched policy's path when it changes, `None` when the watcher stops;
+// never without a watcher
+async fn watch_policy(watcher: &mut Option<FileWatcher>) -> Option<std::path::PathBuf> {
+    let Some(watcher) = watcher else { return std::future::pending().await };
+    watcher.changed().await.then(|| watcher.path().to_path_buf())
+}
+
+// Ctrl+R hits, best first, with the characters the query matched highlighted and a mark for
+// how each command's last run ended
+fn render_history_search(f: &mut Frame, area: Rect, search: &HistorySearch, hits: &[HistoryMatch]) {
+    let mut lines = vec![Line::from(vec![
+        Span::styled("search: ", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
+        Span::raw(search.query.clone()),
+        Span::styled("▏", Style::default().fg(Color::Cyan)),
+    ])];
+    if hits.is_empty() {
+        lines.push(Line::styled("  no matching history", Style::default().fg(Color::DarkGray)));
+    }
+    // Keep the selection in view
+    let visible = area.height.saturating_sub(3) as usize;
+    let first = search.selected.saturating_sub(visible.saturating_sub(1));
+    for (i, hit) in hits.iter().enumerate().skip(first).take(visible) {
+        let base = if i == search.selected { Style::default().fg(Color::Black).bg(Color::White) } else { Style::default().fg(Color::White) };
+        let (mark, color) = match hit.last_exit {
+            Some(0) => ("✓ ", Color::Green),
+            Some(_) => ("✗ ", Color::Red),
+            None => ("· ", Color::DarkGray),
+        };
+        let mut spans = vec![Span::styled(mark, base.fg(color))];
+        spans.extend(hit.command.chars().enumerate().map(|(index, c)| {
+            let style = if hit.positions.contains(&index) { base.fg(Color::Yellow).add_modifier(Modifier::BOLD) } else { base };
+            Span::styled(c.to_string(), style)
+        }));
+        lines.push(Line::from(spans));
+    }
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!("History Search — {} hits, exit status: {}", hits.len(), search.exit_filter))
+        .border_style(Style::default().fg(Color::Magenta));
+    f.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+// The verdict line for a dry run, given the agent opinions in so far
+fn dry_run_verdict(simulation: &Simulation) -> String {
+    let decision = &simulation.inspection.decision;
+    match simulation.guard_outcome.as_ref().err().or(simulation.blocking_error()) {
+        None if !simulation.agents_done() => format!("⏳ Dry run — waiting for {} agent(s)", simulation.pending_agents().count()),
+        None if simulation.denied_by_consensus() => "⛔ Blocked: agents voted to deny".to_string(),
+        None if decision.verdict == Verdict::Deny => format!("🟠 Guard violation logged (LENIENT): {} — awaiting confirmation", decision.reason),
+        None => "🔍 Dry run — awaiting confirmation".to_string(),
+        Some(MatrixError::Quarantined { id, .. }) => format!("🟡 Quarantined as #{} — review in the Quarantine tab", id),
+        Some(e) => format!("⛔ Blocked: {}", e),
+    }
+}
+
+// Shows the tail of the scrollback that fits; stderr in red
+fn render_output(f: &mut Frame, area: Rect, output: &OutputPane, running: bool) {
+    let visible = area.height.saturating_sub(2) as usize;
+    let lines: Vec<Line> = output.lines[output.lines.len().saturating_sub(visible)..]
+        .iter()
+        .map(|line| match line {
+            OutputLine::Stdout(text) => Line::raw(text.as_str()),
+            OutputLine::Stderr(text) => Line::styled(text.as_str(), Style::default().fg(Color::LightRed)),
+        })
+        .collect();
+
+    let title = if output.command.is_empty() {
+        "Output".to_string()
+    } else {
+        format!("Output — `{}` — {}", output.command, output.status)
+    };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(title)
+        .border_style(Style::default().fg(if running { Color::Yellow } else { Color::Green }));
+
+    f.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+fn vote_style(vote: Vote) -> (&'static str, Color) {
+    match vote {
+        Vote::Approve => ("✅", Color::Green),
+        Vote::Deny => ("⛔", Color::Red),
+        Vote::Abstain => ("➖", Color::Gray),
+    }
+}
+
+fn completion_color(kind: CompletionKind) -> Color {
+    match kind {
+        CompletionKind::History => Color::Gray,
+        CompletionKind::Executable => Color::Green,
+        CompletionKind::Path => Color::Blue,
+        CompletionKind::Variable => Color::Magenta,
+        CompletionKind::GitRef => Color::LightRed,
+        CompletionKind::Subcommand => Color::Cyan,
+        CompletionKind::Flag => Color::Yellow,
+    }
+}
+
+fn severity_color(severity: Severity) -> Color {
+    match severity {
+        Severity::Low => Color::Gray,
+        Severity::Medium => Color::Yellow,
+        Severity::High => Color::LightRed,
+        Severity::Critical => Color::Red,
+    }
+}
+
+// Colors each byte of the input by the most severe finding covering it, one line per input line
+fn highlight_risks<'a>(input: &'a str, findings: &[Finding]) -> Text<'a> {
+    let severity_at = |offset: usize| {
+        findings
+            .iter()
+            .filter(|f| f.span.start <= offset && offset < f.span.end)
+            .map(|f| f.severity)
+            .max()
+    };
+
+    let mut lines = vec![];
+    let mut line_start = 0;
+    for line in input.split('\n') {
+        let mut spans = vec![];
+        let mut run_start = 0;
+        let mut run_severity = severity_at(line_start);
+        for (offset, _) in line.char_indices().skip(1) {
+            let severity = severity_at(line_start + offset);
+            if severity != run_severity {
+                spans.push(styled_run(&line[run_start..offset], run_severity));
+                run_start = offset;
+                run_severity = severity;
+            }
+        }
+        spans.push(styled_run(&line[run_start..], run_severity));
+        lines.push(Line::from(spans));
+        line_start += line.len() + 1;
+    }
+    Text::from(lines)
+}
+
+fn styled_run(text: &str, severity: Option<Severity>) -> ratatui::text::Span<'_> {
+    match severity {
+        Some(severity) => ratatui::text::Span::styled(text, Style::default().fg(severity_color(severity)).add_modifier(Modifier::UNDERLINED)),
+        None => ratatui::text::Span::raw(text),
+    }
+}
+
 impl UIState {
-    fn new(agents: Vec<Arc<dyn Agent>>, ux_engine: Arc<UXEngine>, vulkan_instance: Option<vulkano::instance::Instance>) -> Self {
+    fn new(agents: Arc<AgentRegistry>, guard: Arc<EthicalGuard>, audit: Arc<AuditLog>, operator_key: Arc<OperatorKey>, ux_engine: Arc<UXEngine>, vulkan_instance: Option<Arc<vulkano::instance::Instance>>, history: HistoryStore) -> Self {
         Self {
-            input_buffer: String::new(),
-            command_history: vec!["ls".to_string(), "git status".to_string()],
+            editor: LineEditor::new(),
+            history,
+            history_search: None,
+            history_cursor: None,
             suggestion_list_state: ListState::default().with_selected(Some(0)),
+            model_suggestion: None,
             active_tab: 0,
             agents,
+            guard,
+            audit,
+            operator_key,
             ux_engine,
-            vulkan_instance: vulkan_instance.map(|inst| inst), // Convert Arc to owned if needed
+            vulkan_instance,
             live_metrics: LiveMetrics {
                 ethical_passes: 0,
                 gpu_savings: 0.0,
                 avg_latency_ms: 0.0,
                 last_command: "None".to_string(),
             },
+            guard_view: GuardView {
+                strictness: Strictness::default(),
+                quarantine: vec![],
+                quarantine_list_state: ListState::default(),
+                lenient_log: vec![],
+                last_verdict: "None".to_string(),
+            },
+            pending_simulation: None,
+            running: None,
+            output: OutputPane { command: String::new(), terminal: None, lines: vec![], status: "Idle".to_string() },
+            terminals: PtyManager::new(),
+            terminal_focus: false,
+            terminal_size: (24, 80),
+            consensus: Consensus::default(),
+            agent_votes: vec![],
+            completion_cache: None,
         }
     }
 
-    async fn execute_agents(&self, command: &str) -> Vec<String> {
-        // Simplified execution for demo - would integrate with full agent orchestration
-        let mut results = Vec::new();
+    /// Completions for the word at the cursor. Providers read the filesystem and run git, so
+    /// they are asked again only once the line or the cursor has moved, not on every frame, or
+    /// when the operator `requested` them with Tab.
+    fn completions(&mut self, requested: bool) -> &[Completion] {
+        let (text, cursor) = (self.editor.text(), self.editor.cursor());
+        let stale = self.completion_cache.as_ref().is_none_or(|(line, at, _)| line != text || *at != cursor);
+        if stale || requested {
+            let completions = self.ux_engine.auto_complete(text, cursor, &self.history.commands(None), requested);
+            self.completion_cache = Some((text.to_string(), cursor, completions));
+        }
+        self.completion_cache.as_ref().map(|(_, _, completions)| completions.as_slice()).unwrap_or_default()
+    }
+
+    /// Hits for the open Ctrl+R search, ranked from the current directory.
+    fn search_matches(&self) -> Vec<HistoryMatch> {
+        let Some(search) = &self.history_search else { return vec![] };
+        let cwd = std::env::current_dir().unwrap_or_default();
+        self.ux_engine.search_history(&search.query, self.history.frecent(&cwd, search.exit_filter, std::time::SystemTime::now()))
+    }
 
-        // Execute agents in parallel (simplified)
-        for agent in &self.agents {
-            if let Ok(result) = agent.execute(command).await {
-                results.push(result);
+    fn cancel_search(&mut self) {
+        if let Some(search) = self.history_search.take() {
+            self.editor.set_text(&search.draft);
+        }
+    }
+
+    /// Up (`1`) and Down (`-1`) walk the distinct previous commands, newest first; stepping back
+    /// past the newest restores the line that was being edited.
+    fn history_step(&mut self, delta: isize) {
+        let next = match &self.history_cursor {
+            Some((index, _)) => *index as isize + delta,
+            None if delta > 0 => delta - 1,
+            None => return,
+        };
+        if next < 0 {
+            if let Some((_, draft)) = self.history_cursor.take() {
+                self.editor.set_text(&draft);
             }
+            return;
+        }
+        let Some(command) = self.history.commands(None).into_iter().nth(next as usize) else { return };
+        let draft = self.history_cursor.take().map(|(_, draft)| draft).unwrap_or_else(|| self.editor.text().to_string());
+        self.editor.set_text(&command);
+        self.history_cursor = Some((next as usize, draft));
+    }
+
+    /// Appends to the audit trail; a failed write is surfaced as the verdict line and returns false.
+    async fn record(&mut self, event: AuditEvent) -> bool {
+        match self.audit.append(event).await {
+            Ok(_) => true,
+            Err(e) => {
+                self.guard_view.last_verdict = format!("⚠️ Audit log write failed: {}", e);
+                false
+            },
+        }
+    }
+
+    /// Starts an operator-approved command, in an embedded terminal when `tty` is set and confined
+    /// by `sandbox` if the policy chose one; `finish_execution` records the outcome and files the
+    /// signed receipt when it exits.
+    async fn run_approved(
+        &mut self,
+        command: &str,
+        approval: Approval,
+        guard_verdict: GuardVerdict,
+        agent_verdicts: Vec<AgentVerdict>,
+        tty: bool,
+        sandbox: Option<&SandboxProfile>,
+    ) {
+        // The previous command's terminal has finished; its screen gives way to the new run
+        if let Some(previous) = self.output.terminal.take() {
+            self.terminals.remove(previous);
+        }
+        let status = match sandbox {
+            Some(profile) => format!("Running in sandbox '{}'", profile.name),
+            None => "Running".to_string(),
+        };
+        self.output = OutputPane { command: command.to_string(), terminal: None, lines: vec![], status };
+        let process = if tty {
+            let (rows, cols) = self.terminal_size;
+            self.terminals.spawn(command, rows, cols, sandbox).map(|id| {
+                self.output.terminal = Some(id);
+                self.terminal_focus = true;
+                Process::Terminal(id)
+            })
+        } else {
+            execute_command(command, &self.vulkan_instance, sandbox).await.map(Process::Piped)
+        };
+        match process {
+            Ok(process) => self.running = Some(RunningCommand { process, approval, guard_verdict, agent_verdicts }),
+            Err(e) => {
+                self.output.status = format!("Failed to start: {}", e);
+                self.record(AuditEvent::ExecutionFinished { command: command.to_string(), exit_code: None }).await;
+            },
         }
+    }
 
-        results
+    /// Waits for the running command or the pending dry run's agents to make progress.
+    /// Cancel-safe: whatever it consumed is in the returned value or already applied.
+    async fn background(&mut self) -> Activity {
+        let UIState { running, terminals, pending_simulation, model_suggestion, .. } = self;
+        let execution = async move {
+            match running.as_mut().map(|r| &mut r.process) {
+                Some(Process::Piped(execution)) => Activity::Output(execution.next_event().await),
+                Some(Process::Terminal(id)) => match terminals.get_mut(*id) {
+                    Some(session) => {
+                        session.changed().await;
+                        Activity::Screen
+                    },
+                    None => Activity::Screen,
+                },
+                None => std::future::pending().await,
+            }
+        };
+        let agents = async move {
+            match pending_simulation.as_mut() {
+                Some(simulation) => {
+                    simulation.next_opinion().await;
+                    Activity::Opinion
+                },
+                None => std::future::pending().await,
+            }
+        };
+        let suggestion = async move {
+            match model_suggestion.as_mut().and_then(|s| s.stream.as_mut()) {
+                Some(stream) => Activity::Suggestion(stream.next().await),
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            activity = execution => activity,
+            activity = agents => activity,
+            activity = suggestion => activity,
+        }
+    }
+
+    async fn apply(&mut self, activity: Activity) {
+        match activity {
+            Activity::Output(ExecutionEvent::Line(line)) => {
+                self.output.lines.push(line);
+                // Take the rest of a burst now rather than repainting per line
+                if let Some(Process::Piped(execution)) = self.running.as_mut().map(|r| &mut r.process) {
+                    while let Some(line) = execution.try_next_line() {
+                        self.output.lines.push(line);
+                    }
+                }
+                let overflow = self.output.lines.len().saturating_sub(OUTPUT_SCROLLBACK);
+                self.output.lines.drain(..overflow);
+            },
+            Activity::Output(ExecutionEvent::Finished(result)) => self.finish_execution(result).await,
+            Activity::Screen => self.poll_terminal().await,
+            Activity::Opinion => {
+                let Some(simulation) = self.pending_simulation.take() else { return };
+                self.file_opinions(&simulation, simulation.agent_opinions.len().saturating_sub(1)).await;
+                self.pending_simulation = Some(simulation);
+            },
+            Activity::Suggestion(Some(Ok(piece))) => {
+                if let Some(suggestion) = self.model_suggestion.as_mut() {
+                    suggestion.text.push_str(&piece);
+                }
+            },
+            Activity::Suggestion(Some(Err(e))) => {
+                self.model_suggestion = None;
+                self.guard_view.last_verdict = format!("⚠️ Suggestion failed: {}", e);
+            },
+            Activity::Suggestion(None) => {
+                let Some(suggestion) = self.model_suggestion.as_mut() else { return };
+                suggestion.stream = None;
+                if suggestion.text.trim().is_empty() {
+                    self.model_suggestion = None;
+                    return;
+                }
+                let (command, content) = (suggestion.line.clone(), suggestion.text.clone());
+                self.record(AuditEvent::ContentGenerated { command, content }).await;
+            },
+        }
+    }
+
+    /// Asks the model to continue the command line; a suggestion still generating is dropped.
+    fn request_suggestion(&mut self) {
+        let line = self.editor.text().to_string();
+        let cwd = std::env::current_dir().unwrap_or_default();
+        match self.ux_engine.llm_suggest(&line, &self.history.commands(None), &cwd) {
+            Some(stream) => self.model_suggestion = Some(ModelSuggestion { line, text: String::new(), stream: Some(stream) }),
+            None => self.guard_view.last_verdict = "⚠️ No suggestion model loaded (start with --model)".to_string(),
+        }
+    }
+
+    /// The suggestion's text while it still continues the line and the cursor is at its end.
+    fn ghost(&self) -> Option<&str> {
+        let suggestion = self.model_suggestion.as_ref()?;
+        let at_end = self.editor.cursor() == self.editor.text().len();
+        (at_end && suggestion.line == self.editor.text() && !suggestion.text.is_empty()).then_some(suggestion.text.as_str())
+    }
+
+    /// Puts the suggestion on the command line. One accepted before it finished is audited here,
+    /// since the end of its stream will never be seen.
+    async fn accept_suggestion(&mut self) {
+        let Some(suggestion) = self.model_suggestion.take() else { return };
+        self.editor.insert_str(&suggestion.text);
+        self.history_cursor = None;
+        if suggestion.stream.is_some() {
+            self.record(AuditEvent::ContentGenerated { command: suggestion.line, content: suggestion.text }).await;
+        }
+    }
+
+    /// Swaps the edited policy into the guard; a file that no longer parses leaves the old rules in force.
+    async fn reload_policy(&mut self, path: &std::path::Path) {
+        self.guard_view.last_verdict = match Policy::load(path) {
+            Ok(policy) => {
+                self.guard.reload(policy).await;
+                format!("🔄 Policy reloaded from {}", path.display())
+            },
+            Err(e) => format!("⚠️ Policy edit rejected, previous rules still apply: {}", e),
+        };
+    }
+
+    /// Gives up on the agents the pending dry run is still waiting for, which blocks it.
+    async fn stop_agents(&mut self) {
+        let Some(mut simulation) = self.pending_simulation.take() else { return };
+        let from = simulation.agent_opinions.len();
+        let stopped = simulation.cancel_agents();
+        self.file_opinions(&simulation, from).await;
+        self.output.status = format!("Stopped {} agent(s)", stopped);
+        self.pending_simulation = Some(simulation);
+    }
+
+    /// Audits the opinions from index `from` on and refreshes every view of the dry run's state.
+    async fn file_opinions(&mut self, simulation: &Simulation, from: usize) {
+        let arrived = &simulation.agent_opinions[from..];
+        if arrived.is_empty() {
+            return;
+        }
+        for (agent, opinion) in arrived {
+            let (ok, detail) = match opinion {
+                Ok(output) => (true, format!("{} ({:.2}): {}", output.vote, output.confidence, output.rationale)),
+                Err(e) => (false, e.to_string()),
+            };
+            self.record(AuditEvent::AgentResult { command: simulation.command.clone(), agent: agent.clone(), ok, detail }).await;
+        }
+        self.agent_votes = simulation.agent_opinions
+            .iter()
+            .map(|(agent, opinion)| (agent.clone(), opinion.as_ref().cloned().map_err(|e| e.to_string())))
+            .collect();
+        self.guard_view.last_verdict = dry_run_verdict(simulation);
+        // An agent consulting the guard may have quarantined the command
+        self.refresh_guard_view().await;
+    }
+
+    /// Finalizes a command running in the embedded terminal once it has exited.
+    async fn poll_terminal(&mut self) {
+        let Some(Process::Terminal(id)) = self.running.as_ref().map(|r| &r.process) else { return };
+        let id = *id;
+        let result = match self.terminals.get_mut(id) {
+            Some(session) => session.try_finish(),
+            None => Some(Err(MatrixError::AgentPanic(format!("Terminal session {} disappeared", id)))),
+        };
+        if let Some(result) = result {
+            self.finish_execution(result).await;
+        }
+    }
+
+    /// Records the outcome of the running command and files its signed receipt.
+    async fn finish_execution(&mut self, result: Result<ExecutionRecord, MatrixError>) {
+        let Some(running) = self.running.take() else { return };
+        self.terminal_focus = false;
+        let command = self.output.command.clone();
+        let execution = match result {
+            Ok(execution) => execution,
+            Err(e) => {
+                self.output.status = format!("Failed: {}", e);
+                self.record(AuditEvent::ExecutionFinished { command, exit_code: None }).await;
+                return;
+            },
+        };
+        self.output.status = match execution.exit_code {
+            Some(code) => format!("Exited with status {}", code),
+            None => "Terminated by signal".to_string(),
+        };
+        self.record(AuditEvent::ExecutionFinished { command: command.clone(), exit_code: execution.exit_code }).await;
+        let duration = execution.finished_at.duration_since(execution.started_at).unwrap_or_default();
+        if let Err(e) = self.history.finish(&command, execution.exit_code, duration).await {
+            self.guard_view.last_verdict = format!("⚠️ History not recorded: {}", e);
+        }
+        let saved = Receipt::issue(&self.operator_key, &command, &execution, running.approval, running.guard_verdict, running.agent_verdicts)
+            .and_then(|receipt| receipt.save(&receipt::default_dir()));
+        if let Err(e) = saved {
+            self.guard_view.last_verdict = format!("⚠️ Receipt not recorded: {}", e);
+        }
+    }
+
+    async fn refresh_guard_view(&mut self) {
+        let view = &mut self.guard_view;
+        view.strictness = self.guard.strictness().await;
+        view.quarantine = self.guard.quarantined().await;
+        view.lenient_log = self.guard.lenient_log().await;
+        if view.quarantine.is_empty() {
+            view.quarantine_list_state.select(None);
+        } else if view.quarantine_list_state.selected().is_none_or(|i| i >= view.quarantine.len()) {
+            view.quarantine_list_state.select(Some(view.quarantine.len() - 1));
+        }
     }
 }
@@ -0,0 +1,121 @@
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::widgets::Widget;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crate::screen::{Pen, Screen, TermColor};
+
+/// Draws an embedded terminal's screen model, optionally scrolled back into history.
+pub struct TerminalView<'a> {
+    screen: &'a Screen,
+    scroll: usize,
+    focused: bool,
+}
+
+impl<'a> TerminalView<'a> {
+    pub fn new(screen: &'a Screen, scroll: usize, focused: bool) -> Self {
+        Self { screen, scroll, focused }
+    }
+}
+
+impl Widget for TerminalView<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        for (y, row) in self.screen.view(self.scroll).iter().enumerate().take(area.height as usize) {
+            for (x, cell) in row.iter().enumerate().take(area.width as usize) {
+                // The left half of a wide character already covers this column
+                if cell.width == 0 {
+                    continue;
+                }
+                if let Some(target) = buf.cell_mut((area.x + x as u16, area.y + y as u16)) {
+                    target.set_char(cell.ch).set_style(pen_style(&cell.pen));
+                }
+            }
+        }
+
+        // Only the live screen has a meaningful cursor
+        if self.focused && self.scroll == 0 && self.screen.cursor_visible() {
+            let cursor = self.screen.cursor();
+            if (cursor.row as u16) < area.height && (cursor.col as u16) < area.width {
+                if let Some(target) = buf.cell_mut((area.x + cursor.col as u16, area.y + cursor.row as u16)) {
+                    target.set_style(Style::default().add_modifier(Modifier::REVERSED));
+                }
+            }
+        }
+    }
+}
+
+fn pen_style(pen: &Pen) -> Style {
+    let mut style = Style::default().fg(term_color(pen.fg)).bg(term_color(pen.bg));
+    for (enabled, modifier) in [
+        (pen.bold, Modifier::BOLD),
+        (pen.dim, Modifier::DIM),
+        (pen.italic, Modifier::ITALIC),
+        (pen.underline, Modifier::UNDERLINED),
+        (pen.inverse, Modifier::REVERSED),
+    ] {
+        if enabled {
+            style = style.add_modifier(modifier);
+        }
+    }
+    style
+}
+
+fn term_color(color: TermColor) -> Color {
+    match color {
+        TermColor::Default => Color::Reset,
+        TermColor::Indexed(index) => Color::Indexed(index),
+        TermColor::Rgb(r, g, b) => Color::Rgb(r, g, b),
+    }
+}
+
+/// Encodes a key press the way xterm would send it to the program.
+pub fn key_to_bytes(key: KeyEvent, application_cursor: bool) -> Option<Vec<u8>> {
+    let arrow = |code: u8| if application_cursor { vec![0x1b, b'O', code] } else { vec![0x1b, b'[', code] };
+    let bytes = match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => match c.to_ascii_lowercase() {
+            c @ 'a'..='z' => vec![c as u8 - b'a' + 1],
+            '@' | ' ' => vec![0],
+            '[' => vec![0x1b],
+            '\\' => vec![0x1c],
+            ']' => vec![0x1d],
+            _ => return None,
+        },
+        KeyCode::Char(c) => {
+            let mut bytes = if key.modifiers.contains(KeyModifiers::ALT) { vec![0x1b] } else { vec![] };
+            bytes.extend(c.to_string().as_bytes());
+            bytes
+        },
+        KeyCode::Enter => vec![b'\r'],
+        KeyCode::Backspace => vec![0x7f],
+        KeyCode::Tab => vec![b'\t'],
+        KeyCode::BackTab => b"\x1b[Z".to_vec(),
+        KeyCode::Esc => vec![0x1b],
+        KeyCode::Up => arrow(b'A'),
+        KeyCode::Down => arrow(b'B'),
+        KeyCode::Right => arrow(b'C'),
+        KeyCode::Left => arrow(b'D'),
+        KeyCode::Home => arrow(b'H'),
+        KeyCode::End => arrow(b'F'),
+        KeyCode::Insert => b"\x1b[2~".to_vec(),
+        KeyCode::Delete => b"\x1b[3~".to_vec(),
+        KeyCode::PageUp => b"\x1b[5~".to_vec(),
+        KeyCode::PageDown => b"\x1b[6~".to_vec(),
+        KeyCode::F(n @ 1..=4) => vec![0x1b, b'O', b'P' + n - 1],
+        KeyCode::F(n) => {
+            let code = match n {
+                5 => 15,
+                6 => 17,
+                7 => 18,
+                8 => 19,
+                9 => 20,
+                10 => 21,
+                11 => 23,
+                12 => 24,
+                _ => return None,
+            };
+            format!("\x1b[{}~", code).into_bytes()
+        },
+        _ => return None,
+    };
+    Some(bytes)
+}
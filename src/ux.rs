@@ -1,73 +1,97 @@
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
 use ratatui::widgets::{List, ListItem, ListState};
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use candle_core::{Device, Tensor, DType};
-use candle_transformers::generation::LogitsProcessor;
-use tokenizers::Tokenizer;
-use candle_hub::api::Api;
-use candle_transformers::models::bert::{BertModel, Config};
-use std::fs;
+use std::path::Path;
+use super::completion::{Completion, CompletionContext, CompletionEngine};
+use super::history::Frecent;
+use super::suggest::{Suggester, SuggestionStream};
+
+// Previous commands included in a suggestion prompt, for context
+const PROMPT_HISTORY: usize = 8;
+
+/// A history search hit. `positions` are the char indices the query matched, for highlighting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistoryMatch {
+    pub command: String,
+    pub positions: Vec<usize>,
+    pub score: f64,
+    pub last_exit: Option<i32>,
+}
 
 pub struct UXEngine {
     matcher: SkimMatcherV2,
-    suggestions: Arc<Mutex<Vec<String>>>,
-    tokenizer: Arc<Tokenizer>,
-    device: Device,
-    llm_model: Arc<BertModel>,
+    completions: CompletionEngine,
+    /// Local generative model; without one the terminal only completes.
+    suggester: Option<Suggester>,
+}
+
+impl Default for UXEngine {
+    fn default() -> Self {
+        Self { matcher: SkimMatcherV2::default(), completions: CompletionEngine::builtin(), suggester: None }
+    }
 }
 
 impl UXEngine {
     pub fn new() -> Self {
-        let matcher = SkimMatcherV2::default();
-        let device = Device::Cpu;
-        // Embedded vocab (no file dep; tiny BERT proxy)
-        let vocab_str = r#"{
-  "pad_token": "[PAD]",
-  "unk_token": "[UNK]",
-  "bos_token": "[CLS]",
-  "eos_token": "[SEP]",
-  "unk_id": 100,
-  "sep_token": "[SEP]",
-  "cls_token": "[CLS]",
-  "pad_token_id": 0,
-  "sep_token_id": 102,
-  "cls_token_id": 101,
-  "mask_token": "[MASK]",
-  "mask_token_id": 103
-}"#;
-        let tokenizer = Tokenizer::from_str(vocab_str).expect("Embedded vocab load");
-        let api = Api::new().unwrap();
-        let repo = api.repo("distilbert-base-uncased".to_string());
-        let config_filename = repo.get("config.json").unwrap();
-        let tokenizer_filename = repo.get("tokenizer.json").unwrap();
-        let weights_filename = repo.get("model.safetensors").unwrap();
-        let config = fs::read_to_string(config_filename).unwrap();
-        let config: Config = serde_json::from_str(&config).unwrap();
-        let real_tokenizer = Tokenizer::from_file(tokenizer_filename).unwrap();
-        let vb = unsafe { candle_nn::VarBuilder::from_mmaped_safetensors(&[weights_filename], DType::F32, &device).unwrap() };
-        let model = BertModel::load(vb, &config).unwrap();
-        Self { matcher, suggestions: Arc::new(Mutex::new(vec![])), tokenizer: Arc::new(real_tokenizer), device, llm_model: Arc::new(model) }
+        Self::default()
     }
 
-    pub fn auto_complete(&self, input: &str, history: &[String]) -> Vec<String> {
-        let mut matches = vec![];
-        for cmd in history {
-            if self.matcher.fuzzy_match(cmd, input).unwrap_or(0) > 80 {
-                matches.push(cmd.clone());
-            }
-        }
+    /// Replaces the built-in completion providers.
+    pub fn with_completions(mut self, completions: CompletionEngine) -> Self {
+        self.completions = completions;
+        self
+    }
+
+    /// Completions for the word at byte offset `cursor` of `line`, from every provider ranked
+    /// together. `history` is most recent first; `requested` is set when the operator pressed Tab.
+    pub fn auto_complete(&self, line: &str, cursor: usize, history: &[String], requested: bool) -> Vec<Completion> {
+        let cwd = std::env::current_dir().unwrap_or_default();
+        let mut ctx = CompletionContext::new(line, cursor, cwd, history);
+        ctx.requested = requested;
+        self.completions.complete(&ctx)
+    }
+
+    /// Fuzzy-matches `query` against `candidates`, ranking by match quality weighted by frecency.
+    /// An empty query matches everything and keeps the frecency order.
+    pub fn search_history(&self, query: &str, candidates: Vec<Frecent>) -> Vec<HistoryMatch> {
+        let mut matches: Vec<HistoryMatch> = candidates
+            .into_iter()
+            .filter_map(|candidate| {
+                let (quality, positions) = match query {
+                    "" => (1, vec![]),
+                    _ => self.matcher.fuzzy_indices(&candidate.command, query)?,
+                };
+                Some(HistoryMatch {
+                    score: quality as f64 * (1.0 + candidate.score.ln_1p()),
+                    command: candidate.command,
+                    positions,
+                    last_exit: candidate.last_exit,
+                })
+            })
+            .collect();
+        matches.sort_by(|a, b| b.score.total_cmp(&a.score));
         matches
     }
 
-    pub async fn llm_suggest(&self, input: &str) -> Result<String, String> {
-        // Perform real inference proof using the loaded BERT model.
-        let tokens = self.tokenizer.encode(input, true).map_err(|e| e.to_string())?.get_ids().to_vec();
-        let input_tensor = Tensor::new(&tokens[..], &self.device).map_err(|e| e.to_string())?.unsqueeze(0).map_err(|e| e.to_string())?;
-        let logits = self.llm_model.forward(&input_tensor).map_err(|e| e.to_string())?;
-        let inference_score = logits.last_hidden_state().mean(1).map_err(|e| e.to_string())?.to_scalar::<f32>().map_err(|e| e.to_string())?;
-        let suggested = format!("{} (inference score: {:.2})", input, inference_score);
-        Ok(suggested)
+    pub fn with_suggester(mut self, suggester: Suggester) -> Self {
+        self.suggester = Some(suggester);
+        self
+    }
+
+    pub fn suggester(&self) -> Option<&Suggester> {
+        self.suggester.as_ref()
+    }
+
+    /// Streams the model's continuation of `line`, prompted with the most recent of `history`
+    /// as a shell transcript. `None` when no model is loaded.
+    pub fn llm_suggest(&self, line: &str, history: &[String], cwd: &Path) -> Option<SuggestionStream> {
+        let suggester = self.suggester.as_ref()?;
+        let mut prompt = format!("# Shell session in {}\n", cwd.display());
+        for command in history.iter().take(PROMPT_HISTORY).rev() {
+            prompt.push_str(&format!("$ {}\n", command));
+        }
+        prompt.push_str("$ ");
+        prompt.push_str(line);
+        Some(suggester.suggest(&prompt))
     }
 
     pub fn render_tabs(&self, f: &mut ratatui::Frame, area: ratatui::prelude::Rect, state: &ListState, suggestions: &[String]) {
@@ -0,0 +1,48 @@
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+use super::error::MatrixError;
+
+/// Reports changes to one file. The directory is watched rather than the file, since editors
+/// often save by writing a new file and renaming it over the old one.
+pub struct FileWatcher {
+    path: PathBuf,
+    events: mpsc::UnboundedReceiver<()>,
+    // Held so notifications keep flowing
+    _watcher: RecommendedWatcher,
+}
+
+impl FileWatcher {
+    pub fn new(path: &Path) -> Result<Self, MatrixError> {
+        let path = std::fs::canonicalize(path).map_err(|e| MatrixError::io(&format!("Cannot watch {}", path.display()), e))?;
+        let dir = path.parent().unwrap_or(Path::new("/")).to_path_buf();
+        let (tx, events) = mpsc::unbounded_channel();
+        let target = path.clone();
+        let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if let Ok(event) = event {
+                if !matches!(event.kind, EventKind::Access(_)) && event.paths.iter().any(|p| p == &target) {
+                    let _ = tx.send(());
+                }
+            }
+        })
+        .map_err(|e| MatrixError::io(&format!("Cannot watch {}", path.display()), std::io::Error::other(e)))?;
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .map_err(|e| MatrixError::io(&format!("Cannot watch {}", dir.display()), std::io::Error::other(e)))?;
+        Ok(Self { path, events, _watcher: watcher })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Waits for the file to change; the burst of events one save produces counts once.
+    /// Cancel-safe. Returns `false` if the watcher has stopped.
+    pub async fn changed(&mut self) -> bool {
+        if self.events.recv().await.is_none() {
+            return false;
+        }
+        while self.events.try_recv().is_ok() {}
+        true
+    }
+}
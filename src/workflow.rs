@@ -0,0 +1,559 @@
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+use super::agents::{execute_timed, Agent, AgentOutput, InputKind, Vote};
+use super::error::MatrixError;
+use super::registry::AgentRegistry;
+
+/// What a step's failure (after retries) means for the rest of the workflow.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FailurePolicy {
+    /// Stop the workflow and return the step's error.
+    #[default]
+    Abort,
+    /// Record the failure and skip every step that depends on this one.
+    SkipDependents,
+    /// Carry on as if the step had produced this output.
+    Fallback(String),
+}
+
+/// One node of a workflow: an agent, the steps it waits for, and how hard to try.
+pub struct Step {
+    id: String,
+    agent: Arc<dyn Agent>,
+    after: Vec<String>,
+    input: Option<String>,
+    timeout: Option<Duration>,
+    retries: u32,
+    retry_delay: Duration,
+    on_failure: FailurePolicy,
+    /// The agent takes command lines, so outputs spliced into a template are shell-quoted.
+    shell_input: bool,
+}
+
+impl Step {
+    pub fn new(id: &str, agent: Arc<dyn Agent>) -> Self {
+        let shell_input = agent.descriptor().inputs.contains(&InputKind::ShellCommand);
+        Self {
+            id: id.to_string(),
+            agent,
+            after: vec![],
+            input: None,
+            timeout: None,
+            retries: 0,
+            retry_delay: Duration::from_millis(200),
+            on_failure: FailurePolicy::Abort,
+            shell_input,
+        }
+    }
+
+    /// Runs once every step in `ids` has finished.
+    pub fn after(mut self, ids: &[&str]) -> Self {
+        self.after.extend(ids.iter().map(|id| id.to_string()));
+        self
+    }
+
+    /// Builds the step's task from a template: `{task}` is the workflow's task and `{<id>}` the
+    /// payload of a dependency's output, shell-quoted if the agent takes commands. Without a
+    /// template a step gets the task if it has no dependencies, otherwise its dependencies'
+    /// outputs one per line.
+    pub fn input(mut self, template: &str) -> Self {
+        self.input = Some(template.to_string());
+        self
+    }
+
+    /// Limit on each attempt, not on the step as a whole.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn retries(mut self, retries: u32, delay: Duration) -> Self {
+        self.retries = retries;
+        self.retry_delay = delay;
+        self
+    }
+
+    pub fn on_failure(mut self, policy: FailurePolicy) -> Self {
+        self.on_failure = policy;
+        self
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    // One pass over the template, so placeholders inside an upstream output stay literal text
+    fn render_input(&self, task: &str, outputs: &HashMap<String, String>) -> String {
+        match &self.input {
+            Some(template) => {
+                let mut input = String::with_capacity(template.len());
+                let mut rest = template.as_str();
+                while let Some(open) = rest.find('{') {
+                    input.push_str(&rest[..open]);
+                    rest = &rest[open + 1..];
+                    let name = rest.find('}').map(|close| &rest[..close]);
+                    match name {
+                        Some("task") => input.push_str(task),
+                        Some(id) if self.after.iter().any(|d| d == id) => {
+                            let output = outputs.get(id).map(String::as_str).unwrap_or_default();
+                            input.push_str(&if self.shell_input { shell_quote(output) } else { output.to_string() });
+                        },
+                        _ => {
+                            input.push('{');
+                            continue;
+                        },
+                    }
+                    rest = &rest[name.map_or(0, str::len) + 1..];
+                }
+                input.push_str(rest);
+                input
+            },
+            None if self.after.is_empty() => task.to_string(),
+            None => self.after.iter().filter_map(|id| outputs.get(id)).cloned().collect::<Vec<_>>().join("\n"),
+        }
+    }
+}
+
+// Single quotes keep everything literal; an embedded quote closes, escapes and reopens
+fn shell_quote(text: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "_-./:=@%+,".contains(c);
+    if !text.is_empty() && text.chars().all(plain) {
+        return text.to_string();
+    }
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepStatus {
+    Succeeded(AgentOutput),
+    /// Failed, and the failure policy supplied this output instead.
+    FellBack { output: String, error: String },
+    Failed(String),
+    /// Not run because a dependency failed.
+    Skipped,
+}
+
+#[derive(Debug, Clone)]
+pub struct StepOutcome {
+    pub id: String,
+    pub status: StepStatus,
+    pub attempts: u32,
+    pub elapsed: Duration,
+}
+
+/// Every step's outcome, in the order the steps finished.
+#[derive(Debug, Clone, Default)]
+pub struct WorkflowReport {
+    pub steps: Vec<StepOutcome>,
+}
+
+impl WorkflowReport {
+    /// The payload a step produced or fell back to, as its dependents received it.
+    pub fn output(&self, id: &str) -> Option<&str> {
+        self.steps.iter().find(|s| s.id == id).and_then(|s| match &s.status {
+            StepStatus::Succeeded(output) => Some(output.payload()),
+            StepStatus::FellBack { output, .. } => Some(output.as_str()),
+            StepStatus::Failed(_) | StepStatus::Skipped => None,
+        })
+    }
+
+    /// The full output of a step that succeeded.
+    pub fn agent_output(&self, id: &str) -> Option<&AgentOutput> {
+        self.steps.iter().find(|s| s.id == id).and_then(|s| match &s.status {
+            StepStatus::Succeeded(output) => Some(output),
+            _ => None,
+        })
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.steps.iter().all(|s| matches!(s.status, StepStatus::Succeeded(_)))
+    }
+}
+
+// A workflow file: `[[step]]` tables naming registered agents
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WorkflowFile {
+    #[serde(default, rename = "step")]
+    steps: Vec<StepEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StepEntry {
+    id: String,
+    agent: String,
+    #[serde(default)]
+    after: Vec<String>,
+    #[serde(default)]
+    input: Option<String>,
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+    #[serde(default)]
+    retries: u32,
+    #[serde(default)]
+    retry_delay_ms: Option<u64>,
+    #[serde(default)]
+    on_failure: FailurePolicy,
+}
+
+/// A task expressed as a DAG of agent steps. Independent steps run concurrently; a step starts
+/// as soon as all of its dependencies have produced output.
+#[derive(Default)]
+pub struct Workflow {
+    steps: Vec<Arc<Step>>,
+}
+
+impl Workflow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A linear chain in which each agent receives the previous one's output.
+    pub fn pipeline(stages: Vec<(&str, Arc<dyn Agent>)>) -> Self {
+        let mut workflow = Self::new();
+        let mut previous: Option<&str> = None;
+        for (id, agent) in stages {
+            let step = Step::new(id, agent);
+            workflow = workflow.step(match previous {
+                Some(previous) => step.after(&[previous]),
+                None => step,
+            });
+            previous = Some(id);
+        }
+        workflow
+    }
+
+    /// Reads a workflow file from `path`, taking each step's agent from `registry`.
+    pub fn load(path: &Path, registry: &AgentRegistry) -> Result<Self, MatrixError> {
+        let text = std::fs::read_to_string(path).map_err(|e| MatrixError::io(&format!("Cannot read {}", path.display()), e))?;
+        Self::from_toml(&text, |name| registry.get(name).cloned())
+    }
+
+    /// Parses `[[step]]` tables (`id`, `agent`, and optionally `after`, `input`, `timeout_ms`,
+    /// `retries`, `retry_delay_ms`, `on_failure`), resolving agent names with `agent`.
+    pub fn from_toml(text: &str, agent: impl Fn(&str) -> Option<Arc<dyn Agent>>) -> Result<Self, MatrixError> {
+        let file: WorkflowFile = toml::from_str(text).map_err(|e| MatrixError::Workflow(format!("Malformed workflow TOML: {}", e)))?;
+        let mut workflow = Self::new();
+        for entry in file.steps {
+            let resolved = agent(&entry.agent).ok_or_else(|| MatrixError::Workflow(format!("Step '{}' uses unknown agent '{}'", entry.id, entry.agent)))?;
+            let mut step = Step::new(&entry.id, resolved).on_failure(entry.on_failure);
+            step.after = entry.after;
+            step.input = entry.input;
+            step.timeout = entry.timeout_ms.map(Duration::from_millis);
+            step.retries = entry.retries;
+            if let Some(delay) = entry.retry_delay_ms {
+                step.retry_delay = Duration::from_millis(delay);
+            }
+            workflow = workflow.step(step);
+        }
+        workflow.validate()?;
+        Ok(workflow)
+    }
+
+    pub fn step(mut self, step: Step) -> Self {
+        self.steps.push(Arc::new(step));
+        self
+    }
+
+    pub fn steps(&self) -> impl Iterator<Item = &Step> {
+        self.steps.iter().map(|s| s.as_ref())
+    }
+
+    /// Rejects duplicate ids, unknown dependencies and cycles.
+    pub fn validate(&self) -> Result<(), MatrixError> {
+        let mut ids = HashSet::new();
+        for step in &self.steps {
+            if !ids.insert(step.id.as_str()) {
+                return Err(MatrixError::Workflow(format!("Step '{}' is defined twice", step.id)));
+            }
+        }
+        for step in &self.steps {
+            if let Some(missing) = step.after.iter().find(|id| !ids.contains(id.as_str())) {
+                return Err(MatrixError::Workflow(format!("Step '{}' depends on unknown step '{}'", step.id, missing)));
+            }
+        }
+        // Kahn's algorithm: whatever never becomes ready sits on a cycle
+        let mut done: HashSet<&str> = HashSet::new();
+        loop {
+            let ready: Vec<&str> = self
+                .steps
+                .iter()
+                .filter(|s| !done.contains(s.id.as_str()) && s.after.iter().all(|d| done.contains(d.as_str())))
+                .map(|s| s.id.as_str())
+                .collect();
+            if ready.is_empty() {
+                break;
+            }
+            done.extend(ready);
+        }
+        match self.steps.iter().find(|s| !done.contains(s.id.as_str())) {
+            Some(step) => Err(MatrixError::Workflow(format!("Step '{}' is part of a dependency cycle", step.id))),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs the workflow on `task`. Returns the first error of a step whose policy is `Abort`,
+    /// cancelling the steps still in flight; otherwise a report covering every step.
+    pub async fn run(&self, task: &str) -> Result<WorkflowReport, MatrixError> {
+        self.validate()?;
+        let mut report = WorkflowReport::default();
+        let mut outputs: HashMap<String, String> = HashMap::new();
+        let mut failed: HashSet<String> = HashSet::new();
+        let mut started: HashSet<String> = HashSet::new();
+        let mut running = JoinSet::new();
+
+        loop {
+            for step in &self.steps {
+                if started.contains(&step.id) || !step.after.iter().all(|d| outputs.contains_key(d) || failed.contains(d)) {
+                    continue;
+                }
+                started.insert(step.id.clone());
+                if step.after.iter().any(|d| failed.contains(d)) {
+                    failed.insert(step.id.clone());
+                    report.steps.push(StepOutcome { id: step.id.clone(), status: StepStatus::Skipped, attempts: 0, elapsed: Duration::ZERO });
+                    continue;
+                }
+                let input = step.render_input(task, &outputs);
+                let step = step.clone();
+                running.spawn(async move {
+                    let started_at = Instant::now();
+                    let (result, attempts) = run_step(&step, &input).await;
+                    (step, result, attempts, started_at.elapsed())
+                });
+            }
+
+            let Some(joined) = running.join_next().await else { break };
+            let (step, result, attempts, elapsed) = joined.map_err(|e| MatrixError::AgentPanic(e.to_string()))?;
+            let status = match (result, &step.on_failure) {
+                (Ok(output), _) => {
+                    outputs.insert(step.id.clone(), output.payload().to_string());
+                    StepStatus::Succeeded(output)
+                },
+                // Dropping `running` on return aborts the steps still in flight
+                (Err(e), FailurePolicy::Abort) => return Err(e),
+                (Err(e), FailurePolicy::SkipDependents) => {
+                    failed.insert(step.id.clone());
+                    StepStatus::Failed(e.to_string())
+                },
+                (Err(e), FailurePolicy::Fallback(output)) => {
+                    outputs.insert(step.id.clone(), output.clone());
+                    StepStatus::FellBack { output: output.clone(), error: e.to_string() }
+                },
+            };
+            report.steps.push(StepOutcome { id: step.id.clone(), status, attempts, elapsed });
+        }
+        Ok(report)
+    }
+}
+
+// Guard decisions are deterministic, so only transient failures are worth another attempt
+fn retryable(error: &MatrixError) -> bool {
+    !matches!(
+        error,
+        MatrixError::EthicsViolation { .. } | MatrixError::ConfirmationRequired { .. } | MatrixError::Quarantined { .. } | MatrixError::Policy(_) | MatrixError::Cancelled(_)
+    )
+}
+
+// A deny vote fails the step like a guard violation would, so the failure policy applies
+async fn run_step(step: &Step, input: &str) -> (Result<AgentOutput, MatrixError>, u32) {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let result = match step.timeout {
+            Some(limit) => match tokio::time::timeout(limit, execute_timed(step.agent.as_ref(), input)).await {
+                Ok(result) => result,
+                Err(_) => Err(MatrixError::StepTimeout { step: step.id.clone(), after: limit }),
+            },
+            None => execute_timed(step.agent.as_ref(), input).await,
+        };
+        let result = result.and_then(|output| match output.vote {
+            Vote::Deny => Err(MatrixError::EthicsViolation { rule: format!("agent:{}", step.id), reason: output.rationale }),
+            _ => Ok(output),
+        });
+        match result {
+            Err(e) if attempts <= step.retries && retryable(&e) => tokio::time::sleep(step.retry_delay).await,
+            result => return (result, attempts),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::agents::{AgentDescriptor, Capability};
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Mutex;
+
+    // Replies with `reply(input)` after failing `failures` times; remembers every input
+    struct TestAgent {
+        inputs: &'static [InputKind],
+        reply: fn(&str) -> AgentOutput,
+        failures: AtomicU32,
+        delay: Duration,
+        seen: Mutex<Vec<String>>,
+    }
+
+    impl TestAgent {
+        fn new(reply: fn(&str) -> AgentOutput) -> Arc<Self> {
+            Self::build(&[InputKind::ShellCommand, InputKind::NaturalLanguage], reply, 0, Duration::ZERO)
+        }
+
+        fn build(inputs: &'static [InputKind], reply: fn(&str) -> AgentOutput, failures: u32, delay: Duration) -> Arc<Self> {
+            Arc::new(Self { inputs, reply, failures: AtomicU32::new(failures), delay, seen: Mutex::new(vec![]) })
+        }
+
+        fn seen(&self) -> Vec<String> {
+            self.seen.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl Agent for TestAgent {
+        fn descriptor(&self) -> AgentDescriptor {
+            AgentDescriptor::new("test", &[Capability::Compute], 1).inputs(self.inputs)
+        }
+
+        async fn execute(&self, task: &str) -> Result<AgentOutput, MatrixError> {
+            self.seen.lock().unwrap().push(task.to_string());
+            tokio::time::sleep(self.delay).await;
+            if self.failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() {
+                return Err(MatrixError::Remote("transient".to_string()));
+            }
+            Ok((self.reply)(task))
+        }
+    }
+
+    fn echo(task: &str) -> AgentOutput {
+        AgentOutput::abstain("echo").with_rewrite(format!("{}!", task))
+    }
+
+    fn hostile(_: &str) -> AgentOutput {
+        AgentOutput::abstain("note").with_rewrite("x'; rm -rf ~ #{task}")
+    }
+
+    #[tokio::test]
+    async fn pipeline_feeds_each_output_to_the_next() {
+        let (first, second) = (TestAgent::new(echo), TestAgent::new(echo));
+        let workflow = Workflow::pipeline(vec![("first", first.clone() as Arc<dyn Agent>), ("second", second.clone() as Arc<dyn Agent>)]);
+
+        let report = workflow.run("ls").await.unwrap();
+        assert!(report.succeeded());
+        assert_eq!(second.seen(), vec!["ls!"]);
+        assert_eq!(report.output("second"), Some("ls!!"));
+    }
+
+    #[tokio::test]
+    async fn templates_quote_outputs_for_agents_that_take_commands() {
+        let upstream = TestAgent::new(hostile);
+        let shell = TestAgent::build(&[InputKind::ShellCommand], echo, 0, Duration::ZERO);
+        let prose = TestAgent::build(&[InputKind::NaturalLanguage], echo, 0, Duration::ZERO);
+        let workflow = Workflow::new()
+            .step(Step::new("up", upstream))
+            .step(Step::new("shell", shell.clone()).after(&["up"]).input("git commit -m {up} # {task} {unknown}"))
+            .step(Step::new("prose", prose.clone()).after(&["up"]).input("Summarize {up} for {task}"));
+
+        workflow.run("T").await.unwrap();
+        assert_eq!(shell.seen(), vec![r#"git commit -m 'x'\''; rm -rf ~ #{task}' # T {unknown}"#]);
+        // Not quoted, but the `{task}` it carries is not expanded either
+        assert_eq!(prose.seen(), vec!["Summarize x'; rm -rf ~ #{task} for T"]);
+    }
+
+    #[test]
+    fn quotes_only_when_needed() {
+        assert_eq!(shell_quote("main.rs"), "main.rs");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote("$(id)"), "'$(id)'");
+    }
+
+    #[tokio::test]
+    async fn failure_policies_shape_the_rest_of_the_run() {
+        let failing = || TestAgent::build(&[InputKind::ShellCommand], echo, u32::MAX, Duration::ZERO);
+        let downstream = TestAgent::new(echo);
+        let workflow = Workflow::new()
+            .step(Step::new("broken", failing()).on_failure(FailurePolicy::SkipDependents))
+            .step(Step::new("skipped", downstream.clone()).after(&["broken"]))
+            .step(Step::new("patched", failing()).on_failure(FailurePolicy::Fallback("default".to_string())))
+            .step(Step::new("uses_patch", downstream.clone()).after(&["patched"]));
+
+        let report = workflow.run("task").await.unwrap();
+        let status = |id: &str| report.steps.iter().find(|s| s.id == id).unwrap().status.clone();
+        assert!(matches!(status("broken"), StepStatus::Failed(_)));
+        assert_eq!(status("skipped"), StepStatus::Skipped);
+        assert!(matches!(status("patched"), StepStatus::FellBack { .. }));
+        assert_eq!(report.output("uses_patch"), Some("default!"));
+        assert_eq!(downstream.seen(), vec!["default"]);
+        assert!(!report.succeeded());
+
+        let aborting = Workflow::new().step(Step::new("broken", failing()));
+        assert!(matches!(aborting.run("task").await, Err(MatrixError::Remote(_))));
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures_but_not_denials() {
+        let flaky = TestAgent::build(&[InputKind::ShellCommand], echo, 2, Duration::ZERO);
+        let report = Workflow::new().step(Step::new("flaky", flaky).retries(2, Duration::ZERO)).run("ls").await.unwrap();
+        assert_eq!(report.steps[0].attempts, 3);
+
+        let denier = TestAgent::build(&[InputKind::ShellCommand], |_| AgentOutput::deny(1.0, "no"), 0, Duration::ZERO);
+        let workflow = Workflow::new().step(Step::new("deny", denier.clone()).retries(3, Duration::ZERO).on_failure(FailurePolicy::SkipDependents));
+        let report = workflow.run("ls").await.unwrap();
+        assert_eq!(report.steps[0].attempts, 1);
+        assert_eq!(denier.seen().len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timeouts_apply_per_attempt() {
+        let slow = TestAgent::build(&[InputKind::ShellCommand], echo, 0, Duration::from_secs(60));
+        let workflow = Workflow::new().step(Step::new("slow", slow).timeout(Duration::from_secs(1)).retries(1, Duration::ZERO));
+        assert!(matches!(workflow.run("ls").await, Err(MatrixError::StepTimeout { step, .. }) if step == "slow"));
+    }
+
+    #[test]
+    fn validate_rejects_bad_graphs() {
+        let agent = || -> Arc<dyn Agent> { TestAgent::new(echo) };
+        let duplicate = Workflow::new().step(Step::new("a", agent())).step(Step::new("a", agent()));
+        let unknown = Workflow::new().step(Step::new("a", agent()).after(&["b"]));
+        let cycle = Workflow::new().step(Step::new("a", agent()).after(&["b"])).step(Step::new("b", agent()).after(&["a"]));
+        for workflow in [duplicate, unknown, cycle] {
+            assert!(matches!(workflow.validate(), Err(MatrixError::Workflow(_))));
+        }
+    }
+
+    #[test]
+    fn reads_workflow_files() {
+        let agent: Arc<dyn Agent> = TestAgent::new(echo);
+        let resolve = |name: &str| (name == "echo").then(|| agent.clone());
+        let text = r#"
+            [[step]]
+            id = "suggest"
+            agent = "echo"
+
+            [[step]]
+            id = "summarize"
+            agent = "echo"
+            after = ["suggest"]
+            input = "Summarize {suggest}"
+            timeout_ms = 500
+            retries = 2
+            on_failure = { fallback = "nothing to say" }
+        "#;
+        let workflow = Workflow::from_toml(text, resolve).unwrap();
+        let summarize = workflow.steps().find(|s| s.id() == "summarize").unwrap();
+        assert_eq!(summarize.after, vec!["suggest"]);
+        assert_eq!(summarize.timeout, Some(Duration::from_millis(500)));
+        assert_eq!(summarize.on_failure, FailurePolicy::Fallback("nothing to say".to_string()));
+
+        assert!(matches!(Workflow::from_toml("[[step]]\nid = \"x\"\nagent = \"missing\"", resolve), Err(MatrixError::Workflow(_))));
+        assert!(matches!(Workflow::from_toml("[[step]]\nid = \"x\"\nagent = \"echo\"\nafter = [\"x\"]", resolve), Err(MatrixError::Workflow(_))));
+        assert!(matches!(Workflow::from_toml("[[step]]\nid = \"x\"", resolve), Err(MatrixError::Workflow(_))));
+    }
+}